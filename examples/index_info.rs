@@ -0,0 +1,40 @@
+use bitrush_index::{
+    OZBCBitmap,
+    StorageReader,
+};
+
+use std::time::UNIX_EPOCH;
+
+/// Print the meta data and the compression of each chunk of a `BitmapIndex` of `u32`
+/// values saved in storage mode, i.e. the one created from the `storage_index` example:
+/// `cargo run --example index_info -- bitrush_index_u32`.
+fn main() {
+    let dir_path = std::env::args().nth(1).unwrap_or_else(|| String::from("bitrush_index_u32"));
+    let mut reader = match StorageReader::<OZBCBitmap, u32>::open(std::path::Path::new(&dir_path)) {
+        Ok(reader) => reader,
+        Err(err) => panic!("Error occured opening bitmap index {}: {:?}", dir_path, err)
+    };
+    let meta_data = reader.meta_data();
+    let seconds = |t: std::time::SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (major, minor, patch) = meta_data.library_version();
+    println!("--------------------------------------------------");
+    println!("Bitmap index: {}", dir_path);
+    println!("Library version: {}.{}.{}", major, minor, patch);
+    println!("Created at: {} (seconds since UNIX epoch)", seconds(meta_data.created_at()));
+    println!("Modified at: {} (seconds since UNIX epoch)", seconds(meta_data.modified_at()));
+    println!("Number of values: {}", meta_data.num_values());
+    println!("Bit block size: {}", meta_data.build_options().bit_block_size());
+    println!("Chunk size: {}", meta_data.build_options().chunk_size());
+    println!("Bin shift: {}", meta_data.build_options().bin_shift());
+    let report = match reader.compression_report() {
+        Ok(report) => report,
+        Err(err) => panic!("Error occured reading chunks of bitmap index {}: {:?}", dir_path, err)
+    };
+    println!("--------------------------------------------------");
+    println!("chunk  non-empty bitmaps  serialized size  uncompressed size  ratio");
+    for chunk in report.iter() {
+        println!("{:>5}  {:>17}  {:>15}  {:>17}  {:>5.1}", chunk.chunk_id, chunk.non_empty_bitmaps,
+            chunk.serialized_size, chunk.uncompressed_size, chunk.compression_ratio());
+    }
+    println!("--------------------------------------------------");
+}
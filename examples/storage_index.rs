@@ -8,12 +8,12 @@ use std::time::Instant;
 
 fn main() {
     const N: usize = 1 << 30; // 1GB
-    const K: usize = (1 << 20) * 1; // 1M
+    const K: usize = 1 << 20; // 1M
     let mut rng = rand::thread_rng();
     let path = std::path::Path::new("bitrush_index_u32");
 
     let build_options = bitrush_index::new_default_index_options::<u32>();
-    let mut b_index = match BitmapIndex::<OZBCBitmap, u32>::create(&path, build_options) {
+    let mut b_index = match BitmapIndex::<OZBCBitmap, u32>::create(path, build_options) {
         Ok(b_index) => b_index,
         Err(err) => panic!("Error occured creating bitmap index: {:?}", err)
     };
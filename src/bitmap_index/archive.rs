@@ -0,0 +1,196 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Archive
+//!
+//! `export_archive` packs the files of a `BitmapIndex` in storage mode (meta data,
+//! offsets, data and user meta data) into a single archive file, so an index can be
+//! shipped over HTTP or attached to a dataset release, and `import_archive` unpacks it
+//! into a new index directory. The archive starts with a table of contents, with the
+//! FNV-1a digest of each file, followed by the content of the files:
+//!
+//!  |64bit magic|32bit version|32bit num_files|toc entry|...|file content|...|
+//!
+//!  toc entry: |64bit offset|64bit size|64bit digest|16bit extension_len|extension|
+//!
+//! where `offset` is the position of the content of the file in the archive and
+//! `extension` is the extension of the file in the index directory. All integers are
+//! little endian, the files are copied as they are.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::{BitAnd, Shr};
+use std::path::{Path, PathBuf};
+
+use super::data_trailer::{fnv1a_update, FNV1A_OFFSET_BASIS};
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+const ARCHIVE_MAGIC: &[u8; 8] = b"BRIDXARC";
+const ARCHIVE_VERSION: u32 = 1;
+const COPY_BUFFER_SIZE: usize = 1 << 20;
+
+/// Entry of the table of contents of an archive.
+struct ArchiveEntry {
+    offset: u64,
+    size: u64,
+    digest: u64,
+    extension: String
+}
+
+impl ArchiveEntry {
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), IoError> {
+        writer.write_all(&self.offset.to_le_bytes())?;
+        writer.write_all(&self.size.to_le_bytes())?;
+        writer.write_all(&self.digest.to_le_bytes())?;
+        writer.write_all(&(self.extension.len() as u16).to_le_bytes())?;
+        writer.write_all(self.extension.as_bytes())
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self, IoError> {
+        let offset = read_u64(reader)?;
+        let size = read_u64(reader)?;
+        let digest = read_u64(reader)?;
+        let mut extension_len = [0u8; 2];
+        reader.read_exact(&mut extension_len)?;
+        let mut extension = vec![0u8; u16::from_le_bytes(extension_len) as usize];
+        reader.read_exact(&mut extension)?;
+        let extension = String::from_utf8(extension).ok()
+            .filter(|extension| !extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+            .ok_or_else(|| IoError::new(ErrorKind::InvalidData, "invalid archive file extension"))?;
+        Ok(ArchiveEntry { offset, size, digest, extension })
+    }
+
+    fn size(&self) -> u64 {
+        (3 * 8 + 2 + self.extension.len()) as u64
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, IoError> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Copy `size` bytes from `reader` to `writer` and return their FNV-1a digest.
+fn copy_with_digest<R: Read, W: Write>(reader: &mut R, writer: &mut W, size: u64) -> Result<u64, IoError> {
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    let mut digest = FNV1A_OFFSET_BASIS;
+    let mut remaining = size;
+    while remaining > 0 {
+        let len = remaining.min(COPY_BUFFER_SIZE as u64) as usize;
+        reader.read_exact(&mut buf[..len])?;
+        digest = fnv1a_update(digest, &buf[..len]);
+        writer.write_all(&buf[..len])?;
+        remaining -= len as u64;
+    }
+    Ok(digest)
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Write the files of `BitmapIndex` into the archive file `archive_path`, replacing it
+    /// if it exists. Only flushed values are exported (see `flush_chunk`). Error occur if
+    /// `BitmapIndex` is opened in memory mode.
+    pub fn export_archive(&self, archive_path: &Path) -> Result<(), Error> {
+        let storage_idx = self.storage_idx.as_ref().ok_or(Error::ParametersError)?;
+        let dir_path = storage_idx.dir_path.as_path();
+        let name = dir_path.file_name().ok_or(Error::ParametersError)?;
+        let mut files: Vec<(PathBuf, ArchiveEntry)> = Vec::new();
+        for entry in Self::map_io_result(fs::read_dir(dir_path))? {
+            let path = Self::map_io_result(entry)?.path();
+            let extension = path.extension().and_then(|extension| extension.to_str()).map(String::from);
+            if let (Some(extension), true) = (extension, path.is_file() && path.file_stem() == Some(name)) {
+                let size = Self::map_io_result(fs::metadata(&path))?.len();
+                files.push((path, ArchiveEntry { offset: 0, size, digest: 0, extension }));
+            }
+        }
+        files.sort_by(|(_p1, e1), (_p2, e2)| e1.extension.cmp(&e2.extension));
+
+        let toc_size: u64 = files.iter().map(|(_path, entry)| entry.size()).sum();
+        let mut offset = (ARCHIVE_MAGIC.len() + 2 * 4) as u64 + toc_size;
+        for (_path, entry) in files.iter_mut() {
+            entry.offset = offset;
+            offset += entry.size;
+        }
+        let write_r = (|| {
+            let mut writer = BufWriter::with_capacity(COPY_BUFFER_SIZE, File::create(archive_path)?);
+            // The table of contents is written again with the digests after the content.
+            writer.write_all(ARCHIVE_MAGIC)?;
+            writer.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+            writer.write_all(&(files.len() as u32).to_le_bytes())?;
+            for (_path, entry) in files.iter() {
+                entry.write_to(&mut writer)?;
+            }
+            for (path, entry) in files.iter_mut() {
+                let mut file = File::open(path)?;
+                entry.digest = copy_with_digest(&mut file, &mut writer, entry.size)?;
+            }
+            let mut archive_file = writer.into_inner().map_err(|err| err.into_error())?;
+            archive_file.seek(SeekFrom::Start((ARCHIVE_MAGIC.len() + 2 * 4) as u64))?;
+            let mut writer = BufWriter::new(archive_file);
+            for (_path, entry) in files.iter() {
+                entry.write_to(&mut writer)?;
+            }
+            writer.into_inner().map_err(|err| err.into_error())?.sync_all()
+        })();
+        Self::map_io_result(write_r)
+    }
+
+    /// Unpack the archive file `archive_path` into the directory `dir_path`, that must not
+    /// exist, and open the `BitmapIndex` in storage mode. Error occur if the archive is
+    /// not valid or if the digest of a file doesn't match, in that case `dir_path` is
+    /// removed.
+    pub fn import_archive(archive_path: &Path, dir_path: &Path) -> Result<Self, Error> {
+        if Self::check_if_path_exixsts(dir_path) {
+            return Err(Error::ParametersError);
+        }
+        let name = dir_path.file_name().ok_or(Error::ParametersError)?;
+        let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, Self::map_io_result(File::open(archive_path))?);
+        let entries = Self::map_io_result(Self::read_archive_toc(&mut reader))?;
+        Self::map_io_result(fs::create_dir(dir_path))?;
+        let unpack_r = (|| {
+            for entry in entries.iter() {
+                let mut path = PathBuf::from(dir_path);
+                path.push(name);
+                path.set_extension(&entry.extension);
+                reader.seek(SeekFrom::Start(entry.offset))?;
+                let mut writer = BufWriter::with_capacity(COPY_BUFFER_SIZE, File::create(&path)?);
+                let digest = copy_with_digest(&mut reader, &mut writer, entry.size)?;
+                writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+                if digest != entry.digest {
+                    let msg = format!("digest mismatch of the archived {} file", entry.extension);
+                    return Err(IoError::new(ErrorKind::InvalidData, msg));
+                }
+            }
+            Ok(())
+        })();
+        match unpack_r.map_err(Error::FileError).and_then(|_| Self::open(dir_path)) {
+            Ok(b_index) => Ok(b_index),
+            Err(err) => {
+                let _err = fs::remove_dir_all(dir_path);
+                Err(err)
+            }
+        }
+    }
+
+    fn read_archive_toc<R: Read>(reader: &mut R) -> Result<Vec<ArchiveEntry>, IoError> {
+        let mut header = [0u8; 16];
+        reader.read_exact(&mut header)?;
+        let version = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        if &header[..8] != ARCHIVE_MAGIC || version != ARCHIVE_VERSION {
+            return Err(IoError::new(ErrorKind::InvalidData, "not a bitmap index archive"));
+        }
+        let num_files = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+        (0..num_files).map(|_i| ArchiveEntry::read_from(reader)).collect()
+    }
+}
@@ -0,0 +1,113 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Arrow ingestion
+//!
+//! Build a `BitmapIndex` from a column of an Arrow IPC file or from an Arrow array,
+//! available with the `arrow` feature. The IPC file is read one record batch at a
+//! time and each column is casted to the Arrow type of the `BitValue`.
+//! Null values can't be indexed.
+
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    ArrowPrimitiveType,
+    Int16Type,
+    Int32Type,
+    Int64Type,
+    Int8Type,
+    UInt16Type,
+    UInt32Type,
+    UInt64Type,
+    UInt8Type
+};
+use arrow_array::{Array, ArrowNativeTypeOp};
+use arrow_ipc::reader::FileReader;
+use arrow_schema::ArrowError;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    TransmuteToUsize
+};
+
+/// A `BitValue` that has an Arrow primitive type.
+/// `ArrowBitValue` is implemented for:
+/// `u8, u16`, `u32`, `u64`, `i8`, `i16`, `i32`, `i64`.
+pub trait ArrowBitValue: BitValue + ArrowNativeTypeOp {
+    type ArrowType: ArrowPrimitiveType<Native = Self>;
+}
+
+macro_rules! impl_arrow_bit_value {
+    ($($ty:ident => $arrow_ty:ident),+) => {
+        $(impl ArrowBitValue for $ty {
+            type ArrowType = $arrow_ty;
+        })+
+    };
+}
+
+impl_arrow_bit_value!(
+    u8 => UInt8Type, u16 => UInt16Type, u32 => UInt32Type, u64 => UInt64Type,
+    i8 => Int8Type, i16 => Int16Type, i32 => Int32Type, i64 => Int64Type
+);
+
+impl<T: Bitmap, U: ArrowBitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `BitmapIndex` in memory mode that contains the values of `column`
+    /// of the Arrow IPC file `ipc_path`.
+    pub fn build_from_arrow_ipc(ipc_path: &Path, column: &str, build_options: BuildOptions) -> Result<Self, Error> {
+        let mut b_index = Self::new(build_options)?;
+        b_index.push_arrow_ipc_column(ipc_path, column)?;
+        Ok(b_index)
+    }
+
+    /// Insert (in append) the values of `column` of the Arrow IPC file `ipc_path` and
+    /// return the number of values inserted.
+    pub fn push_arrow_ipc_column(&mut self, ipc_path: &Path, column: &str) -> Result<u64, Error> {
+        let file = Self::map_io_result(fs::File::open(ipc_path))?;
+        let reader = Self::map_arrow_result(FileReader::try_new(file, None))?;
+        let i_column = match reader.schema().index_of(column) {
+            Ok(i_column) => i_column,
+            Err(_) => return Err(Error::ParametersError)
+        };
+
+        let mut num_values: u64 = 0;
+        for batch in reader {
+            let batch = Self::map_arrow_result(batch)?;
+            num_values += self.push_arrow_array(batch.column(i_column))?;
+        }
+        Ok(num_values)
+    }
+
+    /// Insert (in append) the values of `array` and return the number of values inserted.
+    /// `array` is casted to the Arrow type of `U`, error occur if a value can't be
+    /// represented as `U` or is null.
+    pub fn push_arrow_array(&mut self, array: &dyn Array) -> Result<u64, Error> {
+        let array = Self::map_arrow_result(arrow_cast::cast(array, &U::ArrowType::DATA_TYPE))?;
+        if array.null_count() > 0 {
+            let msg = "null values or values out of range can't be indexed";
+            return Err(Error::FileError(IoError::new(ErrorKind::InvalidData, msg)));
+        }
+        let values = array.as_primitive::<U::ArrowType>().values();
+        self.push_values(values)?;
+        Ok(values.len() as u64)
+    }
+
+    pub(super) fn map_arrow_result<M>(result: Result<M, ArrowError>) -> Result<M, Error> {
+        match result {
+            Ok(r) => Ok(r),
+            Err(err) => Err(Error::FileError(IoError::new(ErrorKind::InvalidData, err)))
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Audit
+//!
+//! `validate_against` checks an index against its source column, e.g. after a
+//! `rewrite_chunk`, a `rebuild` or a migration of the storage files. The column is
+//! streamed one chunk at a time: every bitmap of the chunk is unrolled and each of its
+//! positions must be in the bitmap of the block that the expected value selects, and
+//! each position must be in one bitmap of every block. The first position that doesn't
+//! match is reported as a `ValueDivergence`.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BlockInfo,
+    Error,
+    TransmuteToUsize
+};
+
+/// The first position where a `BitmapIndex` doesn't match its source column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValueDivergence<U> {
+    /// The index of the value.
+    pub position: u64,
+    /// The value of the source column, `None` if the column is shorter than the index.
+    pub expected: Option<U>,
+    /// The value reconstructed from the bitmaps, `None` if the index is shorter than the
+    /// column or if the position is not in exactly one bitmap of each block.
+    pub found: Option<U>
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Check the index against `values`, the source column, and return the first
+    /// divergence or `None` if every position is in the bitmaps of its value. With a
+    /// `bin_shift` the low `bin_shift` bits of `values` are not checked. Only one chunk
+    /// of values is kept in memory at a time.
+    pub fn validate_against<I: IntoIterator<Item = U>>(&mut self, values: I) -> Result<Option<ValueDivergence<U>>, Error> {
+        let mut values = values.into_iter();
+        let num_chunks = self.num_values.div_ceil(self.chunk_size);
+        for chunk_id in 0..num_chunks {
+            let chunk_start = chunk_id * self.chunk_size;
+            let num_values = (self.num_values - chunk_start).min(self.chunk_size) as usize;
+            let expected: Vec<U> = values.by_ref().take(num_values).collect();
+            let bitmaps = self.chunk_bitmaps(chunk_id)?;
+            let block_info = &self.block_info;
+
+            // Number of blocks where a position is in the expected bitmap, or `None` if
+            // it's in an unexpected one.
+            let mut matches: Vec<Option<usize>> = vec![Some(0); num_values];
+            for (i_bitmap, bitmap) in bitmaps.iter().enumerate() {
+                let i_block = i_bitmap / block_info.num_bitmaps_in_block;
+                let block_bits = i_bitmap % block_info.num_bitmaps_in_block;
+                let shift = block_info.bin_shift + i_block * block_info.bit_block_size;
+                for pos in bitmap.unroll_bitmap() {
+                    let Some(value) = expected.get(pos as usize) else {
+                        continue;
+                    };
+                    let block_matches = &mut matches[pos as usize];
+                    if (value.to_ordered() >> shift).transmute_to_usize() & block_info.bit_block_mask == block_bits {
+                        *block_matches = block_matches.map(|n| n + 1);
+                    } else {
+                        *block_matches = None;
+                    }
+                }
+            }
+            let divergence = matches.iter().enumerate()
+                .position(|(pos, block_matches)| pos >= expected.len() || *block_matches != Some(block_info.num_blocks));
+            if let Some(pos) = divergence {
+                let found = Self::unique_ordered_value_at(block_info, &bitmaps, pos as u32).map(Self::value_from_ordered);
+                return Ok(Some(ValueDivergence {
+                    position: chunk_start + pos as u64,
+                    expected: expected.get(pos).copied(),
+                    found
+                }));
+            }
+        }
+        Ok(values.next().map(|value| ValueDivergence {
+            position: self.num_values,
+            expected: Some(value),
+            found: None
+        }))
+    }
+
+    /// Return the order-preserving representation of the value at `pos` of the chunk with
+    /// `bitmaps`, or `None` if a block has no bitmap or more than one bitmap that
+    /// contains `pos` (see `ordered_value_at`).
+    fn unique_ordered_value_at(block_info: &BlockInfo, bitmaps: &[T], pos: u32) -> Option<u128> {
+        let mut ordered_value: u128 = 0;
+        for (i_block, block_bitmaps) in bitmaps.chunks(block_info.num_bitmaps_in_block).enumerate() {
+            let mut block_values = block_bitmaps.iter().enumerate()
+                .filter(|(_block_bits, bitmap)| bitmap.contains(pos))
+                .map(|(block_bits, _bitmap)| block_bits as u128);
+            let block_bits = match (block_values.next(), block_values.next()) {
+                (Some(block_bits), None) => block_bits,
+                _ => return None
+            };
+            ordered_value |= block_bits << (block_info.bin_shift + i_block * block_info.bit_block_size);
+        }
+        Some(ordered_value)
+    }
+}
@@ -0,0 +1,62 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Auto flush
+//!
+//! A `BitmapIndex` in storage mode always flushes full chunks, `set_flush_policy` adds
+//! the triggers of a `FlushPolicy`: the current chunk is flushed also when the
+//! serialized size of in memory bitmaps exceeds a limit or when a time is passed from
+//! the last durable write, so a low-rate stream bounds the values lost on a crash. A
+//! partial chunk is written over its older versions (see `write_chunk`), so frequent
+//! flushes keep the data file at about three times the size of the chunk. The policy
+//! is checked every `CHECK_POLICY_INTERVAL` values pushed, so with no pushes nothing is
+//! flushed: to flush while waiting for values use an `IndexWriter`, that sets its
+//! policy on the index it wraps.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    FlushPolicy,
+    TransmuteToUsize
+};
+
+/// Number of values pushed between two checks of the flush policy.
+pub(super) const CHECK_POLICY_INTERVAL: u64 = 1 << 12;
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Flush the current chunk also following `flush_policy`, replacing the previous
+    /// policy. Error occur if `BitmapIndex` is opened in memory mode.
+    pub fn set_flush_policy(&mut self, flush_policy: FlushPolicy) -> Result<(), Error> {
+        if self.storage_idx.is_none() {
+            return Err(Error::ParametersError);
+        }
+        self.flush_policy = Some(flush_policy);
+        Ok(())
+    }
+
+    /// Remove the flush policy, so only full chunks are flushed.
+    pub fn remove_flush_policy(&mut self) {
+        self.flush_policy = None;
+    }
+
+    /// Flush the current chunk if it has unflushed values and the flush policy requires it.
+    pub(super) fn apply_flush_policy(&mut self) -> Result<(), Error> {
+        let requires_flush = match self.flush_policy.as_ref() {
+            Some(flush_policy) => flush_policy.requires_flush(self.memory_bitmaps_size(), self.last_durable_write.elapsed()),
+            None => false
+        };
+        if requires_flush && self.is_dirty() {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Online backup
+//!
+//! `backup` copies a `BitmapIndex` in storage mode up to its last flush while the writer
+//! is active (i.e. from another thread or process), without pausing ingestion. A flush
+//! writes the chunk after the last chunk, or over the older versions of the partial
+//! chunk (see `write_chunk`), and then its offsets and the meta data, a `rewrite_chunk`
+//! writes the new chunks after the last chunk and then repoints them. So a backup reads
+//! the meta data, then the offsets, and copies them only if the meta data are unchanged
+//! and the offsets don't point after the last chunk. The data file is copied up to the
+//! end of the last chunk, so a chunk written after the meta data were read is never
+//! included, and the copy is kept only if the meta data are still unchanged after it:
+//! a flush that overwrites the copied bytes changes them. Otherwise everything is read
+//! again.
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    MetaData,
+    StorageIdx,
+    TransmuteToUsize
+};
+
+/// Number of times the meta data and the offsets are read before a backup fails.
+const MAX_BACKUP_ATTEMPTS: usize = 64;
+/// Size of the meta data file: current meta data, last checkpoint and their extents.
+const META_DATA_FILE_SIZE: usize = 2 * mem::size_of::<MetaData>() + 4 * mem::size_of::<u64>();
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Copy the `BitmapIndex` in storage mode saved in `dir_path` up to its last flush in
+    /// the directory `backup_path`, that must not exist, and return the `MetaData` of the
+    /// copy. The index can be written at the same time. `InconsistentStorage` is returned
+    /// if a consistent state can't be read (i.e. the index is flushed continuously), in
+    /// that case `backup_path` is removed.
+    pub fn backup(dir_path: &Path, backup_path: &Path) -> Result<MetaData, Error> {
+        if !Self::check_if_path_exixsts(dir_path) || Self::check_if_path_exixsts(backup_path) {
+            return Err(Error::ParametersError);
+        }
+        let mut storage_idx = Self::new_storage_idx(dir_path)?;
+        Self::map_io_result(fs::create_dir(backup_path))?;
+        let backup_r = Self::new_storage_idx(backup_path).and_then(|mut backup_idx| {
+            let meta_data = Self::copy_backup_state(&mut storage_idx, &mut backup_idx)?;
+            let user_meta_data_path = Self::user_meta_data_path(&storage_idx);
+            if user_meta_data_path.exists() {
+                let copy_r = fs::copy(&user_meta_data_path, Self::user_meta_data_path(&backup_idx));
+                Self::map_io_result(copy_r)?;
+            }
+            Ok(meta_data)
+        });
+        if backup_r.is_err() {
+            let _err = fs::remove_dir_all(backup_path);
+        }
+        backup_r
+    }
+
+    /// Copy the `BitmapIndex` up to its last flush in the directory `backup_path`, see
+    /// `backup`. Unflushed values are not copied. Error occur if `BitmapIndex` is opened
+    /// in memory mode.
+    pub fn backup_to(&self, backup_path: &Path) -> Result<MetaData, Error> {
+        let storage_idx = self.storage_idx.as_ref().ok_or(Error::ParametersError)?;
+        Self::backup(&storage_idx.dir_path, backup_path)
+    }
+
+    /// Copy the meta data, offset and data files of `storage_idx` in `backup_idx` and
+    /// return the copied `MetaData`, retrying while the index is flushed during the copy.
+    fn copy_backup_state(storage_idx: &mut StorageIdx, backup_idx: &mut StorageIdx) -> Result<MetaData, Error> {
+        for _attempt in 0..MAX_BACKUP_ATTEMPTS {
+            let (meta_data_buf, offsets) = Self::read_backup_state(storage_idx)?;
+            let data_end = offsets.last().copied().unwrap_or(0);
+            // a flush can truncate the data file before the copy reaches `data_end`.
+            let copy_r = Self::copy_data_file(storage_idx, backup_idx, data_end);
+            if copy_r.is_ok() && Self::read_meta_data_file(storage_idx)? == meta_data_buf {
+                Self::map_io_result(Self::write_backup(backup_idx, &meta_data_buf, &offsets))?;
+                return Self::meta_data_from_slice_u8(&meta_data_buf);
+            }
+        }
+        Err(Error::InconsistentStorage("the index is flushed during every backup attempt".to_string()))
+    }
+
+    /// Return the content of the meta data file and the offsets of its chunks, read when
+    /// the index is not being flushed.
+    fn read_backup_state(storage_idx: &mut StorageIdx) -> Result<(Vec<u8>, Vec<u64>), Error> {
+        for _attempt in 0..MAX_BACKUP_ATTEMPTS {
+            let meta_data_buf = Self::read_meta_data_file(storage_idx)?;
+            let meta_data = match Self::meta_data_from_slice_u8(&meta_data_buf) {
+                Ok(meta_data) => meta_data,
+                Err(_) => continue
+            };
+            Self::check_meta_data(&meta_data)?;
+            let num_offsets = match meta_data.num_values {
+                0 => 0,
+                num_values => num_values.div_ceil(meta_data.build_options.chunk_size()) + 1
+            };
+            let offsets = match Self::read_offsets(storage_idx, 0, num_offsets as usize) {
+                Ok(offsets) => offsets,
+                Err(_) => continue
+            };
+            let extent_offset = 2 * mem::size_of::<MetaData>();
+            let extent: [u64; 2] = Self::copy_from_slice_u8(&meta_data_buf[extent_offset..]);
+            let last_offsets = match offsets.len() {
+                0 => [0, 0],
+                len => [offsets[len - 2], offsets[len - 1]]
+            };
+            if last_offsets == extent && offsets.iter().all(|offset| *offset <= extent[1])
+                && Self::read_meta_data_file(storage_idx)? == meta_data_buf {
+                return Ok((meta_data_buf, offsets));
+            }
+        }
+        Err(Error::InconsistentStorage("the index is flushed during every backup attempt".to_string()))
+    }
+
+    fn read_meta_data_file(storage_idx: &mut StorageIdx) -> Result<Vec<u8>, Error> {
+        let mut buf: Vec<u8> = vec![0; META_DATA_FILE_SIZE];
+        Self::map_io_result(storage_idx.meta_data_file.seek(SeekFrom::Start(0)))?;
+        Self::map_io_result(storage_idx.meta_data_file.read_exact(&mut buf))?;
+        Ok(buf)
+    }
+
+    /// Copy the first `data_end` bytes of the data file of `storage_idx` in `backup_idx`.
+    fn copy_data_file(storage_idx: &mut StorageIdx, backup_idx: &mut StorageIdx, data_end: u64) -> Result<(), io::Error> {
+        storage_idx.data_file.seek(SeekFrom::Start(0))?;
+        backup_idx.data_file.set_len(0)?;
+        backup_idx.data_file.seek(SeekFrom::Start(0))?;
+        let copied = io::copy(&mut (&mut storage_idx.data_file).take(data_end), &mut backup_idx.data_file)?;
+        if copied != data_end {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        Ok(())
+    }
+
+    /// Write the meta data file `meta_data_buf` and `offsets` in `backup_idx`, whose data
+    /// file has been copied.
+    fn write_backup(backup_idx: &mut StorageIdx, meta_data_buf: &[u8], offsets: &[u64]) -> Result<(), io::Error> {
+        let offsets_buf: Vec<u8> = offsets.iter().flat_map(|offset| offset.to_ne_bytes()).collect();
+        backup_idx.offset_file.write_all(&offsets_buf)?;
+        // the last checkpoint of the copy is its current state.
+        let meta_data_size = mem::size_of::<MetaData>();
+        let extent_offset = 2 * meta_data_size;
+        backup_idx.meta_data_file.write_all(&meta_data_buf[..meta_data_size])?;
+        backup_idx.meta_data_file.write_all(&meta_data_buf[..meta_data_size])?;
+        backup_idx.meta_data_file.write_all(&meta_data_buf[extent_offset..(extent_offset + 2 * mem::size_of::<u64>())])?;
+        backup_idx.meta_data_file.write_all(&meta_data_buf[extent_offset..(extent_offset + 2 * mem::size_of::<u64>())])?;
+        backup_idx.data_file.sync_all()?;
+        backup_idx.offset_file.sync_all()?;
+        backup_idx.meta_data_file.sync_all()
+    }
+}
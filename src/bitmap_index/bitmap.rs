@@ -12,6 +12,7 @@
 
 use std::ops::BitAnd;
 use std::fmt::Debug;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
 
 pub trait Bitmap
 where Self: Clone + Debug, for <'a> &'a Self: BitAnd<&'a Self,Output=Self> {
@@ -19,23 +20,141 @@ where Self: Clone + Debug, for <'a> &'a Self: BitAnd<&'a Self,Output=Self> {
     /// Return a new bitmap.
     fn new() -> Self;
 
+    /// Return a new bitmap with capacity for `expected_bits` set bits. The default
+    /// implementation reserves the capacity with `reserve`, so it falls back to `new`
+    /// for bitmaps that don't implement `reserve`.
+    fn with_capacity(expected_bits: u32) -> Self {
+        let mut bitmap = Self::new();
+        bitmap.reserve(expected_bits);
+        bitmap
+    }
+
+    /// Return a tag that identifies the bitmap serialization format. The tag is stored
+    /// in `BitmapIndex` meta data so an index can't be opened with a different bitmap
+    /// type than the one used to write it. Custom bitmaps should return their own tag.
+    fn format_tag() -> u32 {
+        0
+    }
+
+    /// Reserve capacity for at least `num_bits` more set bits, so setting them doesn't
+    /// reallocate the bitmap content. The default implementation does nothing.
+    fn reserve(&mut self, _num_bits: u32) {}
+
+    /// Select the wide words encoding of the serialized content, if the bitmap has one
+    /// (see `BuildOptions::with_wide_words`). A bitmap read from a serialized content
+    /// takes the encoding of the content. The default implementation does nothing.
+    fn set_wide_words(&mut self, _wide_words: bool) {}
+
+    /// Reset the bitmap to an empty bitmap. Bitmaps should override the default
+    /// implementation, that creates a new bitmap, to keep their allocations so
+    /// `BitmapIndex` can reuse a bitmap for the next chunk.
+    fn clear(&mut self) {
+        *self = Self::new();
+    }
+
     /// Set the ith bit (starting from zero).
     fn set(&mut self, i: u32);
 
     /// Return a Vec with all bit set positions. 
     fn unroll_bitmap(&self) -> Vec<u32>;
 
+    /// Return a bitmap with the bits set in this bitmap and not set in `b1`
+    /// ("logical and not"). Bitmaps should override the default implementation
+    /// that unrolls both bitmaps.
+    fn and_not(&self, b1: &Self) -> Self {
+        let b1_bits = b1.unroll_bitmap();
+        let mut b_result = Self::new();
+        for i in self.unroll_bitmap() {
+            if b1_bits.binary_search(&i).is_err() {
+                b_result.set(i);
+            }
+        }
+        b_result
+    }
+
+    /// Return `true` if no bit is set. Bitmaps should override the default
+    /// implementation that unrolls the bitmap.
+    fn is_empty(&self) -> bool {
+        self.unroll_bitmap().is_empty()
+    }
+
+    /// Return the number of set bits. Bitmaps should override the default
+    /// implementation that unrolls the bitmap.
+    fn cardinality(&self) -> u64 {
+        self.unroll_bitmap().len() as u64
+    }
+
+    /// Return `true` if the ith bit is set. Bitmaps should override the default
+    /// implementation that unrolls the bitmap.
+    fn contains(&self, i: u32) -> bool {
+        self.unroll_bitmap().binary_search(&i).is_ok()
+    }
+
     /// Return the effective size to serialize the bitmap.
     fn size(&self) -> usize;
 
     /// Write bitamp content into buffer_out and return the numbers of bytes written.
     /// Return a generic error if buffer_out size is less then bitmap size. 
+    #[allow(clippy::result_unit_err)]
     fn write_to_buffer(&self, buffer_out: &mut [u8]) -> Result<usize, ()>;
 
+    /// Write bitamp content into writer and return the numbers of bytes written.
+    /// The default implementation serializes the bitmap in a temporary buffer with
+    /// `write_to_buffer`, bitmaps should override it to write their content directly.
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<usize, IoError> {
+        let mut buffer_out: Vec<u8> = vec![0; self.size()];
+        let size = match self.write_to_buffer(&mut buffer_out) {
+            Ok(size) => size,
+            Err(_) => return Err(IoError::new(ErrorKind::InvalidData, "bitmap serialization error"))
+        };
+        writer.write_all(&buffer_out[0..size])?;
+        Ok(size)
+    }
+
     /// Read bitmap content from buffer_in, buffer_in must have the effettive
     /// bitmap content (the size returned from write_to_buffer method).
     /// If `check_bitmap == false` bitmap content is readed without
     /// any check on bitmap content integrity. Return a generic error an error occur.
+    #[allow(clippy::result_unit_err)]
     fn read_from_buffer(&mut self, buffer_in: &[u8], check_bitmap: bool) -> Result<(), ()>;
+
+    /// Read `size` bytes of bitmap content (the size returned from write_to_buffer method)
+    /// from reader. An invalid content is returned as an `InvalidData` error.
+    /// The default implementation reads the content in a temporary buffer of `size` bytes
+    /// and uses `read_from_buffer`, bitmaps should override it to read their content with
+    /// a bounded buffer.
+    fn read_from<R: Read>(&mut self, reader: &mut R, size: usize, check_bitmap: bool) -> Result<(), IoError> {
+        let mut buffer_in: Vec<u8> = vec![0; size];
+        reader.read_exact(&mut buffer_in)?;
+        match self.read_from_buffer(&buffer_in, check_bitmap) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(IoError::new(ErrorKind::InvalidData, "bitmap deserialization error"))
+        }
+    }
 }
 
+
+/// A Trait for bitmaps that can be evaluated directly on their serialized content.
+/// A `View` borrows the bytes written from `write_to_buffer` (i.e. from a memory map
+/// of the data file), so "logical and" and unroll run without copying the content
+/// into an owned bitmap.
+pub trait BitmapView: Bitmap
+where for <'a> &'a Self: BitAnd<&'a Self,Output=Self> {
+
+    /// Borrowed representation of a serialized bitmap.
+    type View<'a>;
+
+    /// Return a view on buffer_in, buffer_in must have the effettive bitmap content.
+    /// Return a generic error if buffer_in is not a valid bitmap content.
+    #[allow(clippy::result_unit_err)]
+    fn view(buffer_in: &[u8]) -> Result<Self::View<'_>, ()>;
+
+    /// Return the "logical and" between two views.
+    fn and_views(b0: &Self::View<'_>, b1: &Self::View<'_>) -> Self;
+
+    /// Return the "logical and" between this bitmap and a view.
+    fn and_view(&self, b1: &Self::View<'_>) -> Self;
+
+    /// Return a Vec with all bit set positions of a view.
+    fn unroll_view(view: &Self::View<'_>) -> Vec<u32>;
+}
@@ -0,0 +1,72 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Block queries
+//!
+//! A value is indexed with one bitmap for each block of its bits, so a query that
+//! constrains only some blocks of the value (i.e. "the high 16 bits are equal to X,
+//! the low bits are anything") intersects only the bitmaps of those blocks.
+//! `run_query_blocks` selects the blocks by position, `run_query_prefix` selects the
+//! blocks of the highest bits of the value. Blocks are numbered from the block of the
+//! lowest bits, like the bitmaps returned from `fetch_bitmaps`.
+
+use std::mem;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the number of blocks of each value.
+    pub fn num_blocks(&self) -> usize {
+        self.block_info.num_blocks
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values whose blocks `blocks` are
+    /// equal to the same blocks of `value`, the other blocks of `value` are ignored.
+    /// `ParametersError` is returned if `blocks` is empty or if a block doesn't exist.
+    /// The parameters `start_index` and `end_index` are optional and if specified define
+    /// the range where query is runned.
+    pub fn run_query_blocks(&mut self, value: U, blocks: &[usize], start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        if blocks.is_empty() || blocks.iter().any(|block| *block >= self.block_info.num_blocks) {
+            return Err(Error::ParametersError);
+        }
+        let value_i_bitmaps = Self::get_query_i_bitmaps(&self.block_info, value);
+        let mut query_i_bitmaps: Vec<usize> = blocks.iter().map(|block| value_i_bitmaps[*block]).collect();
+        query_i_bitmaps.sort_unstable();
+        query_i_bitmaps.dedup();
+        let start_index = start_index.unwrap_or(0);
+        let end_index = end_index.unwrap_or(self.num_values);
+        let mut indexes = self.run_query_i_bitmaps_grouped(&[query_i_bitmaps], start_index, end_index)?;
+        Ok(indexes.pop().unwrap_or_default())
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values whose highest
+    /// `prefix_bits` bits are equal to the same bits of `value`. `ParametersError` is
+    /// returned if `prefix_bits` is 0 or if the prefix doesn't start at a block boundary.
+    /// The parameters `start_index` and `end_index` are optional and if specified define
+    /// the range where query is runned.
+    pub fn run_query_prefix(&mut self, value: U, prefix_bits: usize, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let bits = mem::size_of::<U>() << 3;
+        let block_info = &self.block_info;
+        if prefix_bits == 0 || prefix_bits + block_info.bin_shift > bits {
+            return Err(Error::ParametersError);
+        }
+        let prefix_low = bits - prefix_bits - block_info.bin_shift;
+        if !prefix_low.is_multiple_of(block_info.bit_block_size) {
+            return Err(Error::ParametersError);
+        }
+        let blocks: Vec<usize> = ((prefix_low / block_info.bit_block_size)..block_info.num_blocks).collect();
+        self.run_query_blocks(value, &blocks, start_index, end_index)
+    }
+}
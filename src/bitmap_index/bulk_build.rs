@@ -0,0 +1,144 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Bulk build
+//!
+//! `build` and `build_from_column_file` construct a `BitmapIndex` from a complete
+//! column chunk by chunk, without the per-value bookkeeping of `push_value`: the values
+//! of a chunk are buffered, then for each block a first pass counts the bits of every
+//! bitmap, so each bitmap is reserved for its exact number of bits, and a second pass
+//! sets them. Only the bitmaps of one block are touched by each pass. In storage mode
+//! every chunk is flushed as soon as it is full, so at most one chunk of values
+//! (`chunk_size * size_of::<U>()` bytes) is buffered.
+//!
+//! A column file contains the values in native byte order without a header, like the
+//! dictionary file of a `RemappedIndex`.
+
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Read};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `BitmapIndex` that contains `values`, in storage mode in the
+    /// directory `dir_path` (that must not exist) or in memory mode if `dir_path` is `None`.
+    /// In storage mode the last chunk, if partial, is not flushed (see `flush_chunk`).
+    pub fn build<I>(dir_path: Option<&Path>, build_options: BuildOptions, values: I) -> Result<Self, Error>
+    where I: IntoIterator, I::Item: Borrow<U> {
+        let mut b_index = match dir_path {
+            Some(dir_path) => Self::create(dir_path, build_options)?,
+            None => Self::new(build_options)?
+        };
+        b_index.push_column(values)?;
+        Ok(b_index)
+    }
+
+    /// Return a new `BitmapIndex` that contains the values of the column file
+    /// `column_path`, see `build`.
+    pub fn build_from_column_file(dir_path: Option<&Path>, build_options: BuildOptions, column_path: &Path) -> Result<Self, Error> {
+        let mut b_index = match dir_path {
+            Some(dir_path) => Self::create(dir_path, build_options)?,
+            None => Self::new(build_options)?
+        };
+        b_index.push_column_file(column_path)?;
+        Ok(b_index)
+    }
+
+    /// Insert (in append) `values` into the index chunk by chunk and return the number
+    /// of values inserted.
+    pub fn push_column<I>(&mut self, values: I) -> Result<u64, Error>
+    where I: IntoIterator, I::Item: Borrow<U> {
+        let mut values = values.into_iter();
+        let mut chunk_values: Vec<U> = Vec::new();
+        let mut num_values: u64 = 0;
+        loop {
+            let num_values_in_chunk = self.num_values & self.chunk_size_mask;
+            chunk_values.clear();
+            chunk_values.extend(values.by_ref().take((self.chunk_size - num_values_in_chunk) as usize).map(|v| *v.borrow()));
+            if chunk_values.is_empty() {
+                break;
+            }
+            self.push_chunk_values(&chunk_values)?;
+            num_values += chunk_values.len() as u64;
+        }
+        if self.flush_policy.is_some() {
+            self.apply_flush_policy()?;
+        }
+        Ok(num_values)
+    }
+
+    /// Insert (in append) the values of the column file `column_path` chunk by chunk and
+    /// return the number of values inserted. Error occur if the size of the file is not a
+    /// multiple of the size of `U`, in that case no value is inserted.
+    pub fn push_column_file(&mut self, column_path: &Path) -> Result<u64, Error> {
+        let value_size = mem::size_of::<U>();
+        let mut column_file = Self::map_io_result(File::open(column_path))?;
+        let column_size = Self::map_io_result(column_file.metadata())?.len();
+        if !column_size.is_multiple_of(value_size as u64) {
+            let msg = format!("the column file size {} is not a multiple of {}", column_size, value_size);
+            return Err(Error::FileError(IoError::new(ErrorKind::InvalidData, msg)));
+        }
+        let mut buf: Vec<u8> = Vec::new();
+        let mut chunk_values: Vec<U> = Vec::new();
+        let mut remaining = column_size / value_size as u64;
+        while remaining > 0 {
+            let num_values_in_chunk = self.num_values & self.chunk_size_mask;
+            let len = (self.chunk_size - num_values_in_chunk).min(remaining) as usize;
+            buf.resize(len * value_size, 0);
+            Self::map_io_result(column_file.read_exact(&mut buf))?;
+            chunk_values.clear();
+            chunk_values.extend(buf.chunks_exact(value_size).map(Self::copy_from_slice_u8::<U>));
+            self.push_chunk_values(&chunk_values)?;
+            remaining -= len as u64;
+        }
+        if self.flush_policy.is_some() {
+            self.apply_flush_policy()?;
+        }
+        Ok(column_size / value_size as u64)
+    }
+
+    /// Set the bits of `values`, that must fit the current chunk, one block at a time,
+    /// then complete the chunk if it is full.
+    fn push_chunk_values(&mut self, values: &[U]) -> Result<(), Error> {
+        let num_values_in_chunk = (self.num_values & self.chunk_size_mask) as u32;
+        let block_info = &self.block_info;
+        let mut counts: Vec<u32> = vec![0; block_info.num_bitmaps_in_block];
+        for i_block in 0..block_info.num_blocks {
+            let shift = block_info.bin_shift + i_block * block_info.bit_block_size;
+            let first_bitmap = i_block * block_info.num_bitmaps_in_block;
+            let block_bitmaps = &mut self.bitmaps[first_bitmap..(first_bitmap + block_info.num_bitmaps_in_block)];
+            let bitmap_of = |v: &U| (v.to_ordered() >> shift).transmute_to_usize() & block_info.bit_block_mask;
+            counts.fill(0);
+            for v in values {
+                counts[bitmap_of(v)] += 1;
+            }
+            for (b, count) in block_bitmaps.iter_mut().zip(counts.iter()).filter(|(_b, count)| **count > 0) {
+                b.reserve(*count);
+            }
+            for (i, v) in values.iter().enumerate() {
+                block_bitmaps[bitmap_of(v)].set(num_values_in_chunk + i as u32);
+            }
+        }
+        self.num_values += values.len() as u64;
+        if self.num_values & self.chunk_size_mask == 0 {
+            self.complete_chunk()?;
+        }
+        Ok(())
+    }
+}
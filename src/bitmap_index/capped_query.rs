@@ -0,0 +1,88 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Capped queries
+//!
+//! A query for a frequent value on a huge index can return billions of indexes. With
+//! `set_max_query_results` the queries returning indexes fail with `Error::TooManyResults`
+//! when the result exceeds the limit (checked after each chunk), while `run_query_capped`
+//! switches to a selection result (a compressed bitmap for each chunk, see
+//! `query_selection`) when the result exceeds the limit.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// The result of `run_query_capped`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryResult<T> {
+    /// The sorted indexes of the result.
+    Indexes(Vec<u64>),
+    /// The selection of the result, the ith bitmap represents the indexes of the ith chunk.
+    Selection(Vec<T>)
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Set the maximum number of indexes returned from `run_query`, with `None`
+    /// (default) the number of indexes is not limited.
+    pub fn set_max_query_results(&mut self, max_query_results: Option<u64>) {
+        self.max_query_results = max_query_results;
+    }
+
+    /// Return the maximum number of indexes returned from `run_query`.
+    pub fn max_query_results(&self) -> Option<u64> {
+        self.max_query_results
+    }
+
+    /// Return the indexes of values pushed in `BitmapIndex` equal to `value` if they are
+    /// at most `max_results`, otherwise return their selection. The parameters `start_index`
+    /// and `end_index` are optional and if specified define the range where query is runned.
+    pub fn run_query_capped(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>, max_results: u64) -> Result<QueryResult<T>, Error> {
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let mut num_results: u64 = 0;
+        let mut chunks_results: Vec<(u64, T)> = Vec::new();
+        for (chunk_id, b_result) in self.chunks_results(value, start_index, end_index)? {
+            let offset_index = chunk_id * self.chunk_size;
+            let chunk_indexes: Vec<u32> = b_result.unroll_bitmap();
+            let in_range = |idx: &u32| (offset_index + *idx as u64) >= start_index && (offset_index + *idx as u64) <= end_index;
+            if chunk_indexes.iter().all(in_range) {
+                num_results += chunk_indexes.len() as u64;
+                chunks_results.push((chunk_id, b_result));
+                continue;
+            }
+            // the first and the last chunk of the range can have values out of the range.
+            let mut b_range = T::new();
+            for idx in chunk_indexes.iter().filter(|idx| in_range(idx)) {
+                b_range.set(*idx);
+                num_results += 1;
+            }
+            chunks_results.push((chunk_id, b_range));
+        }
+
+        if num_results <= max_results {
+            let mut indexes: Vec<u64> = Vec::with_capacity(num_results as usize);
+            for (chunk_id, b_result) in chunks_results {
+                let offset_index = chunk_id * self.chunk_size;
+                indexes.extend(b_result.unroll_bitmap().iter().map(|idx| offset_index + *idx as u64));
+            }
+            return Ok(QueryResult::Indexes(indexes));
+        }
+        let mut selection: Vec<T> = vec![T::new(); self.chunk_count() as usize];
+        for (chunk_id, b_result) in chunks_results {
+            selection[chunk_id as usize] = b_result;
+        }
+        Ok(QueryResult::Selection(selection))
+    }
+}
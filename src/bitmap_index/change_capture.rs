@@ -0,0 +1,76 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Change data capture
+//!
+//! Values of a `BitmapIndex` are only appended, so the changes after a watermark (the
+//! `num_values` of a previous sync of a downstream cache or derived index) are the
+//! positions from the watermark to `num_values`. `appended_since` returns them grouped
+//! by chunk and `appended_chunk_values` returns the values of a group, reconstructed
+//! from the bitmaps of the chunk (see `rebuild`), so a derived index can be updated
+//! incrementally one chunk at a time. Chunks changed with `rewrite_chunk` are not
+//! reported, use a `FlushEvent` listener to track them.
+
+use std::ops::{BitAnd, Range, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// The positions of a chunk appended after a watermark.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppendedChunk {
+    /// The id of the chunk.
+    pub chunk_id: u64,
+    /// The indexes of the values of the chunk appended after the watermark.
+    pub value_range: Range<u64>
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the positions appended after `BitmapIndex` had `watermark_num_values`
+    /// values, one `AppendedChunk` for each chunk with appended values. Unflushed values
+    /// are included. `ParametersError` is returned if `watermark_num_values` is greater
+    /// than the number of values.
+    pub fn appended_since(&self, watermark_num_values: u64) -> Result<Vec<AppendedChunk>, Error> {
+        if watermark_num_values > self.num_values {
+            return Err(Error::ParametersError);
+        }
+        let start_chunk_id = Self::get_chunk_id(watermark_num_values, self.chunk_size);
+        let end_chunk_id = self.num_values.div_ceil(self.chunk_size);
+        Ok((start_chunk_id..end_chunk_id).map(|chunk_id| {
+            let chunk_start = chunk_id * self.chunk_size;
+            AppendedChunk {
+                chunk_id,
+                value_range: chunk_start.max(watermark_num_values)..(chunk_start + self.chunk_size).min(self.num_values)
+            }
+        }).filter(|chunk| !chunk.value_range.is_empty()).collect())
+    }
+
+    /// Return the values of the positions of `chunk`, reconstructed from the bitmaps of
+    /// the chunk. With a `bin_shift` the low `bin_shift` bits of the values are 0.
+    /// `ParametersError` is returned if the positions are not in the chunk or if they
+    /// haven't been pushed.
+    pub fn appended_chunk_values(&mut self, chunk: &AppendedChunk) -> Result<Vec<U>, Error> {
+        let chunk_start = chunk.chunk_id * self.chunk_size;
+        let range = &chunk.value_range;
+        if range.start < chunk_start || range.start > range.end || range.end > (chunk_start + self.chunk_size).min(self.num_values) {
+            return Err(Error::ParametersError);
+        }
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut values = self.chunk_values(chunk.chunk_id)?;
+        values.truncate((range.end - chunk_start) as usize);
+        values.drain(..((range.start - chunk_start) as usize));
+        Ok(values)
+    }
+}
@@ -0,0 +1,121 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Chunk access
+//!
+//! Low level access to the chunks of a `BitmapIndex`, to build custom query engines
+//! on top of the bitmaps of each chunk.
+//! The chunk ids go from 0 to `num_values / chunk_size`: the last chunk is the
+//! current chunk, not yet full.
+
+use std::mem;
+use std::ops::{BitAnd, Range, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the number of chunks with at least a value.
+    pub fn chunk_count(&self) -> u64 {
+        self.num_values.div_ceil(self.chunk_size)
+    }
+
+    /// Return the range of positions represented from each chunk with at least a value,
+    /// the range of the last chunk ends with the number of values pushed.
+    pub fn chunk_boundaries(&self) -> Vec<Range<u64>> {
+        (0..self.chunk_count()).map(|chunk_id| {
+            let start = chunk_id * self.chunk_size;
+            start..(start + self.chunk_size).min(self.num_values)
+        }).collect()
+    }
+
+    /// Return the range of bytes of the chunk `chunk_id` in the data file, for a partial
+    /// chunk the range of its last flush. Return `None` if `BitmapIndex` is opened in
+    /// memory mode or if the chunk has never been flushed.
+    /// The end of the range is decoded from the offsets table of the chunk, since a
+    /// rewritten chunk (see `rewrite_chunk`) doesn't end where the next chunk starts.
+    pub fn chunk_disk_extent(&mut self, chunk_id: u64) -> Result<Option<Range<u64>>, Error> {
+        let storage_idx = match self.storage_idx.as_mut() {
+            Some(storage_idx) => storage_idx,
+            None => return Ok(None)
+        };
+        // The start and the end offset of a chunk are written every time it is flushed.
+        let offsets_size = Self::map_io_result(storage_idx.offset_file.metadata())?.len();
+        if chunk_id > Self::get_chunk_id(self.num_values, self.chunk_size)
+            || offsets_size < (chunk_id + 2) * mem::size_of::<u64>() as u64 {
+            return Ok(None);
+        }
+        let chunk_offset = Self::map_io_result(Self::read_offsets(storage_idx, chunk_id, 1))?[0];
+        let chunk_end = Self::read_chunk_data_end(&mut storage_idx.data_file, chunk_offset, self.bitmaps.len())?;
+        Ok(Some(chunk_offset..chunk_end))
+    }
+
+    /// Return the bitmaps of the chunk `chunk_id` that represent `value`, one bitmap
+    /// for each block of the index (the first bitmap represents the lowest bits of `value`).
+    /// Positions in the bitmaps are relative to the chunk, so the "logical and" of the
+    /// bitmaps are the indexes of `value` minus `chunk_id * chunk_size`.
+    /// `ParametersError` is returned if the chunk doesn't exist.
+    pub fn fetch_bitmaps(&mut self, chunk_id: u64, value: U) -> Result<Vec<T>, Error> {
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        self.fetch_i_bitmaps(chunk_id, &query_i_bitmaps)
+    }
+
+    /// Return all bitmaps of the chunk `chunk_id`, in the order of the blocks. A chunk
+    /// flushed on storage is read with a single pass on its content.
+    /// `ParametersError` is returned if the chunk doesn't exist.
+    pub(super) fn chunk_bitmaps(&mut self, chunk_id: u64) -> Result<Vec<T>, Error> {
+        let num_bitmaps = self.bitmaps.len();
+        let in_storage = self.chunks.is_none() && chunk_id < Self::get_chunk_id(self.num_values, self.chunk_size)
+            && self.hot_chunks.as_ref().is_none_or(|hot_chunks| hot_chunks.chunk(chunk_id).is_none());
+        if !in_storage {
+            let i_bitmaps: Vec<usize> = (0..num_bitmaps).collect();
+            return self.fetch_i_bitmaps(chunk_id, &i_bitmaps);
+        }
+        let storage_idx = self.storage_idx.as_mut().ok_or(Error::ParametersError)?;
+        let chunk_offset = Self::map_io_result(Self::read_offsets(storage_idx, chunk_id, 1))?[0];
+        let chunk_end = Self::read_chunk_end(&mut storage_idx.data_file, chunk_offset, num_bitmaps)?;
+        let mut bitmaps: Vec<T> = vec![T::new(); num_bitmaps];
+        Self::read_chunk_bitmaps(&mut storage_idx.data_file, (chunk_offset, chunk_end), &mut bitmaps)?;
+        Ok(bitmaps)
+    }
+
+    /// Return the bitmaps `query_i_bitmaps` of the chunk `chunk_id`, see `fetch_bitmaps`.
+    fn fetch_i_bitmaps(&mut self, chunk_id: u64, query_i_bitmaps: &[usize]) -> Result<Vec<T>, Error> {
+        let current_chunk_id = Self::get_chunk_id(self.num_values, self.chunk_size);
+        if chunk_id > current_chunk_id {
+            return Err(Error::ParametersError);
+        }
+        if chunk_id == current_chunk_id {
+            return Ok(query_i_bitmaps.iter().map(|i_bitmap| self.bitmaps[*i_bitmap].clone()).collect());
+        }
+        if let Some(chunks) = self.chunks.as_ref() {
+            let num_spilled_chunks = self.spilled_chunks();
+            if chunk_id < num_spilled_chunks {
+                return Self::spilled_query_bitmaps(self.spill_chunks.as_ref().unwrap(), chunk_id as usize, query_i_bitmaps);
+            }
+            let chunk_id = chunk_id - num_spilled_chunks;
+            if let Some(bitmaps) = chunks.get(chunk_id as usize) {
+                return Ok(query_i_bitmaps.iter().map(|i_bitmap| bitmaps[*i_bitmap].clone()).collect());
+            }
+            let arena_id = chunk_id as usize - chunks.len();
+            let arena = self.arena_chunks.iter().flatten().nth(arena_id).ok_or(Error::ParametersError)?;
+            return Self::arena_query_bitmaps(arena, query_i_bitmaps);
+        }
+        if let Some(bitmaps) = self.hot_chunks.as_ref().and_then(|hot_chunks| hot_chunks.chunk(chunk_id)) {
+            return Ok(query_i_bitmaps.iter().map(|i_bitmap| bitmaps[*i_bitmap].clone()).collect());
+        }
+        let storage_idx = self.storage_idx.as_mut().ok_or(Error::ParametersError)?;
+        let chunk_offset = Self::map_io_result(Self::read_offsets(storage_idx, chunk_id, 1))?[0];
+        Self::read_query_bitmaps(&mut storage_idx.data_file, chunk_offset, query_i_bitmaps)
+    }
+}
@@ -0,0 +1,108 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Chunk arena
+//!
+//! In memory mode each full chunk keeps thousands of bitmaps and each bitmap owns a
+//! small allocation. With `use_chunk_arena` a full chunk is packed in a `ChunkArena`:
+//! the content of all bitmaps of the chunk is serialized in a single buffer, with the
+//! same layout of a chunk in the data file, so the small allocations are released as
+//! soon as the chunk is full and query bitmaps are decoded from contiguous memory.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// `ChunkArena` struct with the serialized bitmaps of a full chunk.
+pub(super) struct ChunkArena {
+    offsets: Vec<u32>,
+    content: Vec<u8>
+}
+
+impl ChunkArena {
+    /// Return the size in bytes of the arena.
+    pub(super) fn size(&self) -> usize {
+        self.offsets.len() * std::mem::size_of::<u32>() + self.content.len()
+    }
+
+    /// Return the offsets of the bitmaps in the content of the arena.
+    pub(super) fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    /// Return the serialized bitmaps of the arena.
+    pub(super) fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Return the size in bytes of each bitmap of the arena.
+    pub(super) fn bitmap_sizes(&self) -> impl Iterator<Item=u64> + '_ {
+        self.offsets.windows(2).map(|offsets| (offsets[1] - offsets[0]) as u64)
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Pack all full chunks in memory in a `ChunkArena` each and pack every chunk as
+    /// soon as it is full from now on. Error occur if `BitmapIndex` is opened in storage mode.
+    pub fn use_chunk_arena(&mut self) -> Result<(), Error> {
+        let chunks = self.chunks.as_mut().ok_or(Error::ParametersError)?;
+        let mut arena_chunks: Vec<ChunkArena> = Vec::with_capacity(chunks.len());
+        for bitmaps in chunks.iter() {
+            arena_chunks.push(Self::new_chunk_arena(bitmaps)?);
+        }
+        chunks.clear();
+        match self.arena_chunks.as_mut() {
+            Some(old_arena_chunks) => old_arena_chunks.append(&mut arena_chunks),
+            None => self.arena_chunks = Some(arena_chunks)
+        }
+        Ok(())
+    }
+
+    /// Return the total size in bytes of the chunk arenas.
+    pub fn chunk_arena_size(&self) -> usize {
+        self.arena_chunks.iter().flatten().map(|arena| arena.size()).sum()
+    }
+
+    pub(super) fn new_chunk_arena(bitmaps: &[T]) -> Result<ChunkArena, Error> {
+        let mut offsets: Vec<u32> = Vec::with_capacity(bitmaps.len() + 1);
+        let mut bitmaps_size: usize = 0;
+        offsets.push(0);
+        for b in bitmaps {
+            bitmaps_size += b.size();
+            offsets.push(bitmaps_size as u32);
+        }
+        let mut content: Vec<u8> = vec![0; bitmaps_size];
+        for (i, b) in bitmaps.iter().enumerate() {
+            let bitmap_content = &mut content[(offsets[i] as usize)..(offsets[i + 1] as usize)];
+            b.write_to_buffer(bitmap_content).map_err(|_err| Error::BitmapError)?;
+        }
+        Ok(ChunkArena {
+            offsets,
+            content
+        })
+    }
+
+    /// Return the query bitmaps decoded from `arena`.
+    pub(super) fn arena_query_bitmaps(arena: &ChunkArena, query_i_bitmaps: &[usize]) -> Result<Vec<T>, Error> {
+        let mut query_bitmaps: Vec<T> = Vec::with_capacity(query_i_bitmaps.len());
+        for i_bitmap in query_i_bitmaps {
+            let start_offset = arena.offsets[*i_bitmap] as usize;
+            let end_offset = arena.offsets[*i_bitmap + 1] as usize;
+            let mut bitmap = T::new();
+            Self::read_bitmap(&arena.content[start_offset..end_offset], false, &mut bitmap)?;
+            query_bitmaps.push(bitmap);
+        }
+        Ok(query_bitmaps)
+    }
+}
@@ -0,0 +1,481 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Chunk format
+//!
+//! Layout of a chunk in the data file. A `Plain` chunk has no header and starts
+//! with the table of the `num_bitmaps + 1` absolute u32 offsets of its bitmaps:
+//!
+//!  |(num_bitmaps + 1) * 32bit offset|bitmaps content|
+//!
+//! Since the first offset of a `Plain` chunk is the size of the offsets table, its
+//! highest bit is never set. Every other format starts with a header where the
+//! highest bit of the first word is set, followed by a format specific table:
+//!
+//!  |32bit (CHUNK_HEADER_FLAG | format)|32bit table_size|table|bitmaps content|
+//!
+//! - `DeltaOffsets` table: the size of each bitmap encoded as a LEB128 varint.
+//! - `SkipEmpty` table: |32bit num_bitmaps|presence bitmap|varint size of each non-empty bitmap|,
+//!   the presence bitmap has one bit for each bitmap, set if the bitmap is not empty.
+//!   Empty bitmaps have no content in the chunk.
+//! - `Sparse` table: |32bit num_entries|num_entries * (|32bit bitmap_id|32bit offset|)|32bit content_size|,
+//!   one entry for each non-empty bitmap in increasing bitmap id order.
+//! - `WideOffsets` table: (num_bitmaps + 1) * 64bit offsets relative to the bitmaps content.
+//!
+//! `Plain` and `Sparse` offsets are u32, so a chunk larger than 4 GiB is always
+//! written in `WideOffsets` format.
+//!
+//! The format is recorded in each chunk, so chunks written with different formats
+//! can be read from the same index.
+
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+const CHUNK_HEADER_FLAG: u32 = 1 << 31;
+const CHUNK_HEADER_SIZE: usize = 2 * mem::size_of::<u32>();
+/// Size of the buffer used to read the bitmaps of a whole chunk.
+const CHUNK_READ_BUFFER_SIZE: usize = 1 << 16;
+/// A chunk is flushed in `Sparse` format if less than 1 bitmap every
+/// `SPARSE_DENSITY_RATIO` bitmaps is not empty.
+const SPARSE_DENSITY_RATIO: usize = 64;
+/// Maximum size of a chunk with u32 offsets.
+const MAX_U32_OFFSETS_CHUNK_SIZE: u64 = u32::MAX as u64;
+
+/// `ChunkFormat` defines how the bitmap offsets of a chunk are serialized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChunkFormat {
+    /// A table of absolute u32 offsets, one for each bitmap (default).
+    #[default]
+    Plain,
+    /// A table of LEB128 varint bitmap sizes, usually 1 or 2 bytes for each bitmap.
+    DeltaOffsets,
+    /// A presence bitmap flags the empty bitmaps, only non-empty bitmaps are
+    /// serialized. Empty bitmaps are read as new bitmaps.
+    SkipEmpty,
+    /// A table with the id and the offset of non-empty bitmaps only. It is selected
+    /// automatically when a chunk is flushed and less than 1 bitmap every
+    /// `SPARSE_DENSITY_RATIO` is not empty.
+    Sparse,
+    /// A table of u64 offsets, one for each bitmap. It is selected automatically
+    /// instead of `Plain` and `Sparse` when the chunk is larger than 4 GiB.
+    WideOffsets,
+}
+
+impl ChunkFormat {
+    fn id(self) -> u32 {
+        match self {
+            ChunkFormat::Plain => 0,
+            ChunkFormat::DeltaOffsets => 1,
+            ChunkFormat::SkipEmpty => 2,
+            ChunkFormat::Sparse => 3,
+            ChunkFormat::WideOffsets => 4,
+        }
+    }
+
+    fn from_id(id: u32) -> Option<ChunkFormat> {
+        match id {
+            0 => Some(ChunkFormat::Plain),
+            1 => Some(ChunkFormat::DeltaOffsets),
+            2 => Some(ChunkFormat::SkipEmpty),
+            3 => Some(ChunkFormat::Sparse),
+            4 => Some(ChunkFormat::WideOffsets),
+            _ => None
+        }
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Set the format of the next chunks written. The format is not a build option:
+    /// each chunk records its own format, so it can be changed at any time.
+    /// Independently from this format, a chunk where almost all bitmaps are empty
+    /// is written in `Sparse` format.
+    pub fn set_chunk_format(&mut self, chunk_format: ChunkFormat) {
+        self.chunk_format = chunk_format;
+    }
+
+    /// Return the format of the next chunks written.
+    pub fn chunk_format(&self) -> ChunkFormat {
+        self.chunk_format
+    }
+
+    /// Return the format used to flush a chunk with `bitmaps`.
+    pub(super) fn flush_chunk_format(&self, bitmaps: &[T]) -> ChunkFormat {
+        let num_bitmaps = bitmaps.len();
+        let num_non_empty = bitmaps.iter().filter(|b| !b.is_empty()).count();
+        let chunk_format = if num_non_empty * SPARSE_DENSITY_RATIO < num_bitmaps {
+            ChunkFormat::Sparse
+        } else {
+            self.chunk_format
+        };
+        if chunk_format != ChunkFormat::Plain && chunk_format != ChunkFormat::Sparse {
+            return chunk_format;
+        }
+        let table_size = ((num_bitmaps + 1) * mem::size_of::<u32>()) as u64;
+        let bitmaps_size: u64 = bitmaps.iter().map(|b| b.size() as u64).sum();
+        if table_size + bitmaps_size > MAX_U32_OFFSETS_CHUNK_SIZE {
+            return ChunkFormat::WideOffsets;
+        }
+        chunk_format
+    }
+
+    /// Return the serialized header and offsets table of a chunk with `bitmaps` in
+    /// `chunk_format` and the size of the bitmaps content.
+    pub(super) fn chunk_header(bitmaps: &[T], chunk_format: ChunkFormat) -> (Vec<u8>, u64) {
+        let bitmaps_size: u64 = bitmaps.iter()
+            .filter(|b| Self::chunk_writes_bitmap(chunk_format, b))
+            .map(|b| b.size() as u64).sum();
+        let header = match chunk_format {
+            ChunkFormat::Plain => {
+                let table_size = (bitmaps.len() + 1) * mem::size_of::<u32>();
+                let mut header: Vec<u8> = Vec::with_capacity(table_size);
+                let mut offset = table_size as u32;
+                header.extend_from_slice(&offset.to_ne_bytes());
+                for b in bitmaps {
+                    offset += b.size() as u32;
+                    header.extend_from_slice(&offset.to_ne_bytes());
+                }
+                header
+            },
+            ChunkFormat::DeltaOffsets => {
+                let mut table: Vec<u8> = Vec::with_capacity(bitmaps.len());
+                for b in bitmaps {
+                    write_varint(&mut table, b.size() as u32);
+                }
+                Self::with_chunk_header(ChunkFormat::DeltaOffsets, table)
+            },
+            ChunkFormat::SkipEmpty => {
+                let presence_size = bitmaps.len().div_ceil(8);
+                let mut table: Vec<u8> = Vec::with_capacity(mem::size_of::<u32>() + presence_size);
+                table.extend_from_slice(&(bitmaps.len() as u32).to_ne_bytes());
+                table.resize(mem::size_of::<u32>() + presence_size, 0);
+                for (i, b) in bitmaps.iter().enumerate() {
+                    if !b.is_empty() {
+                        table[mem::size_of::<u32>() + (i >> 3)] |= 1 << (i & 7);
+                    }
+                }
+                for b in bitmaps.iter().filter(|b| !b.is_empty()) {
+                    write_varint(&mut table, b.size() as u32);
+                }
+                Self::with_chunk_header(ChunkFormat::SkipEmpty, table)
+            },
+            ChunkFormat::Sparse => {
+                let mut table: Vec<u8> = Vec::new();
+                let mut num_entries: u32 = 0;
+                let mut offset: u32 = 0;
+                table.extend_from_slice(&num_entries.to_ne_bytes());
+                for (i, b) in bitmaps.iter().enumerate().filter(|(_i, b)| !b.is_empty()) {
+                    table.extend_from_slice(&(i as u32).to_ne_bytes());
+                    table.extend_from_slice(&offset.to_ne_bytes());
+                    offset += b.size() as u32;
+                    num_entries += 1;
+                }
+                table.extend_from_slice(&offset.to_ne_bytes());
+                table[0..mem::size_of::<u32>()].copy_from_slice(&num_entries.to_ne_bytes());
+                Self::with_chunk_header(ChunkFormat::Sparse, table)
+            },
+            ChunkFormat::WideOffsets => {
+                let mut table: Vec<u8> = Vec::with_capacity((bitmaps.len() + 1) * mem::size_of::<u64>());
+                let mut offset: u64 = 0;
+                table.extend_from_slice(&offset.to_ne_bytes());
+                for b in bitmaps {
+                    offset += b.size() as u64;
+                    table.extend_from_slice(&offset.to_ne_bytes());
+                }
+                Self::with_chunk_header(ChunkFormat::WideOffsets, table)
+            }
+        };
+        (header, bitmaps_size)
+    }
+
+    /// Return the serialized header and offsets table of a chunk with bitmaps of `sizes`
+    /// bytes and the size of the bitmaps content. The chunk is in `Plain` format, or in
+    /// `WideOffsets` format if it is larger than 4 GiB.
+    pub(super) fn chunk_header_from_sizes(sizes: &[u64]) -> (Vec<u8>, u64) {
+        let bitmaps_size: u64 = sizes.iter().sum();
+        let table_size = (sizes.len() + 1) * mem::size_of::<u32>();
+        if table_size as u64 + bitmaps_size <= MAX_U32_OFFSETS_CHUNK_SIZE {
+            let mut header: Vec<u8> = Vec::with_capacity(table_size);
+            let mut offset = table_size as u32;
+            header.extend_from_slice(&offset.to_ne_bytes());
+            for size in sizes {
+                offset += *size as u32;
+                header.extend_from_slice(&offset.to_ne_bytes());
+            }
+            return (header, bitmaps_size);
+        }
+        let mut table: Vec<u8> = Vec::with_capacity((sizes.len() + 1) * mem::size_of::<u64>());
+        let mut offset: u64 = 0;
+        table.extend_from_slice(&offset.to_ne_bytes());
+        for size in sizes {
+            offset += size;
+            table.extend_from_slice(&offset.to_ne_bytes());
+        }
+        (Self::with_chunk_header(ChunkFormat::WideOffsets, table), bitmaps_size)
+    }
+
+    /// Return `true` if the content of `b` is written in a chunk of `chunk_format`.
+    pub(super) fn chunk_writes_bitmap(chunk_format: ChunkFormat, b: &T) -> bool {
+        match chunk_format {
+            ChunkFormat::SkipEmpty | ChunkFormat::Sparse => !b.is_empty(),
+            _ => true
+        }
+    }
+
+    fn with_chunk_header(chunk_format: ChunkFormat, table: Vec<u8>) -> Vec<u8> {
+        let mut header: Vec<u8> = Vec::with_capacity(CHUNK_HEADER_SIZE + table.len());
+        header.extend_from_slice(&(CHUNK_HEADER_FLAG | chunk_format.id()).to_ne_bytes());
+        header.extend_from_slice(&(table.len() as u32).to_ne_bytes());
+        header.extend_from_slice(&table);
+        header
+    }
+
+    /// Return the absolute offsets in `data_file` of the bitmaps `query_i_bitmaps`
+    /// of the chunk at `chunk_offset`.
+    pub(super) fn read_bitmaps_offsets<R: Read + Seek>(data_file: &mut R, chunk_offset: u64, query_i_bitmaps: &[usize]) -> Result<Vec<(u64, u64)>, Error> {
+        let mut header: [u8; CHUNK_HEADER_SIZE] = [0; CHUNK_HEADER_SIZE];
+        Self::map_io_result(data_file.seek(SeekFrom::Start(chunk_offset)))?;
+        Self::map_io_result(data_file.read_exact(&mut header))?;
+        let format_word = read_u32(&header, 0)?;
+        if format_word & CHUNK_HEADER_FLAG == 0 {
+            let mut bitmaps_offsets: Vec<(u64, u64)> = Vec::with_capacity(query_i_bitmaps.len());
+            for i_bitmap in query_i_bitmaps {
+                bitmaps_offsets.push(Self::read_bitmap_offset(data_file, chunk_offset, *i_bitmap)?);
+            }
+            return Ok(bitmaps_offsets);
+        }
+        let chunk_format = ChunkFormat::from_id(format_word & !CHUNK_HEADER_FLAG).ok_or(Error::BitmapError)?;
+        let table_size = read_u32(&header, mem::size_of::<u32>())? as usize;
+        let mut table: Vec<u8> = vec![0; table_size];
+        Self::map_io_result(data_file.read_exact(&mut table))?;
+
+        let content_offset = chunk_offset + (CHUNK_HEADER_SIZE + table_size) as u64;
+        let bitmaps_offsets = Self::table_bitmaps_offsets(chunk_format, &table, query_i_bitmaps)?;
+        Ok(bitmaps_offsets.iter().map(|(start, end)| (content_offset + start, content_offset + end)).collect())
+    }
+
+    /// Return the offsets table of the chunk at `chunk_offset` of `data_file`, header
+    /// included, that can be decoded with `chunk_bitmaps_offsets`.
+    pub(super) fn read_chunk_table<R: Read + Seek>(data_file: &mut R, chunk_offset: u64) -> Result<Vec<u8>, Error> {
+        let mut header: [u8; CHUNK_HEADER_SIZE] = [0; CHUNK_HEADER_SIZE];
+        Self::map_io_result(data_file.seek(SeekFrom::Start(chunk_offset)))?;
+        Self::map_io_result(data_file.read_exact(&mut header))?;
+        let format_word = read_u32(&header, 0)?;
+        // The first offset of a `Plain` chunk is the size of its table.
+        let table_size = if format_word & CHUNK_HEADER_FLAG == 0 {
+            format_word as usize
+        } else {
+            CHUNK_HEADER_SIZE + read_u32(&header, mem::size_of::<u32>())? as usize
+        };
+        if table_size < CHUNK_HEADER_SIZE {
+            return Err(Error::BitmapError);
+        }
+        let mut table: Vec<u8> = vec![0; table_size];
+        table[0..CHUNK_HEADER_SIZE].copy_from_slice(&header);
+        Self::map_io_result(data_file.read_exact(&mut table[CHUNK_HEADER_SIZE..]))?;
+        Ok(table)
+    }
+
+    /// Return the end offset in `data_file` of the chunk with `num_bitmaps` bitmaps at
+    /// `chunk_offset`, decoded from its offsets table.
+    pub(super) fn read_chunk_end<R: Read + Seek>(data_file: &mut R, chunk_offset: u64, num_bitmaps: usize) -> Result<u64, Error> {
+        let i_bitmaps: Vec<usize> = (0..num_bitmaps).collect();
+        let bitmaps_offsets = Self::read_bitmaps_offsets(data_file, chunk_offset, &i_bitmaps)?;
+        Ok(bitmaps_offsets.iter().map(|(_start, end)| *end).max().unwrap_or(chunk_offset))
+    }
+
+    /// Return the offsets of the bitmaps `i_bitmaps` of the chunk serialized in
+    /// `chunk`, relative to the start of the chunk. `chunk` can be longer than the chunk.
+    pub(super) fn chunk_bitmaps_offsets(chunk: &[u8], i_bitmaps: &[usize]) -> Result<Vec<(u64, u64)>, Error> {
+        let format_word = read_u32(chunk, 0)?;
+        if format_word & CHUNK_HEADER_FLAG == 0 {
+            let mut bitmaps_offsets: Vec<(u64, u64)> = Vec::with_capacity(i_bitmaps.len());
+            for i_bitmap in i_bitmaps {
+                let start_offset = read_u32(chunk, i_bitmap * mem::size_of::<u32>())? as u64;
+                let end_offset = read_u32(chunk, (i_bitmap + 1) * mem::size_of::<u32>())? as u64;
+                bitmaps_offsets.push((start_offset, end_offset));
+            }
+            return Ok(bitmaps_offsets);
+        }
+        let chunk_format = ChunkFormat::from_id(format_word & !CHUNK_HEADER_FLAG).ok_or(Error::BitmapError)?;
+        let table_size = read_u32(chunk, mem::size_of::<u32>())? as usize;
+        let table = chunk.get(CHUNK_HEADER_SIZE..(CHUNK_HEADER_SIZE + table_size)).ok_or(Error::BitmapError)?;
+
+        let content_offset = (CHUNK_HEADER_SIZE + table_size) as u64;
+        let bitmaps_offsets = Self::table_bitmaps_offsets(chunk_format, table, i_bitmaps)?;
+        Ok(bitmaps_offsets.iter().map(|(start, end)| (content_offset + start, content_offset + end)).collect())
+    }
+
+    /// Read all bitmaps of the chunk with `offsets` start and end offsets from reader.
+    /// Only the offsets table is read in memory, bitmaps are read one by one with
+    /// `Bitmap::read_from` through a bounded buffer, so a huge chunk is never allocated.
+    pub(super) fn read_chunk_bitmaps<R: Read + Seek>(reader: &mut R, offsets: (u64, u64), bitmaps: &mut [T]) -> Result<(), Error> {
+        let mut header: [u8; CHUNK_HEADER_SIZE] = [0; CHUNK_HEADER_SIZE];
+        Self::map_io_result(reader.seek(SeekFrom::Start(offsets.0)))?;
+        Self::map_io_result(reader.read_exact(&mut header))?;
+        let format_word = read_u32(&header, 0)?;
+        let table_size = if format_word & CHUNK_HEADER_FLAG == 0 {
+            (bitmaps.len() + 1) * mem::size_of::<u32>()
+        } else {
+            CHUNK_HEADER_SIZE + read_u32(&header, mem::size_of::<u32>())? as usize
+        };
+        if offsets.0 + (table_size as u64) > offsets.1 {
+            return Err(Error::BitmapError);
+        }
+        let mut table: Vec<u8> = vec![0; table_size];
+        table[0..CHUNK_HEADER_SIZE].copy_from_slice(&header);
+        Self::map_io_result(reader.read_exact(&mut table[CHUNK_HEADER_SIZE..]))?;
+        let i_bitmaps: Vec<usize> = (0..bitmaps.len()).collect();
+        let bitmaps_offsets = Self::chunk_bitmaps_offsets(&table, &i_bitmaps)?;
+
+        let mut reader = BufReader::with_capacity(CHUNK_READ_BUFFER_SIZE, reader);
+        let mut position = table_size as u64;
+        for (b, offset) in bitmaps.iter_mut().zip(bitmaps_offsets) {
+            // An empty offset is an empty bitmap omitted from the chunk.
+            if offset.0 == offset.1 {
+                b.clear();
+                continue;
+            }
+            if offset.0 > offset.1 || offsets.0 + offset.1 > offsets.1 {
+                return Err(Error::BitmapError);
+            }
+            if offset.0 != position {
+                Self::map_io_result(reader.seek_relative(offset.0 as i64 - position as i64))?;
+            }
+            Self::read_bitmap_from(&mut reader, (offset.1 - offset.0) as usize, true, b)?;
+            position = offset.1;
+        }
+        Ok(())
+    }
+
+    /// Return the offsets of the bitmaps `i_bitmaps` relative to the start of the
+    /// bitmaps content, decoded from the offsets table of a chunk with a header.
+    fn table_bitmaps_offsets(chunk_format: ChunkFormat, table: &[u8], i_bitmaps: &[usize]) -> Result<Vec<(u64, u64)>, Error> {
+        match chunk_format {
+            ChunkFormat::Plain => Err(Error::BitmapError),
+            ChunkFormat::DeltaOffsets => {
+                let num_offsets = i_bitmaps.iter().max().map_or(0, |i_bitmap| i_bitmap + 2);
+                let mut offsets: Vec<u64> = Vec::with_capacity(num_offsets);
+                let mut offset: u64 = 0;
+                let mut pos: usize = 0;
+                offsets.push(offset);
+                while offsets.len() < num_offsets {
+                    offset += read_varint(table, &mut pos)? as u64;
+                    offsets.push(offset);
+                }
+                Ok(i_bitmaps.iter().map(|i_bitmap| (offsets[*i_bitmap], offsets[*i_bitmap + 1])).collect())
+            },
+            ChunkFormat::SkipEmpty => {
+                let num_bitmaps = read_u32(table, 0)? as usize;
+                let presence = table.get(mem::size_of::<u32>()..(mem::size_of::<u32>() + num_bitmaps.div_ceil(8)))
+                    .ok_or(Error::BitmapError)?;
+                let num_offsets = i_bitmaps.iter().max().map_or(0, |i_bitmap| i_bitmap + 2);
+                if num_offsets > num_bitmaps + 1 {
+                    return Err(Error::BitmapError);
+                }
+                let mut offsets: Vec<u64> = Vec::with_capacity(num_offsets);
+                let mut offset: u64 = 0;
+                let mut pos: usize = mem::size_of::<u32>() + presence.len();
+                offsets.push(offset);
+                for i in 0..(num_offsets - 1) {
+                    if (presence[i >> 3] >> (i & 7)) & 1 == 1 {
+                        offset += read_varint(table, &mut pos)? as u64;
+                    }
+                    offsets.push(offset);
+                }
+                Ok(i_bitmaps.iter().map(|i_bitmap| (offsets[*i_bitmap], offsets[*i_bitmap + 1])).collect())
+            },
+            ChunkFormat::Sparse => {
+                const ENTRY_SIZE: usize = 2 * mem::size_of::<u32>();
+                let num_entries = read_u32(table, 0)? as usize;
+                let entries = table.get(mem::size_of::<u32>()..(mem::size_of::<u32>() + num_entries * ENTRY_SIZE + mem::size_of::<u32>()))
+                    .ok_or(Error::BitmapError)?;
+                let entry_offset = |k: usize| read_u32(entries, k * ENTRY_SIZE + mem::size_of::<u32>());
+                let mut bitmaps_offsets: Vec<(u64, u64)> = Vec::with_capacity(i_bitmaps.len());
+                for i_bitmap in i_bitmaps {
+                    let mut low: usize = 0;
+                    let mut high: usize = num_entries;
+                    while low < high {
+                        let mid = (low + high) >> 1;
+                        if (read_u32(entries, mid * ENTRY_SIZE)? as usize) < *i_bitmap {
+                            low = mid + 1;
+                        } else {
+                            high = mid;
+                        }
+                    }
+                    if low < num_entries && read_u32(entries, low * ENTRY_SIZE)? as usize == *i_bitmap {
+                        let end_offset = match low + 1 < num_entries {
+                            true => entry_offset(low + 1)?,
+                            false => read_u32(entries, num_entries * ENTRY_SIZE)?
+                        };
+                        bitmaps_offsets.push((entry_offset(low)? as u64, end_offset as u64));
+                    } else {
+                        bitmaps_offsets.push((0, 0));
+                    }
+                }
+                Ok(bitmaps_offsets)
+            },
+            ChunkFormat::WideOffsets => {
+                let mut bitmaps_offsets: Vec<(u64, u64)> = Vec::with_capacity(i_bitmaps.len());
+                for i_bitmap in i_bitmaps {
+                    let start_offset = read_u64(table, i_bitmap * mem::size_of::<u64>())?;
+                    let end_offset = read_u64(table, (i_bitmap + 1) * mem::size_of::<u64>())?;
+                    bitmaps_offsets.push((start_offset, end_offset));
+                }
+                Ok(bitmaps_offsets)
+            }
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32, Error> {
+    match buf.get(offset..(offset + mem::size_of::<u32>())) {
+        Some(b) => Ok(u32::from_ne_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(Error::BitmapError)
+    }
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Result<u64, Error> {
+    match buf.get(offset..(offset + mem::size_of::<u64>())) {
+        Some(b) => Ok(u64::from_ne_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])),
+        None => Err(Error::BitmapError)
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u32, Error> {
+    let mut value: u32 = 0;
+    let mut shift: u32 = 0;
+    loop {
+        let byte = *buf.get(*pos).ok_or(Error::BitmapError)?;
+        *pos += 1;
+        if shift > 28 {
+            return Err(Error::BitmapError);
+        }
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
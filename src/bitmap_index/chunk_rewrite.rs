@@ -0,0 +1,151 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Chunk rewrite
+//!
+//! Copy-on-write rewrite of a full chunk of a `BitmapIndex` in storage mode, to update
+//! or delete values. The rewritten chunk is appended to the data file and then its
+//! entry in the offset file is repointed with a single 8 bytes write, so a reader sees
+//! the old or the new chunk but never a partial chunk. The old extent is never
+//! overwritten (its space is left for a future compaction).
+//!
+//! Since the data file is truncated after the last chunk on recovery, the extent of
+//! the last flushed chunk is copied after the rewritten chunk before the repoint.
+
+use std::io::{BufWriter, Error as IoError, IoSlice, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Range, Shr};
+
+use super::data_trailer::ChunkTrailer;
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    ChunkFormat,
+    Error,
+    MetaData,
+    StorageIdx,
+    TransmuteToUsize,
+    WRITE_BUFFER_SIZE
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Read all bitmaps of the full chunk `chunk_id`, call `rewrite` to modify them and
+    /// write the chunk in new space of the data file, then return its new extent.
+    /// Positions set from `rewrite` must be less than the chunk size. `ParametersError`
+    /// is returned if `BitmapIndex` is opened in memory mode or if the chunk is not full.
+    pub fn rewrite_chunk(&mut self, chunk_id: u64, rewrite: impl FnOnce(&mut [T])) -> Result<Range<u64>, Error> {
+        if self.storage_idx.is_none() || chunk_id >= self.num_values / self.chunk_size {
+            return Err(Error::ParametersError);
+        }
+        let num_bitmaps = self.bitmaps.len();
+        let last_chunk_id = Self::get_chunk_id(self.flushed_num_values - 1, self.chunk_size);
+        let storage_idx: &mut StorageIdx = self.storage_idx.as_mut().unwrap();
+        let chunk_offset = Self::map_io_result(Self::read_offsets(storage_idx, chunk_id, 1))?[0];
+        let chunk_end = Self::read_chunk_end(&mut storage_idx.data_file, chunk_offset, num_bitmaps)?;
+        let mut bitmaps: Vec<T> = vec![T::new(); num_bitmaps];
+        Self::read_chunk_bitmaps(&mut storage_idx.data_file, (chunk_offset, chunk_end), &mut bitmaps)?;
+        rewrite(&mut bitmaps);
+
+        let chunk_format = self.flush_chunk_format(&bitmaps);
+        let (chunk_header, bitmaps_size) = Self::chunk_header(&bitmaps, chunk_format);
+        let trailer = self.chunk_trailer(chunk_id, (chunk_id + 1) * self.chunk_size, &chunk_header, bitmaps_size);
+        let storage_idx: &mut StorageIdx = self.storage_idx.as_mut().unwrap();
+        let new_chunk_offset = self.chunk_offset;
+        let new_chunk_end = Self::map_io_result(
+            Self::write_rewritten_chunk(storage_idx, new_chunk_offset, &chunk_header, &bitmaps, chunk_format, &trailer)
+        )?;
+
+        let last_extent = if chunk_id == last_chunk_id {
+            [new_chunk_offset, new_chunk_end]
+        } else {
+            let last_offsets = Self::map_io_result(Self::read_offsets(storage_idx, last_chunk_id, 2))?;
+            let last_size = last_offsets[1] - last_offsets[0];
+            Self::map_io_result(Self::copy_data(storage_idx, last_offsets[0], new_chunk_end, last_size))?;
+            [new_chunk_end, new_chunk_end + last_size]
+        };
+        self.modified_at = MetaData::unix_time_now();
+        let mut meta_data = self.last_checkpoint.clone().ok_or(Error::MetaDataError)?;
+        meta_data.modified_at = self.modified_at;
+        Self::map_io_result(Self::repoint_chunks(storage_idx, last_chunk_id, last_extent, chunk_id, new_chunk_offset, &meta_data))?;
+        if let Some(offset_cache) = storage_idx.offset_cache.as_mut() {
+            offset_cache.remove_chunk(chunk_id, chunk_offset);
+        }
+        self.last_checkpoint = Some(meta_data);
+        self.checkpoint_extent = last_extent;
+        self.chunk_offset = last_extent[1];
+        storage_idx.stats.data_bytes_written += last_extent[1] - new_chunk_offset;
+        storage_idx.stats.chunk_rewrites += 1;
+        Self::update_coarse_layer(&mut self.coarse_layer, chunk_id, &bitmaps);
+        Self::update_hot_tier(&mut self.hot_chunks, chunk_id, &bitmaps);
+        Self::update_value_stats(&mut self.value_stats, chunk_id, new_chunk_offset, &bitmaps)?;
+        if chunk_id != last_chunk_id {
+            // the last chunk is moved after the rewritten chunk.
+            Self::repoint_value_stats(&mut self.value_stats, last_chunk_id, last_extent[0])?;
+        }
+        if let Some(query_cache) = self.query_cache.as_mut() {
+            query_cache.remove_chunk(chunk_id);
+        }
+        self.notify_flush(chunk_id, new_chunk_end - new_chunk_offset);
+
+        Ok(new_chunk_offset..new_chunk_end)
+    }
+
+    /// Write the chunk with `chunk_header`, `bitmaps` and `trailer` at `offset` of the
+    /// data file and return its end offset.
+    fn write_rewritten_chunk(storage_idx: &mut StorageIdx, offset: u64, chunk_header: &[u8], bitmaps: &[T], chunk_format: ChunkFormat, trailer: &ChunkTrailer) -> Result<u64, IoError> {
+        storage_idx.data_file.seek(SeekFrom::Start(offset))?;
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &mut storage_idx.data_file);
+        writer.write_all(chunk_header)?;
+        for b in bitmaps.iter().filter(|b| Self::chunk_writes_bitmap(chunk_format, b)) {
+            b.write_to(&mut writer)?;
+        }
+        writer.write_all(Self::to_slice_u8(trailer))?;
+        writer.flush()?;
+        drop(writer);
+        storage_idx.data_file.stream_position()
+    }
+
+    /// Copy `size` bytes of the data file from `src_offset` to `dst_offset`, the two
+    /// ranges must not overlap.
+    fn copy_data(storage_idx: &mut StorageIdx, src_offset: u64, dst_offset: u64, size: u64) -> Result<(), IoError> {
+        let mut buf: Vec<u8> = vec![0; WRITE_BUFFER_SIZE.min(size as usize)];
+        let mut copied: u64 = 0;
+        while copied < size {
+            let len = buf.len().min((size - copied) as usize);
+            storage_idx.data_file.seek(SeekFrom::Start(src_offset + copied))?;
+            storage_idx.data_file.read_exact(&mut buf[..len])?;
+            storage_idx.data_file.seek(SeekFrom::Start(dst_offset + copied))?;
+            storage_idx.data_file.write_all(&buf[..len])?;
+            copied += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Repoint the last chunk to `last_extent` and the chunk `chunk_id` to `chunk_offset`,
+    /// then write `meta_data` as current meta data and last checkpoint.
+    fn repoint_chunks(storage_idx: &mut StorageIdx, last_chunk_id: u64, last_extent: [u64; 2], chunk_id: u64, chunk_offset: u64, meta_data: &MetaData) -> Result<(), IoError> {
+        storage_idx.offset_file.seek(SeekFrom::Start(Self::get_block_offset(last_chunk_id)))?;
+        storage_idx.offset_file.write_all(Self::to_slice_u8(&last_extent))?;
+        if chunk_id != last_chunk_id {
+            storage_idx.offset_file.seek(SeekFrom::Start(Self::get_block_offset(chunk_id)))?;
+            storage_idx.offset_file.write_all(&chunk_offset.to_ne_bytes())?;
+        }
+        storage_idx.meta_data_file.seek(SeekFrom::Start(0))?;
+        Self::write_all_vectored(&mut storage_idx.meta_data_file, &mut [
+            IoSlice::new(Self::to_slice_u8(meta_data)),
+            IoSlice::new(Self::to_slice_u8(meta_data)),
+            IoSlice::new(Self::to_slice_u8(&last_extent)),
+            IoSlice::new(Self::to_slice_u8(&last_extent))
+        ])?;
+        let offsets_size = if chunk_id != last_chunk_id { 3 } else { 2 };
+        storage_idx.stats.offset_bytes_written += offsets_size * mem::size_of::<u64>() as u64;
+        storage_idx.stats.record_meta_data_write(2 * mem::size_of::<MetaData>() as u64 + 4 * mem::size_of::<u64>() as u64);
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Coarse layer
+//!
+//! With `use_coarse_layer` a `BitmapIndex` keeps in memory a coarse layer over the
+//! regular block bitmaps: one bit for each bitmap of each full chunk, set if the bitmap
+//! is not empty. Queries consult the coarse layer before touching a chunk packed in a
+//! `ChunkArena` or flushed on storage: if one of the query bitmaps of the chunk is empty
+//! the "logical and" of the chunk is empty, so the chunk bitmaps are neither read nor
+//! intersected. The coarse layer takes `num_bitmaps / 8` bytes for each chunk.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// `CoarseLayer` struct with the presence bits of the bitmaps of each chunk.
+pub(super) struct CoarseLayer {
+    words_per_chunk: usize,
+    presence: Vec<u64>
+}
+
+impl CoarseLayer {
+    fn new(num_bitmaps: usize) -> Self {
+        CoarseLayer {
+            words_per_chunk: num_bitmaps.div_ceil(64),
+            presence: Vec::new()
+        }
+    }
+
+    fn num_chunks(&self) -> u64 {
+        (self.presence.len() / self.words_per_chunk) as u64
+    }
+
+    /// Set the presence bits of the chunk `chunk_id` from `non_empty`, one item for each
+    /// bitmap. Chunks before `chunk_id` without presence bits are considered full.
+    pub(super) fn set_chunk(&mut self, chunk_id: u64, non_empty: impl Iterator<Item=bool>) {
+        let start = chunk_id as usize * self.words_per_chunk;
+        if self.presence.len() < start + self.words_per_chunk {
+            self.presence.resize(start, u64::MAX);
+            self.presence.resize(start + self.words_per_chunk, 0);
+        }
+        let words = &mut self.presence[start..(start + self.words_per_chunk)];
+        words.fill(0);
+        for (i_bitmap, _) in non_empty.enumerate().filter(|(_i, non_empty)| *non_empty) {
+            words[i_bitmap >> 6] |= 1 << (i_bitmap & 63);
+        }
+    }
+
+    /// Return `true` if one of `query_i_bitmaps` is empty in the chunk `chunk_id`.
+    pub(super) fn has_empty(&self, chunk_id: u64, query_i_bitmaps: &[usize]) -> bool {
+        if chunk_id >= self.num_chunks() {
+            return false;
+        }
+        let words = &self.presence[(chunk_id as usize * self.words_per_chunk)..];
+        query_i_bitmaps.iter().any(|i_bitmap| words[i_bitmap >> 6] & (1 << (i_bitmap & 63)) == 0)
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Build the coarse layer of all full chunks and keep it updated for the next
+    /// chunks. In storage mode the bitmap offsets of every flushed chunk are read once.
+    pub fn use_coarse_layer(&mut self) -> Result<(), Error> {
+        let num_bitmaps = self.bitmaps.len();
+        let empty_size = T::new().size() as u64;
+        let non_empty = |size: &u64| *size != 0 && *size != empty_size;
+        let mut coarse_layer = CoarseLayer::new(num_bitmaps);
+        let mut chunk_id: u64 = 0;
+        if let Some(spill_chunks) = self.spill_chunks.as_ref() {
+            for i in 0..(spill_chunks.len() as usize) {
+                coarse_layer.set_chunk(chunk_id, spill_chunks.bitmap_sizes(i).map(|size| non_empty(&size)));
+                chunk_id += 1;
+            }
+        }
+        for bitmaps in self.chunks.iter().flatten() {
+            coarse_layer.set_chunk(chunk_id, bitmaps.iter().map(|b| !b.is_empty()));
+            chunk_id += 1;
+        }
+        for arena in self.arena_chunks.iter().flatten() {
+            coarse_layer.set_chunk(chunk_id, arena.bitmap_sizes().map(|size| non_empty(&size)));
+            chunk_id += 1;
+        }
+        if let Some(storage_idx) = self.storage_idx.as_mut() {
+            let all_i_bitmaps: Vec<usize> = (0..num_bitmaps).collect();
+            let num_chunks = Self::get_chunk_id(self.num_values, self.chunk_size);
+            for chunk_id in 0..num_chunks {
+                let chunk_offset = Self::map_io_result(Self::read_offsets(storage_idx, chunk_id, 1))?[0];
+                let offsets = Self::read_bitmaps_offsets(&mut storage_idx.data_file, chunk_offset, &all_i_bitmaps)?;
+                coarse_layer.set_chunk(chunk_id, offsets.iter().map(|offset| non_empty(&(offset.1 - offset.0))));
+            }
+        }
+        self.coarse_layer = Some(coarse_layer);
+        Ok(())
+    }
+
+    /// Return the size in bytes of the coarse layer, 0 if it is not used.
+    pub fn coarse_layer_size(&self) -> usize {
+        self.coarse_layer.as_ref().map_or(0, |coarse_layer| coarse_layer.presence.len() * std::mem::size_of::<u64>())
+    }
+
+    /// Set the presence bits of the chunk `chunk_id` from `bitmaps`, if the coarse layer is used.
+    pub(super) fn update_coarse_layer(coarse_layer: &mut Option<CoarseLayer>, chunk_id: u64, bitmaps: &[T]) {
+        if let Some(coarse_layer) = coarse_layer.as_mut() {
+            coarse_layer.set_chunk(chunk_id, bitmaps.iter().map(|b| !b.is_empty()));
+        }
+    }
+
+    /// Return `true` if the coarse layer shows that one of `query_i_bitmaps` is empty in
+    /// the chunk `chunk_id`.
+    pub(super) fn coarse_chunk_is_empty(coarse_layer: &Option<CoarseLayer>, chunk_id: u64, query_i_bitmaps: &[usize]) -> bool {
+        coarse_layer.as_ref().is_some_and(|coarse_layer| coarse_layer.has_empty(chunk_id, query_i_bitmaps))
+    }
+}
@@ -0,0 +1,127 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Composite keys
+//!
+//! `CompositeKey` packs a small tuple of unsigned integers into a single wider
+//! `BitValue`, the first component in the most significant bits. When block
+//! boundaries are aligned to the component boundaries (see `CompositeKey::build_options`)
+//! `run_query_component` answers the equality of a single component intersecting only
+//! the bitmaps of the blocks of that component.
+
+use std::mem;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    ChunkSize,
+    Error,
+    TransmuteToUsize
+};
+
+/// A tuple that can be packed into the `BitValue` `Packed`.
+pub trait CompositeKey: Copy {
+    /// The `BitValue` indexed in place of the tuple.
+    type Packed: BitValue;
+
+    /// Width in bits of each component, from the first one.
+    const WIDTHS: &'static [usize];
+
+    /// Return the tuple packed into a single value.
+    fn pack(self) -> Self::Packed;
+
+    /// Return the tuple of `packed`.
+    fn unpack(packed: Self::Packed) -> Self;
+
+    /// Return the position of the least significant bit of the component `component`
+    /// in the packed value.
+    fn component_shift(component: usize) -> usize {
+        Self::WIDTHS.iter().skip(component + 1).sum()
+    }
+
+    /// Return the `BuildOptions` with the greatest `bit_block_size` whose block
+    /// boundaries are aligned to the component boundaries.
+    fn build_options(chunk_size: ChunkSize) -> BuildOptions {
+        let packed_bits = mem::size_of::<Self::Packed>() << 3;
+        let aligned = Self::WIDTHS.iter().all(|w| w % 16 == 0) && Self::component_shift(0).is_multiple_of(16)
+            && packed_bits.is_multiple_of(16);
+        BuildOptions::new(if aligned { 16 } else { 8 }, chunk_size)
+    }
+}
+
+macro_rules! impl_composite_key {
+    ($(($($ty:ident $idx:tt),+) -> $packed:ident),+) => {
+        $(
+            impl CompositeKey for ($($ty),+) {
+                type Packed = $packed;
+
+                const WIDTHS: &'static [usize] = &[$(mem::size_of::<$ty>() << 3),+];
+
+                fn pack(self) -> $packed {
+                    let mut packed: $packed = 0;
+                    $(packed = (packed << (mem::size_of::<$ty>() << 3)) | self.$idx as $packed;)+
+                    packed
+                }
+
+                fn unpack(packed: $packed) -> Self {
+                    ($((packed >> Self::component_shift($idx)) as $ty),+)
+                }
+            }
+        )+
+    };
+}
+
+impl_composite_key!(
+    (u8 0, u8 1) -> u16,
+    (u8 0, u16 1) -> u32,
+    (u16 0, u8 1) -> u32,
+    (u16 0, u16 1) -> u32,
+    (u8 0, u8 1, u8 2) -> u32,
+    (u8 0, u32 1) -> u64,
+    (u32 0, u8 1) -> u64,
+    (u16 0, u32 1) -> u64,
+    (u32 0, u16 1) -> u64,
+    (u32 0, u32 1) -> u64,
+    (u16 0, u16 1, u16 2) -> u64,
+    (u64 0, u64 1) -> u128
+);
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a `Vec<u64>` that contains all indexes of keys whose component `component`
+    /// is equal to the same component of `key`, the other components of `key` are
+    /// ignored. `ParametersError` is returned if `component` doesn't exist or if a block
+    /// of `BitmapIndex` isn't aligned to the component boundaries. The parameters
+    /// `start_index` and `end_index` are optional and if specified define the range where
+    /// query is runned.
+    pub fn run_query_component<K: CompositeKey<Packed = U>>(&mut self, key: K, component: usize, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        if component >= K::WIDTHS.len() {
+            return Err(Error::ParametersError);
+        }
+        let bits = mem::size_of::<U>() << 3;
+        let low = K::component_shift(component);
+        // Bits above the first component are always 0, so they belong to it.
+        let high = if component == 0 { bits } else { low + K::WIDTHS[component] };
+        if self.block_info.bin_shift > low {
+            return Err(Error::ParametersError);
+        }
+        let mut blocks: Vec<usize> = Vec::new();
+        for i in 0..self.block_info.num_blocks {
+            let block_low = self.block_info.bin_shift + i * self.block_info.bit_block_size;
+            let block_high = (block_low + self.block_info.bit_block_size).min(bits);
+            if block_low >= low && block_high <= high {
+                blocks.push(i);
+            } else if block_low < high && block_high > low {
+                return Err(Error::ParametersError);
+            }
+        }
+        self.run_query_blocks(key.pack(), &blocks, start_index, end_index)
+    }
+}
@@ -0,0 +1,117 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Compression report
+//!
+//! `compression_report` compares, for each chunk, the size of the serialized bitmaps
+//! with the size of the same bitmaps uncompressed (a bit for each value of the chunk),
+//! and counts the non-empty bitmaps, to check if the block size of the index
+//! compresses well the real data.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    StorageIdx,
+    TransmuteToUsize
+};
+
+/// The compression of the bitmaps of a chunk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkCompression {
+    /// The id of the chunk.
+    pub chunk_id: u64,
+    /// The number of values of the chunk.
+    pub num_values: u64,
+    /// The number of bitmaps with at least a set bit.
+    pub non_empty_bitmaps: usize,
+    /// The size in bytes of the serialized bitmaps.
+    pub serialized_size: u64,
+    /// The size in bytes of the bitmaps uncompressed, one bit for each value.
+    pub uncompressed_size: u64
+}
+
+impl ChunkCompression {
+    /// Return `uncompressed_size / serialized_size`.
+    pub fn compression_ratio(&self) -> f64 {
+        self.uncompressed_size as f64 / self.serialized_size.max(1) as f64
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the `ChunkCompression` of each chunk with at least a value, including
+    /// the current chunk. In storage mode the sizes of the flushed chunks are read
+    /// from their offsets tables.
+    pub fn compression_report(&mut self) -> Result<Vec<ChunkCompression>, Error> {
+        let num_full_chunks = Self::get_chunk_id(self.num_values, self.chunk_size);
+        let mut report: Vec<ChunkCompression> = match self.storage_idx.as_mut() {
+            Some(storage_idx) => Self::storage_compression_report(storage_idx, self.bitmaps.len(), self.chunk_size, num_full_chunks)?,
+            None => Vec::new()
+        };
+        if let Some(spill_chunks) = self.spill_chunks.as_ref() {
+            for i in 0..(spill_chunks.len() as usize) {
+                report.push(Self::chunk_compression(report.len() as u64, self.chunk_size, spill_chunks.bitmap_sizes(i)));
+            }
+        }
+        for bitmaps in self.chunks.iter().flatten() {
+            let sizes = bitmaps.iter().map(|b| b.size() as u64);
+            report.push(Self::chunk_compression(report.len() as u64, self.chunk_size, sizes));
+        }
+        for arena in self.arena_chunks.iter().flatten() {
+            report.push(Self::chunk_compression(report.len() as u64, self.chunk_size, arena.bitmap_sizes()));
+        }
+        let current_num_values = self.num_values - num_full_chunks * self.chunk_size;
+        if current_num_values > 0 {
+            let sizes = self.bitmaps.iter().map(|b| b.size() as u64);
+            report.push(Self::chunk_compression(num_full_chunks, current_num_values, sizes));
+        }
+        Ok(report)
+    }
+
+    /// Return the `ChunkCompression` of the first `num_chunks` full chunks flushed in
+    /// `storage_idx`.
+    pub(super) fn storage_compression_report(storage_idx: &mut StorageIdx, num_bitmaps: usize, chunk_size: u64, num_chunks: u64) -> Result<Vec<ChunkCompression>, Error> {
+        let i_bitmaps: Vec<usize> = (0..num_bitmaps).collect();
+        let mut report: Vec<ChunkCompression> = Vec::with_capacity(num_chunks as usize);
+        if num_chunks == 0 {
+            return Ok(report);
+        }
+        let chunks_offsets = Self::map_io_result(Self::read_offsets(storage_idx, 0, num_chunks as usize))?;
+        for (chunk_id, chunk_offset) in chunks_offsets.into_iter().enumerate() {
+            let bitmaps_offsets = Self::read_bitmaps_offsets(&mut storage_idx.data_file, chunk_offset, &i_bitmaps)?;
+            let sizes = bitmaps_offsets.into_iter().map(|(start, end)| end - start);
+            report.push(Self::chunk_compression(chunk_id as u64, chunk_size, sizes));
+        }
+        Ok(report)
+    }
+
+    /// Return the `ChunkCompression` of a chunk of `num_values` values with bitmaps of
+    /// size `sizes`. A bitmap is empty if its size is the size of a new bitmap (or 0
+    /// for the bitmaps omitted from the chunk format).
+    fn chunk_compression(chunk_id: u64, num_values: u64, sizes: impl Iterator<Item=u64>) -> ChunkCompression {
+        let empty_size = T::new().size() as u64;
+        let mut compression = ChunkCompression {
+            chunk_id,
+            num_values,
+            non_empty_bitmaps: 0,
+            serialized_size: 0,
+            uncompressed_size: 0
+        };
+        for size in sizes {
+            compression.serialized_size += size;
+            compression.uncompressed_size += num_values.div_ceil(8);
+            if size != 0 && size != empty_size {
+                compression.non_empty_bitmaps += 1;
+            }
+        }
+        compression
+    }
+}
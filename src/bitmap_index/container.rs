@@ -0,0 +1,453 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Container storage
+//!
+//! `ContainerIndex` stores an index in a single container file instead of a directory
+//! with a meta data, an offsets and a data file: the three files are sections of the
+//! container, located by a superblock. Values are pushed in an in-memory `BitmapIndex`
+//! and every flush appends the new chunks, then a new offsets and meta data section,
+//! and finally writes the superblock that points to them, so the container always
+//! references a complete set of sections.
+//!
+//! # Encoding
+//! container:
+//!  |superblock slot 0|superblock slot 1|data section|...|
+//! superblock:
+//!  |64bit magic|32bit version|32bit reserved|64bit sequence|section|section|section|64bit digest|
+//! section:
+//!  |64bit offset|64bit size|
+//!
+//! The sections of a superblock are the meta data (a `MetaData`), the offsets (a pair of
+//! 64bit start and end offsets for each chunk) and the data section, that spans from the
+//! end of the superblocks to the last chunk written. Chunks are written in the format of
+//! the data file of `BitmapIndex`. The superblocks are written alternately and the valid
+//! superblock (with a matching FNV-1a digest) with the highest sequence is used, so a
+//! torn write of a superblock never loses the previous one.
+//!
+//! The sections of the previous flushes and a partial chunk rewritten by the next flush
+//! remain in the data section as stale bytes, `compact` writes a new container without
+//! them and atomically renames it over the old one.
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, BufWriter, Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::{Path, PathBuf};
+
+use super::data_trailer::{fnv1a_update, FNV1A_OFFSET_BASIS};
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    MetaData,
+    TransmuteToUsize,
+    KEY_FORMAT,
+    META_DATA_VERSION,
+    WRITE_BUFFER_SIZE
+};
+
+const CONTAINER_MAGIC: &[u8; 8] = b"BRIDXCNT";
+const CONTAINER_VERSION: u32 = 1;
+const SUPERBLOCK_SIZE: usize = 80;
+/// Offset of the data section, after the two superblock slots.
+const DATA_START: u64 = 2 * SUPERBLOCK_SIZE as u64;
+
+const META_DATA_SECTION: usize = 0;
+const OFFSETS_SECTION: usize = 1;
+const DATA_SECTION: usize = 2;
+
+/// Start and end offset of a chunk in the container.
+type ChunkExtent = (u64, u64);
+
+/// Superblock of a container, `sections` are the offset and size of the meta data,
+/// offsets and data sections.
+struct Superblock {
+    sequence: u64,
+    sections: [(u64, u64); 3]
+}
+
+impl Superblock {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::with_capacity(SUPERBLOCK_SIZE);
+        buf.extend_from_slice(CONTAINER_MAGIC);
+        buf.extend_from_slice(&CONTAINER_VERSION.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(&self.sequence.to_ne_bytes());
+        for (offset, size) in self.sections.iter() {
+            buf.extend_from_slice(&offset.to_ne_bytes());
+            buf.extend_from_slice(&size.to_ne_bytes());
+        }
+        let digest = fnv1a_update(FNV1A_OFFSET_BASIS, &buf);
+        buf.extend_from_slice(&digest.to_ne_bytes());
+        buf
+    }
+
+    /// Return the superblock serialized in `buf`, `None` if it is not valid.
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let word = |i: usize| u64::from_ne_bytes(buf[(i * 8)..((i + 1) * 8)].try_into().unwrap());
+        let version = u32::from_ne_bytes(buf[8..12].try_into().unwrap());
+        let digest = fnv1a_update(FNV1A_OFFSET_BASIS, &buf[..(SUPERBLOCK_SIZE - 8)]);
+        if &buf[..8] != CONTAINER_MAGIC || version != CONTAINER_VERSION || digest != word(9) {
+            return None;
+        }
+        Some(Superblock {
+            sequence: word(2),
+            sections: [(word(3), word(4)), (word(5), word(6)), (word(7), word(8))]
+        })
+    }
+
+    /// Return the end of the last section.
+    fn end(&self) -> u64 {
+        self.sections.iter().map(|(offset, size)| offset + size).max().unwrap_or(DATA_START)
+    }
+}
+
+/// `ContainerIndex` struct that stores a bitmap index in a single container file.
+pub struct ContainerIndex<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    path: PathBuf,
+    file: fs::File,
+    build_options: BuildOptions,
+    sequence: u64,
+    /// Start and end offset of each full flushed chunk.
+    chunks: Vec<ChunkExtent>,
+    container_end: u64,
+    flushed_num_values: u64,
+    memtable_start: u64,
+    memtable: BitmapIndex<T, U>,
+    created_at: u64
+}
+
+impl<T: Bitmap, U: BitValue> ContainerIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Create a new empty `ContainerIndex` in the container file `path`, that must not exist.
+    pub fn create(path: &Path, build_options: BuildOptions) -> Result<Self, Error> {
+        if BitmapIndex::<T, U>::check_if_path_exixsts(path) {
+            return Err(Error::ParametersError);
+        }
+        let memtable = BitmapIndex::new(build_options.clone())?;
+        let open_r = fs::OpenOptions::new().read(true).write(true).create_new(true).open(path);
+        let file = BitmapIndex::<T, U>::map_io_result(open_r)?;
+        let mut c_index = ContainerIndex {
+            path: PathBuf::from(path),
+            file,
+            build_options,
+            sequence: 0,
+            chunks: Vec::new(),
+            container_end: DATA_START,
+            flushed_num_values: 0,
+            memtable_start: 0,
+            memtable,
+            created_at: MetaData::unix_time_now()
+        };
+        let write_r = BitmapIndex::<T, U>::map_io_result((&c_index.file).write_all(&[0u8; DATA_START as usize]))
+            .and_then(|_| c_index.write_sections(&[]));
+        match write_r {
+            Ok((new_chunks, container_end)) => {
+                c_index.commit_flush(new_chunks, container_end)?;
+                Ok(c_index)
+            },
+            Err(err) => {
+                let _err = fs::remove_file(path);
+                Err(err)
+            }
+        }
+    }
+
+    /// Open a `ContainerIndex` from the container file `path`.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = BitmapIndex::<T, U>::map_io_result(BitmapIndex::<T, U>::open_file(path, false))?;
+        let mut superblocks: [u8; DATA_START as usize] = [0; DATA_START as usize];
+        BitmapIndex::<T, U>::map_io_result((&file).read_exact(&mut superblocks))?;
+        let superblock = superblocks.chunks_exact(SUPERBLOCK_SIZE)
+            .filter_map(Superblock::from_bytes)
+            .max_by_key(|superblock| superblock.sequence)
+            .ok_or_else(Self::invalid_container)?;
+        let file_size = BitmapIndex::<T, U>::map_io_result(file.metadata())?.len();
+        if superblock.end() > file_size {
+            return Err(Self::invalid_container());
+        }
+
+        let meta_data_buf = Self::read_section(&file, superblock.sections[META_DATA_SECTION])?;
+        if meta_data_buf.len() != mem::size_of::<MetaData>() {
+            return Err(Self::invalid_container());
+        }
+        let meta_data = BitmapIndex::<T, U>::meta_data_from_slice_u8(&meta_data_buf)?;
+        BitmapIndex::<T, U>::check_meta_data(&meta_data)?;
+        let offsets_buf = Self::read_section(&file, superblock.sections[OFFSETS_SECTION])?;
+        let mut chunks: Vec<ChunkExtent> = offsets_buf.chunks_exact(2 * mem::size_of::<u64>())
+            .map(|w| (u64::from_ne_bytes(w[0..8].try_into().unwrap()), u64::from_ne_bytes(w[8..16].try_into().unwrap())))
+            .collect();
+        let (data_start, data_size) = superblock.sections[DATA_SECTION];
+        if chunks.iter().any(|(start, end)| *start < data_start || start > end || *end > data_start + data_size) {
+            return Err(Self::invalid_container());
+        }
+        let chunk_size = meta_data.build_options.chunk_size.clone() as u64;
+        if chunks.len() as u64 != meta_data.num_values.div_ceil(chunk_size) {
+            return Err(Error::MetaDataError);
+        }
+
+        let mut memtable = BitmapIndex::new(meta_data.build_options.clone())?;
+        if meta_data.num_values & (chunk_size - 1) != 0 {
+            let extent = chunks.pop().unwrap();
+            BitmapIndex::<T, U>::read_chunk_bitmaps(&mut &file, extent, &mut memtable.bitmaps)?;
+            memtable.num_values = meta_data.num_values & (chunk_size - 1);
+        }
+        Ok(ContainerIndex {
+            path: PathBuf::from(path),
+            file,
+            build_options: meta_data.build_options.clone(),
+            sequence: superblock.sequence,
+            memtable_start: chunks.len() as u64 * chunk_size,
+            chunks,
+            container_end: superblock.end(),
+            flushed_num_values: meta_data.num_values,
+            memtable,
+            created_at: meta_data.created_at
+        })
+    }
+
+    /// Insert (in append) a `value` into the index.
+    pub fn push_value(&mut self, value: U) -> Result<(), Error> {
+        self.memtable.push_value(value)
+    }
+
+    /// Insert (in append) values into the index.
+    pub fn push_values(&mut self, values: &[U]) -> Result<(), Error> {
+        self.memtable.push_values(values)
+    }
+
+    /// Return the number of values pushed in `ContainerIndex`.
+    pub fn num_values(&self) -> u64 {
+        self.memtable_start + self.memtable.num_values()
+    }
+
+    /// Return the number of values pushed after the last flush.
+    pub fn unflushed_values(&self) -> u64 {
+        self.num_values() - self.flushed_num_values
+    }
+
+    /// Return the path of the container file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the chunks with values pushed after the last flush and the new sections in
+    /// the container, then make them visible writing the superblock. Nothing is written
+    /// if there are no unflushed values.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.unflushed_values() == 0 {
+            return Ok(());
+        }
+        let mut chunks: Vec<&[T]> = self.memtable.chunks.iter().flatten().map(|c| c.as_slice()).collect();
+        if self.memtable.num_values & self.memtable.chunk_size_mask != 0 {
+            chunks.push(&self.memtable.bitmaps);
+        }
+        let (new_chunks, container_end) = self.write_sections(&chunks)?;
+        self.commit_flush(new_chunks, container_end)
+    }
+
+    /// Flush and write the chunks of `ContainerIndex` in a new container without stale
+    /// bytes, then rename it over the container file. Return the number of bytes reclaimed.
+    pub fn compact(&mut self) -> Result<u64, Error> {
+        self.flush()?;
+        let tmp_path = self.path.with_extension("tmp");
+        let compact_r = self.write_compacted(&tmp_path).and_then(|(file, chunks, superblock)| {
+            fs::rename(&tmp_path, &self.path)?;
+            Ok((file, chunks, superblock))
+        });
+        let (file, chunks, superblock) = match compact_r {
+            Ok(r) => r,
+            Err(err) => {
+                let _err = fs::remove_file(&tmp_path);
+                return Err(Error::FileError(err));
+            }
+        };
+        let reclaimed = self.container_end - superblock.end();
+        self.file = file;
+        self.chunks = chunks;
+        self.sequence = superblock.sequence;
+        self.container_end = superblock.end();
+        Ok(reclaimed)
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values pushed in `ContainerIndex`
+    /// equal to `value`. The parameters `start_index` and `end_index` are optional and if
+    /// specified define the range where query is runned.
+    pub fn run_query(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values());
+        let chunk_size = self.memtable.chunk_size;
+        let query_i_bitmaps = BitmapIndex::<T, U>::get_query_i_bitmaps(&self.memtable.block_info, value);
+
+        let mut indexes: Vec<u64> = Vec::new();
+        for (chunk_id, extent) in self.chunks.iter().enumerate() {
+            let chunk_id = chunk_id as u64;
+            if !BitmapIndex::<T, U>::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
+                continue;
+            }
+            let query_bitmaps = BitmapIndex::<T, U>::read_query_bitmaps(&mut &self.file, extent.0, &query_i_bitmaps)?;
+            let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+            BitmapIndex::<T, U>::push_indexes(&query_bitmaps_ref, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+        }
+        if end_index >= self.memtable_start && self.memtable.num_values() > 0 {
+            let local_start = start_index.saturating_sub(self.memtable_start);
+            let local_end = end_index - self.memtable_start;
+            let local_indexes = self.memtable.run_query(value, Some(local_start), Some(local_end))?;
+            indexes.extend(local_indexes.iter().map(|idx| self.memtable_start + idx));
+        }
+        Ok(indexes)
+    }
+
+    fn invalid_container() -> Error {
+        Error::FileError(IoError::new(ErrorKind::InvalidData, "invalid container file"))
+    }
+
+    fn read_section(file: &fs::File, section: (u64, u64)) -> Result<Vec<u8>, Error> {
+        let mut buf: Vec<u8> = vec![0; section.1 as usize];
+        let mut reader = file;
+        BitmapIndex::<T, U>::map_io_result(reader.seek(SeekFrom::Start(section.0)))?;
+        BitmapIndex::<T, U>::map_io_result(reader.read_exact(&mut buf))?;
+        Ok(buf)
+    }
+
+    fn meta_data(&self) -> MetaData {
+        MetaData {
+            meta_data_version: META_DATA_VERSION,
+            key_format: KEY_FORMAT,
+            value_size: mem::size_of::<U>() as u32,
+            num_values: self.num_values(),
+            build_options: self.build_options.clone(),
+            bitmap_tag: T::format_tag(),
+            library_version: MetaData::current_library_version(),
+            created_at: self.created_at,
+            modified_at: MetaData::unix_time_now()
+        }
+    }
+
+    /// Append `chunks` and the new sections at the end of the container and write the
+    /// superblock, then return the extents of the appended chunks and the new end of
+    /// the container.
+    fn write_sections(&self, chunks: &[&[T]]) -> Result<(Vec<ChunkExtent>, u64), Error> {
+        let write_r = (|| {
+            let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &self.file);
+            writer.seek(SeekFrom::Start(self.container_end))?;
+            let new_chunks = self.write_chunks(&mut writer, self.container_end, chunks)?;
+            let mut extents = self.chunks.clone();
+            extents.extend_from_slice(&new_chunks);
+            let data_end = new_chunks.last().map_or(self.container_end, |extent| extent.1);
+            let superblock = self.write_meta_sections(&mut writer, data_end, &extents, self.sequence + 1)?;
+            writer.into_inner().map_err(|err| err.into_error())?;
+            Self::write_superblock(&self.file, &superblock)?;
+            Ok((new_chunks, superblock.end()))
+        })();
+        BitmapIndex::<T, U>::map_io_result(write_r)
+    }
+
+    /// Update `ContainerIndex` after a flush that wrote `new_chunks`: the full chunks of
+    /// the memtable become flushed chunks and only the partial chunk is kept in memory.
+    fn commit_flush(&mut self, mut new_chunks: Vec<ChunkExtent>, container_end: u64) -> Result<(), Error> {
+        let chunk_size_mask = self.memtable.chunk_size_mask;
+        let num_values_in_chunk = self.memtable.num_values & chunk_size_mask;
+        self.sequence += 1;
+        self.container_end = container_end;
+        if num_values_in_chunk != 0 {
+            new_chunks.pop();
+        }
+        self.memtable_start += new_chunks.len() as u64 * self.memtable.chunk_size;
+        self.chunks.extend(new_chunks);
+
+        let mut memtable = BitmapIndex::new(self.build_options.clone())?;
+        mem::swap(&mut memtable.bitmaps, &mut self.memtable.bitmaps);
+        memtable.num_values = num_values_in_chunk;
+        self.memtable = memtable;
+        self.flushed_num_values = self.num_values();
+        Ok(())
+    }
+
+    /// Write `chunks` from `position` and return their extents.
+    fn write_chunks<W: Write>(&self, writer: &mut W, mut position: u64, chunks: &[&[T]]) -> Result<Vec<ChunkExtent>, IoError> {
+        let mut extents: Vec<ChunkExtent> = Vec::with_capacity(chunks.len());
+        for bitmaps in chunks {
+            let chunk_format = self.memtable.flush_chunk_format(bitmaps);
+            let (chunk_header, bitmaps_size) = BitmapIndex::<T, U>::chunk_header(bitmaps, chunk_format);
+            writer.write_all(&chunk_header)?;
+            for b in bitmaps.iter().filter(|b| BitmapIndex::<T, U>::chunk_writes_bitmap(chunk_format, b)) {
+                b.write_to(writer)?;
+            }
+            let chunk_end = position + chunk_header.len() as u64 + bitmaps_size;
+            extents.push((position, chunk_end));
+            position = chunk_end;
+        }
+        Ok(extents)
+    }
+
+    /// Write the offsets section of `extents` and the meta data section from `data_end`,
+    /// flush `writer` and return the superblock with `sequence` that points to them.
+    fn write_meta_sections<W: Write>(&self, writer: &mut BufWriter<W>, data_end: u64, extents: &[ChunkExtent], sequence: u64) -> Result<Superblock, IoError> {
+        let offsets_size = (extents.len() * 2 * mem::size_of::<u64>()) as u64;
+        for (start, end) in extents.iter() {
+            writer.write_all(&start.to_ne_bytes())?;
+            writer.write_all(&end.to_ne_bytes())?;
+        }
+        let meta_data = self.meta_data();
+        writer.write_all(BitmapIndex::<T, U>::to_slice_u8(&meta_data))?;
+        writer.flush()?;
+        Ok(Superblock {
+            sequence,
+            sections: [
+                (data_end + offsets_size, mem::size_of::<MetaData>() as u64),
+                (data_end, offsets_size),
+                (DATA_START, data_end - DATA_START)
+            ]
+        })
+    }
+
+    /// Sync the sections, then write `superblock` in its slot and sync it.
+    fn write_superblock(file: &fs::File, superblock: &Superblock) -> Result<(), IoError> {
+        file.sync_data()?;
+        let mut writer = file;
+        writer.seek(SeekFrom::Start((superblock.sequence % 2) * SUPERBLOCK_SIZE as u64))?;
+        writer.write_all(&superblock.to_bytes())?;
+        file.sync_data()
+    }
+
+    /// Write the flushed chunks in a new container at `path` and return its file, the
+    /// extents of the full chunks and its superblock.
+    fn write_compacted(&self, path: &Path) -> Result<(fs::File, Vec<ChunkExtent>, Superblock), IoError> {
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &file);
+        writer.write_all(&[0u8; DATA_START as usize])?;
+        let mut position = DATA_START;
+        let mut extents: Vec<ChunkExtent> = Vec::with_capacity(self.chunks.len() + 1);
+        for (start, end) in self.chunks.iter() {
+            let mut reader = &self.file;
+            reader.seek(SeekFrom::Start(*start))?;
+            let size = io::copy(&mut reader.take(end - start), &mut writer)?;
+            if size != end - start {
+                return Err(IoError::from(ErrorKind::UnexpectedEof));
+            }
+            extents.push((position, position + size));
+            position += size;
+        }
+        let chunks = extents.clone();
+        if self.memtable.num_values() > 0 {
+            let tail = self.write_chunks(&mut writer, position, &[&self.memtable.bitmaps])?;
+            position = tail[0].1;
+            extents.extend(tail);
+        }
+        let superblock = self.write_meta_sections(&mut writer, position, &extents, 1)?;
+        drop(writer);
+        Self::write_superblock(&file, &superblock)?;
+        Ok((file, chunks, superblock))
+    }
+}
@@ -0,0 +1,101 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Cross index queries
+//!
+//! Queries between two `BitmapIndex` built on the same rows (i.e. two columns of the
+//! same table). The result bitmaps of the two indexes are combined chunk by chunk
+//! ("logical and" or "logical and not") before unrolling, so only the final indexes
+//! are decoded.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a `Vec<u64>` that contains all indexes where this index is equal to `value`
+    /// and `other` is equal to `other_value`. `other` must index the same rows of this
+    /// index with the same `ChunkSize`, otherwise `ParametersError` is returned.
+    /// The parameters `start_index` and `end_index` are optional and if specified define
+    /// the range where query is runned.
+    pub fn run_query_and<V: BitValue>(&mut self, value: U, other: &mut BitmapIndex<T, V>, other_value: V, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error>
+    where <V as Shr<usize>>::Output: TransmuteToUsize {
+        if self.chunk_size != other.chunk_size {
+            return Err(Error::ParametersError);
+        }
+        let start_index = start_index.unwrap_or(0);
+        let end_index = end_index.unwrap_or(self.num_values.min(other.num_values));
+        let chunks_results = self.chunks_results(value, start_index, end_index)?;
+
+        let other_i_bitmaps = BitmapIndex::<T, V>::get_query_i_bitmaps(&other.block_info, other_value);
+        let chunk_size = self.chunk_size;
+        let mut indexes: Vec<u64> = Vec::new();
+        let f = |chunk_id: u64, other_bitmaps: &[&T]| {
+            if let Ok(i) = chunks_results.binary_search_by_key(&chunk_id, |chunk_result| chunk_result.0) {
+                let mut query_bitmaps: Vec<&T> = other_bitmaps.to_vec();
+                query_bitmaps.push(&chunks_results[i].1);
+                Self::push_indexes(&query_bitmaps, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+            }
+        };
+        other.for_each_chunk(&other_i_bitmaps, start_index, end_index, f)?;
+        Ok(indexes)
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes where this index is equal to `value`
+    /// and `other` is not equal to `other_value`. `other` must index the same rows of this
+    /// index with the same `ChunkSize`, otherwise `ParametersError` is returned; the query
+    /// runs only on the rows indexed from both indexes.
+    /// The parameters `start_index` and `end_index` are optional and if specified define
+    /// the range where query is runned.
+    pub fn run_query_and_not<V: BitValue>(&mut self, value: U, other: &mut BitmapIndex<T, V>, other_value: V, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error>
+    where <V as Shr<usize>>::Output: TransmuteToUsize {
+        if self.chunk_size != other.chunk_size {
+            return Err(Error::ParametersError);
+        }
+        let num_values = self.num_values.min(other.num_values);
+        if num_values == 0 {
+            return Ok(Vec::new());
+        }
+        let start_index = start_index.unwrap_or(0);
+        let end_index = end_index.unwrap_or(num_values).min(num_values - 1);
+        let chunks_results = self.chunks_results(value, start_index, end_index)?;
+
+        let other_i_bitmaps = BitmapIndex::<T, V>::get_query_i_bitmaps(&other.block_info, other_value);
+        let chunk_size = self.chunk_size;
+        let mut indexes: Vec<u64> = Vec::new();
+        let f = |chunk_id: u64, other_bitmaps: &[&T]| {
+            if let Ok(i) = chunks_results.binary_search_by_key(&chunk_id, |chunk_result| chunk_result.0) {
+                let b_result = chunks_results[i].1.and_not(&Self::and_bitmaps(other_bitmaps));
+                Self::push_indexes(&[&b_result], chunk_id, chunk_size, start_index, end_index, &mut indexes);
+            }
+        };
+        other.for_each_chunk(&other_i_bitmaps, start_index, end_index, f)?;
+        Ok(indexes)
+    }
+
+    /// Return the not empty result bitmap of `value` of each chunk that intersects
+    /// `[start_index, end_index]`, with its chunk id.
+    pub(super) fn chunks_results(&mut self, value: U, start_index: u64, end_index: u64) -> Result<Vec<(u64, T)>, Error> {
+        let query_i_bitmaps = Self::get_query_i_bitmaps(&self.block_info, value);
+        let mut chunks_results: Vec<(u64, T)> = Vec::new();
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            let b_result = Self::and_bitmaps(query_bitmaps);
+            if !b_result.is_empty() {
+                chunks_results.push((chunk_id, b_result));
+            }
+        };
+        self.for_each_chunk(&query_i_bitmaps, start_index, end_index, f)?;
+        Ok(chunks_results)
+    }
+}
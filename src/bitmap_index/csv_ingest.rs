@@ -0,0 +1,72 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # CSV ingestion
+//!
+//! Build a `BitmapIndex` from a column of a CSV file, available with the `csv` feature.
+//! The CSV file must have headers and is read one record at a time.
+
+use std::io::{Error as IoError, ErrorKind};
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue + FromStr> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `BitmapIndex` in memory mode that contains the values of `column`
+    /// of the CSV file `csv_path`.
+    pub fn build_from_csv(csv_path: &Path, column: &str, build_options: BuildOptions) -> Result<Self, Error> {
+        let mut b_index = Self::new(build_options)?;
+        b_index.push_csv_column(csv_path, column)?;
+        Ok(b_index)
+    }
+
+    /// Insert (in append) the values of `column` of the CSV file `csv_path` and return
+    /// the number of values inserted. Error occur if `column` is not a header of the
+    /// file or if a value can't be parsed as `U`.
+    pub fn push_csv_column(&mut self, csv_path: &Path, column: &str) -> Result<u64, Error> {
+        let mut reader = Self::map_csv_result(csv::Reader::from_path(csv_path))?;
+        let headers = Self::map_csv_result(reader.headers())?;
+        let i_column = match headers.iter().position(|header| header == column) {
+            Some(i_column) => i_column,
+            None => return Err(Error::ParametersError)
+        };
+
+        let mut num_values: u64 = 0;
+        let mut record = csv::StringRecord::new();
+        while Self::map_csv_result(reader.read_record(&mut record))? {
+            let field = record.get(i_column).unwrap_or("");
+            let value: U = match field.trim().parse() {
+                Ok(value) => value,
+                Err(_) => {
+                    let line = record.position().map(|p| p.line()).unwrap_or(0);
+                    let msg = format!("invalid value {:?} at line {}", field, line);
+                    return Err(Error::FileError(IoError::new(ErrorKind::InvalidData, msg)));
+                }
+            };
+            self.push_value(value)?;
+            num_values += 1;
+        }
+        Ok(num_values)
+    }
+
+    fn map_csv_result<M>(result: Result<M, csv::Error>) -> Result<M, Error> {
+        match result {
+            Ok(r) => Ok(r),
+            Err(err) => Err(Error::FileError(err.into()))
+        }
+    }
+}
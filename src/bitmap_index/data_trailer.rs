@@ -0,0 +1,319 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Data file trailer
+//!
+//! Every chunk written in the data file ends with a `ChunkTrailer`, so the data file is
+//! self-describing: the offset and meta data files can be rebuilt scanning the data file
+//! with `rebuild_storage_files`. The trailer is inside the extent of the chunk, after
+//! the bitmaps content, so readers that locate bitmaps from the chunk table ignore it:
+//!
+//!  |chunk table|bitmaps content|trailer|
+//!
+//!  trailer: |64bit chunk_id|64bit value_start|64bit value_end|64bit chunk_size|
+//!            64bit table_digest|32bit bit_block_size|32bit chunk_size|32bit bitmap_capacity|
+//!            32bit wide_words|32bit bin_shift|32bit value_size|32bit bitmap_tag|
+//!            32bit table_size|32bit reserved|32bit magic|
+//!
+//! where the first `chunk_size` is the size in bytes of the chunk (trailer included),
+//! `[value_start, value_end)` are the indexes of the values of the chunk and
+//! `table_digest` is the FNV-1a digest of the `table_size` bytes of the chunk table.
+//!
+//! Chunks are written contiguously, so the data file is scanned backward from its end:
+//! every trailer gives the start of its chunk, that is the end of the previous one.
+//! The last version of each chunk in the data file is the current one (a partial chunk
+//! flushed again or a rewritten chunk is written after the previous version, or over
+//! the older versions of the partial chunk and then the data file is truncated after it).
+//! Until the data file is truncated the older versions overwritten only in part are a
+//! gap without trailers before the last version: the scan skips it searching backward
+//! for the previous trailer, so the last version is kept. A gap left by a chunk that
+//! is lost is found by the checks on the chunk ids.
+//! Indexes written by versions of the library without trailers can't be rebuilt.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    ChunkSize,
+    Error,
+    MetaData,
+    StorageIdx,
+    TransmuteToUsize,
+    KEY_FORMAT,
+    META_DATA_VERSION,
+    WRITE_BUFFER_SIZE
+};
+
+const TRAILER_MAGIC: u32 = u32::from_be_bytes(*b"BTRL");
+pub(super) const CHUNK_TRAILER_SIZE: usize = mem::size_of::<ChunkTrailer>();
+
+/// The trailer written at the end of every chunk in the data file.
+#[derive(Clone)]
+#[repr(C)]
+pub(super) struct ChunkTrailer {
+    chunk_id: u64,
+    value_start: u64,
+    value_end: u64,
+    chunk_size: u64,
+    table_digest: u64,
+    bit_block_size: u32,
+    chunk_size_values: u32,
+    bitmap_capacity: u32,
+    wide_words: u32,
+    bin_shift: u32,
+    value_size: u32,
+    bitmap_tag: u32,
+    table_size: u32,
+    reserved: u32,
+    magic: u32
+}
+
+impl ChunkTrailer {
+    /// Return the size in bytes of the chunk, trailer included.
+    pub(super) fn size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Return the id of the chunk.
+    pub(super) fn chunk_id(&self) -> u64 {
+        self.chunk_id
+    }
+
+    /// Return the index after the last value of the chunk.
+    pub(super) fn value_end(&self) -> u64 {
+        self.value_end
+    }
+
+    /// Return `true` if the chunk has been written by a `BitmapIndex` with the `BitValue`
+    /// size, the bitmap format and the layout of the build options of `meta_data`.
+    pub(super) fn matches(&self, meta_data: &MetaData) -> bool {
+        let build_options = &meta_data.build_options;
+        self.value_size == meta_data.value_size && self.bitmap_tag == meta_data.bitmap_tag
+            && self.bit_block_size as usize == build_options.bit_block_size
+            && self.chunk_size_values as u64 == build_options.chunk_size()
+            && self.bin_shift == build_options.bin_shift
+    }
+
+    fn build_options(&self) -> Option<BuildOptions> {
+        Some(BuildOptions {
+            bit_block_size: self.bit_block_size as usize,
+            chunk_size: ChunkSize::from_u32(self.chunk_size_values)?,
+            bitmap_capacity: self.bitmap_capacity,
+            wide_words: self.wide_words,
+            bin_shift: self.bin_shift
+        })
+    }
+}
+
+/// Initial value of a FNV-1a digest.
+pub(super) const FNV1A_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Return the FNV-1a digest of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_update(FNV1A_OFFSET_BASIS, bytes)
+}
+
+/// Return the FNV-1a digest `hash` continued with `bytes`.
+pub(super) fn fnv1a_update(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, b| (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the trailer of the chunk `chunk_id` with values up to `value_end`, table
+    /// `chunk_table` and `bitmaps_size` bytes of bitmaps content.
+    pub(super) fn chunk_trailer(&self, chunk_id: u64, value_end: u64, chunk_table: &[u8], bitmaps_size: u64) -> ChunkTrailer {
+        ChunkTrailer {
+            chunk_id,
+            value_start: chunk_id * self.chunk_size,
+            value_end,
+            chunk_size: (chunk_table.len() + CHUNK_TRAILER_SIZE) as u64 + bitmaps_size,
+            table_digest: fnv1a(chunk_table),
+            bit_block_size: self.block_info.bit_block_size as u32,
+            chunk_size_values: self.chunk_size as u32,
+            bitmap_capacity: self.bitmap_capacity,
+            wide_words: self.wide_words as u32,
+            bin_shift: self.block_info.bin_shift as u32,
+            value_size: mem::size_of::<U>() as u32,
+            bitmap_tag: T::format_tag(),
+            table_size: chunk_table.len() as u32,
+            reserved: 0,
+            magic: TRAILER_MAGIC
+        }
+    }
+
+    /// Return the end of the chunk at `chunk_offset` of `data_file`, its trailer included
+    /// if it has one (chunks written by versions of the library without trailers haven't).
+    pub(super) fn read_chunk_data_end(data_file: &mut fs::File, chunk_offset: u64, num_bitmaps: usize) -> Result<u64, Error> {
+        let chunk_end = Self::read_chunk_end(data_file, chunk_offset, num_bitmaps)?;
+        let trailer_end = chunk_end + CHUNK_TRAILER_SIZE as u64;
+        if trailer_end > Self::map_io_result(data_file.metadata())?.len() {
+            return Ok(chunk_end);
+        }
+        match Self::read_chunk_trailer(data_file, trailer_end)? {
+            Some(trailer) if trailer.chunk_size == trailer_end - chunk_offset => Ok(trailer_end),
+            _ => Ok(chunk_end)
+        }
+    }
+
+    /// Return the start of the versions of the chunk `chunk_id` written contiguously
+    /// before the version at `chunk_offset`, i.e. the end of the previous chunk, or
+    /// `chunk_offset` if there are none.
+    pub(super) fn chunk_versions_start(data_file: &mut fs::File, chunk_id: u64, chunk_offset: u64) -> Result<u64, Error> {
+        let mut start = chunk_offset;
+        while let Some(trailer) = Self::read_chunk_trailer(data_file, start)? {
+            if trailer.chunk_id != chunk_id {
+                break;
+            }
+            start -= trailer.chunk_size;
+        }
+        Ok(start)
+    }
+
+    /// Rebuild the offset and meta data files of the `BitmapIndex` in `dir_path` scanning
+    /// its data file, then open it. Missing offset and meta data files are created, a
+    /// torn chunk at the end of the data file is truncated. User meta data are not
+    /// changed and creation and modification times are set to now.
+    /// `MetaDataError` is returned if the data file has no chunks or has been written
+    /// with different generic parameters, `InconsistentStorage` if chunks are missing
+    /// or haven't a trailer.
+    pub fn rebuild_storage_files(dir_path: &Path) -> Result<Self, Error> {
+        let mut storage_idx = Self::get_storage_idx(dir_path, None)?;
+        let data_size = Self::map_io_result(storage_idx.data_file.metadata())?.len();
+        let data_end = Self::last_trailer_end(&mut storage_idx.data_file, data_size)?.ok_or(Error::MetaDataError)?;
+        let inconsistent = |message: String| Error::InconsistentStorage(format!("{}, the data file can't be rebuilt", message));
+
+        // chunk id -> (chunk start, chunk end, value end) of the last version of each chunk.
+        let mut chunks: BTreeMap<u64, (u64, u64, u64)> = BTreeMap::new();
+        let mut last_trailer: Option<ChunkTrailer> = None;
+        let mut end = data_end;
+        while end > 0 {
+            let trailer = match Self::read_chunk_trailer(&mut storage_idx.data_file, end)? {
+                Some(trailer) => trailer,
+                None => {
+                    // a gap, that can go back to the start of the data file.
+                    end = Self::last_trailer_end(&mut storage_idx.data_file, end)?.unwrap_or(0);
+                    continue;
+                }
+            };
+            let start = end - trailer.chunk_size;
+            chunks.entry(trailer.chunk_id).or_insert((start, end, trailer.value_end));
+            if last_trailer.is_none() {
+                last_trailer = Some(trailer);
+            }
+            end = start;
+        }
+        let last_trailer = last_trailer.ok_or(Error::MetaDataError)?;
+        let build_options = last_trailer.build_options().ok_or(Error::MetaDataError)?;
+        let chunk_size = build_options.chunk_size();
+        let last_chunk_id = last_trailer.chunk_id;
+        if chunks.keys().copied().ne(0..=last_chunk_id) {
+            return Err(inconsistent(format!("the last chunk is {} but the data file has chunks {:?}", last_chunk_id, chunks.keys().collect::<Vec<_>>())));
+        }
+        if let Some((chunk_id, _chunk)) = chunks.iter().find(|(id, c)| **id < last_chunk_id && c.2 != (**id + 1) * chunk_size) {
+            return Err(inconsistent(format!("the chunk {} is not full", chunk_id)));
+        }
+
+        let now = MetaData::unix_time_now();
+        let meta_data = MetaData {
+            meta_data_version: META_DATA_VERSION,
+            key_format: KEY_FORMAT,
+            value_size: last_trailer.value_size,
+            num_values: last_trailer.value_end,
+            build_options,
+            bitmap_tag: last_trailer.bitmap_tag,
+            library_version: MetaData::current_library_version(),
+            created_at: now,
+            modified_at: now
+        };
+        Self::check_meta_data(&meta_data)?;
+        let mut offsets: Vec<u64> = chunks.values().map(|c| c.0).collect();
+        offsets.push(data_end);
+        Self::map_io_result(Self::write_rebuilt_storage_files(&mut storage_idx, &meta_data, &offsets, data_end))?;
+        drop(storage_idx);
+        Self::open(dir_path)
+    }
+
+    fn write_rebuilt_storage_files(storage_idx: &mut StorageIdx, meta_data: &MetaData, offsets: &[u64], data_end: u64) -> Result<(), std::io::Error> {
+        let extent: [u64; 2] = [offsets[offsets.len() - 2], data_end];
+        storage_idx.data_file.set_len(data_end)?;
+        storage_idx.offset_file.set_len(0)?;
+        storage_idx.offset_file.seek(SeekFrom::Start(0))?;
+        let offsets_buf: Vec<u8> = offsets.iter().flat_map(|offset| offset.to_ne_bytes()).collect();
+        storage_idx.offset_file.write_all(&offsets_buf)?;
+        storage_idx.meta_data_file.set_len(0)?;
+        storage_idx.meta_data_file.seek(SeekFrom::Start(0))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(meta_data))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(meta_data))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(&extent))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(&extent))?;
+        storage_idx.offset_file.sync_all()?;
+        storage_idx.meta_data_file.sync_all()
+    }
+
+    /// Return the end of the last valid trailer in the first `data_size` bytes of
+    /// `data_file`, searching backward if the data file ends with a torn chunk or
+    /// `data_size` is the end of a gap.
+    fn last_trailer_end(data_file: &mut fs::File, data_size: u64) -> Result<Option<u64>, Error> {
+        if data_size == 0 || Self::read_chunk_trailer(data_file, data_size)?.is_some() {
+            return Ok(Some(data_size).filter(|size| *size > 0));
+        }
+        let magic = TRAILER_MAGIC.to_ne_bytes();
+        let mut window_end = data_size;
+        let mut buf: Vec<u8> = Vec::new();
+        while window_end >= CHUNK_TRAILER_SIZE as u64 {
+            // windows overlap of `magic.len() - 1` bytes, so a magic across two windows is found.
+            let window_start = window_end.saturating_sub(WRITE_BUFFER_SIZE as u64);
+            buf.resize((window_end - window_start) as usize, 0);
+            Self::map_io_result(data_file.seek(SeekFrom::Start(window_start)))?;
+            Self::map_io_result(data_file.read_exact(&mut buf))?;
+            for i in (0..buf.len().saturating_sub(magic.len() - 1)).rev() {
+                let end = window_start + (i + magic.len()) as u64;
+                if buf[i..(i + magic.len())] == magic && Self::read_chunk_trailer(data_file, end)?.is_some() {
+                    return Ok(Some(end));
+                }
+            }
+            if window_start == 0 {
+                break;
+            }
+            window_end = window_start + (magic.len() - 1) as u64;
+        }
+        Ok(None)
+    }
+
+    /// Return the trailer that ends at `end` of `data_file`, `None` if there isn't a
+    /// valid trailer or the digest of the chunk table doesn't match.
+    pub(super) fn read_chunk_trailer<R: Read + Seek>(data_file: &mut R, end: u64) -> Result<Option<ChunkTrailer>, Error> {
+        if end < CHUNK_TRAILER_SIZE as u64 {
+            return Ok(None);
+        }
+        let mut buf: [u8; CHUNK_TRAILER_SIZE] = [0; CHUNK_TRAILER_SIZE];
+        Self::map_io_result(data_file.seek(SeekFrom::Start(end - CHUNK_TRAILER_SIZE as u64)))?;
+        Self::map_io_result(data_file.read_exact(&mut buf))?;
+        let trailer: ChunkTrailer = Self::copy_from_slice_u8(&buf);
+        let max_table_size = trailer.chunk_size.saturating_sub(CHUNK_TRAILER_SIZE as u64);
+        if trailer.magic != TRAILER_MAGIC || trailer.chunk_size > end || trailer.chunk_size < CHUNK_TRAILER_SIZE as u64
+            || trailer.table_size as u64 > max_table_size || trailer.build_options().is_none() {
+            return Ok(None);
+        }
+        let mut table: Vec<u8> = vec![0; trailer.table_size as usize];
+        Self::map_io_result(data_file.seek(SeekFrom::Start(end - trailer.chunk_size)))?;
+        Self::map_io_result(data_file.read_exact(&mut table))?;
+        if fnv1a(&table) != trailer.table_digest {
+            return Ok(None);
+        }
+        Ok(Some(trailer))
+    }
+}
@@ -0,0 +1,80 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Disk size
+//!
+//! `on_disk_size` returns the size of each file of a `BitmapIndex` in storage mode and
+//! the size of each flushed chunk in the data file, for capacity planning without
+//! depending on the names of the files of the index.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// The size in bytes of the files of a `BitmapIndex` in storage mode.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiskSize {
+    /// Size of the meta data file.
+    pub meta_data_file: u64,
+    /// Size of the offset file.
+    pub offset_file: u64,
+    /// Size of the data file.
+    pub data_file: u64,
+    /// Size of the user meta data file, 0 if user meta data have never been set.
+    pub user_meta_data_file: u64,
+    /// Size of each flushed chunk in the data file, the ith element is the size of the
+    /// chunk i (for a partial chunk the size of its last flush).
+    pub chunks: Vec<u64>
+}
+
+impl DiskSize {
+    /// Return the total size of the files.
+    pub fn total(&self) -> u64 {
+        self.meta_data_file + self.offset_file + self.data_file + self.user_meta_data_file
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the size of the files and of the flushed chunks of `BitmapIndex`, `None`
+    /// in memory mode. The space of chunks replaced by `rewrite_chunk` is included in
+    /// the size of the data file but not in the size of any chunk.
+    pub fn on_disk_size(&mut self) -> Result<Option<DiskSize>, Error> {
+        let storage_idx = match self.storage_idx.as_ref() {
+            Some(storage_idx) => storage_idx,
+            None => return Ok(None)
+        };
+        let file_size = |file: &fs::File| Self::map_io_result(file.metadata()).map(|m| m.len());
+        let user_meta_data_file = match fs::metadata(Self::user_meta_data_path(storage_idx)) {
+            Ok(m) => m.len(),
+            Err(err) if err.kind() == ErrorKind::NotFound => 0,
+            Err(err) => return Err(Error::FileError(err))
+        };
+        let mut disk_size = DiskSize {
+            meta_data_file: file_size(&storage_idx.meta_data_file)?,
+            offset_file: file_size(&storage_idx.offset_file)?,
+            data_file: file_size(&storage_idx.data_file)?,
+            user_meta_data_file,
+            chunks: Vec::new()
+        };
+        for chunk_id in 0..self.chunk_count() {
+            match self.chunk_disk_extent(chunk_id)? {
+                Some(extent) => disk_size.chunks.push(extent.end - extent.start),
+                None => break
+            }
+        }
+        Ok(Some(disk_size))
+    }
+}
@@ -0,0 +1,141 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # DynBitmapIndex
+//!
+//! A `BitmapIndex` with the `BitValue` type chosen at runtime, for query engines that
+//! discover the type of a column only at runtime. `DynBitmapIndex` is an enum with a
+//! variant for each integer type that implements `BitValue` and values are passed as
+//! `DynValue`.
+
+use std::any::Any;
+use std::ops::BitAnd;
+use std::path::Path;
+
+use super::{
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error
+};
+
+macro_rules! impl_dyn_bitmap_index {
+    ($(($variant:ident, $ty:ident)),+) => {
+        /// Type of the values indexed from a `DynBitmapIndex`.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum DynValueType {
+            $($variant),+
+        }
+
+        /// A value of a `DynBitmapIndex`.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum DynValue {
+            $($variant($ty)),+
+        }
+
+        $(
+            impl From<$ty> for DynValue {
+                fn from(value: $ty) -> Self {
+                    DynValue::$variant(value)
+                }
+            }
+        )+
+
+        impl DynValue {
+            /// Return the type of the value.
+            pub fn value_type(&self) -> DynValueType {
+                match self {
+                    $(DynValue::$variant(_) => DynValueType::$variant),+
+                }
+            }
+
+            /// Return a `DynValue` from `value` if it is one of the supported types.
+            pub fn from_any(value: &dyn Any) -> Option<DynValue> {
+                $(
+                    if let Some(value) = value.downcast_ref::<$ty>() {
+                        return Some(DynValue::$variant(*value));
+                    }
+                )+
+                None
+            }
+        }
+
+        /// `DynBitmapIndex` enum that wraps a `BitmapIndex` of each supported `BitValue`.
+        pub enum DynBitmapIndex<T: Bitmap>
+        where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+            $($variant(BitmapIndex<T, $ty>)),+
+        }
+
+        impl<T: Bitmap> DynBitmapIndex<T>
+        where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+            /// Return a new `DynBitmapIndex` in memory mode of `value_type` values.
+            pub fn new(value_type: DynValueType, build_options: BuildOptions) -> Result<Self, Error> {
+                match value_type {
+                    $(DynValueType::$variant => Ok(DynBitmapIndex::$variant(BitmapIndex::new(build_options)?))),+
+                }
+            }
+
+            /// Return a new `DynBitmapIndex` in storage mode of `value_type` values
+            /// (see `BitmapIndex::create`).
+            pub fn create(bitmap_index_path: &Path, value_type: DynValueType, build_options: BuildOptions) -> Result<Self, Error> {
+                match value_type {
+                    $(DynValueType::$variant => Ok(DynBitmapIndex::$variant(BitmapIndex::create(bitmap_index_path, build_options)?))),+
+                }
+            }
+
+            /// Open a `DynBitmapIndex` in storage mode previusly created with `value_type` values.
+            pub fn open(dir_path: &Path, value_type: DynValueType) -> Result<Self, Error> {
+                match value_type {
+                    $(DynValueType::$variant => Ok(DynBitmapIndex::$variant(BitmapIndex::open(dir_path)?))),+
+                }
+            }
+
+            /// Return the type of the indexed values.
+            pub fn value_type(&self) -> DynValueType {
+                match self {
+                    $(DynBitmapIndex::$variant(_) => DynValueType::$variant),+
+                }
+            }
+
+            /// Insert (in append) a `value` into the index. `ParametersError` is returned
+            /// if the type of `value` is not the type of the index.
+            pub fn push_value_dyn(&mut self, value: DynValue) -> Result<(), Error> {
+                match (self, value) {
+                    $((DynBitmapIndex::$variant(b_index), DynValue::$variant(value)) => b_index.push_value(value),)+
+                    _ => Err(Error::ParametersError)
+                }
+            }
+
+            /// Return all indexes of values equal to `value` (see `BitmapIndex::run_query`).
+            /// `ParametersError` is returned if the type of `value` is not the type of the index.
+            pub fn run_query_dyn(&mut self, value: DynValue, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+                match (self, value) {
+                    $((DynBitmapIndex::$variant(b_index), DynValue::$variant(value)) => b_index.run_query(value, start_index, end_index),)+
+                    _ => Err(Error::ParametersError)
+                }
+            }
+
+            /// Return the number of values pushed in the index.
+            pub fn num_values(&self) -> u64 {
+                match self {
+                    $(DynBitmapIndex::$variant(b_index) => b_index.num_values()),+
+                }
+            }
+
+            /// Serialize current bitmaps chunk (see `BitmapIndex::flush_chunk`).
+            pub fn flush_chunk(&mut self) -> Result<(), Error> {
+                match self {
+                    $(DynBitmapIndex::$variant(b_index) => b_index.flush_chunk()),+
+                }
+            }
+        }
+    };
+}
+
+impl_dyn_bitmap_index!(
+    (U8, u8), (U16, u16), (U32, u32), (U64, u64), (U128, u128),
+    (I8, i8), (I16, i16), (I32, i32), (I64, i64), (I128, i128)
+);
@@ -0,0 +1,111 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Enum values
+//!
+//! `bit_enum!` defines a fieldless enum and implements `BitEnum` on it, mapping each
+//! variant to its position in the declaration as a small integer `BitValue`:
+//!
+//! ```
+//! bitrush_index::bit_enum! {
+//!     #[derive(Debug)]
+//!     pub enum Color: u8 { Red, Green, Blue }
+//! }
+//! ```
+//!
+//! The names of the variants are persisted in the user meta data key
+//! `ENUM_MAPPING_KEY` (one name for each line) by `set_enum_mapping`, so an index can't
+//! be queried with an enum whose variants have been reordered, added or removed.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// User meta data key of the names of the variants of the indexed `BitEnum`.
+pub const ENUM_MAPPING_KEY: &str = "bitrush.enum_mapping";
+
+/// A fieldless enum indexed as the `BitValue` `Value`, usually implemented with `bit_enum!`.
+pub trait BitEnum: Copy {
+    /// The `BitValue` indexed in place of the variants.
+    type Value: BitValue;
+
+    /// Names of the variants, the ith variant is indexed as `i`.
+    const NAMES: &'static [&'static str];
+
+    /// Return the value of the variant.
+    fn to_value(self) -> Self::Value;
+
+    /// Return the variant of `value`, `None` if `value` isn't a variant.
+    fn from_value(value: Self::Value) -> Option<Self>;
+}
+
+/// Define a fieldless enum implementing `BitEnum`, see the module documentation.
+#[macro_export]
+macro_rules! bit_enum {
+    ($(#[$attr:meta])* $vis:vis enum $name:ident: $value:ident { $($variant:ident),+ $(,)? }) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        $vis enum $name {
+            $($variant),+
+        }
+
+        impl $crate::BitEnum for $name {
+            type Value = $value;
+
+            const NAMES: &'static [&'static str] = &[$(stringify!($variant)),+];
+
+            fn to_value(self) -> $value {
+                self as $value
+            }
+
+            fn from_value(value: $value) -> Option<Self> {
+                [$($name::$variant),+].iter().copied().find(|variant| *variant as $value == value)
+            }
+        }
+    };
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Save the names of the variants of `E` in user meta data. `MetaDataError` is
+    /// returned if a different mapping has already been saved.
+    pub fn set_enum_mapping<E: BitEnum<Value = U>>(&mut self) -> Result<(), Error> {
+        match self.user_meta_data(ENUM_MAPPING_KEY) {
+            Some(_) => self.check_enum_mapping::<E>(),
+            None => self.set_user_meta_data(ENUM_MAPPING_KEY, E::NAMES.join("\n").as_bytes())
+        }
+    }
+
+    /// Return `MetaDataError` if the mapping saved in user meta data isn't the one of
+    /// `E` or if no mapping has been saved.
+    pub fn check_enum_mapping<E: BitEnum<Value = U>>(&self) -> Result<(), Error> {
+        match self.user_meta_data(ENUM_MAPPING_KEY) {
+            Some(mapping) if mapping == E::NAMES.join("\n").as_bytes() => Ok(()),
+            _ => Err(Error::MetaDataError)
+        }
+    }
+
+    /// Insert (in append) the value of `variant` into the index, see `push_value`.
+    pub fn push_variant<E: BitEnum<Value = U>>(&mut self, variant: E) -> Result<(), Error> {
+        self.push_value(variant.to_value())
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values equal to `variant`,
+    /// after checking the enum mapping with `check_enum_mapping`. The parameters
+    /// `start_index` and `end_index` are optional and if specified define the range where
+    /// query is runned.
+    pub fn run_query_variant<E: BitEnum<Value = U>>(&mut self, variant: E, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        self.check_enum_mapping::<E>()?;
+        self.run_query(variant.to_value(), start_index, end_index)
+    }
+}
@@ -0,0 +1,75 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Flush events
+//!
+//! A `BitmapIndex` in storage mode can notify each durable chunk write (a flush of the
+//! current chunk or a `rewrite_chunk`) with a `FlushEvent`, to a registered callback
+//! or to a channel, so replication and cache invalidation don't need to poll the meta
+//! data file. The listener is called after the meta data of the write are written.
+
+use std::ops::{BitAnd, Range, Shr};
+use std::sync::mpsc::{self, Receiver};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    TransmuteToUsize
+};
+
+/// A durable write of a chunk of a `BitmapIndex` in storage mode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlushEvent {
+    /// The id of the written chunk.
+    pub chunk_id: u64,
+    /// The indexes of the values of the written chunk.
+    pub value_range: Range<u64>,
+    /// The number of bytes of the chunk written in the data file.
+    pub bytes_written: u64
+}
+
+/// A callback called with every `FlushEvent` of a `BitmapIndex`.
+pub(super) type FlushListener = Box<dyn FnMut(&FlushEvent) + Send + Sync>;
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Register `listener` to be called after each durable chunk write, replacing the
+    /// previous listener.
+    pub fn set_flush_listener(&mut self, listener: impl FnMut(&FlushEvent) + Send + Sync + 'static) {
+        self.flush_listener = Some(Box::new(listener));
+    }
+
+    /// Remove the flush listener, if any.
+    pub fn remove_flush_listener(&mut self) {
+        self.flush_listener = None;
+    }
+
+    /// Register a listener that sends each `FlushEvent` to the returned `Receiver`,
+    /// replacing the previous listener. Events are dropped after the `Receiver` is dropped.
+    pub fn flush_events(&mut self) -> Receiver<FlushEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.set_flush_listener(move |event: &FlushEvent| {
+            let _err = sender.send(event.clone());
+        });
+        receiver
+    }
+
+    /// Call the flush listener, if any, for the write of `bytes_written` bytes of the
+    /// chunk `chunk_id`.
+    pub(super) fn notify_flush(&mut self, chunk_id: u64, bytes_written: u64) {
+        if let Some(listener) = self.flush_listener.as_mut() {
+            let start_index = chunk_id * self.chunk_size;
+            let end_index = self.num_values.min(start_index + self.chunk_size);
+            listener(&FlushEvent {
+                chunk_id,
+                value_range: start_index..end_index,
+                bytes_written
+            });
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Frozen index
+//!
+//! `freeze` consumes a `BitmapIndex` and returns a `FrozenIndex`: an immutable index
+//! with only `&self` queries, that is `Send + Sync` when the bitmap is, so it can be
+//! cached in an `Arc` and shared between request handlers. In storage mode the current
+//! chunk is flushed and the offsets of all chunks are read when the index is frozen,
+//! then bitmaps are read with positioned reads, so queries don't share a file cursor.
+//! On platforms without positioned reads (neither unix nor windows) the seek and the
+//! read of a query hold a lock on the data file instead.
+
+use std::fs;
+use std::io::{Error as IoError, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::{BitAnd, Shr};
+#[cfg(not(any(unix, windows)))]
+use std::sync::{Mutex, PoisonError};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BlockInfo,
+    ChunkArena,
+    Error,
+    TransmuteToUsize
+};
+
+/// `FrozenIndex` struct that represents an immutable `BitmapIndex`.
+pub struct FrozenIndex<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    num_values: u64,
+    chunk_size: u64,
+    block_info: BlockInfo,
+    chunks: Vec<Vec<T>>,
+    arena_chunks: Vec<ChunkArena>,
+    bitmaps: Vec<T>,
+    storage: Option<FrozenStorage>,
+
+    _marker: std::marker::PhantomData<U>
+}
+
+/// The data file of a frozen `BitmapIndex` in storage mode and the start offset of
+/// each chunk.
+struct FrozenStorage {
+    data_file: fs::File,
+    #[cfg(not(any(unix, windows)))]
+    data_file_lock: Mutex<()>,
+    chunk_offsets: Vec<u64>
+}
+
+/// A `Read + Seek` view of the data file of a `FrozenStorage` that reads with positioned
+/// reads, so the cursor of the file is never moved.
+struct PositionedReader<'a> {
+    storage: &'a FrozenStorage,
+    position: u64
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        #[cfg(unix)]
+        let n = std::os::unix::fs::FileExt::read_at(&self.storage.data_file, buf, self.position)?;
+        #[cfg(windows)]
+        let n = std::os::windows::fs::FileExt::seek_read(&self.storage.data_file, buf, self.position)?;
+        #[cfg(not(any(unix, windows)))]
+        let n = {
+            let _lock = self.storage.data_file_lock.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut file = &self.storage.data_file;
+            file.seek(SeekFrom::Start(self.position))?;
+            file.read(buf)?
+        };
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for PositionedReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IoError> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.storage.data_file.metadata()?.len().checked_add_signed(offset)
+        };
+        self.position = position.ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+        Ok(self.position)
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Consume `BitmapIndex` and return a `FrozenIndex` with the same values. In storage
+    /// mode the current chunk is flushed if there are unflushed values. Error occur if
+    /// `BitmapIndex` has chunks spilled to disk (see `use_spill_to_disk`).
+    pub fn freeze(mut self) -> Result<FrozenIndex<T, U>, Error> {
+        if self.spilled_chunks() > 0 {
+            return Err(Error::ParametersError);
+        }
+        let storage = match self.storage_idx.is_some() {
+            true => {
+                if self.is_dirty() {
+                    self.write_chunk()?;
+                }
+                let num_chunks = self.num_values.div_ceil(self.chunk_size) as usize;
+                let mut storage_idx = self.storage_idx.take().unwrap();
+                let chunk_offsets = Self::map_io_result(Self::read_offsets(&mut storage_idx, 0, num_chunks))?;
+                Some(FrozenStorage {
+                    data_file: storage_idx.data_file,
+                    #[cfg(not(any(unix, windows)))]
+                    data_file_lock: Mutex::new(()),
+                    chunk_offsets
+                })
+            },
+            false => None
+        };
+        Ok(FrozenIndex {
+            num_values: self.num_values,
+            chunk_size: self.chunk_size,
+            block_info: self.block_info,
+            chunks: self.chunks.take().unwrap_or_default(),
+            arena_chunks: self.arena_chunks.take().unwrap_or_default(),
+            bitmaps: if storage.is_some() { Vec::new() } else { self.bitmaps },
+            storage,
+            _marker: std::marker::PhantomData
+        })
+    }
+}
+
+impl<T: Bitmap, U: BitValue> FrozenIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the number of values of `FrozenIndex`.
+    pub fn num_values(&self) -> u64 {
+        self.num_values
+    }
+
+    /// Return the number of values of each chunk.
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values equal to `value`. The
+    /// parameters `start_index` and `end_index` are optional and if specified define
+    /// the range where query is runned.
+    pub fn run_query(&self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let query_i_bitmaps: Vec<usize> = BitmapIndex::<T, U>::get_query_i_bitmaps(&self.block_info, value);
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let chunk_size = self.chunk_size;
+        let mut indexes: Vec<u64> = Vec::new();
+        self.for_each_chunk(&query_i_bitmaps, start_index, end_index, |chunk_id, query_bitmaps| {
+            BitmapIndex::<T, U>::push_indexes(query_bitmaps, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+        })?;
+        Ok(indexes)
+    }
+
+    /// Return the number of values equal to `value`, see `run_query`.
+    pub fn run_query_count(&self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<u64, Error> {
+        let query_i_bitmaps: Vec<usize> = BitmapIndex::<T, U>::get_query_i_bitmaps(&self.block_info, value);
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let chunk_size = self.chunk_size;
+        let mut count: u64 = 0;
+        self.for_each_chunk(&query_i_bitmaps, start_index, end_index, |chunk_id, query_bitmaps| {
+            count += BitmapIndex::<T, U>::count_indexes(query_bitmaps, chunk_id, chunk_size, start_index, end_index);
+        })?;
+        Ok(count)
+    }
+
+    /// Call `f` with the bitmaps `query_i_bitmaps` of every chunk with values in
+    /// `[start_index, end_index]`, in the same order of `BitmapIndex::for_each_chunk`.
+    fn for_each_chunk(&self, query_i_bitmaps: &[usize], start_index: u64, end_index: u64, mut f: impl FnMut(u64, &[&T])) -> Result<(), Error> {
+        let in_range = |chunk_id: u64| BitmapIndex::<T, U>::chunk_in_range(chunk_id, self.chunk_size, start_index, end_index);
+        if let Some(storage) = self.storage.as_ref() {
+            let mut reader = PositionedReader { storage, position: 0 };
+            for (chunk_id, chunk_offset) in storage.chunk_offsets.iter().enumerate() {
+                if in_range(chunk_id as u64) {
+                    let query_bitmaps = BitmapIndex::<T, U>::read_query_bitmaps(&mut reader, *chunk_offset, query_i_bitmaps)?;
+                    let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                    f(chunk_id as u64, &query_bitmaps_ref);
+                }
+            }
+            return Ok(());
+        }
+        let mut chunk_id: u64 = 0;
+        for bitmaps in self.chunks.iter() {
+            if in_range(chunk_id) {
+                let query_bitmaps: Vec<&T> = query_i_bitmaps.iter().map(|i_bitmap| &bitmaps[*i_bitmap]).collect();
+                f(chunk_id, &query_bitmaps);
+            }
+            chunk_id += 1;
+        }
+        for arena in self.arena_chunks.iter() {
+            if in_range(chunk_id) {
+                let query_bitmaps = BitmapIndex::<T, U>::arena_query_bitmaps(arena, query_i_bitmaps)?;
+                let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                f(chunk_id, &query_bitmaps_ref);
+            }
+            chunk_id += 1;
+        }
+        if in_range(chunk_id) {
+            let query_bitmaps: Vec<&T> = query_i_bitmaps.iter().map(|i_bitmap| &self.bitmaps[*i_bitmap]).collect();
+            f(chunk_id, &query_bitmaps);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Geo queries
+//!
+//! `GeoGrid` encodes a (latitude, longitude) point in the `u64` value of its cell, so a
+//! `BitmapIndex<T, u64>` of cells can be used for coarse spatial filtering. The cell
+//! value has the bits of the geohash of the point: `precision` bits of the longitude and
+//! of the latitude interleaved starting from the longitude. A bounding box query is
+//! expanded in the cells that cover the box and the cells are probed with
+//! `run_query_grouped`, so points in the border cells but outside the box are returned
+//! too and must be checked downstream.
+
+use std::ops::BitAnd;
+
+use super::{
+    Bitmap,
+    BitmapIndex,
+    Error
+};
+
+/// Max number of bits of each coordinate of a `GeoGrid` cell.
+const GEO_MAX_PRECISION: u32 = 32;
+
+/// `GeoGrid` struct that defines the size of the cells: the longitude range and the
+/// latitude range are split in `2^precision` intervals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GeoGrid {
+    precision: u32
+}
+
+impl GeoGrid {
+    /// Return a new `GeoGrid` with `precision` bits for each coordinate, `ParametersError`
+    /// is returned if `precision` is not in `[1, 32]`.
+    pub fn new(precision: u32) -> Result<Self, Error> {
+        if precision == 0 || precision > GEO_MAX_PRECISION {
+            return Err(Error::ParametersError);
+        }
+        Ok(GeoGrid { precision })
+    }
+
+    /// Return the number of bits of each coordinate.
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    /// Return the value of the cell that contains the point (`lat`, `lon`). Coordinates
+    /// out of range are clamped to [-90, 90] and [-180, 180].
+    pub fn cell(&self, lat: f64, lon: f64) -> u64 {
+        Self::interleave(self.quantize(lon, 180.0), self.quantize(lat, 90.0))
+    }
+
+    /// Return the sorted values of the cells that cover the bounding box from
+    /// (`min_lat`, `min_lon`) to (`max_lat`, `max_lon`). `ParametersError` is returned if
+    /// a min coordinate is greater than the max one.
+    pub fn cover(&self, min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Result<Vec<u64>, Error> {
+        if !(min_lat <= max_lat && min_lon <= max_lon) {
+            return Err(Error::ParametersError);
+        }
+        let (min_lon_q, max_lon_q) = (self.quantize(min_lon, 180.0), self.quantize(max_lon, 180.0));
+        let (min_lat_q, max_lat_q) = (self.quantize(min_lat, 90.0), self.quantize(max_lat, 90.0));
+        let mut cells: Vec<u64> = Vec::with_capacity(((max_lon_q - min_lon_q + 1) * (max_lat_q - min_lat_q + 1)) as usize);
+        for lon_q in min_lon_q..=max_lon_q {
+            for lat_q in min_lat_q..=max_lat_q {
+                cells.push(Self::interleave(lon_q, lat_q));
+            }
+        }
+        cells.sort_unstable();
+        Ok(cells)
+    }
+
+    /// Return the interval of `coordinate` in `[-max, max]`.
+    fn quantize(&self, coordinate: f64, max: f64) -> u64 {
+        let num_intervals = (1u64 << self.precision) as f64;
+        let interval = ((coordinate.clamp(-max, max) + max) / (2.0 * max) * num_intervals) as u64;
+        interval.min((1 << self.precision) - 1)
+    }
+
+    /// Interleave the bits of `lon_q` and `lat_q`, the bits of `lon_q` are the high bit
+    /// of each pair.
+    fn interleave(lon_q: u64, lat_q: u64) -> u64 {
+        let spread = |mut x: u64| -> u64 {
+            x &= 0xffff_ffff;
+            x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+            x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+            x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+            x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+            (x | (x << 1)) & 0x5555_5555_5555_5555
+        };
+        (spread(lon_q) << 1) | spread(lat_q)
+    }
+}
+
+impl<T: Bitmap> BitmapIndex<T, u64>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a sorted `Vec<u64>` that contains all indexes of the cells of `grid` that
+    /// cover the bounding box from (`min_lat`, `min_lon`) to (`max_lat`, `max_lon`). The
+    /// index must contain cells of `grid` (see `GeoGrid::cell`).
+    pub fn run_query_geo_box(&mut self, grid: &GeoGrid, min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Result<Vec<u64>, Error> {
+        let cells = grid.cover(min_lat, min_lon, max_lat, max_lon)?;
+        let cells_indexes = self.run_query_grouped(&cells)?;
+        let mut indexes: Vec<u64> = cells_indexes.into_iter().flat_map(|(_cell, indexes)| indexes).collect();
+        indexes.sort_unstable();
+        Ok(indexes)
+    }
+}
@@ -0,0 +1,60 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Grouped query
+//!
+//! `run_query_grouped` returns the indexes of many probe values reading each chunk only
+//! once: the query bitmaps of all values are read together and every value is resolved
+//! on the bitmaps of the chunk before moving to the next one.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return, for each value of `values` in the same order, the value with all indexes
+    /// of values pushed in `BitmapIndex` equal to it. The result is the same of running
+    /// `run_query` on each value but every chunk is read only once.
+    pub fn run_query_grouped(&mut self, values: &[U]) -> Result<Vec<(U, Vec<u64>)>, Error> {
+        let values_i_bitmaps: Vec<Vec<usize>> = values.iter()
+            .map(|value| Self::get_query_i_bitmaps(&self.block_info, *value)).collect();
+        let values_indexes = self.run_query_i_bitmaps_grouped(&values_i_bitmaps, 0, self.num_values)?;
+        Ok(values.iter().copied().zip(values_indexes).collect())
+    }
+
+    /// Return, for each query bitmaps of `values_i_bitmaps`, the indexes in
+    /// `[start_index, end_index]` set in all its bitmaps reading each chunk only once.
+    pub(super) fn run_query_i_bitmaps_grouped(&mut self, values_i_bitmaps: &[Vec<usize>], start_index: u64, end_index: u64) -> Result<Vec<Vec<u64>>, Error> {
+        let mut query_i_bitmaps: Vec<usize> = values_i_bitmaps.iter().flatten().copied().collect();
+        query_i_bitmaps.sort_unstable();
+        query_i_bitmaps.dedup();
+        // Position of the bitmaps of each value in the bitmaps read for each chunk.
+        let values_positions: Vec<Vec<usize>> = values_i_bitmaps.iter().map(|i_bitmaps| {
+            i_bitmaps.iter().map(|i_bitmap| query_i_bitmaps.binary_search(i_bitmap).unwrap_or_default()).collect()
+        }).collect();
+
+        let chunk_size = self.chunk_size;
+        let mut results: Vec<Vec<u64>> = vec![Vec::new(); values_i_bitmaps.len()];
+        let f = |chunk_id: u64, chunk_bitmaps: &[&T]| {
+            for (positions, result) in values_positions.iter().zip(results.iter_mut()) {
+                let query_bitmaps: Vec<&T> = positions.iter().map(|i| chunk_bitmaps[*i]).collect();
+                Self::push_indexes(&query_bitmaps, chunk_id, chunk_size, start_index, end_index, result);
+            }
+        };
+        if !values_i_bitmaps.is_empty() {
+            self.for_each_chunk_exact(&query_i_bitmaps, start_index, end_index, f)?;
+        }
+        Ok(results)
+    }
+}
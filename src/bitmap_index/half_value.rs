@@ -0,0 +1,143 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Half-precision values
+//!
+//! `BitValue` wrappers of the [`half`] `f16` and `bf16` floats, available with the
+//! `half` feature. Floats are stored with an order-preserving encoding of their 16
+//! bits: the sign bit is flipped for positive floats and all bits are flipped for
+//! negative ones, so bins and approximate range queries follow the order of the
+//! floats. Note that `0.0` and `-0.0` are different values for the index.
+//!
+//! Exact float equality is rarely useful, `run_query_approx` returns the indexes of the
+//! floats within an epsilon of a value: the interval is mapped to the first and the last
+//! representable float inside it and the range is probed with `run_query_range_approx`.
+//!
+//! [`half`]: https://docs.rs/half
+
+use std::fmt;
+use std::ops::{BitAnd, BitOr, Shr};
+
+use half::{bf16, f16};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    ChunkSize,
+    Error,
+    TransmuteToUsize
+};
+
+macro_rules! impl_half_value {
+    ($name:ident, $float:ident) => {
+        #[doc = concat!("A `", stringify!($float), "` value that can be indexed by `BitmapIndex`.")]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(u16);
+
+        impl $name {
+            /// Return the value of the float `value`.
+            pub fn new(value: $float) -> Self {
+                let bits = value.to_bits();
+                if bits & 0x8000 != 0 { $name(!bits) } else { $name(bits | 0x8000) }
+            }
+
+            /// Return the float of the value.
+            pub fn value(self) -> $float {
+                let bits = if self.0 & 0x8000 != 0 { self.0 & 0x7fff } else { !self.0 };
+                $float::from_bits(bits)
+            }
+
+            /// Return the `BuildOptions` with a single block of 16 bits (one bitmap
+            /// for each possible value).
+            pub fn build_options(chunk_size: ChunkSize) -> BuildOptions {
+                BuildOptions::new(16, chunk_size)
+            }
+        }
+
+        impl From<$float> for $name {
+            fn from(value: $float) -> Self {
+                $name::new(value)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.value(), f)
+            }
+        }
+
+        impl BitAnd for $name {
+            type Output = $name;
+
+            fn bitand(self, rhs: $name) -> $name {
+                $name(self.0 & rhs.0)
+            }
+        }
+
+        impl BitOr for $name {
+            type Output = $name;
+
+            fn bitor(self, rhs: $name) -> $name {
+                $name(self.0 | rhs.0)
+            }
+        }
+
+        impl Shr<usize> for $name {
+            type Output = u16;
+
+            fn shr(self, rhs: usize) -> u16 {
+                self.0 >> rhs
+            }
+        }
+
+        impl TransmuteToUsize for $name {
+            fn transmute_to_usize(self) -> usize {
+                self.0 as usize
+            }
+        }
+
+        impl BitValue for $name {}
+
+        impl<T: Bitmap> BitmapIndex<T, $name>
+        where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+            /// Return a sorted `Vec<u64>` that contains all indexes of floats `v` with
+            /// `|v - value| <= epsilon`, `0.0` and `-0.0` included. With a `bin_shift` the
+            /// indexes of the first and the last bin are candidates, see
+            /// `run_query_range_approx`. `ParametersError` is returned if `value` or
+            /// `epsilon` is NaN or if `epsilon` is negative. The parameters `start_index` and
+            /// `end_index` are optional and if specified define the range where query is runned.
+            pub fn run_query_approx(&mut self, value: $float, epsilon: $float, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+                if value.is_nan() || epsilon.is_nan() || epsilon.to_f64() < 0.0 {
+                    return Err(Error::ParametersError);
+                }
+                let (low_bound, high_bound) = (value.to_f64() - epsilon.to_f64(), value.to_f64() + epsilon.to_f64());
+                // The encoding follows the order of the floats, so the nearest floats of
+                // the bounds are moved to the first and the last float inside them (NaNs
+                // around the infinities never compare as inside).
+                let mut low = $name::new($float::from_f64(low_bound));
+                while low.value().to_f64() < low_bound {
+                    low = $name(low.0 + 1);
+                }
+                while low.0 > 0 && $name(low.0 - 1).value().to_f64() >= low_bound {
+                    low = $name(low.0 - 1);
+                }
+                let mut high = $name::new($float::from_f64(high_bound));
+                while high.value().to_f64() > high_bound {
+                    high = $name(high.0 - 1);
+                }
+                while high.0 < u16::MAX && $name(high.0 + 1).value().to_f64() <= high_bound {
+                    high = $name(high.0 + 1);
+                }
+                self.run_query_range_approx(low, high, start_index, end_index)
+            }
+        }
+    };
+}
+
+impl_half_value!(F16Value, f16);
+impl_half_value!(Bf16Value, bf16);
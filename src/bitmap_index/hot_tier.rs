@@ -0,0 +1,172 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Hot tier
+//!
+//! With `use_hot_tier` a `BitmapIndex` in storage mode keeps resident in memory the
+//! bitmaps of its most recent full chunks, bounded by a `HotTier` (a number of chunks
+//! or a size in bytes). Queries read the resident chunks without I/O, older chunks are
+//! read from the data file as usual. When a chunk is full it enters the hot tier and
+//! the oldest resident chunks are evicted, a rewritten chunk (see `rewrite_chunk`) is
+//! replaced in place.
+
+use std::collections::VecDeque;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// Bound of the chunks kept resident in memory by `BitmapIndex::use_hot_tier`.
+#[derive(Clone, Copy, Debug)]
+pub enum HotTier {
+    /// Keep at most this number of the most recent full chunks.
+    Chunks(usize),
+    /// Keep the most recent full chunks whose bitmaps take at most this number of bytes.
+    Bytes(usize)
+}
+
+/// `HotChunks` struct with the bitmaps of the full chunks from `first_chunk_id` on.
+pub(super) struct HotChunks<T> {
+    hot_tier: HotTier,
+    first_chunk_id: u64,
+    chunks: VecDeque<(Vec<T>, usize)>,
+    size: usize
+}
+
+impl<T: Bitmap> HotChunks<T>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    fn new(hot_tier: HotTier, first_chunk_id: u64) -> Self {
+        HotChunks {
+            hot_tier,
+            first_chunk_id,
+            chunks: VecDeque::new(),
+            size: 0
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        match self.hot_tier {
+            HotTier::Chunks(max_chunks) => self.chunks.len() > max_chunks,
+            HotTier::Bytes(max_bytes) => self.size > max_bytes
+        }
+    }
+
+    /// Evict the oldest chunks until the hot tier is within its bound.
+    fn evict(&mut self) {
+        while self.is_over() {
+            if let Some((_bitmaps, size)) = self.chunks.pop_front() {
+                self.size -= size;
+            }
+            self.first_chunk_id += 1;
+        }
+    }
+
+    /// Add the bitmaps of the chunk `chunk_id`, if it is the next full chunk, or replace
+    /// them if the chunk is resident, then evict the oldest chunks.
+    fn set_chunk(&mut self, chunk_id: u64, bitmaps: &[T]) {
+        let size = bitmaps.iter().map(|b| b.size()).sum();
+        let next_chunk_id = self.first_chunk_id + self.chunks.len() as u64;
+        if chunk_id == next_chunk_id {
+            self.chunks.push_back((bitmaps.to_vec(), size));
+        } else if chunk_id >= self.first_chunk_id && chunk_id < next_chunk_id {
+            let chunk = &mut self.chunks[(chunk_id - self.first_chunk_id) as usize];
+            self.size -= chunk.1;
+            *chunk = (bitmaps.to_vec(), size);
+        } else {
+            return;
+        }
+        self.size += size;
+        self.evict();
+    }
+
+    /// Return the id of the first resident chunk, all chunks after it are resident.
+    pub(super) fn first_chunk_id(&self) -> u64 {
+        self.first_chunk_id
+    }
+
+    /// Return the resident chunks with their id.
+    pub(super) fn chunks(&self) -> impl Iterator<Item=(u64, &[T])> {
+        self.chunks.iter().zip(self.first_chunk_id..).map(|((bitmaps, _size), chunk_id)| (chunk_id, bitmaps.as_slice()))
+    }
+
+    /// Return the bitmaps of the chunk `chunk_id`, `None` if it isn't resident.
+    pub(super) fn chunk(&self, chunk_id: u64) -> Option<&[T]> {
+        let i = chunk_id.checked_sub(self.first_chunk_id)?;
+        self.chunks.get(i as usize).map(|(bitmaps, _size)| bitmaps.as_slice())
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Keep resident in memory the most recent full chunks within `hot_tier`, replacing
+    /// the previous hot tier. The resident chunks are read once from the data file.
+    /// Error occur if `BitmapIndex` is opened in memory mode, where all chunks are
+    /// already in memory.
+    pub fn use_hot_tier(&mut self, hot_tier: HotTier) -> Result<(), Error> {
+        let num_bitmaps = self.bitmaps.len();
+        let num_chunks = Self::get_chunk_id(self.num_values, self.chunk_size);
+        let storage_idx = self.storage_idx.as_mut().ok_or(Error::ParametersError)?;
+        let mut hot_chunks: HotChunks<T> = HotChunks::new(hot_tier, num_chunks);
+        // Chunks are read from the most recent, until one doesn't fit the hot tier.
+        let mut resident: Vec<(Vec<T>, usize)> = Vec::new();
+        let mut size: usize = 0;
+        for chunk_id in (0..num_chunks).rev() {
+            if let HotTier::Chunks(max_chunks) = hot_tier {
+                if resident.len() >= max_chunks {
+                    break;
+                }
+            }
+            let chunk_offset = Self::map_io_result(Self::read_offsets(storage_idx, chunk_id, 1))?[0];
+            let chunk_end = Self::read_chunk_end(&mut storage_idx.data_file, chunk_offset, num_bitmaps)?;
+            let mut bitmaps: Vec<T> = vec![T::new(); num_bitmaps];
+            Self::read_chunk_bitmaps(&mut storage_idx.data_file, (chunk_offset, chunk_end), &mut bitmaps)?;
+            storage_idx.stats.bytes_read += chunk_end - chunk_offset;
+            let chunk_size: usize = bitmaps.iter().map(|b| b.size()).sum();
+            if let HotTier::Bytes(max_bytes) = hot_tier {
+                if size + chunk_size > max_bytes {
+                    break;
+                }
+            }
+            size += chunk_size;
+            resident.push((bitmaps, chunk_size));
+        }
+        hot_chunks.first_chunk_id -= resident.len() as u64;
+        hot_chunks.size = size;
+        hot_chunks.chunks = resident.into_iter().rev().collect();
+        self.hot_chunks = Some(hot_chunks);
+        Ok(())
+    }
+
+    /// Drop the resident chunks, so all full chunks are read from the data file.
+    pub fn remove_hot_tier(&mut self) {
+        self.hot_chunks = None;
+    }
+
+    /// Return the number of resident chunks, 0 if the hot tier is not used.
+    pub fn num_hot_chunks(&self) -> usize {
+        self.hot_chunks.as_ref().map_or(0, |hot_chunks| hot_chunks.chunks.len())
+    }
+
+    /// Return the size in bytes of the bitmaps of the resident chunks, 0 if the hot
+    /// tier is not used.
+    pub fn hot_tier_size(&self) -> usize {
+        self.hot_chunks.as_ref().map_or(0, |hot_chunks| hot_chunks.size)
+    }
+
+    /// Set the bitmaps of the full chunk `chunk_id` in the hot tier, if it is used.
+    pub(super) fn update_hot_tier(hot_chunks: &mut Option<HotChunks<T>>, chunk_id: u64, bitmaps: &[T]) {
+        if let Some(hot_chunks) = hot_chunks.as_mut() {
+            hot_chunks.set_chunk(chunk_id, bitmaps);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # IndexWriter
+//!
+//! An ingestion sink for a `BitmapIndex` in storage mode. `IndexWriter` consumes values
+//! from an iterator or a channel and flushes the current chunk following a
+//! `FlushPolicy`, so every flush is also a checkpoint of the index.
+//!
+//! Multiple producer threads can feed a single `IndexWriter` through clones of an
+//! `IndexWriterHandle`: every batch of values is sent with the global position (the
+//! index in the `BitmapIndex`) of its first value, and `consume_ordered` inserts the
+//! batches in position order, so the global ordering doesn't depend on the
+//! scheduling of the producers.
+
+use std::collections::BTreeMap;
+use std::io::{Error as IoError, ErrorKind};
+use std::ops::{BitAnd, Shr};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::Duration;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// `FlushPolicy` defines when an `IndexWriter` flushes the current chunk. A chunk
+/// is always flushed when is full, optionally it can be flushed also when the
+/// serialized size of in memory bitmaps exceeds `max_memory_bytes` or when
+/// `max_interval` time is passed from the last flush. Each flush of a partial chunk
+/// writes all of it, but over its older versions: the data file doesn't grow with the
+/// number of flushes.
+#[derive(Clone, Debug, Default)]
+pub struct FlushPolicy {
+    max_memory_bytes: Option<usize>,
+    max_interval: Option<Duration>
+}
+
+impl FlushPolicy {
+    /// Return a `FlushPolicy` that flushes only full chunks.
+    pub fn chunk_full() -> Self {
+        FlushPolicy::default()
+    }
+
+    /// Flush also when the serialized size of in memory bitmaps (see
+    /// `BitmapIndex::memory_bitmaps_size`) exceeds `max_memory_bytes`.
+    pub fn with_max_memory_bytes(mut self, max_memory_bytes: usize) -> Self {
+        self.max_memory_bytes = Some(max_memory_bytes);
+        self
+    }
+
+    /// Flush also when `max_interval` time is passed from the last flush.
+    pub fn with_max_interval(mut self, max_interval: Duration) -> Self {
+        self.max_interval = Some(max_interval);
+        self
+    }
+
+    /// Return `true` if in memory bitmaps of `memory_bytes` or `elapsed` time from the
+    /// last flush require a flush.
+    pub(super) fn requires_flush(&self, memory_bytes: usize, elapsed: Duration) -> bool {
+        self.max_interval.is_some_and(|max_interval| elapsed >= max_interval)
+            || self.max_memory_bytes.is_some_and(|max_memory_bytes| memory_bytes >= max_memory_bytes)
+    }
+}
+
+/// `IndexWriterHandle` is a clonable handle that sends values from multiple threads to
+/// an `IndexWriter` through an `IndexWriterReceiver`.
+pub struct IndexWriterHandle<U> {
+    sender: SyncSender<(u64, Vec<U>)>
+}
+
+/// `IndexWriterReceiver` receives the values sent from the clones of an `IndexWriterHandle`,
+/// see `IndexWriter::consume_ordered`.
+pub struct IndexWriterReceiver<U> {
+    receiver: Receiver<(u64, Vec<U>)>
+}
+
+impl<U> IndexWriterHandle<U> {
+    /// Return a new `IndexWriterHandle` and its `IndexWriterReceiver`. At most `bound`
+    /// batches are queued, then producers block until the writer receives them.
+    pub fn channel(bound: usize) -> (Self, IndexWriterReceiver<U>) {
+        let (sender, receiver) = mpsc::sync_channel(bound);
+        (IndexWriterHandle { sender }, IndexWriterReceiver { receiver })
+    }
+
+    /// Send `value` that must be inserted in the global position `position`.
+    pub fn push_value_at(&self, position: u64, value: U) -> Result<(), Error> {
+        self.push_values_at(position, vec![value])
+    }
+
+    /// Send `values` that must be inserted starting from the global position `position`.
+    /// `FileError` (with `BrokenPipe` kind) is returned if the receiver has been dropped.
+    pub fn push_values_at(&self, position: u64, values: Vec<U>) -> Result<(), Error> {
+        match self.sender.send((position, values)) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(Error::FileError(IoError::new(ErrorKind::BrokenPipe, "index writer receiver dropped")))
+        }
+    }
+}
+
+impl<U> Clone for IndexWriterHandle<U> {
+    fn clone(&self) -> Self {
+        IndexWriterHandle {
+            sender: self.sender.clone()
+        }
+    }
+}
+
+/// `IndexWriter` struct that owns a `BitmapIndex` in storage mode.
+pub struct IndexWriter<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    index: BitmapIndex<T, U>
+}
+
+impl<T: Bitmap, U: BitValue> IndexWriter<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `IndexWriter` that sets `flush_policy` as the flush policy of `index`
+    /// (see `BitmapIndex::set_flush_policy`). Error occur if `index` is opened in memory mode.
+    pub fn new(mut index: BitmapIndex<T, U>, flush_policy: FlushPolicy) -> Result<Self, Error> {
+        index.set_flush_policy(flush_policy)?;
+        Ok(IndexWriter {
+            index
+        })
+    }
+
+    /// Return a reference to the wrapped `BitmapIndex`.
+    pub fn index(&self) -> &BitmapIndex<T, U> {
+        &self.index
+    }
+
+    /// Return a mutable reference to the wrapped `BitmapIndex`.
+    pub fn index_mut(&mut self) -> &mut BitmapIndex<T, U> {
+        &mut self.index
+    }
+
+    /// Return the number of values that have not been flushed yet.
+    pub fn unflushed_values(&self) -> u64 {
+        self.index.unflushed_values()
+    }
+
+    /// Insert (in append) a `value` and flush the current chunk if required from
+    /// the flush policy.
+    pub fn push_value(&mut self, value: U) -> Result<(), Error> {
+        self.index.push_value(value)
+    }
+
+    /// Insert (in append) all values of `values` and return the number of values
+    /// inserted.
+    pub fn extend<I: IntoIterator<Item=U>>(&mut self, values: I) -> Result<u64, Error> {
+        let mut num_values: u64 = 0;
+        for value in values {
+            self.push_value(value)?;
+            num_values += 1;
+        }
+        Ok(num_values)
+    }
+
+    /// Insert (in append) all values received from `receiver` until the channel is
+    /// disconnected and return the number of values inserted. If the flush policy
+    /// has a `max_interval` the current chunk is flushed also while waiting for
+    /// values.
+    pub fn consume(&mut self, receiver: &Receiver<U>) -> Result<u64, Error> {
+        let mut num_values: u64 = 0;
+        while let Some(value) = self.recv(receiver)? {
+            self.push_value(value)?;
+            num_values += 1;
+        }
+        Ok(num_values)
+    }
+
+    /// Insert (in append) all values sent from the `IndexWriterHandle` clones of `receiver`
+    /// in order of position, until all handles are dropped, and return the number of values
+    /// inserted. Batches received before their position are kept until the values before
+    /// them are inserted. `ParametersError` is returned if a batch overlaps values already
+    /// received or if there are missing positions when all handles are dropped.
+    pub fn consume_ordered(&mut self, receiver: &IndexWriterReceiver<U>) -> Result<u64, Error> {
+        let start_num_values = self.index.num_values();
+        let mut pending: BTreeMap<u64, Vec<U>> = BTreeMap::new();
+        while let Some((position, values)) = self.recv(&receiver.receiver)? {
+            let end_position = position + values.len() as u64;
+            let overlaps_prev = pending.range(..position).next_back().is_some_and(|(p, v)| p + v.len() as u64 > position);
+            let overlaps_next = pending.range(position..).next().is_some_and(|(p, _v)| *p < end_position);
+            if position < self.index.num_values() || overlaps_prev || overlaps_next {
+                return Err(Error::ParametersError);
+            }
+            if !values.is_empty() {
+                pending.insert(position, values);
+            }
+            while let Some(values) = pending.remove(&self.index.num_values()) {
+                for value in values {
+                    self.push_value(value)?;
+                }
+            }
+        }
+        if !pending.is_empty() {
+            return Err(Error::ParametersError);
+        }
+        Ok(self.index.num_values() - start_num_values)
+    }
+
+    /// Flush the current chunk if there are unflushed values.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.index.is_dirty() {
+            self.index.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Flush the current chunk and return the wrapped `BitmapIndex`.
+    pub fn finish(mut self) -> Result<BitmapIndex<T, U>, Error> {
+        self.flush()?;
+        Ok(self.index)
+    }
+
+    /// Return the next message received from `receiver` or `None` when the channel is
+    /// disconnected. If the flush policy has a `max_interval` the current chunk is flushed
+    /// also while waiting for messages, `max_interval` after the last durable write.
+    fn recv<M>(&mut self, receiver: &Receiver<M>) -> Result<Option<M>, Error> {
+        loop {
+            let max_interval = self.index.flush_policy.as_ref().and_then(|flush_policy| flush_policy.max_interval);
+            match max_interval {
+                Some(max_interval) if self.index.is_dirty() => {
+                    let timeout = max_interval.checked_sub(self.index.last_durable_write.elapsed()).unwrap_or_default();
+                    match receiver.recv_timeout(timeout) {
+                        Ok(message) => return Ok(Some(message)),
+                        Err(RecvTimeoutError::Timeout) => self.index.apply_flush_policy()?,
+                        Err(RecvTimeoutError::Disconnected) => return Ok(None)
+                    }
+                },
+                _ => return Ok(receiver.recv().ok())
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # I/O throttle
+//!
+//! Rate limit of the I/O of background operations (i.e. segment merges), so they
+//! don't starve foreground queries on the same disk. An `IoThrottle` sleeps when the
+//! bytes read and written from the start of the operation exceed the rate limit.
+
+use std::io::{Error as IoError, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub(super) struct IoThrottle {
+    bytes_per_sec: Option<u64>,
+    start: Instant,
+    num_bytes: u64
+}
+
+impl IoThrottle {
+    /// Return a new `IoThrottle`, with `None` the I/O is not limited.
+    pub(super) fn new(bytes_per_sec: Option<u64>) -> Self {
+        IoThrottle {
+            bytes_per_sec,
+            start: Instant::now(),
+            num_bytes: 0
+        }
+    }
+
+    /// Account `num_bytes` read or written bytes and sleep until the I/O rate is
+    /// under the limit.
+    pub(super) fn consume(&mut self, num_bytes: usize) {
+        let bytes_per_sec = match self.bytes_per_sec {
+            Some(bytes_per_sec) => bytes_per_sec.max(1),
+            None => return
+        };
+        self.num_bytes += num_bytes as u64;
+        let min_elapsed = Duration::from_secs_f64(self.num_bytes as f64 / bytes_per_sec as f64);
+        let elapsed = self.start.elapsed();
+        if elapsed < min_elapsed {
+            thread::sleep(min_elapsed - elapsed);
+        }
+    }
+}
+
+/// A writer that accounts the written bytes in an `IoThrottle`.
+pub(super) struct ThrottledWriter<'a, W: Write> {
+    writer: W,
+    io_throttle: &'a mut IoThrottle
+}
+
+impl<'a, W: Write> ThrottledWriter<'a, W> {
+    pub(super) fn new(writer: W, io_throttle: &'a mut IoThrottle) -> Self {
+        ThrottledWriter {
+            writer,
+            io_throttle
+        }
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        let n = self.writer.write(buf)?;
+        self.io_throttle.consume(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.writer.flush()
+    }
+}
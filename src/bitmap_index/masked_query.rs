@@ -0,0 +1,104 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Masked queries
+//!
+//! `run_query_masked` returns the indexes of values with `value & mask == target`, i.e.
+//! "bit 3 is set" on a column of flags. The bits of each block not in `mask` are
+//! expanded to all the block values that match the bits in `mask`, blocks without bits
+//! in `mask` are ignored: the indexes are the union, over all combinations of the
+//! candidate block values, of the intersection of their bitmaps. The number of
+//! combinations grows with the unconstrained bits of the partially masked blocks, so it
+//! is bounded with `set_max_mask_expansion`.
+
+use std::mem;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Set the maximum number of combinations of candidate block values that
+    /// `run_query_masked` expands, by default is 4096.
+    pub fn set_max_mask_expansion(&mut self, max_mask_expansion: usize) {
+        self.max_mask_expansion = max_mask_expansion;
+    }
+
+    /// Return the maximum number of combinations of candidate block values that
+    /// `run_query_masked` expands.
+    pub fn max_mask_expansion(&self) -> usize {
+        self.max_mask_expansion
+    }
+
+    /// Return a sorted `Vec<u64>` that contains all indexes of values with
+    /// `value & mask == target`. The result is empty if `target` has bits that are not in
+    /// `mask`. `ParametersError` is returned if `mask` has bits dropped from the index
+    /// (see `BuildOptions::with_bin_shift`) or if the query expands more combinations than
+    /// `max_mask_expansion`. The parameters `start_index` and `end_index` are optional and
+    /// if specified define the range where query is runned.
+    pub fn run_query_masked(&mut self, mask: U, target: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let block_info = &self.block_info;
+        let bits = mem::size_of::<U>() << 3;
+        let low_mask = 1usize.checked_shl(block_info.bin_shift as u32).map_or(usize::MAX, |low| low - 1);
+        if (mask >> 0).transmute_to_usize() & low_mask != 0 {
+            return Err(Error::ParametersError);
+        }
+        if (target >> 0).transmute_to_usize() & low_mask != 0 {
+            return Ok(Vec::new());
+        }
+
+        // Candidate bitmaps of each block with bits in mask.
+        let mut blocks_i_bitmaps: Vec<Vec<usize>> = Vec::new();
+        let mut num_combinations: usize = 1;
+        for i in 0..block_info.num_blocks {
+            let shift = block_info.bin_shift + i * block_info.bit_block_size;
+            let valid_mask = (1usize << block_info.bit_block_size.min(bits - shift)) - 1;
+            let mask_block = (mask >> shift).transmute_to_usize() & valid_mask;
+            if (target >> shift).transmute_to_usize() & valid_mask & !mask_block != 0 {
+                return Ok(Vec::new());
+            }
+            if mask_block == 0 {
+                continue;
+            }
+            let target_block = (target.to_ordered() >> shift).transmute_to_usize() & mask_block;
+            let i_bitmaps: Vec<usize> = (0..block_info.num_bitmaps_in_block)
+                .filter(|block_value| block_value & mask_block == target_block)
+                .map(|block_value| i * block_info.num_bitmaps_in_block + block_value)
+                .collect();
+            num_combinations = num_combinations.saturating_mul(i_bitmaps.len());
+            if num_combinations > self.max_mask_expansion {
+                return Err(Error::ParametersError);
+            }
+            blocks_i_bitmaps.push(i_bitmaps);
+        }
+
+        let start_index = start_index.unwrap_or(0);
+        let end_index = end_index.unwrap_or(self.num_values);
+        if blocks_i_bitmaps.is_empty() {
+            return Ok((start_index..end_index.saturating_add(1).min(self.num_values)).collect());
+        }
+        let mut combinations: Vec<Vec<usize>> = vec![Vec::new()];
+        for i_bitmaps in blocks_i_bitmaps.iter() {
+            combinations = combinations.iter().flat_map(|combination| {
+                i_bitmaps.iter().map(move |i_bitmap| {
+                    let mut combination = combination.clone();
+                    combination.push(*i_bitmap);
+                    combination
+                })
+            }).collect();
+        }
+        let mut indexes: Vec<u64> = self.run_query_i_bitmaps_grouped(&combinations, start_index, end_index)?.concat();
+        indexes.sort_unstable();
+        Ok(indexes)
+    }
+}
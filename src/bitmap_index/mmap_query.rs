@@ -0,0 +1,102 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Mmap query
+//!
+//! A zero-copy read path for a [`StorageReader`], available with the `mmap` feature.
+//! The data file is mapped in memory and query bitmaps are evaluated as
+//! [`BitmapView`] on the mapped bytes, so no bitmap content is copied into an owned
+//! bitmap before the "logical and".
+//!
+//! [`StorageReader`]: ./storage_reader.rs
+//! [`BitmapView`]: ./bitmap.rs
+
+use std::ops::{BitAnd, Shr};
+
+use memmap2::Mmap;
+
+use super::{
+    BitValue,
+    BitmapIndex,
+    BitmapView,
+    Error,
+    StorageReader,
+    TransmuteToUsize
+};
+
+impl<T: BitmapView, U: BitValue> StorageReader<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Same of `query` but query bitmaps are read from a memory map of the data file
+    /// without copying them.
+    pub fn query_mmap(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let (start_index, end_index) = self.query_range(start_index, end_index);
+        let (start_chunk_id, end_chunk_id) = self.chunk_id_range(start_index, end_index);
+        let chunk_size = self.chunk_size();
+        let mut indexes: Vec<u64> = Vec::new();
+        if start_chunk_id >= end_chunk_id {
+            return Ok(indexes);
+        }
+        let query_i_bitmaps = self.query_i_bitmaps(value);
+        let chunks_offsets = self.chunks_offsets(start_chunk_id, (end_chunk_id - start_chunk_id) as usize)?;
+
+        // Flushed chunks are never overwritten (a rewritten chunk is appended to the
+        // data file), so the mapped content of the queried chunks doesn't change.
+        let mmap = unsafe { Mmap::map(self.data_file()) }.map_err(Error::FileError)?;
+
+        // Empty bitmaps can be omitted from a chunk, they are read as views of a new bitmap.
+        let mut empty_content: Vec<u8> = vec![0; T::new().size()];
+        T::new().write_to_buffer(&mut empty_content).map_err(|_err| Error::BitmapError)?;
+
+        for (chunk_id, chunk_offset) in (start_chunk_id..end_chunk_id).zip(chunks_offsets) {
+            let chunk = mmap.get((chunk_offset as usize)..).ok_or(Error::BitmapError)?;
+            let bitmaps_offsets = BitmapIndex::<T, U>::chunk_bitmaps_offsets(chunk, &query_i_bitmaps)?;
+            let mut contents: Vec<&[u8]> = Vec::with_capacity(query_i_bitmaps.len());
+            for (start_offset, end_offset) in bitmaps_offsets {
+                contents.push(match start_offset == end_offset {
+                    true => &empty_content[..],
+                    false => chunk.get((start_offset as usize)..(end_offset as usize)).ok_or(Error::BitmapError)?
+                });
+            }
+            let views: Result<Vec<T::View<'_>>, ()> = contents.iter().map(|content| T::view(content)).collect();
+
+            let unrolled_bitmap = match views {
+                Ok(views) if views.len() == 1 => T::unroll_view(&views[0]),
+                Ok(views) => {
+                    let mut b_result: T = T::and_views(&views[0], &views[1]);
+                    for view in views.iter().skip(2) {
+                        b_result = b_result.and_view(view);
+                    }
+                    b_result.unroll_bitmap()
+                },
+                // a content without a view (i.e. OZBC wide words) is read in owned bitmaps.
+                Err(()) => Self::and_contents(&contents)?.unroll_bitmap()
+            };
+
+            let offset_index: u64 = chunk_id * chunk_size;
+            indexes.extend(unrolled_bitmap.iter()
+                           .map(|idx| offset_index + *idx as u64)
+                           .filter(|idx| *idx >= start_index && *idx <= end_index)
+            );
+        }
+        Ok(indexes)
+    }
+
+    /// Return the "logical and" of the serialized bitmaps `contents`.
+    fn and_contents(contents: &[&[u8]]) -> Result<T, Error> {
+        let mut bitmaps: Vec<T> = Vec::with_capacity(contents.len());
+        for content in contents {
+            let mut bitmap = T::new();
+            BitmapIndex::<T, U>::read_bitmap(content, false, &mut bitmap)?;
+            bitmaps.push(bitmap);
+        }
+        let mut b_result: T = bitmaps[0].clone();
+        for b in bitmaps.iter().skip(1) {
+            b_result = &b_result & b;
+        }
+        Ok(b_result)
+    }
+}
@@ -8,21 +8,232 @@
 //! A serializable bitmap-index for each value that implement BitValue trait
 //! and for each bitmap that implement Bitmap trait.
 //! On default `BitValue` is implemented for:
-//! `u8, u16`, `u32`, `u64`, `u128`, `i8`, `i16`, `i32`, `i64`, `i128`.
-
-/// [`Bitmap`]: ./bitmap.rs
+//! `u8`, `u16`, `u32`, `u64`, `u128`, `usize`, `i8`, `i16`, `i32`, `i64`, `i128`,
+//! `isize`. Signed values are indexed in an order-preserving way, the sign bit is
+//! flipped before the value is decomposed in blocks (see `BitValue::to_ordered`).
+//!
+//! [`Bitmap`]: ./bitmap.rs
 
-use std::ops::{BitAnd, BitOr, Shr};
+use std::ops::{BitAnd, BitOr, ControlFlow, Shr};
 use std::marker::Copy;
 use std::fmt::{Display};
 use std::convert::From;
 use std::mem;
-use std::io::{Write, Error as IoError, SeekFrom, Seek, Read};
+use std::io::{BufWriter, Write, Error as IoError, ErrorKind, IoSlice, SeekFrom, Seek, Read};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 mod bitmap;
-pub use self::bitmap::Bitmap;
+pub use self::bitmap::{Bitmap, BitmapView};
+
+mod storage_reader;
+pub use self::storage_reader::StorageReader;
+
+mod user_meta_data;
+
+mod index_writer;
+pub use self::index_writer::{IndexWriter, IndexWriterHandle, IndexWriterReceiver, FlushPolicy};
+
+mod auto_flush;
+
+mod bulk_build;
+
+mod offline_builder;
+pub use self::offline_builder::OfflineBuilder;
+
+mod rebuild;
+
+mod value_lookup;
+
+mod value_scan;
+pub use self::value_scan::ValueScan;
+
+mod audit;
+pub use self::audit::ValueDivergence;
+
+mod roaring_import;
+
+mod archive;
+
+mod backup;
+
+mod data_trailer;
+use self::data_trailer::CHUNK_TRAILER_SIZE;
+
+mod chunk_format;
+pub use self::chunk_format::ChunkFormat;
+
+mod chunk_arena;
+use self::chunk_arena::ChunkArena;
+
+mod spill;
+use self::spill::SpillChunks;
+
+mod coarse_layer;
+use self::coarse_layer::CoarseLayer;
+
+mod value_stats;
+use self::value_stats::ValueStats;
+
+mod offset_cache;
+use self::offset_cache::OffsetCache;
+
+mod query_cache;
+use self::query_cache::QueryCache;
+
+mod hot_tier;
+pub use self::hot_tier::HotTier;
+use self::hot_tier::HotChunks;
+
+mod query_explain;
+pub use self::query_explain::{QueryExplain, ChunkExplain};
+
+mod query_deadline;
+pub use self::query_deadline::QueryProgress;
+
+mod capped_query;
+pub use self::capped_query::QueryResult;
+
+mod query_visit;
+
+mod snapshot_query;
+
+mod reader_session;
+pub use self::reader_session::{ReaderSession, SharedTail};
+
+mod change_capture;
+pub use self::change_capture::AppendedChunk;
+
+mod grouped_query;
+
+mod range_query;
+
+mod block_query;
+
+mod masked_query;
+
+mod sample_query;
+
+mod cross_query;
+
+mod selection;
+
+mod query_result_file;
+
+mod chunk_access;
+
+mod chunk_rewrite;
+
+mod storage_stats;
+pub use self::storage_stats::StorageStats;
+use self::storage_stats::CountingReader;
+
+mod compression_report;
+pub use self::compression_report::ChunkCompression;
+
+mod disk_size;
+pub use self::disk_size::DiskSize;
+
+mod flush_events;
+pub use self::flush_events::FlushEvent;
+use self::flush_events::FlushListener;
+
+mod replication;
+pub use self::replication::{ChunkPayload, ReplicationStream};
+
+mod recovery;
+
+mod upgrade;
+
+mod dyn_index;
+pub use self::dyn_index::{DynBitmapIndex, DynValue, DynValueType};
+
+mod multi_column_query;
+pub use self::multi_column_query::ColumnCombine;
+
+mod token_index;
+pub use self::token_index::{TokenIndex, Tokenizer};
+
+mod string_index;
+pub use self::string_index::{StringIndex, StringDictionary, StringNormalizer, STRING_NORMALIZER_KEY};
+
+mod geo_query;
+pub use self::geo_query::GeoGrid;
+
+mod frozen;
+pub use self::frozen::FrozenIndex;
+
+mod io_throttle;
+
+mod segmented;
+pub use self::segmented::{SegmentedIndex, SegmentInfo, MergePolicy};
+
+mod container;
+pub use self::container::ContainerIndex;
+
+mod partitioned;
+pub use self::partitioned::{PartitionedIndex, PartitionInfo};
+
+mod sharded;
+pub use self::sharded::{ShardedIndex, ShardStrategy};
+
+mod composite_key;
+pub use self::composite_key::CompositeKey;
+
+mod enum_value;
+pub use self::enum_value::{BitEnum, ENUM_MAPPING_KEY};
+
+mod remapped;
+pub use self::remapped::{RemappedIndex, LowCardinalityIndex, DictionaryId, is_low_cardinality};
+
+#[cfg(feature = "parallel")]
+mod parallel;
+
+#[cfg(feature = "async")]
+mod query_stream;
+#[cfg(feature = "async")]
+pub use self::query_stream::QueryStream;
+
+#[cfg(feature = "mmap")]
+mod mmap_query;
+
+#[cfg(feature = "csv")]
+mod csv_ingest;
+
+#[cfg(feature = "arrow")]
+mod arrow_ingest;
+#[cfg(feature = "arrow")]
+pub use self::arrow_ingest::ArrowBitValue;
+
+#[cfg(feature = "parquet")]
+mod parquet_ingest;
+
+#[cfg(feature = "polars")]
+mod polars_results;
+#[cfg(feature = "polars")]
+pub use self::polars_results::indexes_to_polars_mask;
+
+#[cfg(feature = "half")]
+mod half_value;
+#[cfg(feature = "half")]
+pub use self::half_value::{F16Value, Bf16Value};
+
+/// Size of the chunks serialized in memory and written with a single vectored write, a
+/// bigger chunk is streamed with a buffer of this capacity: a write call every
+/// `WRITE_BUFFER_SIZE` bytes.
+const WRITE_BUFFER_SIZE: usize = 1 << 20;
+/// Number of values inserted in a batch from `push_values`.
+const PUSH_BATCH_SIZE: usize = 1 << 12;
+/// Default maximum number of combinations of block values expanded from `run_query_masked`.
+const MAX_MASK_EXPANSION: usize = 1 << 12;
+
+/// Key format of the indexes whose signed values are decomposed with
+/// `BitValue::to_ordered`. Signed indexes written with an older key format map
+/// values to different bitmaps, so they are rejected with `MetaDataError` and must be
+/// rebuilt; unsigned indexes are not affected.
+pub const ORDERED_KEY_FORMAT: u16 = 1;
 
 /// A trait that allow to convert `BitValue` to `usize`.
 pub trait TransmuteToUsize {
@@ -41,8 +252,22 @@ macro_rules! impl_transmute_to_usize_for_number {
 
 /// `BitValue` trait.
 /// On default `BitValue` is implemented for:
-/// `u8, u16`, `u32`, `u64`, `u128`, `i8`, `i16`, `i32`, `i64`, `i128`.
-pub trait BitValue: Copy + Display + BitAnd + BitOr + Shr<usize> + TransmuteToUsize {}
+/// `u8, u16`, `u32`, `u64`, `u128`, `usize`, `i8`, `i16`, `i32`, `i64`, `i128`, `isize`.
+/// The width of the value is recorded in `MetaData::value_size`, so an index of
+/// `usize` or `isize` written on a 64-bit host can't be opened on a 32-bit host
+/// (`MetaDataError`).
+///
+/// Values are decomposed in blocks after `to_ordered`, an order-preserving mapping:
+/// it is the identity for unsigned types while for signed types it flips the sign
+/// bit, so negative values come before positive ones when their bits are compared
+/// as unsigned numbers (i.e. in bins and ranges).
+pub trait BitValue: Copy + Display + BitAnd + BitOr + Shr<usize> + TransmuteToUsize {
+    /// `true` if `to_ordered` is not the identity.
+    const SIGNED: bool = false;
+
+    /// Return the order-preserving representation of self.
+    fn to_ordered(self) -> Self { self }
+}
 
 macro_rules! impl_bit_value_for_number {
     ($ty:ident) => {
@@ -56,14 +281,33 @@ macro_rules! impl_bit_value_for_number {
     }    
 }
 
-impl_bit_value_for_number!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+macro_rules! impl_bit_value_for_signed_number {
+    ($($ty:ident),+) => {
+        $(
+            impl_transmute_to_usize_for_number!($ty);
+            impl BitValue for $ty {
+                const SIGNED: bool = true;
+
+                fn to_ordered(self) -> Self { self ^ $ty::MIN }
+            }
+        )+
+    };
+}
+
+impl_bit_value_for_number!(u8, u16, u32, u64, u128, usize);
+impl_bit_value_for_signed_number!(i8, i16, i32, i64, i128, isize);
 
 /// `BitmapIndex` errors types.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     ParametersError,
     FileError(IoError),
     BitmapError,
+    MetaDataError,
+    Timeout(QueryProgress),
+    TooManyResults(u64),
+    InconsistentStorage(String),
 }
 
 /// `BitmapIndex` works in chunks, each chunk represent `ChunkSize` values,
@@ -80,12 +324,27 @@ pub enum ChunkSize {
     M32 = (1 << 25),
 }
 
+impl ChunkSize {
+    fn from_u32(chunk_size: u32) -> Option<ChunkSize> {
+        match chunk_size {
+            x if x == ChunkSize::M1 as u32 => Some(ChunkSize::M1),
+            x if x == ChunkSize::M2 as u32 => Some(ChunkSize::M2),
+            x if x == ChunkSize::M4 as u32 => Some(ChunkSize::M4),
+            x if x == ChunkSize::M8 as u32 => Some(ChunkSize::M8),
+            x if x == ChunkSize::M16 as u32 => Some(ChunkSize::M16),
+            x if x == ChunkSize::M32 as u32 => Some(ChunkSize::M32),
+            _ => None
+        }
+    }
+}
+
 /// `BitmapIndex` struct that requires a bitmap that implement [`Bitmap`] trait and
 /// a type that implement `BitValue` trait.
 pub struct BitmapIndex<T: Bitmap, U: BitValue>
 where <U as Shr<usize>>::Output: TransmuteToUsize,
 for <'a> &'a T: BitAnd<&'a T, Output=T> {
     num_values: u64,
+    flushed_num_values: u64,
     chunk_size: u64,
     chunk_size_mask: u64,
     
@@ -95,7 +354,28 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     storage_idx: Option<StorageIdx>,
     chunk_offset: u64,
     chunks: Option<Vec<Vec<T>>>,
+    arena_chunks: Option<Vec<ChunkArena>>,
+    spill_chunks: Option<SpillChunks>,
+    coarse_layer: Option<CoarseLayer>,
+    value_stats: Option<ValueStats>,
+    hot_chunks: Option<HotChunks<T>>,
+    query_cache: Option<QueryCache<T>>,
+    chunk_format: ChunkFormat,
+    bitmap_capacity: u32,
+    wide_words: bool,
+    max_query_results: Option<u64>,
+    max_mask_expansion: usize,
     last_checkpoint: Option<MetaData>,
+    checkpoint_extent: [u64; 2],
+    user_meta_data: BTreeMap<String, Vec<u8>>,
+    created_at: u64,
+    modified_at: u64,
+    flush_listener: Option<FlushListener>,
+    flush_policy: Option<FlushPolicy>,
+    last_durable_write: Instant,
+
+    #[cfg(feature = "parallel")]
+    thread_pool: Option<std::sync::Arc<rayon::ThreadPool>>,
     
     _marker: std::marker::PhantomData<U>
 }
@@ -103,6 +383,7 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
 struct BlockInfo {
     bit_block_size: usize,
     bit_block_mask: usize,
+    bin_shift: usize,
     num_blocks: usize,
     num_bitmaps_in_block: usize,
 }
@@ -114,11 +395,20 @@ struct BlockInfo {
 /// For example a `BitmapIndex` of a `BitValue` 'U=`u16`' with 'size_in_bit(u16) = 16'
 /// and `bit_block_size = 8` is composed from '16 / 8 = 2' blocks of '2^8 = 256' bitmaps each,
 /// so is composed from '2 * 256 = 512' bitmaps.
+/// `bitmap_capacity` is the number of set bits that every bitmap of a chunk
+/// preallocates (see [`Bitmap::with_capacity`]), by default is 0 and bitmaps grow on demand.
+/// `wide_words` selects the wide words encoding of the serialized bitmaps (see
+/// [`Bitmap::set_wide_words`]), by default is disabled.
+/// `bin_shift` is the number of low bits dropped from each value before indexing it,
+/// by default is 0 and values are indexed exactly.
 #[derive(Clone)]
 #[repr(C)]
 pub struct BuildOptions {
     bit_block_size: usize,
-    chunk_size: ChunkSize
+    chunk_size: ChunkSize,
+    bitmap_capacity: u32,
+    wide_words: u32,
+    bin_shift: u32
 }
 
 impl BuildOptions {
@@ -126,23 +416,187 @@ impl BuildOptions {
     pub fn new(bit_block_size: usize, chunk_size: ChunkSize) -> Self {
         BuildOptions {
             bit_block_size,
-            chunk_size
+            chunk_size,
+            bitmap_capacity: 0,
+            wide_words: 0,
+            bin_shift: 0
         }
     }
+
+    /// Return options that minimize the size of a `BitmapIndex` of `U` values: blocks of
+    /// 4 bits, so every block has only 16 bitmaps (a query intersects more bitmaps), and
+    /// the largest chunks, so the per-chunk overhead is paid less often.
+    pub fn optimize_for_space<U: BitValue>() -> Self {
+        BuildOptions::new(4, ChunkSize::M32)
+    }
+
+    /// Return options that minimize the query time on a `BitmapIndex` of `U` values: the
+    /// largest blocks (16 bits, 8 bits for 1 byte values), so a query intersects the fewest
+    /// bitmaps, at the cost of more (sparser) bitmaps in memory.
+    pub fn optimize_for_speed<U: BitValue>() -> Self {
+        if mem::size_of::<U>() == 1 {
+            return BuildOptions::new(8, ChunkSize::M16);
+        }
+        BuildOptions::new(16, ChunkSize::M16)
+    }
+
+    /// Set the number of set bits preallocated by every bitmap of a chunk. Every chunk
+    /// allocates `num_bitmaps * bitmap_capacity` bits in advance, so keep it close to
+    /// the expected number of values of each bitmap in a chunk.
+    pub fn with_bitmap_capacity(mut self, bitmap_capacity: u32) -> Self {
+        self.bitmap_capacity = bitmap_capacity;
+        self
+    }
+
+    /// Set the bitmap capacity from the expected density of each bitmap, that is
+    /// the expected fraction of values in a chunk that set a bitmap.
+    /// I.e. on uniform values with `bit_block_size = 8` the density is `1 / 256`.
+    pub fn with_expected_density(self, density: f64) -> Self {
+        let chunk_size = self.chunk_size.clone() as u32;
+        let bitmap_capacity = (chunk_size as f64 * density.clamp(0.0, 1.0)).ceil() as u32;
+        self.with_bitmap_capacity(bitmap_capacity)
+    }
+
+    /// Return the size in bit of each sub-index.
+    pub fn bit_block_size(&self) -> usize {
+        self.bit_block_size
+    }
+
+    /// Return the number of values of each chunk.
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size.clone() as u64
+    }
+
+    /// Return the number of set bits preallocated by every bitmap of a chunk.
+    pub fn bitmap_capacity(&self) -> u32 {
+        self.bitmap_capacity
+    }
+
+    /// Serialize bitmaps in their wide words encoding, if the bitmap has one. With
+    /// `OZBCBitmap` it makes ultra-sparse bitmaps smaller, since a run of zeros of any
+    /// length is encoded in a single word.
+    pub fn with_wide_words(mut self, wide_words: bool) -> Self {
+        self.wide_words = wide_words as u32;
+        self
+    }
+
+    /// Return `true` if bitmaps are serialized in their wide words encoding.
+    pub fn wide_words(&self) -> bool {
+        self.wide_words != 0
+    }
+
+    /// Index each value in the bin `value >> bin_shift` instead of the exact value, so
+    /// continuous measurements have fewer distinct indexed values. A query of `value`
+    /// returns all values of its bin and `run_query_range_approx` answers range queries
+    /// with the bins that intersect the range, so results are candidates that must be
+    /// checked downstream. `bin_shift` must be less than the size in bit of the value.
+    pub fn with_bin_shift(mut self, bin_shift: u32) -> Self {
+        self.bin_shift = bin_shift;
+        self
+    }
+
+    /// Return the number of low bits dropped from each value before indexing it.
+    pub fn bin_shift(&self) -> u32 {
+        self.bin_shift
+    }
 }
 
 /// `StorageIdx` defines a `BitmapIndex` opened in read-only storage mode.
 pub struct StorageIdx {
+    dir_path: PathBuf,
     meta_data_file: fs::File,
     offset_file: fs::File,
-    data_file: fs::File
+    data_file: fs::File,
+    stats: StorageStats,
+    offset_cache: Option<OffsetCache>
 }
 
-/// `MetaData` defines the meta data of a `BitmapIndex`.
+/// Version of the layout of the `MetaData` written by this library, a `MetaData`
+/// with a different version is rejected with `MetaDataError`.
+pub const META_DATA_VERSION: u16 = 1;
+
+/// Format of the keys that values are decomposed into by this library.
+const KEY_FORMAT: u16 = ORDERED_KEY_FORMAT;
+
+/// `MetaData` defines the meta data of a `BitmapIndex`. Besides the build options
+/// it records the size of the indexed `BitValue` and the format tag of the bitmap
+/// used, so a `BitmapIndex` can't be opened with different generic parameters.
+/// It starts with the version of its own layout and the format of the keys, so
+/// later changes of either can be detected. It also records the version of the
+/// library that last wrote the index and the creation and last modification times
+/// (in seconds since the UNIX epoch), so stale or version-skewed indexes can be
+/// detected.
+#[derive(Clone)]
 #[repr(C)]
 pub struct MetaData {
+    meta_data_version: u16,
+    key_format: u16,
+    value_size: u32,
     num_values: u64,
-    build_options: BuildOptions
+    build_options: BuildOptions,
+    bitmap_tag: u32,
+    library_version: [u16; 3],
+    created_at: u64,
+    modified_at: u64
+}
+
+impl MetaData {
+    /// Return the number of values of the `BitmapIndex`.
+    pub fn num_values(&self) -> u64 {
+        self.num_values
+    }
+
+    /// Return the build options of the `BitmapIndex`.
+    pub fn build_options(&self) -> &BuildOptions {
+        &self.build_options
+    }
+
+    /// Return the size in bytes of the indexed `BitValue`.
+    pub fn value_size(&self) -> u32 {
+        self.value_size
+    }
+
+    /// Return the format tag of the bitmap (see [`Bitmap::format_tag`]).
+    pub fn bitmap_tag(&self) -> u32 {
+        self.bitmap_tag
+    }
+
+    /// Return the version (major, minor, patch) of the library that last wrote the
+    /// `BitmapIndex`.
+    pub fn library_version(&self) -> (u16, u16, u16) {
+        (self.library_version[0], self.library_version[1], self.library_version[2])
+    }
+
+    /// Return the creation time of the `BitmapIndex`.
+    pub fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created_at)
+    }
+
+    /// Return the time of the last chunk written (or flushed) of the `BitmapIndex`,
+    /// the creation time if no chunk has been written.
+    pub fn modified_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.modified_at)
+    }
+
+    /// Return the format of the keys of the `BitmapIndex`: `0` if signed values have
+    /// been decomposed without `BitValue::to_ordered`, `ORDERED_KEY_FORMAT` otherwise.
+    pub fn key_format(&self) -> u16 {
+        self.key_format
+    }
+
+    /// Return the version of this library in the `library_version` format.
+    fn current_library_version() -> [u16; 3] {
+        [
+            env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0),
+            env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0),
+            env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or(0)
+        ]
+    }
+
+    /// Return the seconds elapsed from the UNIX epoch.
+    fn unix_time_now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+    }
 }
 
 impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
@@ -151,7 +605,7 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
 
     /// Return a `BitmapIndex` in storage mode and create a folder with `bitmap_index_path` path.
     /// The created folder contain 3 files that represent a `BitmapIndex`:
-    /// 1) A file with 'mbidx' extension that represent `BitmapIndex` meta data.
+    /// 1) A file with 'mbidx' extension that represent `BitmapIndex` meta data and its last checkpoint.
     /// 2) A file with 'obidx' extension that represent all offsets of all bitmaps chunks.
     /// 3) A file with 'dbix' extension that represent all bitmaps chunks content.
     pub fn create(bitmap_index_path: &Path, build_options: BuildOptions) -> Result<Self, Error> {
@@ -168,101 +622,90 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     }
 
     /// Open a `BitmapIndex` in storage mode previusly created.
+    /// `InconsistentStorage` error is returned if the meta data don't match the offset
+    /// and data files (i.e. after a torn write), in that case use `recover`.
     pub fn open(dir_path: &Path) -> Result<Self, Error> {
         let mut storage_idx = Self::get_storage_idx(dir_path, None)?;
-        let m = Self::map_io_result(Self::read_meta_data(&mut storage_idx))?;
-        let mut bitmap_index = Self::new_index(m.0.build_options, true)?;
+        let m = Self::read_meta_data(&mut storage_idx)?;
+        Self::check_meta_data(&m.0)?;
+        Self::check_storage_files(&mut storage_idx, &m.0)?;
+        let mut bitmap_index = Self::new_index(m.0.build_options.clone(), true)?;
         bitmap_index.num_values = m.0.num_values;
+        bitmap_index.flushed_num_values = m.0.num_values;
+        bitmap_index.created_at = m.0.created_at;
+        bitmap_index.modified_at = m.0.modified_at;
 
         let offsets_r = Self::read_chunk_offset(&mut storage_idx, bitmap_index.num_values, bitmap_index.chunk_size);
         let offsets = Self::map_io_result(offsets_r)?;
-        bitmap_index.chunk_offset = offsets.0;
+        // Next write of a partial chunk is appended or written over its older versions
+        // (see `write_chunk`), the last version is never overwritten.
+        bitmap_index.chunk_offset = offsets.1;
         if bitmap_index.num_values & bitmap_index.chunk_size_mask != 0 {
-            let r_buf_chunk = Self::read_chunk(&mut storage_idx, offsets);
-            let buf_chunk = Self::map_io_result(r_buf_chunk)?;
-            Self::read_bitmaps(&buf_chunk, &mut bitmap_index.bitmaps)?;
+            Self::read_chunk_bitmaps(&mut storage_idx.data_file, offsets, &mut bitmap_index.bitmaps)?;
         }
+        bitmap_index.user_meta_data = Self::read_user_meta_data(&storage_idx)?;
+        bitmap_index.checkpoint_extent = Self::map_io_result(
+            Self::last_chunk_extent(&mut storage_idx, bitmap_index.num_values, bitmap_index.chunk_size)
+        )?;
         bitmap_index.storage_idx = Some(storage_idx);
-        bitmap_index.last_checkpoint = Some(m.1);
+        bitmap_index.last_checkpoint = Some(m.0);
+        bitmap_index.open_value_stats()?;
 
         Ok(bitmap_index)
     }
 
-    fn read_bitmaps(buf: &[u8], bitmaps: &mut [T]) -> Result<(), Error> {
-        let num_offsets = bitmaps.len() + 1;
-        let buf_offsets_size = num_offsets * mem::size_of::<u32>();
-        let v_offsets: &[u32] = Self::convert_slice(&buf[0..buf_offsets_size]);
-        let v_bitmap: &[u8] = &buf[buf_offsets_size..];
-
-        let mut i = 0;
-        let mut start_offset = 0;
-        
-        for b in bitmaps.iter_mut() {
-            let b_size: usize = (v_offsets[i + 1] - v_offsets[i]) as usize;
-            let end_offset = start_offset + b_size;
-            Self::read_bitmap(&v_bitmap[start_offset..end_offset], true, b)?;
-            start_offset = end_offset;
-            i += 1;
-        }
-        Ok(())
-    }
-    
     fn read_bitmap(buf: &[u8], check_bitmap: bool, bitmap: &mut T) -> Result<(), Error> {
         let r = bitmap.read_from_buffer(buf, check_bitmap);
         Self::map_bitmap_result(r)
     }
 
+    /// Read a bitmap of `size` bytes from reader with `Bitmap::read_from`.
+    fn read_bitmap_from<R: Read>(reader: &mut R, size: usize, check_bitmap: bool, bitmap: &mut T) -> Result<(), Error> {
+        match bitmap.read_from(reader, size, check_bitmap) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::InvalidData => Err(Error::BitmapError),
+            Err(err) => Err(Error::FileError(err))
+        }
+    }
+
     fn map_bitmap_result(result: Result<(), ()>) -> Result<(), Error> {
-        let r = match result {
+        match result {
             Err(_) => Err(Error::BitmapError),
             Ok(_) => Ok(())
-        };
-        r
-    }
-
-    fn read_chunk(storage_idx: &mut StorageIdx, offsets: (u64, u64)) -> Result<Vec<u8>, IoError> {
-        let buf_chunk_size: usize = (offsets.1 - offsets.0) as usize;
-        let mut buf_chunk: Vec<u8> = vec![0; buf_chunk_size];
-        storage_idx.data_file.seek(SeekFrom::Start(offsets.0))?;
-        storage_idx.data_file.read_exact(&mut buf_chunk)?;
-
-        Ok(buf_chunk)
+        }
     }
 
-    fn read_bitmap_offset(storage_idx: &mut StorageIdx, chunk_offset: u64, i_bitmap: usize) -> Result<(u64, u64), Error> {
+    fn read_bitmap_offset<R: Read + Seek>(data_file: &mut R, chunk_offset: u64, i_bitmap: usize) -> Result<(u64, u64), Error> {
         let i_bitmap_offset = chunk_offset + (i_bitmap * mem::size_of::<u32>()) as u64;
-        let r_seek = storage_idx.data_file.seek(SeekFrom::Start(i_bitmap_offset));
+        let r_seek = data_file.seek(SeekFrom::Start(i_bitmap_offset));
         Self::map_io_result(r_seek)?;
         const BUF_SIZE: usize = mem::size_of::<u32>() * 2;
         let mut buf: [u8; BUF_SIZE] = [0; BUF_SIZE];
 
-        Self::map_io_result(storage_idx.data_file.read_exact(&mut buf))?;
+        Self::map_io_result(data_file.read_exact(&mut buf))?;
         let start_offset: u32 = Self::copy_from_slice_u8(&buf[0..mem::size_of::<u32>()]);
         let end_offset: u32 = Self::copy_from_slice_u8(&buf[mem::size_of::<u32>()..]);
         
         Ok((chunk_offset + start_offset as u64, chunk_offset + end_offset as u64)) 
     }
 
-    fn read_query_bitmaps(storage_idx: &mut StorageIdx, chunk_offset: u64, query_i_bitmaps: &[usize]) -> Result<Vec<T>, Error> {
-        let vec_len = query_i_bitmaps.len();
-        let mut bitmaps_offset: Vec<(u64, u64)> = Vec::with_capacity(vec_len);
-        for i_bitmap in query_i_bitmaps {
-            let bitmap_offset = Self::read_bitmap_offset(storage_idx, chunk_offset, *i_bitmap)?;
-            bitmaps_offset.push(bitmap_offset);
-        }
+    /// Read the bitmaps `query_i_bitmaps` of the chunk at `chunk_offset` of `data_file`.
+    fn read_query_bitmaps<R: Read + Seek>(data_file: &mut R, chunk_offset: u64, query_i_bitmaps: &[usize]) -> Result<Vec<T>, Error> {
+        let bitmaps_offset: Vec<(u64, u64)> = Self::read_bitmaps_offsets(data_file, chunk_offset, query_i_bitmaps)?;
+        Self::read_bitmaps_at(data_file, bitmaps_offset)
+    }
 
-        let mut query_bitmaps: Vec<T> = Vec::with_capacity(vec_len);
-        let mut buf: Vec<u8> = Vec::new();
+    /// Read the bitmaps at the absolute offsets `bitmaps_offset` of `data_file`.
+    fn read_bitmaps_at<R: Read + Seek>(data_file: &mut R, bitmaps_offset: Vec<(u64, u64)>) -> Result<Vec<T>, Error> {
+        let mut query_bitmaps: Vec<T> = Vec::with_capacity(bitmaps_offset.len());
         for offset in bitmaps_offset {
-            let buf_len = (offset.1 - offset.0) as usize;
+            let bitmap_size = (offset.1 - offset.0) as usize;
             let mut bitmap = T::new();
-            if buf.len() < buf_len {
-                buf = vec![0; buf_len];
+            if bitmap_size > 0 {
+                // bitmaps are read from the data file without a scratch buffer as large as the bitmap.
+                Self::map_io_result(data_file.seek(SeekFrom::Start(offset.0)))?;
+                Self::read_bitmap_from(data_file, bitmap_size, true, &mut bitmap)?;
             }
-            Self::map_io_result(storage_idx.data_file.seek(SeekFrom::Start(offset.0)))?;
-            let r_read = storage_idx.data_file.read_exact(&mut buf[0..buf_len]);
-            Self::map_io_result(r_read)?;
-            Self::read_bitmap(&buf[0..buf_len], true, &mut bitmap)?;
             query_bitmaps.push(bitmap);
         }
         
@@ -274,12 +717,12 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
         let offsets = if num_values == 0 {
             (0, 0)
         } else {
-            let start_index = num_values - (num_values & chunk_size_mask);
-            let end_index = num_values;
-            let offsets = Self::read_chunks_offsets(storage_idx, start_index, end_index, chunk_size, 2, 1)?;
-            if offsets.len() == 1 {
+            let chunk_id = Self::get_chunk_id(num_values, chunk_size);
+            if num_values & chunk_size_mask == 0 {
+                let offsets = Self::read_offsets(storage_idx, chunk_id, 1)?;
                 (offsets[0], offsets[0])
             } else {
+                let offsets = Self::read_offsets(storage_idx, chunk_id, 2)?;
                 (offsets[0], offsets[1])
             }
         };
@@ -287,16 +730,10 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
         Ok((offsets.0, offsets.1))
     }
 
-    fn read_chunks_offsets(storage_idx: &mut StorageIdx, start_index: u64, end_index: u64, chunk_size: u64, max_num_offsets: usize, plus_chunk: usize) -> Result<Vec<u64>, IoError> {
-        let start_offset = Self::get_chunk_id(start_index, chunk_size);
-        let mut num_offsets = (Self::get_chunk_id(end_index, chunk_size) - start_offset) as usize + plus_chunk;
-        if num_offsets > max_num_offsets
-        {
-            num_offsets = max_num_offsets;
-        }
+    fn read_offsets(storage_idx: &mut StorageIdx, start_chunk_id: u64, num_offsets: usize) -> Result<Vec<u64>, IoError> {
         let offsets: Vec<u64> = vec![0; num_offsets];
         
-        let block_offset: u64 = Self::get_block_offset(start_offset);
+        let block_offset: u64 = Self::get_block_offset(start_chunk_id);
         let buf: &mut [u8] = Self::convert_slice(&offsets);
 
         storage_idx.offset_file.seek(SeekFrom::Start(block_offset))?;
@@ -306,22 +743,50 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     }
 
 
-    fn read_meta_data(storage_idx: &mut StorageIdx) -> Result<(MetaData, MetaData), IoError> {
+    fn read_meta_data(storage_idx: &mut StorageIdx) -> Result<(MetaData, MetaData), Error> {
         const META_DATA_SIZE: usize = mem::size_of::<MetaData>();
         let mut meta_data_buf: [u8; META_DATA_SIZE] = [0; META_DATA_SIZE];
-        storage_idx.meta_data_file.seek(SeekFrom::Start(0))?;
-        storage_idx.meta_data_file.read_exact(&mut meta_data_buf)?;
-        let meta_data: MetaData = Self::copy_from_slice_u8(&meta_data_buf);
-        storage_idx.meta_data_file.read_exact(&mut meta_data_buf)?; 
-        let last_check_point: MetaData = Self::copy_from_slice_u8(&meta_data_buf);
+        let mut last_check_point_buf: [u8; META_DATA_SIZE] = [0; META_DATA_SIZE];
+        Self::map_io_result(storage_idx.meta_data_file.seek(SeekFrom::Start(0)))?;
+        Self::map_io_result(storage_idx.meta_data_file.read_exact(&mut meta_data_buf))?;
+        Self::map_io_result(storage_idx.meta_data_file.read_exact(&mut last_check_point_buf))?;
+        let meta_data = Self::meta_data_from_slice_u8(&meta_data_buf)?;
+        let last_check_point = Self::meta_data_from_slice_u8(&last_check_point_buf)?;
         Ok((meta_data, last_check_point))
     }
 
-    fn check_if_path_exixsts(dir_path: &Path) -> bool {
-        match fs::metadata(dir_path) {
-            Ok(_) => true,
-            Err(_) => false
+    /// Convert a serialized `MetaData` checking that `chunk_size` is a valid `ChunkSize`
+    /// before interpreting it.
+    fn meta_data_from_slice_u8(buf: &[u8]) -> Result<MetaData, Error> {
+        let chunk_size_offset = mem::offset_of!(MetaData, build_options) + mem::offset_of!(BuildOptions, chunk_size);
+        let mut chunk_size_buf: [u8; 4] = [0; 4];
+        chunk_size_buf.copy_from_slice(&buf[chunk_size_offset..(chunk_size_offset + 4)]);
+        if ChunkSize::from_u32(u32::from_ne_bytes(chunk_size_buf)).is_none() {
+            return Err(Error::MetaDataError);
+        }
+        Ok(Self::copy_from_slice_u8(buf))
+    }
+
+    /// Check that `meta_data` has the current layout and was written by a `BitmapIndex`
+    /// with the same `BitValue` size and the same `Bitmap` format of `Self`.
+    fn check_meta_data(meta_data: &MetaData) -> Result<(), Error> {
+        if meta_data.meta_data_version != META_DATA_VERSION {
+            return Err(Error::MetaDataError);
+        }
+        if meta_data.value_size as usize != mem::size_of::<U>() || meta_data.bitmap_tag != T::format_tag() {
+            return Err(Error::MetaDataError);
         }
+        if U::SIGNED && meta_data.key_format() < ORDERED_KEY_FORMAT {
+            return Err(Error::MetaDataError);
+        }
+        match Self::new_block_info(&meta_data.build_options) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::MetaDataError)
+        }
+    }
+
+    fn check_if_path_exixsts(dir_path: &Path) -> bool {
+        fs::metadata(dir_path).is_ok()
     }
 
     fn map_io_result<M>(result: Result<M, IoError>) -> Result<M, Error> {
@@ -336,6 +801,9 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     }
 
     fn get_storage_idx(dir_path: &Path, build_options: Option<BuildOptions>) -> Result<StorageIdx, Error> {
+        if build_options.is_none() {
+            Self::upgrade_baseline(dir_path)?;
+        }
         let storage_idx = match Self::open_storage_idx(dir_path, build_options.clone()) {
             Ok(s_idx) => s_idx,
             Err(err) => {
@@ -371,14 +839,25 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
         let offset_file = Self::open_file(offset_path.as_path(), true)?;
         let meta_data_file = Self::open_file(meta_data_path.as_path(), true)?;
         let mut storage_idx = StorageIdx {
+            dir_path: PathBuf::from(dir_path),
             meta_data_file,
             offset_file,
-            data_file
+            data_file,
+            stats: StorageStats::default(),
+            offset_cache: None
         };
-        if build_options.is_some() {
+        if let Some(build_options) = build_options {
+            let now = MetaData::unix_time_now();
             let meta_data = MetaData {
+                meta_data_version: META_DATA_VERSION,
+                key_format: KEY_FORMAT,
+                value_size: mem::size_of::<U>() as u32,
                 num_values: 0,
-                build_options: build_options.unwrap()
+                build_options,
+                bitmap_tag: T::format_tag(),
+                library_version: MetaData::current_library_version(),
+                created_at: now,
+                modified_at: now
             };
             Self::write_empty_storage_idx(&mut storage_idx, &meta_data)?;
         }
@@ -387,18 +866,32 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     }
 
     fn write_empty_storage_idx(storage_idx: &mut StorageIdx, meta_data: &MetaData) -> Result<(), IoError> {
+        // the second copy is the last checkpoint.
         storage_idx.meta_data_file.write_all(Self::to_slice_u8(meta_data))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(meta_data))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(&[0u64; 4]))?;
+        storage_idx.stats.record_meta_data_write(2 * mem::size_of::<MetaData>() as u64 + 4 * mem::size_of::<u64>() as u64);
         Ok(())
     }
 
     
     fn get_meta_data(&self) -> MetaData {
         MetaData {
+            meta_data_version: META_DATA_VERSION,
+            key_format: KEY_FORMAT,
+            value_size: mem::size_of::<U>() as u32,
             num_values: self.num_values,
             build_options: BuildOptions {
                 bit_block_size: self.block_info.bit_block_size,
-                chunk_size: unsafe { mem::transmute::<u32, ChunkSize>(self.chunk_size as u32) }
-            }
+                chunk_size: unsafe { mem::transmute::<u32, ChunkSize>(self.chunk_size as u32) },
+                bitmap_capacity: self.bitmap_capacity,
+                wide_words: self.wide_words as u32,
+                bin_shift: self.block_info.bin_shift as u32
+            },
+            bitmap_tag: T::format_tag(),
+            library_version: MetaData::current_library_version(),
+            created_at: self.created_at,
+            modified_at: self.modified_at
         }
     }
 
@@ -414,9 +907,10 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
         unsafe { mem::transmute_copy::<M, M>(&*m) }
     }
 
+    #[allow(clippy::mut_from_ref)]
     fn convert_slice<M,N>(slice: &[M]) -> &mut [N] {
         let n: *mut N = Self::convert_pointer::<M,N>(slice.as_ptr()) as *mut N;
-        let len = slice.len() * mem::size_of::<M>() / mem::size_of::<N>();
+        let len = mem::size_of_val(slice) / mem::size_of::<N>();
         unsafe {
             std::slice::from_raw_parts_mut(n, len)
         }
@@ -435,17 +929,20 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     }
 
 
-    fn new_block_info(bit_block_size: usize) -> Result<BlockInfo, Error> {
+    fn new_block_info(build_options: &BuildOptions) -> Result<BlockInfo, Error> {
+        let bit_block_size = build_options.bit_block_size;
+        let bin_shift = build_options.bin_shift as usize;
         let bit_value_size = mem::size_of::<U>() << 3;
-        if bit_value_size % bit_block_size != 0 || bit_block_size > 16 || bit_block_size == 1 {
+        if !bit_value_size.is_multiple_of(bit_block_size) || bit_block_size > 16 || bit_block_size == 1 || bin_shift >= bit_value_size {
             return Err(Error::ParametersError);
         }
 
-        let num_blocks = bit_value_size / bit_block_size;
+        let num_blocks = (bit_value_size - bin_shift).div_ceil(bit_block_size);
         let num_bitmaps_in_block = 1 << bit_block_size;
         Ok(BlockInfo {
             bit_block_size,
             bit_block_mask: num_bitmaps_in_block - 1,
+            bin_shift,
             num_blocks,
             num_bitmaps_in_block            
         })
@@ -458,35 +955,72 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     }
 
     fn new_index(build_options: BuildOptions, is_storage_idx: bool) -> Result<Self, Error> {
-        let block_info = Self::new_block_info(build_options.bit_block_size)?;
+        let block_info = Self::new_block_info(&build_options)?;
         let num_bitmaps = block_info.num_blocks * block_info.num_bitmaps_in_block;
         let chunk_size: u64 = build_options.chunk_size as u64;
+        let created_at = MetaData::unix_time_now();
 
         let mut b_index = BitmapIndex {
             num_values: 0,
+            flushed_num_values: 0,
             chunk_size,
             chunk_size_mask: chunk_size - 1,
             
-            bitmaps: vec![T::new(); num_bitmaps],
+            bitmaps: Self::new_bitmaps(num_bitmaps, build_options.bitmap_capacity, build_options.wide_words != 0),
             block_info,
 
             storage_idx: None,
             chunk_offset: 0,
             chunks: None,
+            arena_chunks: None,
+            spill_chunks: None,
+            coarse_layer: None,
+            value_stats: None,
+            hot_chunks: None,
+            query_cache: None,
+            chunk_format: ChunkFormat::default(),
+            bitmap_capacity: build_options.bitmap_capacity,
+            wide_words: build_options.wide_words != 0,
+            max_query_results: None,
+            max_mask_expansion: MAX_MASK_EXPANSION,
             last_checkpoint: None,
+            checkpoint_extent: [0, 0],
+            user_meta_data: BTreeMap::new(),
+            created_at,
+            modified_at: created_at,
+            flush_listener: None,
+            flush_policy: None,
+            last_durable_write: Instant::now(),
+
+            #[cfg(feature = "parallel")]
+            thread_pool: None,
             
             _marker: std::marker::PhantomData,
         };
-        if is_storage_idx == false {
+        if !is_storage_idx {
             b_index.chunks = Some(Vec::new());
         }
         Ok(b_index)
     }
 
+    fn new_bitmaps(num_bitmaps: usize, bitmap_capacity: u32, wide_words: bool) -> Vec<T> {
+        let mut bitmaps: Vec<T> = if bitmap_capacity == 0 {
+            vec![T::new(); num_bitmaps]
+        } else {
+            // `vec![T::new(); n]` clones bitmaps and a cloned `Vec` doesn't keep its capacity.
+            (0..num_bitmaps).map(|_| T::with_capacity(bitmap_capacity)).collect()
+        };
+        if wide_words {
+            bitmaps.iter_mut().for_each(|b| b.set_wide_words(true));
+        }
+        bitmaps
+    }
+
     fn run_f_on_i_bitmaps(block_info: &BlockInfo, value: U, mut f: impl FnMut(usize)) {
+        let value = value.to_ordered();
         let mut i_block: usize = 0;
-        let mut i_bitmap = value.transmute_to_usize() & block_info.bit_block_mask;
-        let mut shift_value: usize = 0;
+        let mut shift_value: usize = block_info.bin_shift;
+        let mut i_bitmap = (value >> shift_value).transmute_to_usize() & block_info.bit_block_mask;
         f(i_bitmap);
 
         for _i in 1..block_info.num_blocks {
@@ -510,18 +1044,42 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
         self.num_values += 1;
 
         if self.num_values & self.chunk_size_mask != 0 {
+            if self.flush_policy.is_some() && self.num_values.is_multiple_of(auto_flush::CHECK_POLICY_INTERVAL) {
+                return self.apply_flush_policy();
+            }
             return Ok(());
         }
+        self.complete_chunk()
+    }
+
+    /// Serialize (in storage mode) or store the current chunk when it's full and
+    /// prepare the bitmaps for the next chunk.
+    fn complete_chunk(&mut self) -> Result<(), Error> {
         if self.storage_idx.is_some() {
             self.write_chunk()?;
-            self.bitmaps = vec![T::new(); self.bitmaps.len()];
-        } else if self.chunks.is_some() {
-            let chunks = self.chunks.as_mut().unwrap();
-            let mut bitmaps = vec![T::new(); self.bitmaps.len()];
+            self.clear_bitmaps();
+        } else if let Some(arena_chunks) = self.arena_chunks.as_mut() {
+            arena_chunks.push(Self::new_chunk_arena(&self.bitmaps)?);
+            Self::update_coarse_layer(&mut self.coarse_layer, (self.num_values - 1) / self.chunk_size, &self.bitmaps);
+            Self::update_value_stats(&mut self.value_stats, (self.num_values - 1) / self.chunk_size, 0, &self.bitmaps)?;
+            self.clear_bitmaps();
+            self.modified_at = MetaData::unix_time_now();
+        } else if let Some(chunks) = self.chunks.as_mut() {
+            Self::update_coarse_layer(&mut self.coarse_layer, (self.num_values - 1) / self.chunk_size, &self.bitmaps);
+            Self::update_value_stats(&mut self.value_stats, (self.num_values - 1) / self.chunk_size, 0, &self.bitmaps)?;
+            let mut bitmaps = Self::new_bitmaps(self.bitmaps.len(), self.bitmap_capacity, self.wide_words);
             mem::swap(&mut bitmaps, &mut self.bitmaps);
             chunks.push(bitmaps);
+            self.modified_at = MetaData::unix_time_now();
+        }
+        self.spill_over_limit()
+    }
+
+    /// Recycle the bitmaps of a chunk already serialized for the next chunk.
+    fn clear_bitmaps(&mut self) {
+        for b in self.bitmaps.iter_mut() {
+            b.clear();
         }
-        Ok(())
     }
 
     fn get_chunk_id(num_values: u64, chunk_size: u64) -> u64 {
@@ -540,6 +1098,36 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
         self.write_chunk()
     }
 
+    /// Return the number of values pushed in `BitmapIndex`.
+    pub fn num_values(&self) -> u64 {
+        self.num_values
+    }
+
+    /// Return the number of values pushed after the last flush, that are lost if the
+    /// process crashes. In memory mode all values are unflushed.
+    pub fn unflushed_values(&self) -> u64 {
+        self.num_values - self.flushed_num_values
+    }
+
+    /// Return `true` if there are values pushed after the last flush.
+    pub fn is_dirty(&self) -> bool {
+        self.unflushed_values() > 0
+    }
+
+    /// Return the current `MetaData`, that includes the unflushed values, and the
+    /// `MetaData` of the last checkpoint, that is the durable state of the index
+    /// saved from the last flush (`None` in memory mode).
+    pub fn snapshot_metadata(&self) -> (MetaData, Option<MetaData>) {
+        (self.get_meta_data(), self.last_checkpoint.clone())
+    }
+
+    /// Return `true` if `BitmapIndex` is opened in storage mode.
+    pub fn is_storage_mode(&self) -> bool {
+        self.storage_idx.is_some()
+    }
+
+    /// Return the size in bytes of the bitmaps of the current chunk once serialized
+    /// (see [`Bitmap::size`]), not the memory allocated for them.
     pub fn memory_bitmaps_size(&self) -> usize {
         let mut bitmaps_size = 0;
         for b in &self.bitmaps {
@@ -549,68 +1137,182 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     }
     
     fn write_chunk(&mut self) -> Result<(), Error> {
-        let num_bitmaps: usize = self.bitmaps.len();
-        let mut bitmaps_size: usize = 0;
-        let mut bitmaps_offset: Vec<u32> = vec![0; num_bitmaps + 1];
-        let bitmap_start_offset: u32 = (bitmaps_offset.len() * mem::size_of::<u32>()) as u32;
-        let mut i: usize = 1;
-        bitmaps_offset[0] = bitmap_start_offset;
-        for b in &self.bitmaps {
-            bitmaps_size += b.size();
-            bitmaps_offset[i] = bitmap_start_offset + bitmaps_size as u32;
-            i += 1;
-        }
-        
-        let mut bitmaps_content: Vec<u8> = vec![0; bitmaps_size];
-        if let Err(_) = self.write_bitmaps_into_buffer(&mut bitmaps_content) {
-            return Err(Error::BitmapError);
-        };
-        let chunk_id = Self::get_chunk_id(self.num_values, self.chunk_size);
+        let chunk_format = self.flush_chunk_format(&self.bitmaps);
+        let (chunk_header, bitmaps_size) = Self::chunk_header(&self.bitmaps, chunk_format);
+        let chunk_id = Self::get_chunk_id(self.num_values.saturating_sub(1), self.chunk_size);
         let block_offset: u64 = Self::get_block_offset(chunk_id);
-        let b_offsets = Self::convert_slice(&bitmaps_offset);
+        
+        let chunk_data_size = (chunk_header.len() + CHUNK_TRAILER_SIZE) as u64 + bitmaps_size;
+        let reused_offset = self.reused_chunk_offset(chunk_id, chunk_data_size)?;
         
         let storage_idx: &mut StorageIdx = self.storage_idx.as_mut().unwrap();
         let storage_idx2: &'static mut StorageIdx = unsafe { &mut*(storage_idx as *mut StorageIdx)};
+        if let Some(offset) = reused_offset {
+            Self::map_io_result(self.write_checkpoint(storage_idx2))?;
+            self.chunk_offset = offset;
+        }
+        let chunk_start_offset = self.chunk_offset;
+        self.modified_at = MetaData::unix_time_now();
+        if reused_offset.is_some() {
+            // the versions after the new one are truncated, so it's also the checkpoint.
+            self.last_checkpoint = Some(self.get_meta_data());
+            self.checkpoint_extent = [chunk_start_offset, chunk_start_offset + chunk_data_size];
+        }
         self.chunk_offset = Self::map_io_result(
-            self.write_bitmaps_sync(storage_idx2, &b_offsets, &bitmaps_content, block_offset)
+            self.write_bitmaps_sync(storage_idx2, chunk_format, &chunk_header, bitmaps_size, block_offset)
         )?;
+        if reused_offset.is_some() {
+            Self::map_io_result(storage_idx2.data_file.set_len(self.chunk_offset))?;
+        }
         self.last_checkpoint = Some(self.get_meta_data());
+        self.checkpoint_extent = [chunk_start_offset, self.chunk_offset];
+        self.flushed_num_values = self.num_values;
+        self.last_durable_write = Instant::now();
+        Self::update_coarse_layer(&mut self.coarse_layer, chunk_id, &self.bitmaps);
+        if self.num_values & self.chunk_size_mask == 0 {
+            Self::update_hot_tier(&mut self.hot_chunks, chunk_id, &self.bitmaps);
+            Self::update_value_stats(&mut self.value_stats, chunk_id, chunk_start_offset, &self.bitmaps)?;
+        }
+        self.notify_flush(chunk_id, self.chunk_offset - chunk_start_offset);
+
+        Ok(())
+    }
+
+    /// Return the offset where the chunk `chunk_id` of `chunk_data_size` bytes is written
+    /// over the older versions of the partial chunk, or `None` if it's appended. Every
+    /// flush of a partial chunk writes a new version and the last flushed one is kept
+    /// until the new one is durable, so a version is written at the start of the older
+    /// versions when it fits before the last flushed one: the versions of a chunk take
+    /// about three times its size, regardless of the number of flushes.
+    fn reused_chunk_offset(&mut self, chunk_id: u64, chunk_data_size: u64) -> Result<Option<u64>, Error> {
+        let flushed_chunk_id = Self::get_chunk_id(self.flushed_num_values, self.chunk_size);
+        if self.flushed_num_values & self.chunk_size_mask == 0 || flushed_chunk_id != chunk_id {
+            return Ok(None);
+        }
+        let storage_idx: &mut StorageIdx = self.storage_idx.as_mut().ok_or(Error::ParametersError)?;
+        let last_offset = self.checkpoint_extent[0];
+        let versions_start = Self::chunk_versions_start(&mut storage_idx.data_file, chunk_id, last_offset)?;
+        Ok(Some(versions_start).filter(|start| start + chunk_data_size <= last_offset))
+    }
 
+    /// Write the last checkpoint as current meta data too, so the data file has only the
+    /// last flushed version of the partial chunk and the older ones can be overwritten.
+    fn write_checkpoint(&self, storage_idx: &mut StorageIdx) -> Result<(), IoError> {
+        let checkpoint: &MetaData = self.last_checkpoint.as_ref().ok_or_else(|| IoError::from(ErrorKind::InvalidData))?;
+        storage_idx.meta_data_file.seek(SeekFrom::Start(0))?;
+        Self::write_all_vectored(&mut storage_idx.meta_data_file, &mut [
+            IoSlice::new(Self::to_slice_u8(checkpoint)),
+            IoSlice::new(Self::to_slice_u8(checkpoint)),
+            IoSlice::new(Self::to_slice_u8(&self.checkpoint_extent)),
+            IoSlice::new(Self::to_slice_u8(&self.checkpoint_extent))
+        ])?;
+        storage_idx.stats.record_meta_data_write(2 * mem::size_of::<MetaData>() as u64 + 4 * mem::size_of::<u64>() as u64);
         Ok(())
     }
 
-    fn write_bitmaps_sync(&mut self, storage_idx: &mut StorageIdx, bitmaps_offsets: &[u8], bitmaps_content: &[u8], block_offset: u64) -> Result<u64, IoError> {
+    fn write_bitmaps_sync(&mut self, storage_idx: &mut StorageIdx, chunk_format: ChunkFormat, chunk_header: &[u8], bitmaps_size: u64, block_offset: u64) -> Result<u64, IoError> {
+        let chunk_id = Self::get_chunk_id(self.num_values.saturating_sub(1), self.chunk_size);
+        let trailer = self.chunk_trailer(chunk_id, self.num_values, chunk_header, bitmaps_size);
+        let chunk_bitmaps = self.bitmaps.iter().filter(|b| Self::chunk_writes_bitmap(chunk_format, b));
         storage_idx.data_file.seek(SeekFrom::Start(self.chunk_offset))?;
-        storage_idx.data_file.write_all(bitmaps_offsets)?;
-        storage_idx.data_file.write_all(bitmaps_content)?;
+        if trailer.size() <= WRITE_BUFFER_SIZE as u64 {
+            // A small chunk is serialized in memory and written with a single vectored
+            // write of the header, the bitmaps and the trailer.
+            let mut bitmaps_content: Vec<u8> = Vec::with_capacity(bitmaps_size as usize);
+            for b in chunk_bitmaps {
+                b.write_to(&mut bitmaps_content)?;
+            }
+            Self::write_all_vectored(&mut storage_idx.data_file, &mut [
+                IoSlice::new(chunk_header),
+                IoSlice::new(&bitmaps_content),
+                IoSlice::new(Self::to_slice_u8(&trailer))
+            ])?;
+        } else {
+            // Bitmaps of a bigger chunk are serialized directly into the buffered data
+            // file, so the content of the whole chunk is never allocated.
+            let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &mut storage_idx.data_file);
+            writer.write_all(chunk_header)?;
+            for b in chunk_bitmaps {
+                b.write_to(&mut writer)?;
+            }
+            writer.write_all(Self::to_slice_u8(&trailer))?;
+            writer.flush()?;
+        }
+
+        self.write_chunk_extent(storage_idx, block_offset, trailer.size())
+    }
 
-        let chunk_data_size: u64 = (bitmaps_offsets.len() + bitmaps_content.len()) as u64;
+    /// Write the start and end offset of the chunk of `chunk_data_size` bytes written at
+    /// `chunk_offset` and the meta data, then return the end offset of the chunk.
+    fn write_chunk_extent(&self, storage_idx: &mut StorageIdx, block_offset: u64, chunk_data_size: u64) -> Result<u64, IoError> {
         let chunk_next_offset: u64 = self.chunk_offset + chunk_data_size;
-        storage_idx.offset_file.seek(SeekFrom::Start(block_offset))?;        
-        storage_idx.offset_file.write_all(&chunk_next_offset.to_ne_bytes())?;
+        // Write start and end offset of the chunk, the end offset is also the
+        // start offset of the next chunk.
+        storage_idx.offset_file.seek(SeekFrom::Start(block_offset))?;
+        Self::write_all_vectored(&mut storage_idx.offset_file, &mut [
+            IoSlice::new(&self.chunk_offset.to_ne_bytes()),
+            IoSlice::new(&chunk_next_offset.to_ne_bytes())
+        ])?;
 
         let meta_data: MetaData = self.get_meta_data();
+        let chunk_extent: [u64; 2] = [self.chunk_offset, chunk_next_offset];
         storage_idx.meta_data_file.seek(SeekFrom::Start(0))?;
-        storage_idx.meta_data_file.write_all(Self::to_slice_u8(&meta_data))?;
-        storage_idx.meta_data_file.write_all(Self::to_slice_u8(&self.last_checkpoint))?;
+        Self::write_all_vectored(&mut storage_idx.meta_data_file, &mut [
+            IoSlice::new(Self::to_slice_u8(&meta_data)),
+            IoSlice::new(Self::to_slice_u8(&self.last_checkpoint)),
+            IoSlice::new(Self::to_slice_u8(&chunk_extent)),
+            IoSlice::new(Self::to_slice_u8(&self.checkpoint_extent))
+        ])?;
+        storage_idx.stats.data_bytes_written += chunk_data_size;
+        storage_idx.stats.offset_bytes_written += 2 * mem::size_of::<u64>() as u64;
+        storage_idx.stats.record_meta_data_write(2 * mem::size_of::<MetaData>() as u64 + 4 * mem::size_of::<u64>() as u64);
+        storage_idx.stats.chunk_flushes += 1;
         
         Ok(chunk_next_offset)
     }
 
-
-    fn write_bitmaps_into_buffer(&mut self, buf: &mut [u8]) -> Result<(), ()> {
-        let mut start_offest: usize = 0;
-        for b in &self.bitmaps {
-            let b_size = b.write_to_buffer(&mut buf[start_offest..])?;
-            start_offest += b_size;
+    /// Write all `bufs` in `file`, usually with a single `write_vectored` call. It's used
+    /// for the offsets, the meta data and the chunks up to `WRITE_BUFFER_SIZE` bytes.
+    fn write_all_vectored(file: &mut fs::File, mut bufs: &mut [IoSlice<'_>]) -> Result<(), IoError> {
+        while !bufs.is_empty() {
+            match file.write_vectored(bufs) {
+                Ok(0) => return Err(IoError::from(ErrorKind::WriteZero)),
+                Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {},
+                Err(err) => return Err(err)
+            }
         }
         Ok(())
     }
 
-    /// Insert (in append) values into the index.
+
+    /// Insert (in append) values into the index. Values are inserted in batches: the
+    /// bitmaps to set for a batch are computed first and then set grouped by bitmap.
     pub fn push_values(&mut self, values: &[U]) -> Result<(), Error> {
-        for v in values {
-            self.push_value(*v)?;
+        let mut values = values;
+        // each set is encoded as `i_bitmap << 32 | position in the chunk`, so sorting
+        // the sets groups them by bitmap keeping the positions in increasing order.
+        let mut sets: Vec<u64> = Vec::with_capacity(PUSH_BATCH_SIZE * self.block_info.num_blocks);
+        while !values.is_empty() {
+            let num_values_in_chunk = self.num_values & self.chunk_size_mask;
+            let batch_size = (self.chunk_size - num_values_in_chunk).min(PUSH_BATCH_SIZE as u64) as usize;
+            let (batch, next_values) = values.split_at(batch_size.min(values.len()));
+            sets.clear();
+            for (i, v) in batch.iter().enumerate() {
+                let position = num_values_in_chunk + i as u64;
+                Self::run_f_on_i_bitmaps(&self.block_info, *v, |i_bitmap| sets.push(((i_bitmap as u64) << 32) | position));
+            }
+            sets.sort_unstable();
+            for set in sets.iter() {
+                self.bitmaps[(set >> 32) as usize].set(*set as u32);
+            }
+            self.num_values += batch.len() as u64;
+            if self.num_values & self.chunk_size_mask == 0 {
+                self.complete_chunk()?;
+            } else if self.flush_policy.is_some() {
+                self.apply_flush_policy()?;
+            }
+            values = next_values;
         }
         Ok(())
     }
@@ -619,97 +1321,248 @@ for <'a> &'a T: BitAnd<&'a T, Output=T> {
     /// in `BitmapIndex` equal to `value`. The parameters `start_index` and `end_index`
     /// are optional and if specified define the range where query is runned.
     pub fn run_query(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
-        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let mut indexes: Vec<u64> = Vec::new();
+        self.run_query_into(value, start_index, end_index, &mut indexes)?;
+        Ok(indexes)
+    }
 
+    /// Like `run_query`, but append the indexes to `indexes`, so a result buffer can be
+    /// reused between queries. If an error occurs `indexes` is left unchanged.
+    pub fn run_query_into(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>, indexes: &mut Vec<u64>) -> Result<(), Error> {
         let start_index: u64 = start_index.unwrap_or(0);
         let end_index: u64 = end_index.unwrap_or(self.num_values);
-        let mut chunk_id = 0;
-        let mut indexes: Vec<u64> = Vec::new();
-        
-        if self.chunks.is_some() {
-            let chunks: &Vec<Vec<T>> = self.chunks.as_ref().unwrap();
-            for bitmaps in chunks {
-                let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
-                    .map(|i_bitmap| &bitmaps[*i_bitmap]).collect();
-                Self::push_indexes(&query_bitmaps, chunk_id, self.chunk_size, start_index, end_index, &mut indexes);
-                chunk_id += 1;
+        if self.query_cache.is_some() {
+            return self.run_query_cached(value, start_index, end_index, indexes);
+        }
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let chunk_size = self.chunk_size;
+        let max_query_results = self.max_query_results.unwrap_or(u64::MAX);
+        let initial_len = indexes.len();
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            Self::push_indexes(query_bitmaps, chunk_id, chunk_size, start_index, end_index, indexes);
+            if (indexes.len() - initial_len) as u64 > max_query_results {
+                return Err(Error::TooManyResults(max_query_results));
             }
-        } else if self.storage_idx.is_some() {
-            let meta_data = self.get_meta_data(); 
-            let storage_idx = self.storage_idx.as_mut().unwrap();
-            chunk_id = self.num_values / self.chunk_size;
-            let end_index = end_index - (end_index & self.chunk_size_mask);
-            let s_indexes = Self::run_query_from_storage_idx(storage_idx, value, Some(start_index), Some(end_index), Some(meta_data))?;
-            indexes = s_indexes;
+            Ok(ControlFlow::Continue(()))
+        };
+        let result = self.try_for_each_chunk(&query_i_bitmaps, start_index, end_index, f);
+        if result.is_err() {
+            indexes.truncate(initial_len);
         }
-        let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
-            .map(|i_bitmap| &self.bitmaps[*i_bitmap]).collect();
-        Self::push_indexes(&query_bitmaps, chunk_id, self.chunk_size, start_index, end_index, &mut indexes);
-
-        Ok(indexes)
+        result.map(|_| ())
     }
 
     /// Return a `Vec<u64>` that contains all indexes of values pushed in a storage `BitmapIndex`
     /// equal to `value`. Differently from `run_query` method allow to run a query only on
     /// the chunks already flushed of a `BitmapIndex`.
     pub fn run_query_from_storage_idx(storage_idx: &mut StorageIdx, value: U, start_index: Option<u64>, end_index: Option<u64>, meta_data: Option<MetaData>) -> Result<Vec<u64>, Error> {
-        let m_data = if meta_data.is_some() {
-            meta_data.unwrap()
+        let m_data = if let Some(meta_data) = meta_data {
+            meta_data
         } else {
-            let meta_data_r = Self::read_meta_data(storage_idx);
-            let meta_data = Self::map_io_result(meta_data_r)?;
+            let meta_data = Self::read_meta_data(storage_idx)?;
+            Self::check_meta_data(&meta_data.0)?;
             meta_data.0
         };
-        let block_info = Self::new_block_info(m_data.build_options.bit_block_size)?;
-        let mut start_index = start_index.unwrap_or(0);
-        if start_index > m_data.num_values {
-            return Ok(Vec::new())
-        }
-        let mut end_index = end_index.unwrap_or(m_data.num_values);
-        if end_index > m_data.num_values {
-            end_index = m_data.num_values;
-        }
+        let block_info = Self::new_block_info(&m_data.build_options)?;
+        let start_index = start_index.unwrap_or(0);
+        let end_index = end_index.unwrap_or(m_data.num_values);
         let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&block_info, value);
+        let chunk_size = m_data.build_options.chunk_size as u64;
 
         let mut indexes: Vec<u64> = Vec::new();
-        const MAX_NUM_OFFSETS: usize = 1 << 13;
-        let chunk_size = m_data.build_options.chunk_size as u64;
-        let mut chunk_id = 0;
-        while start_index < end_index {
-            let chunks_offsets_r = Self::read_chunks_offsets(storage_idx, start_index, end_index, chunk_size, MAX_NUM_OFFSETS, 0);
-            let chunks_offsets: Vec<u64> = Self::map_io_result(chunks_offsets_r)?;
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            Self::push_indexes(query_bitmaps, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+        };
+        Self::for_each_storage_chunk(storage_idx, &query_i_bitmaps, chunk_size, m_data.num_values, start_index, end_index, f)?;
+
+        Ok(indexes)
+    }
+
+    /// Read the query bitmaps of each flushed chunk that intersect `[start_index, end_index]`
+    /// and call `f` with the chunk id and the read bitmaps.
+    fn for_each_storage_chunk(storage_idx: &mut StorageIdx, query_i_bitmaps: &[usize], chunk_size: u64, num_values: u64, start_index: u64, end_index: u64, mut f: impl FnMut(u64, &[&T])) -> Result<(), Error> {
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            f(chunk_id, query_bitmaps);
+            Ok(ControlFlow::Continue(()))
+        };
+        Self::try_for_each_storage_chunk(storage_idx, query_i_bitmaps, chunk_size, num_values, start_index, end_index, |_chunk_id| false, f).map(|_| ())
+    }
+
+    /// Like `for_each_storage_chunk`, but stop at the first error or `ControlFlow::Break`
+    /// returned from `f`. The bitmaps of a chunk where `skip_chunk` returns `true` are not
+    /// read and `f` is called with empty bitmaps.
+    #[allow(clippy::too_many_arguments)]
+    fn try_for_each_storage_chunk(storage_idx: &mut StorageIdx, query_i_bitmaps: &[usize], chunk_size: u64, num_values: u64, start_index: u64, end_index: u64, skip_chunk: impl Fn(u64) -> bool, mut f: impl FnMut(u64, &[&T]) -> Result<ControlFlow<()>, Error>) -> Result<ControlFlow<()>, Error> {
+        const MAX_NUM_OFFSETS: u64 = 1 << 13;
+        let num_chunks = Self::get_chunk_id(num_values, chunk_size);
+        let end_chunk_id = num_chunks.min(Self::get_chunk_id(end_index, chunk_size) + 1);
+        let mut chunk_id = Self::get_chunk_id(start_index, chunk_size);
+        let mut bytes_read: u64 = 0;
+        let mut control_flow = ControlFlow::Continue(());
+        
+        'chunks: while chunk_id < end_chunk_id {
+            let num_offsets = (end_chunk_id - chunk_id).min(MAX_NUM_OFFSETS) as usize;
+            let chunks_offsets: Vec<u64> = Self::map_io_result(Self::read_full_chunk_offsets(storage_idx, chunk_id, num_offsets, &mut bytes_read))?;
             for chunk_offset in chunks_offsets.iter() {
-                let query_bitmaps = Self::read_query_bitmaps(storage_idx, *chunk_offset, &query_i_bitmaps)?;
-                let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().map(|q| q).collect();
-                Self::push_indexes(&query_bitmaps_ref, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+                let query_bitmaps = if skip_chunk(chunk_id) {
+                    vec![T::new(); query_i_bitmaps.len()]
+                } else {
+                    let mut reader = CountingReader::new(&mut storage_idx.data_file);
+                    let bitmaps_offset = Self::read_full_chunk_bitmaps_offsets(&mut storage_idx.offset_cache, &mut reader, *chunk_offset, query_i_bitmaps)?;
+                    let query_bitmaps = Self::read_bitmaps_at(&mut reader, bitmaps_offset)?;
+                    bytes_read += reader.bytes_read;
+                    query_bitmaps
+                };
+                let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                if f(chunk_id, &query_bitmaps_ref)?.is_break() {
+                    control_flow = ControlFlow::Break(());
+                    break 'chunks;
+                }
                 chunk_id += 1;
             }
-
-            start_index += (chunks_offsets.len() as u64) * chunk_size;
         }
+        storage_idx.stats.record_query(bytes_read);
+        Ok(control_flow)
+    }
 
-        Ok(indexes)
+    /// Call `f` with the chunk id and the `query_i_bitmaps` bitmaps of each chunk (in memory,
+    /// spilled to disk, in a chunk arena, flushed on storage or current) that intersect `[start_index, end_index]`.
+    fn for_each_chunk(&mut self, query_i_bitmaps: &[usize], start_index: u64, end_index: u64, mut f: impl FnMut(u64, &[&T])) -> Result<(), Error> {
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            f(chunk_id, query_bitmaps);
+            Ok(ControlFlow::Continue(()))
+        };
+        self.try_for_each_chunk(query_i_bitmaps, start_index, end_index, f).map(|_| ())
+    }
+
+    /// Like `for_each_chunk`, but stop at the first error or `ControlFlow::Break`
+    /// returned from `f`.
+    fn try_for_each_chunk(&mut self, query_i_bitmaps: &[usize], start_index: u64, end_index: u64, f: impl FnMut(u64, &[&T]) -> Result<ControlFlow<()>, Error>) -> Result<ControlFlow<()>, Error> {
+        self.try_for_each_chunk_coarse(query_i_bitmaps, start_index, end_index, true, f)
+    }
+
+    /// Like `for_each_chunk`, but `f` always receives the bitmaps of each chunk, also
+    /// when the coarse layer shows that the "logical and" of the chunk is empty. Used
+    /// from callers that don't "and" all query bitmaps of a chunk.
+    fn for_each_chunk_exact(&mut self, query_i_bitmaps: &[usize], start_index: u64, end_index: u64, mut f: impl FnMut(u64, &[&T])) -> Result<(), Error> {
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            f(chunk_id, query_bitmaps);
+            Ok(ControlFlow::Continue(()))
+        };
+        self.try_for_each_chunk_coarse(query_i_bitmaps, start_index, end_index, false, f).map(|_| ())
+    }
+
+    /// Call `f` like `try_for_each_chunk`. With `use_coarse == true` the bitmaps of a
+    /// chunk where the coarse layer shows an empty query bitmap are not read and `f`
+    /// is called with empty bitmaps, since their "logical and" is empty.
+    fn try_for_each_chunk_coarse(&mut self, query_i_bitmaps: &[usize], start_index: u64, end_index: u64, use_coarse: bool, mut f: impl FnMut(u64, &[&T]) -> Result<ControlFlow<()>, Error>) -> Result<ControlFlow<()>, Error> {
+        let coarse_layer = if use_coarse { &self.coarse_layer } else { &None };
+        let mut chunk_id = 0;
+        if let Some(chunks) = self.chunks.as_ref() {
+            let num_spilled_chunks = self.spilled_chunks() as usize;
+            for i in 0..num_spilled_chunks {
+                if Self::chunk_in_range(chunk_id, self.chunk_size, start_index, end_index) {
+                    let query_bitmaps = if Self::coarse_chunk_is_empty(coarse_layer, chunk_id, query_i_bitmaps) {
+                        vec![T::new(); query_i_bitmaps.len()]
+                    } else {
+                        Self::spilled_query_bitmaps(self.spill_chunks.as_ref().unwrap(), i, query_i_bitmaps)?
+                    };
+                    let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                    if f(chunk_id, &query_bitmaps_ref)?.is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+                chunk_id += 1;
+            }
+            for bitmaps in chunks {
+                if Self::chunk_in_range(chunk_id, self.chunk_size, start_index, end_index) {
+                    let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
+                        .map(|i_bitmap| &bitmaps[*i_bitmap]).collect();
+                    if f(chunk_id, &query_bitmaps)?.is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+                chunk_id += 1;
+            }
+            for arena in self.arena_chunks.iter().flatten() {
+                if Self::chunk_in_range(chunk_id, self.chunk_size, start_index, end_index) {
+                    let query_bitmaps = if Self::coarse_chunk_is_empty(coarse_layer, chunk_id, query_i_bitmaps) {
+                        vec![T::new(); query_i_bitmaps.len()]
+                    } else {
+                        Self::arena_query_bitmaps(arena, query_i_bitmaps)?
+                    };
+                    let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                    if f(chunk_id, &query_bitmaps_ref)?.is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+                chunk_id += 1;
+            }
+        } else if let Some(storage_idx) = self.storage_idx.as_mut() {
+            chunk_id = self.num_values / self.chunk_size;
+            // Chunks of the hot tier are the most recent full chunks, older chunks are read.
+            let first_hot_chunk_id = self.hot_chunks.as_ref().map_or(chunk_id, |hot_chunks| hot_chunks.first_chunk_id());
+            let skip_chunk = |chunk_id: u64| Self::coarse_chunk_is_empty(coarse_layer, chunk_id, query_i_bitmaps);
+            if Self::try_for_each_storage_chunk(storage_idx, query_i_bitmaps, self.chunk_size, first_hot_chunk_id * self.chunk_size, start_index, end_index, skip_chunk, &mut f)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+            for (hot_chunk_id, bitmaps) in self.hot_chunks.iter().flat_map(|hot_chunks| hot_chunks.chunks()) {
+                if Self::chunk_in_range(hot_chunk_id, self.chunk_size, start_index, end_index) {
+                    let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
+                        .map(|i_bitmap| &bitmaps[*i_bitmap]).collect();
+                    if f(hot_chunk_id, &query_bitmaps)?.is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+            }
+        }
+        if Self::chunk_in_range(chunk_id, self.chunk_size, start_index, end_index) {
+            let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
+                .map(|i_bitmap| &self.bitmaps[*i_bitmap]).collect();
+            return f(chunk_id, &query_bitmaps);
+        }
+        Ok(ControlFlow::Continue(()))
     }
 
     fn push_indexes(query_bitmaps: &[&T], chunk_id: u64, chunk_size: u64, start_index: u64, end_index: u64, indexes: &mut Vec<u64>)
     {
-        let offset_index: u64 = chunk_id * chunk_size;
-        if offset_index + chunk_size < start_index || offset_index > end_index {
+        if !Self::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
             return;
         }
-        let mut b_result: T = query_bitmaps[0].clone();
-        for i in 1..query_bitmaps.len() {
-            b_result = (&b_result) & query_bitmaps[i];
-        }
-        // let new_indexes: Vec<u64> = b_result.unroll_bitmap().iter()
-        //                .map(|idx| offset_index + *idx as u64)
-        //                .filter(|idx| *idx >= start_index && *idx <= end_index).collect();
+        let offset_index: u64 = chunk_id * chunk_size;
+        let b_result: T = Self::and_bitmaps(query_bitmaps);
         indexes.extend(b_result.unroll_bitmap().iter()
                        .map(|idx| offset_index + *idx as u64)
                        .filter(|idx| *idx >= start_index && *idx <= end_index)
         );
     }
 
+    fn count_indexes(query_bitmaps: &[&T], chunk_id: u64, chunk_size: u64, start_index: u64, end_index: u64) -> u64 {
+        if !Self::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
+            return 0;
+        }
+        let offset_index: u64 = chunk_id * chunk_size;
+        let b_result: T = Self::and_bitmaps(query_bitmaps);
+        b_result.unroll_bitmap().iter()
+            .map(|idx| offset_index + *idx as u64)
+            .filter(|idx| *idx >= start_index && *idx <= end_index)
+            .count() as u64
+    }
+
+    /// Return `true` if the chunk `chunk_id` has values in `[start_index, end_index]`.
+    fn chunk_in_range(chunk_id: u64, chunk_size: u64, start_index: u64, end_index: u64) -> bool {
+        let offset_index: u64 = chunk_id * chunk_size;
+        offset_index + chunk_size > start_index && offset_index <= end_index
+    }
+
+    fn and_bitmaps(query_bitmaps: &[&T]) -> T {
+        let mut b_result: T = query_bitmaps[0].clone();
+        for query_bitmap in query_bitmaps.iter().skip(1) {
+            b_result = (&b_result) & *query_bitmap;
+        }
+        b_result
+    }
+
     fn get_query_i_bitmaps(block_info: &BlockInfo, value: U) -> Vec<usize> {
         let mut query_i_bitmaps: Vec<usize> = Vec::new();
         
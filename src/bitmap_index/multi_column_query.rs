@@ -0,0 +1,89 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Multi column queries
+//!
+//! Queries of predicates on several `DynBitmapIndex` built on the same rows (i.e. the
+//! columns of a table). Each column is queried on its own thread and the indexes of the
+//! columns are combined after the queries, so columns can have different `ChunkSize`
+//! and a different number of values.
+
+use std::ops::BitAnd;
+use std::panic;
+use std::thread;
+
+use super::{
+    Bitmap,
+    DynBitmapIndex,
+    DynValue,
+    Error
+};
+
+/// `ColumnCombine` defines how the indexes of the columns of a multi column query are
+/// combined: with `And` a row matches if it matches the predicate of every column, with
+/// `Or` if it matches the predicate of at least one column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnCombine {
+    And,
+    Or
+}
+
+impl<T: Bitmap + Send> DynBitmapIndex<T>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a sorted `Vec<u64>` that contains all indexes of rows that match the
+    /// predicates combined with `combine`, where each predicate is a column and the
+    /// value that the column must be equal to. The columns are queried in parallel, one
+    /// thread for each column. The parameters `start_index` and `end_index` are optional
+    /// and if specified define the range where query is runned.
+    pub fn run_query_columns(predicates: Vec<(&mut DynBitmapIndex<T>, DynValue)>, combine: ColumnCombine, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let columns_results: Vec<Result<Vec<u64>, Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = predicates.into_iter()
+                .map(|(column, value)| scope.spawn(move || column.run_query_dyn(value, start_index, end_index)))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|err| panic::resume_unwind(err)))
+                .collect()
+        });
+        let mut columns_indexes: Vec<Vec<u64>> = Vec::with_capacity(columns_results.len());
+        for column_result in columns_results {
+            columns_indexes.push(column_result?);
+        }
+
+        match combine {
+            ColumnCombine::And => {
+                columns_indexes.sort_unstable_by_key(|indexes| indexes.len());
+                let mut columns_indexes = columns_indexes.into_iter();
+                let mut indexes = columns_indexes.next().unwrap_or_default();
+                for column_indexes in columns_indexes {
+                    indexes = intersect_sorted(&indexes, &column_indexes);
+                }
+                Ok(indexes)
+            },
+            ColumnCombine::Or => {
+                let mut indexes: Vec<u64> = columns_indexes.concat();
+                indexes.sort_unstable();
+                indexes.dedup();
+                Ok(indexes)
+            }
+        }
+    }
+}
+
+/// Return the indexes in both sorted `indexes_0` and `indexes_1`.
+fn intersect_sorted(indexes_0: &[u64], indexes_1: &[u64]) -> Vec<u64> {
+    let mut intersection: Vec<u64> = Vec::with_capacity(indexes_0.len().min(indexes_1.len()));
+    let mut j: usize = 0;
+    for idx in indexes_0 {
+        j += indexes_1[j..].partition_point(|idx_1| idx_1 < idx);
+        if j == indexes_1.len() {
+            break;
+        }
+        if indexes_1[j] == *idx {
+            intersection.push(*idx);
+        }
+    }
+    intersection
+}
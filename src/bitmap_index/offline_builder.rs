@@ -0,0 +1,288 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Offline builder
+//!
+//! `OfflineBuilder` builds a `BitmapIndex` in storage mode with bounded memory, for
+//! datasets much larger than RAM. The bitmaps of a chunk are never kept in memory:
+//! each value pushed adds a posting (bitmap id, position in the chunk) for each
+//! block to a buffer of at most `max_memory_bytes`. A full buffer is sorted and
+//! spilled in a run file of the temporary directory. When a chunk is full the runs
+//! are merged (external sort) and each bitmap is built in order, from its sorted
+//! postings, and serialized in a content file. Then the chunk is appended to the
+//! index, in `Plain` format (`WideOffsets` if larger than 4 GiB), copying the content
+//! file, so only one bitmap at a time is in memory.
+//!
+//! # Encoding
+//! run file:
+//!  |u64 posting|...|
+//! where each posting is `bitmap_id << 32 | position` and postings are sorted, so they
+//! are grouped by bitmap with increasing positions.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    MetaData,
+    StorageIdx,
+    TransmuteToUsize,
+    WRITE_BUFFER_SIZE
+};
+
+/// Size of the buffer used to read each run file while merging.
+const RUN_READ_BUFFER_SIZE: usize = 1 << 16;
+
+/// `OfflineBuilder` struct that builds a `BitmapIndex` spilling its postings to
+/// temporary files, see the module documentation.
+pub struct OfflineBuilder<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    index: BitmapIndex<T, U>,
+    dir_path: PathBuf,
+    tmp_dir: PathBuf,
+    max_postings: usize,
+    postings: Vec<u64>,
+    runs: Vec<PathBuf>,
+    num_spilled_runs: u64
+}
+
+impl<T: Bitmap, U: BitValue> OfflineBuilder<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `OfflineBuilder` of a `BitmapIndex` created in `dir_path`, that must
+    /// not exist. Run files are written in the directory `tmp_dir`, that must exist, and
+    /// the postings buffered in memory take at most `max_memory_bytes` bytes.
+    pub fn new(dir_path: &Path, build_options: BuildOptions, tmp_dir: &Path, max_memory_bytes: usize) -> Result<Self, Error> {
+        if !tmp_dir.is_dir() {
+            return Err(Error::ParametersError);
+        }
+        let max_postings = (max_memory_bytes / mem::size_of::<u64>()).max(1);
+        let index = BitmapIndex::create(dir_path, build_options)?;
+        Ok(OfflineBuilder {
+            index,
+            dir_path: PathBuf::from(dir_path),
+            tmp_dir: PathBuf::from(tmp_dir),
+            max_postings,
+            postings: Vec::new(),
+            runs: Vec::new(),
+            num_spilled_runs: 0
+        })
+    }
+
+    /// Insert (in append) `values` into the index.
+    pub fn push_values(&mut self, values: &[U]) -> Result<(), Error> {
+        for value in values {
+            let position = self.index.num_values & self.index.chunk_size_mask;
+            let postings = &mut self.postings;
+            BitmapIndex::<T, U>::run_f_on_i_bitmaps(&self.index.block_info, *value, |i_bitmap| {
+                postings.push(((i_bitmap as u64) << 32) | position);
+            });
+            self.index.num_values += 1;
+            if self.postings.len() >= self.max_postings {
+                self.spill_postings()?;
+            }
+            if self.index.num_values & self.index.chunk_size_mask == 0 {
+                self.write_chunk()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the number of values pushed.
+    pub fn num_values(&self) -> u64 {
+        self.index.num_values
+    }
+
+    /// Return the number of run files spilled since the builder was created.
+    pub fn num_spilled_runs(&self) -> u64 {
+        self.num_spilled_runs
+    }
+
+    /// Write the last partial chunk, remove the temporary files and return the
+    /// `BitmapIndex` opened in storage mode.
+    pub fn finish(mut self) -> Result<BitmapIndex<T, U>, Error> {
+        if self.index.num_values & self.index.chunk_size_mask != 0 {
+            self.write_chunk()?;
+        }
+        let dir_path = self.dir_path.clone();
+        drop(self);
+        BitmapIndex::open(&dir_path)
+    }
+
+    /// Sort the buffered postings and write them in a new run file.
+    fn spill_postings(&mut self) -> Result<(), Error> {
+        self.postings.sort_unstable();
+        let run_path = self.tmp_file_path(&format!("run{}", self.runs.len()));
+        self.runs.push(run_path.clone());
+        let write_run = || -> Result<(), IoError> {
+            let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, File::create(&run_path)?);
+            for posting in self.postings.iter() {
+                writer.write_all(&posting.to_ne_bytes())?;
+            }
+            writer.flush()
+        };
+        BitmapIndex::<T, U>::map_io_result(write_run())?;
+        self.postings.clear();
+        self.num_spilled_runs += 1;
+        Ok(())
+    }
+
+    /// Merge the runs and the buffered postings of the current chunk, build its bitmaps
+    /// one at a time in the content file and append the chunk to the index.
+    fn write_chunk(&mut self) -> Result<(), Error> {
+        self.postings.sort_unstable();
+        let content_path = self.tmp_file_path("content");
+        let num_bitmaps = self.index.bitmaps.len();
+        let wide_words = self.index.wide_words;
+        let merge = || -> Result<(Vec<u64>, File), IoError> {
+            let mut bitmap = T::new();
+            bitmap.set_wide_words(wide_words);
+            let mut content = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&content_path)?;
+            let mut sizes: Vec<u64> = Vec::with_capacity(num_bitmaps);
+            let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &mut content);
+            let mut emit = |bitmap: &T, sizes: &mut Vec<u64>| -> Result<(), IoError> {
+                sizes.push(bitmap.write_to(&mut writer)? as u64);
+                Ok(())
+            };
+            let mut i_bitmap: usize = 0;
+            for posting in Self::merge_postings(&self.runs, &self.postings)? {
+                let posting = posting?;
+                let posting_i_bitmap = (posting >> 32) as usize;
+                while i_bitmap < posting_i_bitmap {
+                    emit(&bitmap, &mut sizes)?;
+                    bitmap.clear();
+                    i_bitmap += 1;
+                }
+                bitmap.set(posting as u32);
+            }
+            while i_bitmap < num_bitmaps {
+                emit(&bitmap, &mut sizes)?;
+                bitmap.clear();
+                i_bitmap += 1;
+            }
+            writer.flush()?;
+            drop(writer);
+            Ok((sizes, content))
+        };
+        let (sizes, mut content) = BitmapIndex::<T, U>::map_io_result(merge())?;
+        for run in self.runs.drain(..) {
+            BitmapIndex::<T, U>::map_io_result(fs::remove_file(run))?;
+        }
+        self.postings.clear();
+        self.index.write_external_chunk(&sizes, &mut content)
+    }
+
+    /// Return the postings of the sorted `runs` files and of the sorted `postings`
+    /// merged in increasing order.
+    fn merge_postings<'a>(runs: &[PathBuf], postings: &'a [u64]) -> Result<impl Iterator<Item=Result<u64, IoError>> + 'a, IoError> {
+        let mut readers: Vec<BufReader<File>> = Vec::with_capacity(runs.len());
+        for run in runs {
+            readers.push(BufReader::with_capacity(RUN_READ_BUFFER_SIZE, File::open(run)?));
+        }
+        let mut postings = postings.iter().copied();
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::with_capacity(readers.len() + 1);
+        for (i_run, reader) in readers.iter_mut().enumerate() {
+            if let Some(posting) = read_posting(reader)? {
+                heap.push(Reverse((posting, i_run)));
+            }
+        }
+        // The buffered postings are the last source of the merge.
+        let i_postings = readers.len();
+        if let Some(posting) = postings.next() {
+            heap.push(Reverse((posting, i_postings)));
+        }
+        Ok(std::iter::from_fn(move || {
+            let Reverse((posting, i_source)) = heap.pop()?;
+            let next = if i_source == i_postings {
+                Ok(postings.next())
+            } else {
+                read_posting(&mut readers[i_source])
+            };
+            match next {
+                Ok(Some(next_posting)) => heap.push(Reverse((next_posting, i_source))),
+                Ok(None) => {},
+                Err(err) => return Some(Err(err))
+            }
+            Some(Ok(posting))
+        }))
+    }
+
+    fn tmp_file_path(&self, suffix: &str) -> PathBuf {
+        let name = self.dir_path.file_name().map_or_else(|| "index".into(), |name| name.to_string_lossy());
+        self.tmp_dir.join(format!("{}.{}", name, suffix))
+    }
+}
+
+impl<T: Bitmap, U: BitValue> Drop for OfflineBuilder<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    fn drop(&mut self) {
+        for run in self.runs.iter() {
+            let _err = fs::remove_file(run);
+        }
+        let _err = fs::remove_file(self.tmp_file_path("content"));
+    }
+}
+
+/// Read the next posting of a run file, `None` at the end of the file.
+fn read_posting<R: Read>(reader: &mut R) -> Result<Option<u64>, IoError> {
+    let mut buf: [u8; mem::size_of::<u64>()] = [0; mem::size_of::<u64>()];
+    match reader.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(u64::from_ne_bytes(buf))),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err)
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Append the chunk of the values pushed after the last flush, with bitmaps of
+    /// `sizes` bytes serialized in `content`, like `write_chunk` does with the bitmaps
+    /// in memory.
+    fn write_external_chunk(&mut self, sizes: &[u64], content: &mut File) -> Result<(), Error> {
+        let (chunk_header, bitmaps_size) = Self::chunk_header_from_sizes(sizes);
+        let chunk_id = Self::get_chunk_id(self.num_values - 1, self.chunk_size);
+        let trailer = self.chunk_trailer(chunk_id, self.num_values, &chunk_header, bitmaps_size);
+        let mut storage_idx: StorageIdx = self.storage_idx.take().ok_or(Error::ParametersError)?;
+        let chunk_start_offset = self.chunk_offset;
+        self.modified_at = MetaData::unix_time_now();
+        let mut write = || -> Result<u64, IoError> {
+            storage_idx.data_file.seek(SeekFrom::Start(self.chunk_offset))?;
+            let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, &mut storage_idx.data_file);
+            writer.write_all(&chunk_header)?;
+            content.seek(SeekFrom::Start(0))?;
+            if io::copy(&mut content.take(bitmaps_size), &mut writer)? != bitmaps_size {
+                return Err(IoError::from(ErrorKind::UnexpectedEof));
+            }
+            writer.write_all(Self::to_slice_u8(&trailer))?;
+            writer.flush()?;
+            drop(writer);
+            self.write_chunk_extent(&mut storage_idx, Self::get_block_offset(chunk_id), trailer.size())
+        };
+        let write_r = write();
+        self.storage_idx = Some(storage_idx);
+        self.chunk_offset = Self::map_io_result(write_r)?;
+        self.last_checkpoint = Some(self.get_meta_data());
+        self.checkpoint_extent = [chunk_start_offset, self.chunk_offset];
+        self.flushed_num_values = self.num_values;
+        self.last_durable_write = Instant::now();
+        self.notify_flush(chunk_id, self.chunk_offset - chunk_start_offset);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Offset cache
+//!
+//! A storage query reads the offsets of the chunks from the offset file and the offsets
+//! table of each chunk from the data file before reading the query bitmaps. With
+//! `use_offset_cache` these structures are cached in memory the first time they are
+//! read, so repeated queries only read the bitmaps content.
+//!
+//! Only full chunks are cached: a full chunk is never overwritten, appending values
+//! only adds chunks to the cache. A chunk moved from `rewrite_chunk` is removed from
+//! the cache with the chunks after it, since the last chunk may have moved too.
+
+use std::collections::HashMap;
+use std::io::{Error as IoError, Read, Seek};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    StorageIdx,
+    TransmuteToUsize
+};
+
+/// `OffsetCache` struct with the offsets of the first full chunks and the offsets
+/// tables of the full chunks already queried.
+#[derive(Default)]
+pub(super) struct OffsetCache {
+    chunk_offsets: Vec<u64>,
+    tables: HashMap<u64, Vec<u8>>,
+    tables_size: usize
+}
+
+impl OffsetCache {
+    /// Return the offsets of `num_offsets` chunks from `chunk_id`, `None` if they aren't
+    /// all cached.
+    fn chunk_offsets(&self, chunk_id: u64, num_offsets: usize) -> Option<&[u64]> {
+        self.chunk_offsets.get((chunk_id as usize)..(chunk_id as usize + num_offsets))
+    }
+
+    /// Add the offsets of the full chunks from `chunk_id`, if they follow the cached ones.
+    fn insert_chunk_offsets(&mut self, chunk_id: u64, chunk_offsets: &[u64]) {
+        if chunk_id as usize <= self.chunk_offsets.len() {
+            self.chunk_offsets.truncate(chunk_id as usize);
+            self.chunk_offsets.extend_from_slice(chunk_offsets);
+        }
+    }
+
+    /// Remove the chunk `chunk_id` at `chunk_offset` and the chunks after it.
+    pub(super) fn remove_chunk(&mut self, chunk_id: u64, chunk_offset: u64) {
+        self.chunk_offsets.truncate(chunk_id as usize);
+        if let Some(table) = self.tables.remove(&chunk_offset) {
+            self.tables_size -= table.len();
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.chunk_offsets.len() * mem::size_of::<u64>() + self.tables_size
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Cache in memory the offsets of the full chunks and their offsets tables the first
+    /// time a query reads them. Error occur if `BitmapIndex` is opened in memory mode.
+    pub fn use_offset_cache(&mut self) -> Result<(), Error> {
+        let storage_idx = self.storage_idx.as_mut().ok_or(Error::ParametersError)?;
+        if storage_idx.offset_cache.is_none() {
+            storage_idx.offset_cache = Some(OffsetCache::default());
+        }
+        Ok(())
+    }
+
+    /// Drop the offset cache, so queries read all offsets from storage.
+    pub fn remove_offset_cache(&mut self) {
+        if let Some(storage_idx) = self.storage_idx.as_mut() {
+            storage_idx.offset_cache = None;
+        }
+    }
+
+    /// Return the size in bytes of the offset cache, 0 if it is not used.
+    pub fn offset_cache_size(&self) -> usize {
+        self.storage_idx.as_ref().and_then(|storage_idx| storage_idx.offset_cache.as_ref()).map_or(0, |offset_cache| offset_cache.size())
+    }
+
+    /// Return the offsets of `num_offsets` full chunks from `chunk_id`, from the offset
+    /// cache if it is used, adding to `bytes_read` the bytes read from the offset file.
+    pub(super) fn read_full_chunk_offsets(storage_idx: &mut StorageIdx, chunk_id: u64, num_offsets: usize, bytes_read: &mut u64) -> Result<Vec<u64>, IoError> {
+        if let Some(chunk_offsets) = storage_idx.offset_cache.as_ref().and_then(|c| c.chunk_offsets(chunk_id, num_offsets)) {
+            return Ok(chunk_offsets.to_vec());
+        }
+        let chunk_offsets = Self::read_offsets(storage_idx, chunk_id, num_offsets)?;
+        *bytes_read += (num_offsets * mem::size_of::<u64>()) as u64;
+        if let Some(offset_cache) = storage_idx.offset_cache.as_mut() {
+            offset_cache.insert_chunk_offsets(chunk_id, &chunk_offsets);
+        }
+        Ok(chunk_offsets)
+    }
+
+    /// Return the absolute offsets of the bitmaps `query_i_bitmaps` of the full chunk at
+    /// `chunk_offset` of `data_file`, decoded from the table in `offset_cache` if it is used.
+    pub(super) fn read_full_chunk_bitmaps_offsets<R: Read + Seek>(offset_cache: &mut Option<OffsetCache>, data_file: &mut R, chunk_offset: u64, query_i_bitmaps: &[usize]) -> Result<Vec<(u64, u64)>, Error> {
+        let offset_cache = match offset_cache.as_mut() {
+            Some(offset_cache) => offset_cache,
+            None => return Self::read_bitmaps_offsets(data_file, chunk_offset, query_i_bitmaps)
+        };
+        if !offset_cache.tables.contains_key(&chunk_offset) {
+            let table = Self::read_chunk_table(data_file, chunk_offset)?;
+            offset_cache.tables_size += table.len();
+            offset_cache.tables.insert(chunk_offset, table);
+        }
+        let bitmaps_offsets = Self::chunk_bitmaps_offsets(&offset_cache.tables[&chunk_offset], query_i_bitmaps)?;
+        Ok(bitmaps_offsets.iter().map(|(start, end)| (chunk_offset + start, chunk_offset + end)).collect())
+    }
+}
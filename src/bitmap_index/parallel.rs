@@ -0,0 +1,109 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Parallel
+//!
+//! Parallel operations on `BitmapIndex`, available with the `parallel` feature.
+//! Parallel work runs on the rayon thread pool set with `set_thread_pool`, so it
+//! can be isolated from latency-sensitive application threads. If no thread pool
+//! is set the rayon global pool is used.
+
+use std::ops::{BitAnd, Shr};
+use std::sync::Arc;
+
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Set the thread pool used by parallel operations of this `BitmapIndex`.
+    pub fn set_thread_pool(&mut self, thread_pool: Arc<ThreadPool>) {
+        self.thread_pool = Some(thread_pool);
+    }
+
+    /// Return the thread pool used by parallel operations, `None` if the rayon
+    /// global pool is used.
+    pub fn thread_pool(&self) -> Option<&Arc<ThreadPool>> {
+        self.thread_pool.as_ref()
+    }
+
+    /// Run `f` inside the thread pool of this `BitmapIndex`.
+    pub(crate) fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match &self.thread_pool {
+            Some(thread_pool) => thread_pool.install(f),
+            None => f()
+        }
+    }
+
+    /// Same of `run_query` but the chunks of a `BitmapIndex` in memory mode are
+    /// queried in parallel. On a `BitmapIndex` in storage mode or that spills its
+    /// chunks to disk this method is the same of `run_query`. `TooManyResults` is returned
+    /// if the indexes exceed `max_query_results`.
+    pub fn run_query_par(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error>
+    where T: Send + Sync {
+        let chunks = match self.chunks.as_ref() {
+            Some(chunks) if self.spill_chunks.is_none() => chunks,
+            _ => return self.run_query(value, start_index, end_index)
+        };
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let chunk_size = self.chunk_size;
+        let max_query_results = self.max_query_results.unwrap_or(u64::MAX);
+        let check_results = |indexes: Vec<u64>| {
+            if indexes.len() as u64 > max_query_results {
+                return Err(Error::TooManyResults(max_query_results));
+            }
+            Ok(indexes)
+        };
+
+        let chunks_indexes: Vec<Result<Vec<u64>, Error>> = self.install(|| {
+            chunks.par_iter().enumerate().map(|(chunk_id, bitmaps)| {
+                let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
+                    .map(|i_bitmap| &bitmaps[*i_bitmap]).collect();
+                let mut indexes: Vec<u64> = Vec::new();
+                Self::push_indexes(&query_bitmaps, chunk_id as u64, chunk_size, start_index, end_index, &mut indexes);
+                check_results(indexes)
+            }).collect()
+        });
+
+        let num_memory_chunks = chunks.len();
+        let arena_chunks = self.arena_chunks.as_deref().unwrap_or_default();
+        let arena_chunks_indexes: Vec<Result<Vec<u64>, Error>> = self.install(|| {
+            arena_chunks.par_iter().enumerate().map(|(i, arena)| {
+                let chunk_id = (num_memory_chunks + i) as u64;
+                let mut indexes: Vec<u64> = Vec::new();
+                if Self::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
+                    let query_bitmaps = Self::arena_query_bitmaps(arena, &query_i_bitmaps)?;
+                    let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                    Self::push_indexes(&query_bitmaps_ref, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+                }
+                check_results(indexes)
+            }).collect()
+        });
+
+        let mut indexes: Vec<u64> = Vec::new();
+        for chunk_indexes in chunks_indexes.into_iter().chain(arena_chunks_indexes) {
+            indexes.extend(chunk_indexes?);
+            indexes = check_results(indexes)?;
+        }
+        let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
+            .map(|i_bitmap| &self.bitmaps[*i_bitmap]).collect();
+        let chunk_id = (num_memory_chunks + arena_chunks.len()) as u64;
+        Self::push_indexes(&query_bitmaps, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+
+        check_results(indexes)
+    }
+}
@@ -0,0 +1,105 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Parquet ingestion
+//!
+//! Build a `BitmapIndex` from a column of a Parquet file, available with the `parquet`
+//! feature. Only the pages of the indexed column are read, one record batch at a time.
+//! The position range of each row group is saved in user meta data, so a position
+//! returned from a query can be mapped back to its row group.
+
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::mem;
+use std::ops::{BitAnd, Range, Shr};
+use std::path::Path;
+
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use parquet::errors::ParquetError;
+
+use super::{
+    ArrowBitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    TransmuteToUsize
+};
+
+/// User meta data key of the row groups position ranges.
+const PARQUET_ROW_GROUPS_KEY: &str = "parquet_row_groups";
+
+/// Max number of values read in a record batch.
+const PARQUET_BATCH_SIZE: usize = 1 << 16;
+
+impl<T: Bitmap, U: ArrowBitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `BitmapIndex` in memory mode that contains the values of `column`
+    /// of the Parquet file `parquet_path`.
+    pub fn build_from_parquet(parquet_path: &Path, column: &str, build_options: BuildOptions) -> Result<Self, Error> {
+        let mut b_index = Self::new(build_options)?;
+        b_index.push_parquet_column(parquet_path, column)?;
+        Ok(b_index)
+    }
+
+    /// Insert (in append) the values of `column` of the Parquet file `parquet_path`
+    /// and return the number of values inserted. The position ranges of the file row
+    /// groups are appended to the ones returned from `parquet_row_groups`.
+    pub fn push_parquet_column(&mut self, parquet_path: &Path, column: &str) -> Result<u64, Error> {
+        let file = Self::map_io_result(fs::File::open(parquet_path))?;
+        let builder = Self::map_parquet_result(ParquetRecordBatchReaderBuilder::try_new(file))?;
+        let i_column = match builder.schema().index_of(column) {
+            Ok(i_column) => i_column,
+            Err(_) => return Err(Error::ParametersError)
+        };
+
+        let mut row_groups = self.parquet_row_groups();
+        let mut start_index = self.num_values;
+        for row_group in builder.metadata().row_groups() {
+            let end_index = start_index + row_group.num_rows() as u64;
+            row_groups.push(start_index..end_index);
+            start_index = end_index;
+        }
+
+        let mask = ProjectionMask::roots(builder.parquet_schema(), [i_column]);
+        let reader = Self::map_parquet_result(builder.with_projection(mask).with_batch_size(PARQUET_BATCH_SIZE).build())?;
+        let mut num_values: u64 = 0;
+        for batch in reader {
+            let batch = Self::map_arrow_result(batch)?;
+            num_values += self.push_arrow_array(batch.column(0))?;
+        }
+
+        let mut row_groups_buf: Vec<u8> = Vec::with_capacity(row_groups.len() * 2 * mem::size_of::<u64>());
+        for row_group in row_groups {
+            row_groups_buf.extend_from_slice(&row_group.start.to_ne_bytes());
+            row_groups_buf.extend_from_slice(&row_group.end.to_ne_bytes());
+        }
+        self.set_user_meta_data(PARQUET_ROW_GROUPS_KEY, &row_groups_buf)?;
+        Ok(num_values)
+    }
+
+    /// Return the position range of each row group inserted with `push_parquet_column`.
+    pub fn parquet_row_groups(&self) -> Vec<Range<u64>> {
+        let row_groups_buf = self.user_meta_data(PARQUET_ROW_GROUPS_KEY).unwrap_or(&[]);
+        let to_u64 = |buf: &[u8]| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(buf);
+            u64::from_ne_bytes(bytes)
+        };
+        row_groups_buf.chunks_exact(2 * mem::size_of::<u64>())
+            .map(|range| to_u64(&range[0..8])..to_u64(&range[8..16]))
+            .collect()
+    }
+
+    fn map_parquet_result<M>(result: Result<M, ParquetError>) -> Result<M, Error> {
+        match result {
+            Ok(r) => Ok(r),
+            Err(err) => Err(Error::FileError(IoError::new(ErrorKind::InvalidData, err)))
+        }
+    }
+}
@@ -0,0 +1,197 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Partitioned index
+//!
+//! `PartitionedIndex` manages a `BitmapIndex` in storage mode for each partition (i.e.
+//! for each day) in a directory. Partitions are identified from an increasing `u64`
+//! key, values are appended to the last partition and a value with a new key seals
+//! the last partition (flushing it) and starts a new one. Values have global indexes:
+//! the partition `i` covers `[start_index, start_index + num_values)`, so queries fan
+//! out on the selected partitions and return global indexes.
+//!
+//! Each partition is stored in the subdirectory named from its key in hex format, only
+//! the last partition is kept open, sealed partitions are opened by queries.
+
+use std::fs;
+use std::ops::{BitAnd, RangeBounds, Shr};
+use std::path::{Path, PathBuf};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    TransmuteToUsize
+};
+
+/// `PartitionInfo` describes a partition of a `PartitionedIndex`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionInfo {
+    key: u64,
+    start_index: u64,
+    num_values: u64
+}
+
+impl PartitionInfo {
+    /// Return the key of the partition.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Return the global index of the first value of the partition.
+    pub fn start_index(&self) -> u64 {
+        self.start_index
+    }
+
+    /// Return the number of values of the partition.
+    pub fn num_values(&self) -> u64 {
+        self.num_values
+    }
+}
+
+/// `PartitionedIndex` struct that manages a `BitmapIndex` for each partition.
+pub struct PartitionedIndex<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    dir_path: PathBuf,
+    build_options: BuildOptions,
+    partitions: Vec<PartitionInfo>,
+    last_partition: Option<BitmapIndex<T, U>>
+}
+
+impl<T: Bitmap, U: BitValue> PartitionedIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Create a new `PartitionedIndex` without partitions in the directory `dir_path`,
+    /// that must not exist. Every partition is created with `build_options`.
+    pub fn create(dir_path: &Path, build_options: BuildOptions) -> Result<Self, Error> {
+        BitmapIndex::<T, U>::new_block_info(&build_options)?;
+        BitmapIndex::<T, U>::map_io_result(fs::create_dir(dir_path))?;
+        Ok(PartitionedIndex {
+            dir_path: PathBuf::from(dir_path),
+            build_options,
+            partitions: Vec::new(),
+            last_partition: None
+        })
+    }
+
+    /// Open a `PartitionedIndex` from the directory `dir_path`. The build options of
+    /// new partitions are the ones of the last partition, `MetaDataError` is returned
+    /// if the directory has no partitions.
+    pub fn open(dir_path: &Path) -> Result<Self, Error> {
+        let mut keys: Vec<u64> = Vec::new();
+        for entry in BitmapIndex::<T, U>::map_io_result(fs::read_dir(dir_path))? {
+            let entry = BitmapIndex::<T, U>::map_io_result(entry)?;
+            if let Some(key) = entry.file_name().to_str().and_then(|name| u64::from_str_radix(name, 16).ok()) {
+                keys.push(key);
+            }
+        }
+        keys.sort_unstable();
+        let last_key = *keys.last().ok_or(Error::MetaDataError)?;
+
+        let mut partitions: Vec<PartitionInfo> = Vec::with_capacity(keys.len());
+        let mut start_index: u64 = 0;
+        for key in keys.iter().take(keys.len() - 1) {
+            let num_values = BitmapIndex::<T, U>::open(&Self::partition_path(dir_path, *key))?.num_values();
+            partitions.push(PartitionInfo { key: *key, start_index, num_values });
+            start_index += num_values;
+        }
+        let last_partition = BitmapIndex::<T, U>::open(&Self::partition_path(dir_path, last_key))?;
+        partitions.push(PartitionInfo { key: last_key, start_index, num_values: last_partition.num_values() });
+        Ok(PartitionedIndex {
+            dir_path: PathBuf::from(dir_path),
+            build_options: last_partition.get_meta_data().build_options,
+            partitions,
+            last_partition: Some(last_partition)
+        })
+    }
+
+    /// Insert (in append) a `value` in the partition `key`. If `key` is greater than the
+    /// key of the last partition, the last partition is flushed and a new partition is
+    /// created. `ParametersError` is returned if `key` is less than the last key.
+    pub fn push_value(&mut self, key: u64, value: U) -> Result<(), Error> {
+        self.route_partition(key)?;
+        self.last_partition.as_mut().unwrap().push_value(value)?;
+        self.partitions.last_mut().unwrap().num_values += 1;
+        Ok(())
+    }
+
+    /// Insert (in append) values in the partition `key`, see `push_value`.
+    pub fn push_values(&mut self, key: u64, values: &[U]) -> Result<(), Error> {
+        for v in values {
+            self.push_value(key, *v)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the current chunk of the last partition, sealed partitions are always flushed.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        match self.last_partition.as_mut() {
+            Some(last_partition) => last_partition.flush_chunk(),
+            None => Ok(())
+        }
+    }
+
+    /// Return the number of values pushed in all partitions.
+    pub fn num_values(&self) -> u64 {
+        self.partitions.last().map_or(0, |p| p.start_index + p.num_values)
+    }
+
+    /// Return the partitions ordered by key.
+    pub fn partitions(&self) -> &[PartitionInfo] {
+        &self.partitions
+    }
+
+    /// Return the `PartitionInfo` of the partition that contains the value with global
+    /// index `index`.
+    pub fn partition_of(&self, index: u64) -> Option<&PartitionInfo> {
+        let i = self.partitions.partition_point(|p| p.start_index + p.num_values <= index);
+        self.partitions.get(i)
+    }
+
+    /// Return a `Vec<u64>` that contains the global indexes of all values equal to
+    /// `value` pushed in the partitions with key in `keys`.
+    pub fn run_query(&mut self, value: U, keys: impl RangeBounds<u64>) -> Result<Vec<u64>, Error> {
+        let mut indexes: Vec<u64> = Vec::new();
+        let num_partitions = self.partitions.len();
+        for (i, partition) in self.partitions.iter().enumerate() {
+            if !keys.contains(&partition.key) || partition.num_values == 0 {
+                continue;
+            }
+            let local_indexes = if i + 1 == num_partitions {
+                self.last_partition.as_mut().unwrap().run_query(value, None, None)?
+            } else {
+                let mut b_index = BitmapIndex::<T, U>::open(&Self::partition_path(&self.dir_path, partition.key))?;
+                b_index.run_query(value, None, None)?
+            };
+            indexes.extend(local_indexes.iter().map(|idx| partition.start_index + idx));
+        }
+        Ok(indexes)
+    }
+
+    /// Make the partition `key` the last partition.
+    fn route_partition(&mut self, key: u64) -> Result<(), Error> {
+        let start_index = self.num_values();
+        match self.partitions.last() {
+            Some(last) if last.key == key => return Ok(()),
+            Some(last) if last.key > key => return Err(Error::ParametersError),
+            _ => {}
+        }
+        if let Some(last_partition) = self.last_partition.as_mut() {
+            last_partition.flush_chunk()?;
+        }
+        let b_index = BitmapIndex::<T, U>::create(&Self::partition_path(&self.dir_path, key), self.build_options.clone())?;
+        self.partitions.push(PartitionInfo { key, start_index, num_values: 0 });
+        self.last_partition = Some(b_index);
+        Ok(())
+    }
+
+    fn partition_path(dir_path: &Path, key: u64) -> PathBuf {
+        dir_path.join(format!("{:016x}", key))
+    }
+}
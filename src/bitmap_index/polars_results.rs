@@ -0,0 +1,52 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Polars results
+//!
+//! Convert query results into Polars arrays, available with the `polars` feature.
+//! Query indexes are returned as a `UInt64Chunked`, or as a `BooleanChunked` mask
+//! with one value for each value pushed in `BitmapIndex`, ready to be used with
+//! `DataFrame::filter`.
+
+use std::ops::{BitAnd, Shr};
+
+use polars_core::prelude::{BooleanChunked, NewChunkedArray, UInt64Chunked};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Same of `run_query` but indexes are returned as a `UInt64Chunked` named `name`.
+    pub fn run_query_polars(&mut self, name: &str, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<UInt64Chunked, Error> {
+        let indexes = self.run_query(value, start_index, end_index)?;
+        Ok(UInt64Chunked::from_vec(name.into(), indexes))
+    }
+
+    /// Return a `BooleanChunked` named `name` with one value for each value pushed
+    /// in `BitmapIndex`, where the ith value is `true` if the ith value pushed is
+    /// equal to `value`.
+    pub fn run_query_polars_mask(&mut self, name: &str, value: U) -> Result<BooleanChunked, Error> {
+        let indexes = self.run_query(value, None, None)?;
+        Ok(indexes_to_polars_mask(name, &indexes, self.num_values))
+    }
+}
+
+/// Return a `BooleanChunked` named `name` of length `len`, where the values at
+/// positions in `indexes` are `true`.
+pub fn indexes_to_polars_mask(name: &str, indexes: &[u64], len: u64) -> BooleanChunked {
+    let mut mask: Vec<bool> = vec![false; len as usize];
+    for index in indexes.iter().filter(|index| **index < len) {
+        mask[*index as usize] = true;
+    }
+    BooleanChunked::from_slice(name.into(), &mask)
+}
@@ -0,0 +1,156 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Query cache
+//!
+//! With `use_query_cache` a `BitmapIndex` keeps the result bitmap ("logical and" of
+//! the query bitmaps) of each full chunk for the most recently queried values, so
+//! `run_query` repeated on the same value (i.e. from a dashboard) reads and intersects
+//! only the chunks not yet cached. The cache is keyed by value, the results of a chunk
+//! serve every range that intersects it.
+//!
+//! Full chunks never change when values are pushed, so the cache is extended
+//! incrementally as new chunks are filled: only the current chunk is intersected at
+//! every query. A chunk modified with `rewrite_chunk` is removed from the cache.
+
+use std::collections::{HashMap, VecDeque};
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// `QueryCache` struct with the result bitmaps of each full chunk of the cached values,
+/// keyed by the query bitmaps of the value.
+pub(super) struct QueryCache<T> {
+    max_values: usize,
+    results: HashMap<Vec<usize>, Vec<Option<T>>>,
+    order: VecDeque<Vec<usize>>
+}
+
+impl<T> QueryCache<T> {
+    fn new(max_values: usize) -> Self {
+        QueryCache {
+            max_values,
+            results: HashMap::new(),
+            order: VecDeque::new()
+        }
+    }
+
+    /// Remove and return the results of `query_i_bitmaps`, empty if they aren't cached.
+    fn take(&mut self, query_i_bitmaps: &[usize]) -> Vec<Option<T>> {
+        self.results.remove(query_i_bitmaps).unwrap_or_default()
+    }
+
+    /// Insert the results of `query_i_bitmaps` as the most recently used, evicting the
+    /// least recently used value if the cache is full.
+    fn insert(&mut self, query_i_bitmaps: Vec<usize>, chunk_results: Vec<Option<T>>) {
+        self.order.retain(|key| *key != query_i_bitmaps);
+        self.order.push_back(query_i_bitmaps.clone());
+        self.results.insert(query_i_bitmaps, chunk_results);
+        while self.order.len() > self.max_values {
+            if let Some(key) = self.order.pop_front() {
+                self.results.remove(&key);
+            }
+        }
+    }
+
+    /// Remove the results of the chunk `chunk_id` of all values.
+    pub(super) fn remove_chunk(&mut self, chunk_id: u64) {
+        for chunk_results in self.results.values_mut() {
+            if let Some(chunk_result) = chunk_results.get_mut(chunk_id as usize) {
+                *chunk_result = None;
+            }
+        }
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Cache the results of `run_query` for the last `max_values` queried values,
+    /// replacing the previous cache.
+    pub fn use_query_cache(&mut self, max_values: usize) {
+        self.query_cache = Some(QueryCache::new(max_values));
+    }
+
+    /// Drop the query cache, so every query intersects the bitmaps of all chunks.
+    pub fn remove_query_cache(&mut self) {
+        self.query_cache = None;
+    }
+
+    /// Return the number of values in the query cache, 0 if it is not used.
+    pub fn query_cache_len(&self) -> usize {
+        self.query_cache.as_ref().map_or(0, |query_cache| query_cache.results.len())
+    }
+
+    /// Append to `indexes` the indexes of values equal to `value` in `[start_index, end_index]`
+    /// computing only the results of the full chunks not in the query cache.
+    pub(super) fn run_query_cached(&mut self, value: U, start_index: u64, end_index: u64, indexes: &mut Vec<u64>) -> Result<(), Error> {
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let mut chunk_results = self.query_cache.as_mut().ok_or(Error::ParametersError)?.take(&query_i_bitmaps);
+        let result = self.fill_chunk_results(&query_i_bitmaps, start_index, end_index, &mut chunk_results)
+            .and_then(|_| self.push_cached_indexes(&query_i_bitmaps, &chunk_results, start_index, end_index, indexes));
+        if let Some(query_cache) = self.query_cache.as_mut() {
+            query_cache.insert(query_i_bitmaps, chunk_results);
+        }
+        result
+    }
+
+    /// Append to `indexes` the indexes in `[start_index, end_index]` of the full chunks
+    /// from `chunk_results` and of the current chunk from its bitmaps.
+    fn push_cached_indexes(&self, query_i_bitmaps: &[usize], chunk_results: &[Option<T>], start_index: u64, end_index: u64, indexes: &mut Vec<u64>) -> Result<(), Error> {
+        let chunk_size = self.chunk_size;
+        let max_query_results = self.max_query_results.unwrap_or(u64::MAX);
+        let initial_len = indexes.len();
+        let num_chunks = Self::get_chunk_id(self.num_values, chunk_size);
+        let start_chunk_id = Self::get_chunk_id(start_index, chunk_size);
+        let end_chunk_id = num_chunks.min(Self::get_chunk_id(end_index, chunk_size) + 1);
+        let current_bitmaps: Vec<&T> = query_i_bitmaps.iter().map(|i_bitmap| &self.bitmaps[*i_bitmap]).collect();
+        let chunks = (start_chunk_id..end_chunk_id)
+            .filter_map(|chunk_id| chunk_results[chunk_id as usize].as_ref().map(|chunk_result| (chunk_id, vec![chunk_result])))
+            .chain(Some((num_chunks, current_bitmaps)));
+        for (chunk_id, query_bitmaps) in chunks {
+            Self::push_indexes(&query_bitmaps, chunk_id, chunk_size, start_index, end_index, indexes);
+            if (indexes.len() - initial_len) as u64 > max_query_results {
+                indexes.truncate(initial_len);
+                return Err(Error::TooManyResults(max_query_results));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set in `chunk_results` the result bitmaps of the full chunks that intersect
+    /// `[start_index, end_index]` and aren't cached, reading each run of missing chunks at once.
+    fn fill_chunk_results(&mut self, query_i_bitmaps: &[usize], start_index: u64, end_index: u64, chunk_results: &mut Vec<Option<T>>) -> Result<(), Error> {
+        let chunk_size = self.chunk_size;
+        let num_chunks = Self::get_chunk_id(self.num_values, chunk_size);
+        let end_chunk_id = num_chunks.min(Self::get_chunk_id(end_index, chunk_size) + 1);
+        if chunk_results.len() < end_chunk_id as usize {
+            chunk_results.resize(end_chunk_id as usize, None);
+        }
+        let mut chunk_id = Self::get_chunk_id(start_index, chunk_size);
+        while chunk_id < end_chunk_id {
+            if chunk_results[chunk_id as usize].is_some() {
+                chunk_id += 1;
+                continue;
+            }
+            let run_end = (chunk_id..end_chunk_id).find(|id| chunk_results[*id as usize].is_some()).unwrap_or(end_chunk_id);
+            let f = |result_chunk_id: u64, query_bitmaps: &[&T]| {
+                if result_chunk_id >= chunk_id && result_chunk_id < run_end {
+                    chunk_results[result_chunk_id as usize] = Some(Self::and_bitmaps(query_bitmaps));
+                }
+            };
+            self.for_each_chunk(query_i_bitmaps, chunk_id * chunk_size, run_end * chunk_size - 1, f)?;
+            chunk_id = run_end;
+        }
+        Ok(())
+    }
+}
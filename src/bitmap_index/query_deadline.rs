@@ -0,0 +1,79 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Query deadline
+//!
+//! A query with a deadline checks the deadline between chunks and stops with
+//! `Error::Timeout` when the deadline is passed, so a service can bound the response
+//! time of a query that touches many chunks. The error reports the progress of the
+//! query when it was stopped.
+
+use std::ops::{BitAnd, ControlFlow, Shr};
+use std::time::{Duration, Instant};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// `QueryProgress` reports the progress of a query stopped from a timeout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryProgress {
+    /// Number of chunks processed before the timeout.
+    pub scanned_chunks: u64,
+    /// Number of chunks that intersect the query range.
+    pub num_chunks: u64,
+    /// Number of indexes found in the processed chunks.
+    pub num_indexes: u64,
+    /// Time elapsed from the start of the query.
+    pub elapsed: Duration
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Like `run_query`, but `Error::Timeout` is returned if the query is not completed
+    /// before `deadline`. The deadline is checked before processing each chunk.
+    pub fn run_query_with_deadline(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>, deadline: Instant) -> Result<Vec<u64>, Error> {
+        let query_start = Instant::now();
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let num_chunks = self.num_range_chunks(start_index, end_index);
+        let chunk_size = self.chunk_size;
+
+        let mut scanned_chunks: u64 = 0;
+        let mut indexes: Vec<u64> = Vec::new();
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout(QueryProgress {
+                    scanned_chunks,
+                    num_chunks,
+                    num_indexes: indexes.len() as u64,
+                    elapsed: now.duration_since(query_start)
+                }));
+            }
+            Self::push_indexes(query_bitmaps, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+            scanned_chunks += 1;
+            Ok(ControlFlow::Continue(()))
+        };
+        let _control_flow = self.try_for_each_chunk(&query_i_bitmaps, start_index, end_index, f)?;
+        Ok(indexes)
+    }
+
+    /// Return the number of chunks with values in `[start_index, end_index]`.
+    fn num_range_chunks(&self, start_index: u64, end_index: u64) -> u64 {
+        if self.num_values == 0 || start_index >= self.num_values || start_index > end_index {
+            return 0;
+        }
+        let end_index = end_index.min(self.num_values - 1);
+        end_index / self.chunk_size - start_index / self.chunk_size + 1
+    }
+}
@@ -0,0 +1,166 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Query explain
+//!
+//! `run_query_explain` runs the same query of `run_query` and returns, together with
+//! the found indexes, a `QueryExplain` report with the chunks scanned or skipped,
+//! the bytes read and the decoded bitmap sizes of each chunk and the time spent
+//! in each phase of the query.
+
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::time::{Duration, Instant};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// `ChunkExplain` reports how a query has processed a single chunk.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkExplain {
+    /// Id of the chunk.
+    pub chunk_id: u64,
+    /// `false` if the chunk has been skipped because it is out of the query range.
+    pub scanned: bool,
+    /// `true` if the chunk has been read from storage.
+    pub from_storage: bool,
+    /// Bytes read from the data file (zero for in memory chunks).
+    pub bytes_read: u64,
+    /// Content size of each query bitmap.
+    pub bitmap_sizes: Vec<usize>,
+    /// Number of indexes found in the chunk.
+    pub num_indexes: u64
+}
+
+/// `QueryExplain` is the report returned by `run_query_explain`.
+#[derive(Clone, Debug, Default)]
+pub struct QueryExplain {
+    /// Id of the bitmaps read for each chunk.
+    pub query_i_bitmaps: Vec<usize>,
+    /// One entry for each chunk of the index, in chunk id order.
+    pub chunks: Vec<ChunkExplain>,
+    /// Time spent reading and decoding bitmaps from storage or from chunk arenas.
+    pub read_time: Duration,
+    /// Time spent running "logical and" between query bitmaps.
+    pub and_time: Duration,
+    /// Time spent unrolling the result bitmaps into indexes.
+    pub unroll_time: Duration,
+    /// Total query time.
+    pub total_time: Duration
+}
+
+impl QueryExplain {
+    /// Return the number of scanned chunks.
+    pub fn num_scanned_chunks(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.scanned).count()
+    }
+
+    /// Return the total bytes read from storage.
+    pub fn bytes_read(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.bytes_read).sum()
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Same of `run_query` but return also a `QueryExplain` report of the query.
+    pub fn run_query_explain(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<(Vec<u64>, QueryExplain), Error> {
+        let timer = Instant::now();
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let chunk_size = self.chunk_size;
+        let num_chunks = Self::get_chunk_id(self.num_values, chunk_size);
+
+        let mut explain = QueryExplain {
+            query_i_bitmaps: query_i_bitmaps.clone(),
+            ..QueryExplain::default()
+        };
+        let mut indexes: Vec<u64> = Vec::new();
+
+        if let Some(chunks) = self.chunks.as_ref() {
+            for (chunk_id, bitmaps) in chunks.iter().enumerate() {
+                let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
+                    .map(|i_bitmap| &bitmaps[*i_bitmap]).collect();
+                let chunk = Self::explain_chunk(&query_bitmaps, chunk_id as u64, chunk_size, start_index, end_index, &mut indexes, &mut explain);
+                explain.chunks.push(chunk);
+            }
+            let num_memory_chunks = chunks.len() as u64;
+            for (i, arena) in self.arena_chunks.iter().flatten().enumerate() {
+                let chunk_id = num_memory_chunks + i as u64;
+                if !Self::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
+                    explain.chunks.push(ChunkExplain { chunk_id, ..ChunkExplain::default() });
+                    continue;
+                }
+                let read_timer = Instant::now();
+                let query_bitmaps = Self::arena_query_bitmaps(arena, &query_i_bitmaps)?;
+                explain.read_time += read_timer.elapsed();
+                let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                let chunk = Self::explain_chunk(&query_bitmaps_ref, chunk_id, chunk_size, start_index, end_index, &mut indexes, &mut explain);
+                explain.chunks.push(chunk);
+            }
+        } else if let Some(storage_idx) = self.storage_idx.as_mut() {
+            for chunk_id in 0..num_chunks {
+                if !Self::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
+                    explain.chunks.push(ChunkExplain { chunk_id, ..ChunkExplain::default() });
+                    continue;
+                }
+                let read_timer = Instant::now();
+                let chunk_offset = Self::map_io_result(Self::read_offsets(storage_idx, chunk_id, 1))?[0];
+                let query_bitmaps = Self::read_query_bitmaps(&mut storage_idx.data_file, chunk_offset, &query_i_bitmaps)?;
+                explain.read_time += read_timer.elapsed();
+
+                let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+                let mut chunk = Self::explain_chunk(&query_bitmaps_ref, chunk_id, chunk_size, start_index, end_index, &mut indexes, &mut explain);
+                chunk.from_storage = true;
+                chunk.bytes_read = (mem::size_of::<u64>() + 2 * mem::size_of::<u32>() * query_i_bitmaps.len()) as u64
+                    + chunk.bitmap_sizes.iter().sum::<usize>() as u64;
+                explain.chunks.push(chunk);
+            }
+        }
+
+        let query_bitmaps: Vec<&T> = query_i_bitmaps.iter()
+            .map(|i_bitmap| &self.bitmaps[*i_bitmap]).collect();
+        let chunk = Self::explain_chunk(&query_bitmaps, num_chunks, chunk_size, start_index, end_index, &mut indexes, &mut explain);
+        explain.chunks.push(chunk);
+        explain.total_time = timer.elapsed();
+
+        Ok((indexes, explain))
+    }
+
+    fn explain_chunk(query_bitmaps: &[&T], chunk_id: u64, chunk_size: u64, start_index: u64, end_index: u64, indexes: &mut Vec<u64>, explain: &mut QueryExplain) -> ChunkExplain {
+        let mut chunk = ChunkExplain {
+            chunk_id,
+            ..ChunkExplain::default()
+        };
+        if !Self::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
+            return chunk;
+        }
+        chunk.scanned = true;
+        chunk.bitmap_sizes = query_bitmaps.iter().map(|bitmap| bitmap.size()).collect();
+
+        let and_timer = Instant::now();
+        let b_result: T = Self::and_bitmaps(query_bitmaps);
+        explain.and_time += and_timer.elapsed();
+
+        let unroll_timer = Instant::now();
+        let offset_index: u64 = chunk_id * chunk_size;
+        let num_indexes = indexes.len();
+        indexes.extend(b_result.unroll_bitmap().iter()
+                       .map(|idx| offset_index + *idx as u64)
+                       .filter(|idx| *idx >= start_index && *idx <= end_index)
+        );
+        explain.unroll_time += unroll_timer.elapsed();
+        chunk.num_indexes = (indexes.len() - num_indexes) as u64;
+        chunk
+    }
+}
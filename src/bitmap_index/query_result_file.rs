@@ -0,0 +1,106 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Query result files
+//!
+//! A query result saved on a file as the selection of the query (a compressed bitmap
+//! for each chunk, see `query_selection`), so an expensive recurring query can be
+//! reused across process restarts or shared between processes. The file is keyed by
+//! the `MetaData` of the index and by the queried value: it can be loaded only from
+//! an index with the same build options and the same number of values.
+//!
+//! # Encoding
+//!  |MetaData|value|u32 num_chunks|u32 bitmap_size|...|bitmap|...|
+
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    MetaData,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Save the selection of all indexes of values pushed in `BitmapIndex` equal to
+    /// `value` in a file with `path` path and return the selection.
+    pub fn save_query_result(&mut self, value: U, path: &Path) -> Result<Vec<T>, Error> {
+        let selection = self.query_selection(value)?;
+        let mut buf: Vec<u8> = Vec::new();
+        buf.extend_from_slice(Self::to_slice_u8(&self.get_meta_data()));
+        buf.extend_from_slice(Self::to_slice_u8(&value));
+        buf.extend_from_slice(&(selection.len() as u32).to_ne_bytes());
+        for b in selection.iter() {
+            buf.extend_from_slice(&(b.size() as u32).to_ne_bytes());
+        }
+        for b in selection.iter() {
+            Self::map_io_result(b.write_to(&mut buf))?;
+        }
+        // write a temporary file and rename it, so a query result file is never torn.
+        let tmp_path = path.with_extension("tmp");
+        Self::map_io_result(fs::write(&tmp_path, &buf))?;
+        Self::map_io_result(fs::rename(&tmp_path, path))?;
+        Ok(selection)
+    }
+
+    /// Load the selection of `value` saved with `save_query_result` in the file with
+    /// `path` path. `MetaDataError` is returned if the file has been saved from an
+    /// index with different meta data (i.e. an index with more values) or for another value.
+    pub fn load_query_result(&self, value: U, path: &Path) -> Result<Vec<T>, Error> {
+        let buf = Self::map_io_result(fs::read(path))?;
+        let mut offset: usize = 0;
+        let meta_data_buf = Self::read_query_result_slice(&buf, &mut offset, mem::size_of::<MetaData>())?;
+        let meta_data = Self::meta_data_from_slice_u8(meta_data_buf)?;
+        let value_buf = Self::read_query_result_slice(&buf, &mut offset, mem::size_of::<U>())?;
+        if !self.same_meta_data(&meta_data) || value_buf != Self::to_slice_u8(&value) {
+            return Err(Error::MetaDataError);
+        }
+        let num_chunks = Self::read_query_result_u32(&buf, &mut offset)?;
+        let mut bitmaps_sizes: Vec<usize> = Vec::with_capacity(num_chunks.min(buf.len()));
+        for _i in 0..num_chunks {
+            bitmaps_sizes.push(Self::read_query_result_u32(&buf, &mut offset)?);
+        }
+        let mut selection: Vec<T> = Vec::with_capacity(num_chunks);
+        for bitmap_size in bitmaps_sizes {
+            let b_buf = Self::read_query_result_slice(&buf, &mut offset, bitmap_size)?;
+            let mut b = T::new();
+            Self::read_bitmap(b_buf, true, &mut b)?;
+            selection.push(b);
+        }
+        Ok(selection)
+    }
+
+    fn same_meta_data(&self, meta_data: &MetaData) -> bool {
+        let self_meta_data = self.get_meta_data();
+        Self::check_meta_data(meta_data).is_ok()
+            && meta_data.num_values == self_meta_data.num_values
+            && meta_data.build_options.bit_block_size == self_meta_data.build_options.bit_block_size
+            && meta_data.build_options.bin_shift == self_meta_data.build_options.bin_shift
+            && meta_data.build_options.chunk_size.clone() as u32 == self_meta_data.build_options.chunk_size as u32
+    }
+
+    fn read_query_result_slice<'b>(buf: &'b [u8], offset: &mut usize, len: usize) -> Result<&'b [u8], Error> {
+        let slice = match buf.get(*offset..(*offset + len)) {
+            Some(slice) => slice,
+            None => return Err(Error::FileError(IoError::new(ErrorKind::InvalidData, "invalid query result file")))
+        };
+        *offset += len;
+        Ok(slice)
+    }
+
+    fn read_query_result_u32(buf: &[u8], offset: &mut usize) -> Result<usize, Error> {
+        let s = Self::read_query_result_slice(buf, offset, mem::size_of::<u32>())?;
+        Ok(u32::from_ne_bytes([s[0], s[1], s[2], s[3]]) as usize)
+    }
+}
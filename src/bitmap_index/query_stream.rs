@@ -0,0 +1,135 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # QueryStream
+//!
+//! A [`Stream`] of query results on a [`StorageReader`], available with the `async`
+//! feature. Chunks are read one at a time by a worker thread, on duplicated handles of
+//! the files of the reader, and sent to the stream that wakes its task when a chunk is
+//! ready, so `poll_next` never blocks. The worker reads at most one chunk ahead of the
+//! indexes consumed, so the first results are available before the whole index has
+//! been scanned. Dropping the stream stops the worker and waits for it.
+//!
+//! [`Stream`]: https://docs.rs/futures-core/latest/futures_core/stream/trait.Stream.html
+//! [`StorageReader`]: ./storage_reader.rs
+
+use std::ops::{BitAnd, Shr};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+use futures_core::Stream;
+
+use super::{
+    BitValue,
+    Bitmap,
+    Error,
+    StorageReader,
+    TransmuteToUsize
+};
+
+/// `QueryStream` struct returned by `StorageReader::query_stream`.
+pub struct QueryStream<'r, T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    receiver: Receiver<Result<Vec<u64>, Error>>,
+    waker: Arc<Mutex<Option<Waker>>>,
+    worker: Option<JoinHandle<()>>,
+    indexes: std::vec::IntoIter<u64>,
+
+    // the files of the reader are used by the worker.
+    _reader: std::marker::PhantomData<&'r mut StorageReader<T, U>>
+}
+
+impl<T: Bitmap, U: BitValue> StorageReader<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a `QueryStream` that yields all indexes of values equal to `value`
+    /// reading one chunk at a time. The parameters `start_index` and `end_index`
+    /// are optional and if specified define the range where query is runned.
+    pub fn query_stream(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> QueryStream<'_, T, U>
+    where T: Send + 'static, U: Send + 'static {
+        let (start_index, end_index) = self.query_range(start_index, end_index);
+        let (chunk_id, end_chunk_id) = self.chunk_id_range(start_index, end_index);
+        let query_i_bitmaps = self.query_i_bitmaps(value);
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+        let worker = match self.try_clone() {
+            Ok(mut reader) => {
+                let waker = Arc::clone(&waker);
+                let wake = move || {
+                    if let Some(waker) = waker.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                        waker.wake();
+                    }
+                };
+                Some(thread::spawn(move || {
+                    for chunk_id in chunk_id..end_chunk_id {
+                        let chunk_indexes = reader.chunk_indexes(chunk_id, &query_i_bitmaps, start_index, end_index);
+                        let is_err = chunk_indexes.is_err();
+                        let is_sent = sender.send(chunk_indexes).is_ok();
+                        wake();
+                        if is_err || !is_sent {
+                            break;
+                        }
+                    }
+                    // the stream ends when the sender is dropped.
+                    drop(sender);
+                    wake();
+                }))
+            },
+            Err(err) => {
+                let _err = sender.send(Err(err));
+                None
+            }
+        };
+        QueryStream {
+            receiver,
+            waker,
+            worker,
+            indexes: Vec::new().into_iter(),
+            _reader: std::marker::PhantomData
+        }
+    }
+}
+
+impl<'r, T: Bitmap, U: BitValue> Stream for QueryStream<'r, T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    type Item = Result<u64, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let stream = self.get_mut();
+        loop {
+            if let Some(index) = stream.indexes.next() {
+                return Poll::Ready(Some(Ok(index)));
+            }
+            // the waker is set before the channel is checked, so a chunk sent in between
+            // wakes the task.
+            *stream.waker.lock().unwrap_or_else(PoisonError::into_inner) = Some(cx.waker().clone());
+            match stream.receiver.try_recv() {
+                Ok(Ok(indexes)) => stream.indexes = indexes.into_iter(),
+                Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Err(TryRecvError::Empty) => return Poll::Pending,
+                Err(TryRecvError::Disconnected) => return Poll::Ready(None)
+            }
+        }
+    }
+}
+
+impl<'r, T: Bitmap, U: BitValue> Drop for QueryStream<'r, T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    fn drop(&mut self) {
+        // a worker blocked on a full channel returns when the receiver is dropped.
+        let (_sender, receiver) = mpsc::sync_channel(0);
+        drop(std::mem::replace(&mut self.receiver, receiver));
+        if let Some(worker) = self.worker.take() {
+            let _err = worker.join();
+        }
+    }
+}
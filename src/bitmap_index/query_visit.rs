@@ -0,0 +1,56 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Query visitor
+//!
+//! `run_query_visit` calls a visitor with each index of the result, in increasing
+//! order, without allocating the result. The visitor can stop the query returning
+//! `ControlFlow::Break`, i.e. to check if at least a value matches in a range.
+
+use std::ops::{BitAnd, ControlFlow, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Call `visit` with each index of values pushed in `BitmapIndex` equal to `value`,
+    /// in increasing order, until `visit` returns `ControlFlow::Break`. Return
+    /// `ControlFlow::Break` if the query has been stopped from `visit`. The parameters
+    /// `start_index` and `end_index` are optional and if specified define the range
+    /// where query is runned.
+    pub fn run_query_visit(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>, mut visit: impl FnMut(u64) -> ControlFlow<()>) -> Result<ControlFlow<()>, Error> {
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let chunk_size = self.chunk_size;
+
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            let offset_index: u64 = chunk_id * chunk_size;
+            let b_result: T = Self::and_bitmaps(query_bitmaps);
+            for idx in b_result.unroll_bitmap() {
+                let index = offset_index + idx as u64;
+                if index < start_index {
+                    continue;
+                }
+                if index > end_index {
+                    break;
+                }
+                if visit(index).is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        };
+        self.try_for_each_chunk(&query_i_bitmaps, start_index, end_index, f)
+    }
+}
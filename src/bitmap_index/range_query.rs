@@ -0,0 +1,63 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Approximate range queries
+//!
+//! `run_query_range_approx` answers a range query on a `BitmapIndex` built with
+//! `BuildOptions::with_bin_shift`: all bins that intersect the range are probed reading
+//! each chunk only once. The values of the first and the last bin can be out of the
+//! range, so the indexes are candidates that must be checked downstream.
+
+use std::mem;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BlockInfo,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a sorted `Vec<u64>` that contains all indexes of values in the bins of
+    /// `[low, high]`, a superset of the indexes of values in `[low, high]`. Bins are
+    /// computed on `BitValue::to_ordered`, so signed ranges may cross zero.
+    /// `ParametersError` is returned if the bin of `low` is greater than the bin
+    /// of `high`. The parameters `start_index` and `end_index` are optional and if
+    /// specified define the range where query is runned.
+    pub fn run_query_range_approx(&mut self, low: U, high: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let (low_bin, high_bin) = (Self::get_bin(&self.block_info, low), Self::get_bin(&self.block_info, high));
+        if low_bin > high_bin {
+            return Err(Error::ParametersError);
+        }
+        let bins_i_bitmaps: Vec<Vec<usize>> = (low_bin..=high_bin)
+            .map(|bin| Self::get_bin_i_bitmaps(&self.block_info, bin)).collect();
+        let start_index = start_index.unwrap_or(0);
+        let end_index = end_index.unwrap_or(self.num_values);
+        let mut indexes: Vec<u64> = self.run_query_i_bitmaps_grouped(&bins_i_bitmaps, start_index, end_index)?.concat();
+        indexes.sort_unstable();
+        Ok(indexes)
+    }
+
+    /// Return the bin of `value`, limited to the low bits of `usize`.
+    fn get_bin(block_info: &BlockInfo, value: U) -> usize {
+        let bin_size = (mem::size_of::<U>() << 3) - block_info.bin_shift;
+        let bin_mask = usize::MAX.checked_shr(usize::BITS.saturating_sub(bin_size as u32)).unwrap_or(0);
+        (value.to_ordered() >> block_info.bin_shift).transmute_to_usize() & bin_mask
+    }
+
+    /// Return the query bitmaps of the values of `bin`.
+    fn get_bin_i_bitmaps(block_info: &BlockInfo, bin: usize) -> Vec<usize> {
+        (0..block_info.num_blocks).map(|i| {
+            let block_value = bin.checked_shr((i * block_info.bit_block_size) as u32).unwrap_or(0);
+            i * block_info.num_bitmaps_in_block + (block_value & block_info.bit_block_mask)
+        }).collect()
+    }
+}
@@ -0,0 +1,191 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Reader sessions
+//!
+//! A `StorageReader` sees only the full chunks flushed on storage, while `run_query` on
+//! the writer requires `&mut`. The writer publishes the bitmaps of its current partial
+//! chunk in a `SharedTail` (`publish_tail`, e.g. after each batch of pushes) and a
+//! `ReaderSession` answers queries on the durable chunks read by a `StorageReader`
+//! followed by the last published tail, so readers on other threads get the values
+//! pushed up to the last publish.
+//!
+//! A full chunk is flushed before the tail of the next chunk is published: a session
+//! reads the tail before refreshing the reader, so the durable chunks always reach the
+//! chunk of the tail. A tail older than the durable chunks is ignored.
+
+use std::ops::{BitAnd, Shr};
+use std::sync::{Arc, RwLock};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    StorageReader,
+    TransmuteToUsize
+};
+
+/// The bitmaps of the current chunk of a writer `BitmapIndex` at its last publish.
+#[derive(Clone)]
+struct TailState<T> {
+    chunk_id: u64,
+    chunk_size: u64,
+    num_values: u64,
+    bitmaps: Arc<Vec<T>>
+}
+
+/// `SharedTail` is a clonable handle to the current partial chunk of a writer
+/// `BitmapIndex`, see `BitmapIndex::publish_tail` and `ReaderSession`.
+#[derive(Clone)]
+pub struct SharedTail<T> {
+    state: Arc<RwLock<TailState<T>>>
+}
+
+impl<T: Clone> SharedTail<T> {
+    /// Return a new `SharedTail` without a published chunk.
+    pub fn new() -> Self {
+        SharedTail {
+            state: Arc::new(RwLock::new(TailState {
+                chunk_id: 0,
+                chunk_size: 0,
+                num_values: 0,
+                bitmaps: Arc::new(Vec::new())
+            }))
+        }
+    }
+
+    /// Return the number of values of the writer at the last publish.
+    pub fn num_values(&self) -> u64 {
+        self.state().num_values
+    }
+
+    fn state(&self) -> TailState<T> {
+        // the state is replaced with a single assignment, so a poisoned lock has a
+        // consistent state.
+        self.state.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    fn set_state(&self, state: TailState<T>) {
+        *self.state.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = state;
+    }
+}
+
+impl<T: Clone> Default for SharedTail<T> {
+    fn default() -> Self {
+        SharedTail::new()
+    }
+}
+
+/// `ReaderSession` queries the durable chunks of a `BitmapIndex` and the current chunk
+/// published by its writer in a `SharedTail`.
+pub struct ReaderSession<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    reader: StorageReader<T, U>,
+    tail: SharedTail<T>
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Publish the bitmaps of the current chunk in `tail`, so `ReaderSession`s see the
+    /// values pushed until now. The bitmaps are copied, so publish after a batch of
+    /// pushes rather than after each value. `ParametersError` is returned if
+    /// `BitmapIndex` is opened in memory mode.
+    pub fn publish_tail(&self, tail: &SharedTail<T>) -> Result<(), Error> {
+        if !self.is_storage_mode() {
+            return Err(Error::ParametersError);
+        }
+        tail.set_state(TailState {
+            chunk_id: Self::get_chunk_id(self.num_values, self.chunk_size),
+            chunk_size: self.chunk_size,
+            num_values: self.num_values,
+            bitmaps: Arc::new(self.bitmaps.clone())
+        });
+        Ok(())
+    }
+}
+
+impl<T: Bitmap, U: BitValue> ReaderSession<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `ReaderSession` on the durable chunks of `reader` and the chunk
+    /// published in `tail`.
+    pub fn new(reader: StorageReader<T, U>, tail: SharedTail<T>) -> Self {
+        ReaderSession {
+            reader,
+            tail
+        }
+    }
+
+    /// Return the wrapped `StorageReader`.
+    pub fn reader(&self) -> &StorageReader<T, U> {
+        &self.reader
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values equal to `value`, in the
+    /// durable chunks and in the last published tail. The parameters `start_index` and
+    /// `end_index` are optional and if specified define the range where query is runned.
+    /// `InconsistentStorage` is returned if the tail is after the durable chunks and
+    /// `ParametersError` if the tail has been published by another index.
+    pub fn query(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let (tail, durable_end) = self.snapshot()?;
+        let (start_index, end_index) = (start_index.unwrap_or(0), end_index.unwrap_or(u64::MAX));
+        let mut indexes: Vec<u64> = match durable_end > start_index {
+            true => self.reader.query(value, Some(start_index), Some(end_index.min(durable_end - 1)))?,
+            false => Vec::new()
+        };
+        if let Some(tail) = tail {
+            let query_bitmaps = self.tail_query_bitmaps(&tail, value)?;
+            BitmapIndex::<T, U>::push_indexes(&query_bitmaps, tail.chunk_id, tail.chunk_size, start_index, end_index, &mut indexes);
+        }
+        Ok(indexes)
+    }
+
+    /// Return the number of values equal to `value`, without collecting their indexes,
+    /// see `query`.
+    pub fn query_count(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<u64, Error> {
+        let (tail, durable_end) = self.snapshot()?;
+        let (start_index, end_index) = (start_index.unwrap_or(0), end_index.unwrap_or(u64::MAX));
+        let mut count: u64 = match durable_end > start_index {
+            true => self.reader.query_count(value, Some(start_index), Some(end_index.min(durable_end - 1)))?,
+            false => 0
+        };
+        if let Some(tail) = tail {
+            let query_bitmaps = self.tail_query_bitmaps(&tail, value)?;
+            count += BitmapIndex::<T, U>::count_indexes(&query_bitmaps, tail.chunk_id, tail.chunk_size, start_index, end_index);
+        }
+        Ok(count)
+    }
+
+    /// Return the published tail (`None` if it's older than the durable chunks) and the
+    /// number of durable values to query.
+    fn snapshot(&mut self) -> Result<(Option<TailState<T>>, u64), Error> {
+        let tail = self.tail.state();
+        self.reader.refresh()?;
+        let tail_start = tail.chunk_id * tail.chunk_size;
+        let durable_end = self.reader.len();
+        if tail.num_values <= durable_end {
+            return Ok((None, durable_end));
+        }
+        if tail_start > durable_end {
+            return Err(Error::InconsistentStorage("the published tail is after the flushed chunks".to_string()));
+        }
+        Ok((Some(tail), tail_start))
+    }
+
+    /// Return the bitmaps of `tail` that represent `value`.
+    fn tail_query_bitmaps<'t>(&self, tail: &'t TailState<T>, value: U) -> Result<Vec<&'t T>, Error> {
+        if tail.chunk_size != self.reader.meta_data().build_options().chunk_size() {
+            return Err(Error::ParametersError);
+        }
+        self.reader.query_i_bitmaps(value).iter()
+            .map(|i_bitmap| tail.bitmaps.get(*i_bitmap).ok_or(Error::ParametersError))
+            .collect()
+    }
+}
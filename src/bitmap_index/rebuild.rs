@@ -0,0 +1,97 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Rebuild
+//!
+//! `rebuild` writes a copy of an index with different `BuildOptions` (i.e. another
+//! `bit_block_size` or `ChunkSize`) without the original column: the values of each
+//! chunk are reconstructed from the bitmaps of the chunk, every block gives the bits of
+//! the value it covers, and are pushed in the new index. Only one chunk of values is
+//! kept in memory at a time.
+//!
+//! The low `bin_shift` bits of a value are not indexed, so a reconstructed value is the
+//! first value of its bin: an index can be rebuilt with a larger `bin_shift` but not
+//! with a smaller one.
+
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Create a new `BitmapIndex` in `dst_path` (that must not exist) with `build_options`
+    /// and the values of the index in `src_path`, together with its user meta data. The
+    /// new index is returned with all chunks flushed. `ParametersError` is returned if
+    /// the `bin_shift` of `build_options` is smaller than the one of the source index.
+    pub fn rebuild(src_path: &Path, dst_path: &Path, build_options: BuildOptions) -> Result<Self, Error> {
+        let mut src_index = Self::open(src_path)?;
+        if (build_options.bin_shift() as usize) < src_index.block_info.bin_shift {
+            return Err(Error::ParametersError);
+        }
+        let mut dst_index = Self::create(dst_path, build_options)?;
+        let num_chunks = src_index.num_values.div_ceil(src_index.chunk_size);
+        for chunk_id in 0..num_chunks {
+            let values = src_index.chunk_values(chunk_id)?;
+            dst_index.push_column(&values)?;
+        }
+        for (key, value) in src_index.user_meta_data.iter() {
+            dst_index.set_user_meta_data(key, value)?;
+        }
+        if dst_index.num_values & dst_index.chunk_size_mask != 0 {
+            dst_index.flush_chunk()?;
+        }
+        Ok(dst_index)
+    }
+
+    /// Return the values of the chunk `chunk_id` reconstructed from its bitmaps, with
+    /// the low `bin_shift` bits set to 0. `ParametersError` is returned if the chunk
+    /// doesn't exist.
+    pub(super) fn chunk_values(&mut self, chunk_id: u64) -> Result<Vec<U>, Error> {
+        let chunk_start = chunk_id * self.chunk_size;
+        if chunk_start >= self.num_values {
+            return Err(Error::ParametersError);
+        }
+        let num_values = (self.num_values - chunk_start).min(self.chunk_size) as usize;
+        let bitmaps = self.chunk_bitmaps(chunk_id)?;
+        let block_info = &self.block_info;
+        let mut ordered_values: Vec<u128> = vec![0; num_values];
+        for (i_bitmap, bitmap) in bitmaps.iter().enumerate() {
+            let i_block = i_bitmap / block_info.num_bitmaps_in_block;
+            let block_bits = (i_bitmap % block_info.num_bitmaps_in_block) as u128;
+            if block_bits == 0 {
+                continue;
+            }
+            let shift = block_info.bin_shift + i_block * block_info.bit_block_size;
+            for pos in bitmap.unroll_bitmap() {
+                if let Some(ordered_value) = ordered_values.get_mut(pos as usize) {
+                    *ordered_value |= block_bits << shift;
+                }
+            }
+        }
+        Ok(ordered_values.into_iter().map(Self::value_from_ordered).collect())
+    }
+
+    /// Return the value with the order-preserving representation `ordered_value`.
+    pub(super) fn value_from_ordered(ordered_value: u128) -> U {
+        let mut bytes = ordered_value.to_le_bytes();
+        let bytes = &mut bytes[..mem::size_of::<U>()];
+        if cfg!(target_endian = "big") {
+            bytes.reverse();
+        }
+        // `to_ordered` is its own inverse.
+        Self::copy_from_slice_u8::<U>(bytes).to_ordered()
+    }
+}
@@ -0,0 +1,167 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Recovery
+//!
+//! The meta data file keeps two copies of `MetaData`: the current one and the last
+//! checkpoint (the state before the last flush). Each copy is followed from the extent
+//! in the data file of its last chunk, so the offsets of a partial chunk rewritten by
+//! the last flush can be restored:
+//!  |MetaData|MetaData|u64 chunk_start|u64 chunk_end|u64 chunk_start|u64 chunk_end|
+//!
+//! `recover` checks the current meta data against the offset and data files and if the
+//! tail of the index is inconsistent (i.e. a torn write) rolls back to the last checkpoint.
+//! If the offset or meta data files are lost use `rebuild_storage_files` instead.
+
+use std::io::{Error as IoError, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    MetaData,
+    StorageIdx,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Open a `BitmapIndex` in storage mode whose tail can be inconsistent and return it
+    /// with the number of values rolled back. If the current meta data don't match the
+    /// offset and data files the index is rolled back to the last checkpoint, in both
+    /// cases the offset and data files are truncated after the last chunk.
+    /// Only the last chunk content is validated.
+    pub fn recover(dir_path: &Path) -> Result<(Self, u64), Error> {
+        const META_DATA_SIZE: usize = mem::size_of::<MetaData>();
+        let mut storage_idx = Self::get_storage_idx(dir_path, None)?;
+        let mut buf: Vec<u8> = Vec::new();
+        Self::map_io_result(storage_idx.meta_data_file.seek(SeekFrom::Start(0)))?;
+        Self::map_io_result(storage_idx.meta_data_file.read_to_end(&mut buf))?;
+        let meta_data = Self::meta_data_copy(&buf, 0);
+        let last_checkpoint = Self::meta_data_copy(&buf, META_DATA_SIZE);
+        let extents: Option<Vec<u64>> = buf.get((2 * META_DATA_SIZE)..(2 * META_DATA_SIZE + 4 * mem::size_of::<u64>()))
+            .map(|extents_buf| extents_buf.chunks_exact(mem::size_of::<u64>())
+                 .map(|b| u64::from_ne_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])).collect());
+
+        let (recovered, extent) = match meta_data.as_ref() {
+            Ok(m) if Self::check_storage_state(&mut storage_idx, m).is_ok() => {
+                let chunk_size = m.build_options.chunk_size();
+                let extent = Self::map_io_result(Self::last_chunk_extent(&mut storage_idx, m.num_values, chunk_size))?;
+                (m.clone(), extent)
+            },
+            _ => {
+                let extents = extents.ok_or(Error::MetaDataError)?;
+                (last_checkpoint?, [extents[2], extents[3]])
+            }
+        };
+        let num_values = meta_data.map(|m| m.num_values).unwrap_or(recovered.num_values);
+        Self::map_io_result(Self::truncate_storage_idx(&mut storage_idx, &recovered, extent))?;
+        drop(storage_idx);
+
+        let b_index = Self::open(dir_path)?;
+        Ok((b_index, num_values.saturating_sub(recovered.num_values)))
+    }
+
+    /// Return the extent in the data file of the last chunk with values.
+    pub(super) fn last_chunk_extent(storage_idx: &mut StorageIdx, num_values: u64, chunk_size: u64) -> Result<[u64; 2], IoError> {
+        if num_values == 0 {
+            return Ok([0, 0]);
+        }
+        let chunk_id = Self::get_chunk_id(num_values - 1, chunk_size);
+        let offsets = Self::read_offsets(storage_idx, chunk_id, 2)?;
+        Ok([offsets[0], offsets[1]])
+    }
+
+    fn meta_data_copy(buf: &[u8], offset: usize) -> Result<MetaData, Error> {
+        let meta_data_buf = buf.get(offset..(offset + mem::size_of::<MetaData>())).ok_or(Error::MetaDataError)?;
+        let meta_data = Self::meta_data_from_slice_u8(meta_data_buf)?;
+        Self::check_meta_data(&meta_data)?;
+        Ok(meta_data)
+    }
+
+    /// Check that the offset file has exactly the offsets of the chunks of `meta_data`
+    /// and that the last offset is the size of the data file.
+    pub(super) fn check_storage_files(storage_idx: &mut StorageIdx, meta_data: &MetaData) -> Result<(), Error> {
+        let chunk_size = meta_data.build_options.chunk_size();
+        let num_offsets = match meta_data.num_values {
+            0 => 0,
+            num_values => num_values.div_ceil(chunk_size) + 1
+        };
+        let offsets_size = Self::map_io_result(storage_idx.offset_file.metadata())?.len();
+        let data_size = Self::map_io_result(storage_idx.data_file.metadata())?.len();
+        let inconsistent = |message: String| {
+            Err(Error::InconsistentStorage(format!("{}, use `BitmapIndex::recover` to repair the index", message)))
+        };
+        if offsets_size != num_offsets * mem::size_of::<u64>() as u64 {
+            return inconsistent(format!("{} values require {} chunk offsets but the offset file has {} bytes",
+                meta_data.num_values, num_offsets, offsets_size));
+        }
+        let last_offset = match num_offsets {
+            0 => 0,
+            _ => Self::map_io_result(Self::read_offsets(storage_idx, num_offsets - 1, 1))?[0]
+        };
+        if last_offset != data_size {
+            return inconsistent(format!("the last chunk offset is {} but the data file has {} bytes", last_offset, data_size));
+        }
+        Ok(())
+    }
+
+    /// Check that offset and data files have all the chunks of `meta_data` and that
+    /// the last partial chunk can be read.
+    fn check_storage_state(storage_idx: &mut StorageIdx, meta_data: &MetaData) -> Result<(), Error> {
+        let chunk_size = meta_data.build_options.chunk_size();
+        if meta_data.num_values == 0 {
+            return Ok(());
+        }
+        let num_offsets = meta_data.num_values.div_ceil(chunk_size) + 1;
+        let offsets_size = Self::map_io_result(storage_idx.offset_file.metadata())?.len();
+        if offsets_size < num_offsets * mem::size_of::<u64>() as u64 {
+            return Err(Error::MetaDataError);
+        }
+        let offsets = Self::map_io_result(Self::read_offsets(storage_idx, 0, num_offsets as usize))?;
+        let data_size = Self::map_io_result(storage_idx.data_file.metadata())?.len();
+        let last_offsets = (offsets[offsets.len() - 2], offsets[offsets.len() - 1]);
+        // offsets are not sorted when chunks have been rewritten (see `rewrite_chunk`),
+        // but every rewritten chunk is before the end of the last chunk.
+        if offsets.iter().any(|offset| *offset > last_offsets.1) || last_offsets.0 > last_offsets.1 || last_offsets.1 > data_size {
+            return Err(Error::MetaDataError);
+        }
+        if meta_data.num_values & (chunk_size - 1) != 0 {
+            let block_info = Self::new_block_info(&meta_data.build_options)?;
+            let mut bitmaps = vec![T::new(); block_info.num_blocks * block_info.num_bitmaps_in_block];
+            Self::read_chunk_bitmaps(&mut storage_idx.data_file, last_offsets, &mut bitmaps)?;
+        }
+        Ok(())
+    }
+
+    /// Restore the offsets of the last chunk of `meta_data` and truncate offset and data
+    /// files after it, then write `meta_data` as current meta data and last checkpoint.
+    fn truncate_storage_idx(storage_idx: &mut StorageIdx, meta_data: &MetaData, extent: [u64; 2]) -> Result<(), IoError> {
+        let chunk_size = meta_data.build_options.chunk_size();
+        let num_offsets = if meta_data.num_values == 0 {
+            0
+        } else {
+            let chunk_id = Self::get_chunk_id(meta_data.num_values - 1, chunk_size);
+            storage_idx.offset_file.seek(SeekFrom::Start(Self::get_block_offset(chunk_id)))?;
+            storage_idx.offset_file.write_all(Self::to_slice_u8(&extent))?;
+            chunk_id + 2
+        };
+        storage_idx.offset_file.set_len(num_offsets * mem::size_of::<u64>() as u64)?;
+        storage_idx.data_file.set_len(extent[1])?;
+
+        storage_idx.meta_data_file.seek(SeekFrom::Start(0))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(meta_data))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(meta_data))?;
+        storage_idx.meta_data_file.write_all(Self::to_slice_u8(&[extent, extent]))?;
+        let meta_data_file_size = storage_idx.meta_data_file.stream_position()?;
+        storage_idx.meta_data_file.set_len(meta_data_file_size)
+    }
+}
@@ -0,0 +1,217 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Remapped index
+//!
+//! `RemappedIndex` indexes values with a huge domain (i.e. `u64` or `u128` ids) but a
+//! moderate number of distinct values: a dictionary maps each distinct value to a dense
+//! id (by default `u32`), in order of first insertion, and a `BitmapIndex` indexes the
+//! ids, so it has fewer blocks and denser bitmaps than a `BitmapIndex` of the values.
+//!
+//! `LowCardinalityIndex` is a `RemappedIndex` with `u8` ids for columns with at most 256
+//! distinct values (i.e. enum-like columns): created with `dictionary_options` it has a
+//! single block, so each value has its own bitmap and a query reads one bitmap without
+//! "logical and". `is_low_cardinality` tells from a sample of the values if a column
+//! fits it.
+//!
+//! In storage mode the dictionary is saved in the directory of the `BitmapIndex`, in a
+//! file with 'rbidx' extension that contains the distinct values in id order. A new
+//! value is appended to the dictionary before its id is pushed in the index, so the
+//! dictionary always covers the ids of the index.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::{Path, PathBuf};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    ChunkSize,
+    Error,
+    TransmuteToUsize
+};
+
+/// Maximum number of distinct values of a `LowCardinalityIndex`.
+const LOW_CARDINALITY_MAX_VALUES: usize = 1 << 8;
+
+/// A `BitValue` that can be used as dense id of a `RemappedIndex`.
+pub trait DictionaryId: BitValue + TryFrom<usize> {}
+
+impl DictionaryId for u8 {}
+impl DictionaryId for u16 {}
+impl DictionaryId for u32 {}
+
+/// A `RemappedIndex` with a bitmap for each value, see `RemappedIndex::dictionary_options`.
+pub type LowCardinalityIndex<T, U> = RemappedIndex<T, U, u8>;
+
+/// Return `true` if `sample` has at most 256 distinct values, so the column fits a
+/// `LowCardinalityIndex`.
+pub fn is_low_cardinality<U: BitValue + Eq + Hash>(sample: &[U]) -> bool {
+    let mut distinct: HashSet<U> = HashSet::new();
+    sample.iter().all(|v| {
+        distinct.insert(*v);
+        distinct.len() <= LOW_CARDINALITY_MAX_VALUES
+    })
+}
+
+/// `RemappedIndex` struct that requires a [`Bitmap`] type, a `BitValue` `U` for the
+/// values of the dictionary and a `DictionaryId` `I` for their ids.
+pub struct RemappedIndex<T: Bitmap, U: BitValue + Eq + Hash, I: DictionaryId = u32>
+where <I as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    index: BitmapIndex<T, I>,
+    ids: HashMap<U, I>,
+    values: Vec<U>,
+    dictionary_file: Option<File>
+}
+
+impl<T: Bitmap, U: BitValue + Eq + Hash, I: DictionaryId> RemappedIndex<T, U, I>
+where <I as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the `BuildOptions` with a single block, so each id has its own bitmap.
+    /// They are valid only for `u8` and `u16` ids.
+    pub fn dictionary_options(chunk_size: ChunkSize) -> BuildOptions {
+        BuildOptions::new(mem::size_of::<I>() << 3, chunk_size)
+    }
+
+    /// Return a new empty `RemappedIndex` in memory mode. `build_options` are the
+    /// options of the `BitmapIndex` of ids.
+    pub fn new(build_options: BuildOptions) -> Result<Self, Error> {
+        Ok(RemappedIndex {
+            index: BitmapIndex::new(build_options)?,
+            ids: HashMap::new(),
+            values: Vec::new(),
+            dictionary_file: None
+        })
+    }
+
+    /// Create a new `RemappedIndex` in storage mode in the directory `dir_path`, that
+    /// must not exist.
+    pub fn create(dir_path: &Path, build_options: BuildOptions) -> Result<Self, Error> {
+        let index = BitmapIndex::create(dir_path, build_options)?;
+        let file_r = OpenOptions::new().append(true).create_new(true).open(Self::dictionary_path(dir_path));
+        Ok(RemappedIndex {
+            index,
+            ids: HashMap::new(),
+            values: Vec::new(),
+            dictionary_file: Some(BitmapIndex::<T, I>::map_io_result(file_r)?)
+        })
+    }
+
+    /// Open a `RemappedIndex` previusly created in `dir_path`. A value torn from the end
+    /// of the dictionary (i.e. after a crash) is discarded: its id has never been
+    /// pushed in the index.
+    pub fn open(dir_path: &Path) -> Result<Self, Error> {
+        let index = BitmapIndex::<T, I>::open(dir_path)?;
+        let path = Self::dictionary_path(dir_path);
+        let file_r = OpenOptions::new().read(true).append(true).open(&path);
+        let mut dictionary_file = BitmapIndex::<T, I>::map_io_result(file_r)?;
+        let mut buf: Vec<u8> = Vec::new();
+        BitmapIndex::<T, I>::map_io_result(dictionary_file.read_to_end(&mut buf))?;
+        let value_size = mem::size_of::<U>();
+        let num_values = buf.len() / value_size;
+        if buf.len() != num_values * value_size {
+            BitmapIndex::<T, I>::map_io_result(dictionary_file.set_len((num_values * value_size) as u64))?;
+        }
+        let values: Vec<U> = buf.chunks_exact(value_size)
+            .map(BitmapIndex::<T, I>::copy_from_slice_u8::<U>).collect();
+        let mut ids: HashMap<U, I> = HashMap::with_capacity(values.len());
+        for (id, value) in values.iter().enumerate() {
+            ids.insert(*value, I::try_from(id).map_err(|_| Error::MetaDataError)?);
+        }
+        Ok(RemappedIndex {
+            index,
+            ids,
+            values,
+            dictionary_file: Some(dictionary_file)
+        })
+    }
+
+    /// Insert (in append) a `value` into the index, adding it to the dictionary if it is
+    /// a new value. `ParametersError` is returned if the dictionary is full, i.e. it has
+    /// already `u32::MAX + 1` values with `u32` ids.
+    pub fn push_value(&mut self, value: U) -> Result<(), Error> {
+        let id = match self.ids.get(&value) {
+            Some(id) => *id,
+            None => self.insert_value(value)?
+        };
+        self.index.push_value(id)
+    }
+
+    /// Insert (in append) values into the index, see `push_value`.
+    pub fn push_values(&mut self, values: &[U]) -> Result<(), Error> {
+        for value in values {
+            self.push_value(*value)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the current chunk of the index, see `BitmapIndex::flush_chunk`.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.index.flush_chunk()
+    }
+
+    /// Return the number of values pushed in the index.
+    pub fn num_values(&self) -> u64 {
+        self.index.num_values()
+    }
+
+    /// Return the number of distinct values of the dictionary.
+    pub fn num_distinct_values(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Return the id of `value`, `None` if `value` has never been pushed.
+    pub fn value_id(&self, value: U) -> Option<I> {
+        self.ids.get(&value).copied()
+    }
+
+    /// Return the value of the id `id`, `None` if `id` isn't in the dictionary.
+    pub fn id_value(&self, id: I) -> Option<U> {
+        self.values.get(id.transmute_to_usize()).copied()
+    }
+
+    /// Return the `BitmapIndex` of the ids.
+    pub fn index_mut(&mut self) -> &mut BitmapIndex<T, I> {
+        &mut self.index
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values equal to `value`, an
+    /// empty `Vec` if `value` has never been pushed. The parameters `start_index` and
+    /// `end_index` are optional and if specified define the range where query is runned.
+    pub fn run_query(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        match self.value_id(value) {
+            Some(id) => self.index.run_query(id, start_index, end_index),
+            None => Ok(Vec::new())
+        }
+    }
+
+    fn insert_value(&mut self, value: U) -> Result<I, Error> {
+        let id = I::try_from(self.values.len()).map_err(|_| Error::ParametersError)?;
+        if let Some(dictionary_file) = self.dictionary_file.as_mut() {
+            BitmapIndex::<T, I>::map_io_result(dictionary_file.write_all(BitmapIndex::<T, I>::to_slice_u8(&value)))?;
+        }
+        self.values.push(value);
+        self.ids.insert(value, id);
+        Ok(id)
+    }
+
+    fn dictionary_path(dir_path: &Path) -> PathBuf {
+        let mut path = PathBuf::from(dir_path);
+        if let Some(name) = dir_path.file_name() {
+            path.push(name);
+        }
+        path.set_extension("rbidx");
+        path
+    }
+}
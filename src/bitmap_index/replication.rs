@@ -0,0 +1,156 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Replication
+//!
+//! Chunk-level replication of a `BitmapIndex` in storage mode (the leader) to follower
+//! indexes, so read replicas can serve queries without ingesting the source data again.
+//! The leader returns each durable chunk as a `ChunkPayload` (the bytes of the chunk in
+//! the data file, trailer included) and `replication_stream` returns the payloads of the
+//! chunks written after the number of values of a follower. The follower applies them
+//! in order with `apply_chunk_payload`: a payload of the current chunk of the follower
+//! replaces it, a payload of a full chunk (i.e. after a `rewrite_chunk` of the leader,
+//! notified by a `FlushEvent`) rewrites it.
+//!
+//! The trailer of the chunk validates the payload, so chunks written by versions of the
+//! library without trailers can't be replicated.
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::ops::{BitAnd, Shr};
+
+use super::data_trailer::CHUNK_TRAILER_SIZE;
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// A durable chunk of a leader `BitmapIndex`, to apply on its followers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkPayload {
+    /// The id of the chunk.
+    pub chunk_id: u64,
+    /// The number of values of the index after the chunk.
+    pub num_values: u64,
+    /// The chunk as written in the data file of the leader, trailer included.
+    pub bytes: Vec<u8>
+}
+
+/// An iterator over the `ChunkPayload`s of a leader `BitmapIndex`, see `replication_stream`.
+pub struct ReplicationStream<'a, T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'b> &'b T: BitAnd<&'b T, Output=T> {
+    b_index: &'a mut BitmapIndex<T, U>,
+    next_chunk_id: u64,
+    end_chunk_id: u64
+}
+
+impl<T: Bitmap, U: BitValue> Iterator for ReplicationStream<'_, T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'b> &'b T: BitAnd<&'b T, Output=T> {
+    type Item = Result<ChunkPayload, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_chunk_id >= self.end_chunk_id {
+            return None;
+        }
+        let payload_r = self.b_index.chunk_payload(self.next_chunk_id);
+        // the stream ends at the first error.
+        self.next_chunk_id = if payload_r.is_ok() { self.next_chunk_id + 1 } else { self.end_chunk_id };
+        Some(payload_r)
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the durable content of the chunk `chunk_id`, for a partial chunk its
+    /// content at the last flush. `ParametersError` is returned if `BitmapIndex` is
+    /// opened in memory mode or if the chunk has never been flushed.
+    pub fn chunk_payload(&mut self, chunk_id: u64) -> Result<ChunkPayload, Error> {
+        let chunk_start = chunk_id * self.chunk_size;
+        if chunk_start >= self.flushed_num_values {
+            return Err(Error::ParametersError);
+        }
+        let extent = self.chunk_disk_extent(chunk_id)?.ok_or(Error::ParametersError)?;
+        let storage_idx = self.storage_idx.as_mut().ok_or(Error::ParametersError)?;
+        let mut bytes: Vec<u8> = vec![0; (extent.end - extent.start) as usize];
+        Self::map_io_result(storage_idx.data_file.seek(SeekFrom::Start(extent.start)))?;
+        Self::map_io_result(storage_idx.data_file.read_exact(&mut bytes))?;
+        Ok(ChunkPayload {
+            chunk_id,
+            num_values: self.flushed_num_values.min(chunk_start + self.chunk_size),
+            bytes
+        })
+    }
+
+    /// Return the payloads of the chunks flushed after the first `num_values` values, to
+    /// apply on a follower with `num_values` values. The payloads end with the chunk of
+    /// the last flush. `ParametersError` is returned if `BitmapIndex` is opened in memory
+    /// mode or if it has less than `num_values` flushed values.
+    pub fn replication_stream(&mut self, num_values: u64) -> Result<ReplicationStream<'_, T, U>, Error> {
+        if self.storage_idx.is_none() || num_values > self.flushed_num_values {
+            return Err(Error::ParametersError);
+        }
+        let end_chunk_id = self.flushed_num_values.div_ceil(self.chunk_size);
+        let next_chunk_id = match num_values == self.flushed_num_values {
+            true => end_chunk_id,
+            false => Self::get_chunk_id(num_values, self.chunk_size)
+        };
+        Ok(ReplicationStream {
+            b_index: self,
+            next_chunk_id,
+            end_chunk_id
+        })
+    }
+
+    /// Apply `payload` of a leader with the same build options: the current chunk is
+    /// replaced and (in storage mode) flushed, a full chunk is rewritten with `rewrite_chunk`.
+    /// `BitmapError` is returned if `payload` is not a valid chunk, `MetaDataError` if it has
+    /// been written with different generic parameters or build options and `ParametersError`
+    /// if it is not the next payload of the follower or if the follower has unflushed values.
+    pub fn apply_chunk_payload(&mut self, payload: &ChunkPayload) -> Result<(), Error> {
+        if self.storage_idx.is_some() && self.is_dirty() {
+            return Err(Error::ParametersError);
+        }
+        let payload_size = payload.bytes.len() as u64;
+        let mut reader = Cursor::new(payload.bytes.as_slice());
+        let trailer = Self::read_chunk_trailer(&mut reader, payload_size)?
+            .filter(|trailer| trailer.size() == payload_size)
+            .ok_or(Error::BitmapError)?;
+        if !trailer.matches(&self.get_meta_data()) {
+            return Err(Error::MetaDataError);
+        }
+        if trailer.chunk_id() != payload.chunk_id || trailer.value_end() != payload.num_values {
+            return Err(Error::BitmapError);
+        }
+        let mut bitmaps: Vec<T> = Self::new_bitmaps(self.bitmaps.len(), 0, self.wide_words);
+        Self::read_chunk_bitmaps(&mut reader, (0, payload_size - CHUNK_TRAILER_SIZE as u64), &mut bitmaps)?;
+
+        let chunk_start = payload.chunk_id * self.chunk_size;
+        let chunk_end = chunk_start + self.chunk_size;
+        if payload.chunk_id < Self::get_chunk_id(self.num_values, self.chunk_size) {
+            if payload.num_values != chunk_end {
+                return Err(Error::ParametersError);
+            }
+            return self.rewrite_chunk(payload.chunk_id, |chunk| chunk.clone_from_slice(&bitmaps)).map(|_| ());
+        }
+        if chunk_start > self.num_values || payload.num_values <= self.num_values || payload.num_values > chunk_end {
+            return Err(Error::ParametersError);
+        }
+        self.bitmaps = bitmaps;
+        self.num_values = payload.num_values;
+        if self.num_values & self.chunk_size_mask == 0 {
+            return self.complete_chunk();
+        }
+        if self.storage_idx.is_some() {
+            return self.write_chunk();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Roaring import
+//!
+//! Build a `BitmapIndex` from per-value bitmaps produced by other bitmap-index systems:
+//! every file is the bitmap of the positions that have one value. Files can be in the
+//! portable Roaring format (the format of `serialize` of CRoaring, Roaring for Java and
+//! Go, with or without run containers) or in the Pilosa Roaring format, whose containers
+//! have 64-bit keys. Operations appended by Pilosa after the containers are ignored.
+//!
+//! The bitmaps must cover the positions `[0, n)` exactly once, where `n` is their total
+//! number of bits, since a position has one value. The values are reassembled in memory
+//! as a column of `n` values and inserted with `push_column`.
+
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    TransmuteToUsize
+};
+
+const SERIAL_COOKIE_NO_RUNCONTAINER: u32 = 12346;
+const SERIAL_COOKIE: u32 = 12347;
+const PILOSA_COOKIE: u32 = 12348;
+/// Number of containers from which a portable bitmap with run containers has offsets.
+const NO_OFFSET_THRESHOLD: usize = 4;
+/// Maximum cardinality of an array container in the portable format.
+const MAX_ARRAY_CARDINALITY: usize = 4096;
+const BITMAP_CONTAINER_SIZE: usize = 8192;
+
+const PILOSA_ARRAY_CONTAINER: u16 = 1;
+const PILOSA_BITMAP_CONTAINER: u16 = 2;
+const PILOSA_RUN_CONTAINER: u16 = 3;
+
+/// Cursor on the bytes of a serialized bitmap, all integers are little endian.
+struct RoaringReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> RoaringReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], IoError> {
+        let bytes = self.data.get(self.pos..(self.pos + len)).ok_or_else(|| invalid_data("truncated roaring bitmap"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, IoError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, IoError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, IoError> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    /// Append to `positions` the `cardinality` positions of an array container.
+    fn array_container(&mut self, key: u64, cardinality: usize, positions: &mut Vec<u64>) -> Result<(), IoError> {
+        for _i in 0..cardinality {
+            positions.push(key << 16 | self.u16()? as u64);
+        }
+        Ok(())
+    }
+
+    /// Append to `positions` the positions of a bitmap container.
+    fn bitmap_container(&mut self, key: u64, positions: &mut Vec<u64>) -> Result<(), IoError> {
+        let words = self.bytes(BITMAP_CONTAINER_SIZE)?;
+        for (i_word, word) in words.chunks_exact(8).enumerate() {
+            let mut word = u64::from_le_bytes([word[0], word[1], word[2], word[3], word[4], word[5], word[6], word[7]]);
+            while word != 0 {
+                positions.push(key << 16 | (i_word * 64) as u64 | word.trailing_zeros() as u64);
+                word &= word - 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append to `positions` the positions of a run container, every run is a start and
+    /// a length minus one in the portable format, a start and a last in the Pilosa format.
+    fn run_container(&mut self, key: u64, inclusive_last: bool, positions: &mut Vec<u64>) -> Result<(), IoError> {
+        let num_runs = self.u16()?;
+        for _i in 0..num_runs {
+            let start = self.u16()? as u64;
+            let last = if inclusive_last { self.u16()? as u64 } else { start + self.u16()? as u64 };
+            if last < start || last > u16::MAX as u64 {
+                return Err(invalid_data("invalid run container"));
+            }
+            positions.extend((start..=last).map(|low| key << 16 | low));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(msg: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, msg)
+}
+
+/// Return the positions of the serialized Roaring bitmap `data`, in the portable or
+/// in the Pilosa format.
+fn roaring_positions(data: &[u8]) -> Result<Vec<u64>, IoError> {
+    let mut reader = RoaringReader { data, pos: 0 };
+    let cookie = reader.u32()?;
+    let mut positions: Vec<u64> = Vec::new();
+    if cookie & 0xffff == PILOSA_COOKIE {
+        let num_containers = reader.u32()? as usize;
+        let mut containers: Vec<(u64, u16, usize)> = Vec::new();
+        for _i in 0..num_containers {
+            let key = reader.u64()?;
+            if key > u64::MAX >> 16 {
+                return Err(invalid_data("invalid pilosa container key"));
+            }
+            containers.push((key, reader.u16()?, reader.u16()? as usize + 1));
+        }
+        let mut offsets: Vec<usize> = Vec::new();
+        for _i in 0..num_containers {
+            offsets.push(reader.u32()? as usize);
+        }
+        for ((key, container_type, cardinality), offset) in containers.into_iter().zip(offsets) {
+            reader.pos = offset;
+            match container_type {
+                PILOSA_ARRAY_CONTAINER => reader.array_container(key, cardinality, &mut positions)?,
+                PILOSA_BITMAP_CONTAINER => reader.bitmap_container(key, &mut positions)?,
+                PILOSA_RUN_CONTAINER => reader.run_container(key, true, &mut positions)?,
+                _ => return Err(invalid_data("unknown pilosa container type"))
+            }
+        }
+        return Ok(positions);
+    }
+
+    let (num_containers, run_flags) = if cookie & 0xffff == SERIAL_COOKIE {
+        let num_containers = (cookie >> 16) as usize + 1;
+        (num_containers, Some(reader.bytes(num_containers.div_ceil(8))?))
+    } else if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+        (reader.u32()? as usize, None)
+    } else {
+        return Err(invalid_data("unknown roaring cookie"));
+    };
+    let mut containers: Vec<(u64, usize)> = Vec::new();
+    for _i in 0..num_containers {
+        containers.push((reader.u16()? as u64, reader.u16()? as usize + 1));
+    }
+    if run_flags.is_none() || num_containers >= NO_OFFSET_THRESHOLD {
+        // Containers are stored in order, so their offsets are not needed.
+        reader.bytes(num_containers * 4)?;
+    }
+    for (i, (key, cardinality)) in containers.into_iter().enumerate() {
+        if run_flags.is_some_and(|run_flags| run_flags[i / 8] & (1 << (i % 8)) != 0) {
+            reader.run_container(key, false, &mut positions)?;
+        } else if cardinality > MAX_ARRAY_CARDINALITY {
+            reader.bitmap_container(key, &mut positions)?;
+        } else {
+            reader.array_container(key, cardinality, &mut positions)?;
+        }
+    }
+    Ok(positions)
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `BitmapIndex` that contains the values of `bitmap_files`, pairs of a
+    /// value and the Roaring bitmap file of its positions, in storage mode in the
+    /// directory `dir_path` (that must not exist) or in memory mode if `dir_path` is
+    /// `None`. See `push_roaring_bitmaps`.
+    pub fn import_roaring<I, P>(dir_path: Option<&Path>, build_options: BuildOptions, bitmap_files: I) -> Result<Self, Error>
+    where I: IntoIterator<Item=(U, P)>, P: AsRef<Path> {
+        let mut b_index = match dir_path {
+            Some(dir_path) => Self::create(dir_path, build_options)?,
+            None => Self::new(build_options)?
+        };
+        b_index.push_roaring_bitmaps(bitmap_files)?;
+        Ok(b_index)
+    }
+
+    /// Return a new `BitmapIndex` from the Roaring bitmap files of the directory
+    /// `roaring_dir`, the value of each file is `value_of` its name without extension
+    /// and files whose value is `None` are skipped. See `import_roaring`.
+    pub fn import_roaring_dir<F>(dir_path: Option<&Path>, build_options: BuildOptions, roaring_dir: &Path, mut value_of: F) -> Result<Self, Error>
+    where F: FnMut(&str) -> Option<U> {
+        let mut bitmap_files = Vec::new();
+        for entry in Self::map_io_result(fs::read_dir(roaring_dir))? {
+            let path = Self::map_io_result(entry)?.path();
+            let value = path.file_stem().and_then(|stem| stem.to_str()).and_then(&mut value_of);
+            if let (Some(value), true) = (value, path.is_file()) {
+                bitmap_files.push((value, path));
+            }
+        }
+        Self::import_roaring(dir_path, build_options, bitmap_files)
+    }
+
+    /// Insert (in append) the values of `bitmap_files`, pairs of a value and the Roaring
+    /// bitmap file of its positions (relative to the first value inserted), and return
+    /// the number of values inserted. Error occur if a file is not a valid Roaring
+    /// bitmap or if the bitmaps don't cover `[0, n)` exactly once, in that case no value
+    /// is inserted.
+    pub fn push_roaring_bitmaps<I, P>(&mut self, bitmap_files: I) -> Result<u64, Error>
+    where I: IntoIterator<Item=(U, P)>, P: AsRef<Path> {
+        let mut value_positions: Vec<(U, Vec<u64>)> = Vec::new();
+        for (value, path) in bitmap_files {
+            let data = Self::map_io_result(fs::read(path))?;
+            value_positions.push((value, Self::map_io_result(roaring_positions(&data))?));
+        }
+        let num_values: usize = value_positions.iter().map(|(_value, positions)| positions.len()).sum();
+        let mut column: Vec<Option<U>> = vec![None; num_values];
+        for (value, positions) in value_positions {
+            for pos in positions {
+                match column.get_mut(pos as usize) {
+                    Some(column_value @ None) => *column_value = Some(value),
+                    _ => {
+                        let msg = format!("position {} is out of range or set by more values", pos);
+                        return Err(Error::FileError(IoError::new(ErrorKind::InvalidData, msg)));
+                    }
+                }
+            }
+        }
+        // Positions are in range and distinct, so they cover all the column.
+        self.push_column(column.into_iter().flatten())
+    }
+}
@@ -0,0 +1,84 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Sampling queries
+//!
+//! `run_query_sample` returns a uniform random subset of the indexes of a value without
+//! unrolling all of them. The result bitmap of each chunk is kept compressed and its
+//! cardinality allocates the sample: k distinct ranks are drawn from the total number of
+//! matches (Floyd's algorithm) and only the chunks with a drawn rank are unrolled, one
+//! at a time. The ranks are drawn with a splitmix64 generator, so a `seed` always
+//! returns the same sample of the same index.
+
+use std::collections::HashSet;
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// A splitmix64 pseudo random number generator.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Return a number in `[0, n)`.
+    fn next_below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a sorted `Vec<u64>` with `k` indexes drawn uniformly, without repetitions,
+    /// from the indexes of values pushed in `BitmapIndex` equal to `value`. All indexes
+    /// are returned if they are at most `k`. The same `seed` returns the same sample.
+    pub fn run_query_sample(&mut self, value: U, k: usize, seed: u64) -> Result<Vec<u64>, Error> {
+        let chunks_results = self.chunks_results(value, 0, self.num_values)?;
+        let cardinalities: Vec<u64> = chunks_results.iter().map(|(_chunk_id, b_result)| b_result.cardinality()).collect();
+        let num_matches: u64 = cardinalities.iter().sum();
+        let k = (k as u64).min(num_matches);
+
+        let mut rng = SplitMix64(seed);
+        let mut ranks: HashSet<u64> = HashSet::with_capacity(k as usize);
+        for j in (num_matches - k)..num_matches {
+            let rank = rng.next_below(j + 1);
+            if !ranks.insert(rank) {
+                ranks.insert(j);
+            }
+        }
+        let mut ranks: Vec<u64> = ranks.into_iter().collect();
+        ranks.sort_unstable();
+
+        let mut indexes: Vec<u64> = Vec::with_capacity(ranks.len());
+        let mut ranks = ranks.into_iter().peekable();
+        let mut chunk_first_rank: u64 = 0;
+        for ((chunk_id, b_result), cardinality) in chunks_results.iter().zip(cardinalities) {
+            let chunk_end_rank = chunk_first_rank + cardinality;
+            if ranks.peek().is_some_and(|rank| *rank < chunk_end_rank) {
+                let offset_index = chunk_id * self.chunk_size;
+                let chunk_indexes: Vec<u32> = b_result.unroll_bitmap();
+                while let Some(rank) = ranks.next_if(|rank| *rank < chunk_end_rank) {
+                    indexes.push(offset_index + chunk_indexes[(rank - chunk_first_rank) as usize] as u64);
+                }
+            }
+            chunk_first_rank = chunk_end_rank;
+        }
+        Ok(indexes)
+    }
+}
@@ -0,0 +1,556 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Segmented storage
+//!
+//! `SegmentedIndex` stores an index as a set of immutable segment files listed in a
+//! manifest, instead of three ever-growing files. Values are pushed in an in-memory
+//! `BitmapIndex` and every flush writes the values pushed since the previous flush in
+//! a new segment, so a segment is never rewritten: a snapshot is a hard link of the
+//! segment files and old segments can be replicated or deleted one by one.
+//!
+//! The segment `i` of a manifest covers the values `[start_index, start_index + num_values)`
+//! and its chunks are relative to `start_index`. The manifest is replaced atomically
+//! (written in a temporary file and renamed), a segment is visible only after its
+//! manifest entry is written.
+//!
+//! # Encoding
+//! manifest:
+//!  |MetaData|u64 next_segment_id|u64 num_segments|u64 id|u64 start_index|u64 num_values|...|
+//! segment:
+//!  |u64 num_values|u64 num_chunks|u64 chunk_offset|...|u64 end_offset|chunk|...|
+//! where each chunk is written in `ChunkFormat::Plain` format.
+//!
+//! # Merge
+//! Low-rate ingestion flushes many small segments, a `MergePolicy` bounds their number
+//! merging runs of adjacent small segments in a larger one (size-tiered merge). A merge
+//! writes the new segment, replaces the merged segments in the manifest and then
+//! deletes their files, so queries and snapshots always see a consistent set of segments.
+//! The I/O of merges can be limited with `set_merge_io_rate`.
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::{BufWriter, Error as IoError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Range, Shr};
+use std::path::{Path, PathBuf};
+
+use super::io_throttle::{IoThrottle, ThrottledWriter};
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error,
+    MetaData,
+    TransmuteToUsize,
+    KEY_FORMAT,
+    META_DATA_VERSION,
+    WRITE_BUFFER_SIZE
+};
+
+const MANIFEST_FILE_NAME: &str = "manifest";
+const SEGMENT_EXTENSION: &str = "sbidx";
+
+/// `SegmentInfo` describes a segment listed in the manifest of a `SegmentedIndex`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentInfo {
+    id: u64,
+    start_index: u64,
+    num_values: u64
+}
+
+impl SegmentInfo {
+    /// Return the id of the segment, that is also its file name.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Return the index of the first value of the segment.
+    pub fn start_index(&self) -> u64 {
+        self.start_index
+    }
+
+    /// Return the number of values of the segment.
+    pub fn num_values(&self) -> u64 {
+        self.num_values
+    }
+
+    /// Return the index after the last value of the segment.
+    pub fn end_index(&self) -> u64 {
+        self.start_index + self.num_values
+    }
+}
+
+/// `MergePolicy` defines when a `SegmentedIndex` merges its segments: segments with
+/// less than `max_segment_values` values are small and every run of at least
+/// `min_merge_segments` adjacent small segments is merged in a segment with at most
+/// `max_segment_values` values.
+#[derive(Clone, Debug)]
+pub struct MergePolicy {
+    min_merge_segments: usize,
+    max_segment_values: u64
+}
+
+impl MergePolicy {
+    /// Return a new size-tiered `MergePolicy`, `min_merge_segments` must be at least 2.
+    pub fn size_tiered(min_merge_segments: usize, max_segment_values: u64) -> Result<Self, Error> {
+        if min_merge_segments < 2 {
+            return Err(Error::ParametersError);
+        }
+        Ok(MergePolicy {
+            min_merge_segments,
+            max_segment_values
+        })
+    }
+
+    /// Return the ranges of `segments` to merge.
+    fn merge_runs(&self, segments: &[SegmentInfo]) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        let mut run_start: usize = 0;
+        let mut run_values: u64 = 0;
+        for (i, segment) in segments.iter().enumerate() {
+            let is_small = segment.num_values < self.max_segment_values;
+            if !is_small || run_values + segment.num_values > self.max_segment_values {
+                if i - run_start >= self.min_merge_segments {
+                    runs.push(run_start..i);
+                }
+                run_start = if is_small { i } else { i + 1 };
+                run_values = 0;
+            }
+            if is_small {
+                run_values += segment.num_values;
+            }
+        }
+        if segments.len() - run_start >= self.min_merge_segments {
+            runs.push(run_start..segments.len());
+        }
+        runs
+    }
+}
+
+/// `SegmentedIndex` struct that stores a bitmap index as immutable segments.
+pub struct SegmentedIndex<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    dir_path: PathBuf,
+    build_options: BuildOptions,
+    segments: Vec<SegmentInfo>,
+    next_segment_id: u64,
+    flushed_num_values: u64,
+    memtable: BitmapIndex<T, U>,
+    merge_policy: Option<MergePolicy>,
+    merge_io_rate: Option<u64>,
+    created_at: u64
+}
+
+impl<T: Bitmap, U: BitValue> SegmentedIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Create a new empty `SegmentedIndex` in the directory `dir_path`, that must not exist.
+    pub fn create(dir_path: &Path, build_options: BuildOptions) -> Result<Self, Error> {
+        let memtable = BitmapIndex::new(build_options.clone())?;
+        BitmapIndex::<T, U>::map_io_result(fs::create_dir(dir_path))?;
+        let s_index = SegmentedIndex {
+            dir_path: PathBuf::from(dir_path),
+            build_options,
+            segments: Vec::new(),
+            next_segment_id: 0,
+            flushed_num_values: 0,
+            memtable,
+            merge_policy: None,
+            merge_io_rate: None,
+            created_at: MetaData::unix_time_now()
+        };
+        s_index.write_manifest()?;
+        Ok(s_index)
+    }
+
+    /// Open a `SegmentedIndex` from the directory `dir_path`.
+    pub fn open(dir_path: &Path) -> Result<Self, Error> {
+        let buf = BitmapIndex::<T, U>::map_io_result(fs::read(dir_path.join(MANIFEST_FILE_NAME)))?;
+        let meta_data_size = mem::size_of::<MetaData>();
+        let meta_data_buf = buf.get(0..meta_data_size).ok_or_else(Self::invalid_manifest)?;
+        let meta_data = BitmapIndex::<T, U>::meta_data_from_slice_u8(meta_data_buf)?;
+        BitmapIndex::<T, U>::check_meta_data(&meta_data)?;
+
+        let words: Vec<u64> = buf[meta_data_size..].chunks_exact(mem::size_of::<u64>())
+            .map(|w| u64::from_ne_bytes([w[0], w[1], w[2], w[3], w[4], w[5], w[6], w[7]]))
+            .collect();
+        if words.len() < 2 || words.len() != 2 + words[1] as usize * 3 {
+            return Err(Self::invalid_manifest());
+        }
+        let segments: Vec<SegmentInfo> = words[2..].chunks_exact(3)
+            .map(|s| SegmentInfo { id: s[0], start_index: s[1], num_values: s[2] })
+            .collect();
+        let flushed_num_values = segments.last().map_or(0, |s| s.end_index());
+        if flushed_num_values != meta_data.num_values {
+            return Err(Error::MetaDataError);
+        }
+        Ok(SegmentedIndex {
+            dir_path: PathBuf::from(dir_path),
+            build_options: meta_data.build_options.clone(),
+            segments,
+            next_segment_id: words[0],
+            flushed_num_values,
+            memtable: BitmapIndex::new(meta_data.build_options.clone())?,
+            merge_policy: None,
+            merge_io_rate: None,
+            created_at: meta_data.created_at
+        })
+    }
+
+    /// Set the `MergePolicy` applied after every flush.
+    pub fn set_merge_policy(&mut self, merge_policy: Option<MergePolicy>) {
+        self.merge_policy = merge_policy;
+    }
+
+    /// Limit the I/O of merges to `bytes_per_sec` bytes per second (read and written),
+    /// so merges don't starve queries on the same disk. With `None` the I/O of merges
+    /// is not limited.
+    pub fn set_merge_io_rate(&mut self, bytes_per_sec: Option<u64>) {
+        self.merge_io_rate = bytes_per_sec;
+    }
+
+    /// Insert (in append) a `value` into the index.
+    pub fn push_value(&mut self, value: U) -> Result<(), Error> {
+        self.memtable.push_value(value)
+    }
+
+    /// Insert (in append) values into the index.
+    pub fn push_values(&mut self, values: &[U]) -> Result<(), Error> {
+        self.memtable.push_values(values)
+    }
+
+    /// Return the number of values pushed in `SegmentedIndex`.
+    pub fn num_values(&self) -> u64 {
+        self.flushed_num_values + self.memtable.num_values()
+    }
+
+    /// Return the number of values pushed after the last flush.
+    pub fn unflushed_values(&self) -> u64 {
+        self.memtable.num_values()
+    }
+
+    /// Return the segments listed in the manifest, ordered by `start_index`.
+    pub fn segments(&self) -> &[SegmentInfo] {
+        &self.segments
+    }
+
+    /// Return the path of the file of `segment`.
+    pub fn segment_path(&self, segment: &SegmentInfo) -> PathBuf {
+        Self::segment_file_path(&self.dir_path, segment.id)
+    }
+
+    /// Write the values pushed after the last flush in a new segment and add it to the
+    /// manifest, then merge the segments following the `MergePolicy` (if any).
+    /// Nothing is written if there are no unflushed values.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.memtable.num_values() == 0 {
+            return Ok(());
+        }
+        let segment = SegmentInfo {
+            id: self.next_segment_id,
+            start_index: self.flushed_num_values,
+            num_values: self.memtable.num_values()
+        };
+        let mut chunks: Vec<&[T]> = self.memtable.chunks.iter().flatten().map(|c| c.as_slice()).collect();
+        if self.memtable.num_values & self.memtable.chunk_size_mask != 0 {
+            chunks.push(&self.memtable.bitmaps);
+        }
+        self.write_segment(segment.id, segment.num_values, &chunks, &mut IoThrottle::new(None))?;
+
+        self.next_segment_id += 1;
+        self.flushed_num_values = segment.end_index();
+        self.segments.push(segment);
+        if let Err(err) = self.write_manifest() {
+            let segment = self.segments.pop().unwrap();
+            self.flushed_num_values = segment.start_index;
+            let _err = fs::remove_file(self.segment_path(&segment));
+            return Err(err);
+        }
+        self.memtable = BitmapIndex::new(self.build_options.clone())?;
+        self.merge_segments()?;
+        Ok(())
+    }
+
+    /// Merge the segments following the `MergePolicy` and return the number of merged
+    /// segments. Nothing is merged if `SegmentedIndex` has no `MergePolicy`.
+    pub fn merge_segments(&mut self) -> Result<usize, Error> {
+        let runs = match self.merge_policy.as_ref() {
+            Some(merge_policy) => merge_policy.merge_runs(&self.segments),
+            None => return Ok(0)
+        };
+        let mut num_merged: usize = 0;
+        // merge from the last run, so the ranges of the previous runs are still valid.
+        for run in runs.into_iter().rev() {
+            num_merged += run.len();
+            self.merge_run(run)?;
+        }
+        Ok(num_merged)
+    }
+
+    /// Merge the adjacent segments `run` in a new segment.
+    fn merge_run(&mut self, run: Range<usize>) -> Result<(), Error> {
+        let chunk_size = self.memtable.chunk_size;
+        let num_bitmaps = self.memtable.bitmaps.len();
+        let start_index = self.segments[run.start].start_index;
+        let num_values = self.segments[run.end - 1].end_index() - start_index;
+        let num_chunks = num_values.div_ceil(chunk_size) as usize;
+        let wide_words = self.memtable.wide_words;
+        let mut chunks: Vec<Vec<T>> = (0..num_chunks).map(|_| BitmapIndex::<T, U>::new_bitmaps(num_bitmaps, 0, wide_words)).collect();
+
+        let mut io_throttle = IoThrottle::new(self.merge_io_rate);
+        let mut segment_chunk: Vec<T> = vec![T::new(); num_bitmaps];
+        for segment in self.segments[run.clone()].iter() {
+            let mut file = BitmapIndex::<T, U>::map_io_result(fs::File::open(self.segment_path(segment)))?;
+            let chunks_offsets = Self::read_segment_offsets(&mut file, segment)?;
+            for (chunk_id, chunk_offsets) in chunks_offsets.windows(2).enumerate() {
+                BitmapIndex::<T, U>::read_chunk_bitmaps(&mut file, (chunk_offsets[0], chunk_offsets[1]), &mut segment_chunk)?;
+                io_throttle.consume((chunk_offsets[1] - chunk_offsets[0]) as usize);
+
+                let offset_index = segment.start_index - start_index + chunk_id as u64 * chunk_size;
+                for (i_bitmap, b) in segment_chunk.iter().enumerate() {
+                    for idx in b.unroll_bitmap() {
+                        let index = offset_index + idx as u64;
+                        chunks[(index / chunk_size) as usize][i_bitmap].set((index % chunk_size) as u32);
+                    }
+                }
+            }
+        }
+        let segment = SegmentInfo {
+            id: self.next_segment_id,
+            start_index,
+            num_values
+        };
+        let chunks_ref: Vec<&[T]> = chunks.iter().map(|c| c.as_slice()).collect();
+        self.write_segment(segment.id, num_values, &chunks_ref, &mut io_throttle)?;
+        self.next_segment_id += 1;
+
+        let segment_path = self.segment_path(&segment);
+        let merged: Vec<SegmentInfo> = self.segments.splice(run.clone(), [segment]).collect();
+        if let Err(err) = self.write_manifest() {
+            self.segments.splice(run.start..(run.start + 1), merged);
+            let _err = fs::remove_file(segment_path);
+            return Err(err);
+        }
+        for segment in merged.iter() {
+            BitmapIndex::<T, U>::map_io_result(fs::remove_file(self.segment_path(segment)))?;
+        }
+        Ok(())
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values pushed in `SegmentedIndex`
+    /// equal to `value`. The parameters `start_index` and `end_index` are optional and if
+    /// specified define the range where query is runned. Values of removed segments
+    /// are not returned.
+    pub fn run_query(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values());
+        let query_i_bitmaps = BitmapIndex::<T, U>::get_query_i_bitmaps(&self.memtable.block_info, value);
+
+        let mut indexes: Vec<u64> = Vec::new();
+        for segment in self.segments.iter() {
+            if segment.end_index() <= start_index || segment.start_index > end_index {
+                continue;
+            }
+            let local_start = start_index.saturating_sub(segment.start_index);
+            let local_end = (end_index - segment.start_index).min(segment.num_values - 1);
+            let mut file = BitmapIndex::<T, U>::map_io_result(fs::File::open(self.segment_path(segment)))?;
+            let chunks_offsets = Self::read_segment_offsets(&mut file, segment)?;
+            let local_indexes = Self::run_query_on_segment(&mut file, &chunks_offsets, &query_i_bitmaps, self.memtable.chunk_size, local_start, local_end)?;
+            indexes.extend(local_indexes.iter().map(|idx| segment.start_index + idx));
+        }
+        if end_index >= self.flushed_num_values && self.memtable.num_values() > 0 {
+            let local_start = start_index.saturating_sub(self.flushed_num_values);
+            let local_end = end_index - self.flushed_num_values;
+            let local_indexes = self.memtable.run_query(value, Some(local_start), Some(local_end))?;
+            indexes.extend(local_indexes.iter().map(|idx| self.flushed_num_values + idx));
+        }
+        Ok(indexes)
+    }
+
+    /// Create a snapshot of the flushed values of `SegmentedIndex` in the directory
+    /// `dir_path`, that must not exist. Segment files are hard linked (or copied if the
+    /// file system doesn't support hard links), so a snapshot is cheap and remains valid
+    /// when segments are removed from this index.
+    pub fn snapshot(&self, dir_path: &Path) -> Result<SegmentedIndex<T, U>, Error> {
+        BitmapIndex::<T, U>::map_io_result(fs::create_dir(dir_path))?;
+        for segment in self.segments.iter() {
+            let src_path = self.segment_path(segment);
+            let dst_path = Self::segment_file_path(dir_path, segment.id);
+            if fs::hard_link(&src_path, &dst_path).is_err() {
+                BitmapIndex::<T, U>::map_io_result(fs::copy(&src_path, &dst_path))?;
+            }
+        }
+        let snapshot = SegmentedIndex {
+            dir_path: PathBuf::from(dir_path),
+            build_options: self.build_options.clone(),
+            segments: self.segments.clone(),
+            next_segment_id: self.next_segment_id,
+            flushed_num_values: self.flushed_num_values,
+            memtable: BitmapIndex::new(self.build_options.clone())?,
+            merge_policy: None,
+            merge_io_rate: None,
+            created_at: self.created_at
+        };
+        snapshot.write_manifest()?;
+        Ok(snapshot)
+    }
+
+    /// Remove from the manifest and delete the segments whose values are all before
+    /// `index` and return the number of removed segments. The indexes of the other
+    /// values don't change.
+    pub fn remove_segments_before(&mut self, index: u64) -> Result<usize, Error> {
+        let num_removed = self.segments.iter().take_while(|s| s.end_index() <= index).count();
+        if num_removed == 0 {
+            return Ok(0);
+        }
+        let removed: Vec<SegmentInfo> = self.segments.drain(0..num_removed).collect();
+        if let Err(err) = self.write_manifest() {
+            self.segments.splice(0..0, removed);
+            return Err(err);
+        }
+        for segment in removed.iter() {
+            BitmapIndex::<T, U>::map_io_result(fs::remove_file(self.segment_path(segment)))?;
+        }
+        Ok(num_removed)
+    }
+
+    fn segment_file_path(dir_path: &Path, id: u64) -> PathBuf {
+        let mut path = PathBuf::from(dir_path);
+        path.push(format!("{:016x}", id));
+        path.set_extension(SEGMENT_EXTENSION);
+        path
+    }
+
+    fn invalid_manifest() -> Error {
+        Error::FileError(IoError::new(ErrorKind::InvalidData, "invalid segments manifest"))
+    }
+
+    fn invalid_segment() -> Error {
+        Error::FileError(IoError::new(ErrorKind::InvalidData, "invalid segment file"))
+    }
+
+    fn write_manifest(&self) -> Result<(), Error> {
+        let meta_data = MetaData {
+            meta_data_version: META_DATA_VERSION,
+            key_format: KEY_FORMAT,
+            value_size: mem::size_of::<U>() as u32,
+            num_values: self.flushed_num_values,
+            build_options: self.build_options.clone(),
+            bitmap_tag: T::format_tag(),
+            library_version: MetaData::current_library_version(),
+            created_at: self.created_at,
+            modified_at: MetaData::unix_time_now()
+        };
+        let mut buf: Vec<u8> = Vec::new();
+        buf.extend_from_slice(BitmapIndex::<T, U>::to_slice_u8(&meta_data));
+        buf.extend_from_slice(&self.next_segment_id.to_ne_bytes());
+        buf.extend_from_slice(&(self.segments.len() as u64).to_ne_bytes());
+        for segment in self.segments.iter() {
+            buf.extend_from_slice(&segment.id.to_ne_bytes());
+            buf.extend_from_slice(&segment.start_index.to_ne_bytes());
+            buf.extend_from_slice(&segment.num_values.to_ne_bytes());
+        }
+        let manifest_path = self.dir_path.join(MANIFEST_FILE_NAME);
+        let tmp_path = manifest_path.with_extension("tmp");
+        BitmapIndex::<T, U>::map_io_result(fs::write(&tmp_path, &buf))?;
+        BitmapIndex::<T, U>::map_io_result(fs::rename(&tmp_path, &manifest_path))
+    }
+
+    fn write_segment(&self, id: u64, num_values: u64, chunks: &[&[T]], io_throttle: &mut IoThrottle) -> Result<(), Error> {
+        let path = Self::segment_file_path(&self.dir_path, id);
+        let tmp_path = path.with_extension("tmp");
+        BitmapIndex::<T, U>::map_io_result(Self::write_segment_file(&tmp_path, num_values, chunks, io_throttle))?;
+        BitmapIndex::<T, U>::map_io_result(fs::rename(&tmp_path, &path))
+    }
+
+    fn write_segment_file(path: &Path, num_values: u64, chunks: &[&[T]], io_throttle: &mut IoThrottle) -> Result<(), IoError> {
+        let file = fs::File::create(path)?;
+        let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, ThrottledWriter::new(file, io_throttle));
+        writer.write_all(&num_values.to_ne_bytes())?;
+        writer.write_all(&(chunks.len() as u64).to_ne_bytes())?;
+
+        let mut chunk_offset = (mem::size_of::<u64>() * (chunks.len() + 3)) as u64;
+        writer.write_all(&chunk_offset.to_ne_bytes())?;
+        for bitmaps in chunks {
+            let table_size = (bitmaps.len() + 1) * mem::size_of::<u32>();
+            let bitmaps_size: usize = bitmaps.iter().map(|b| b.size()).sum();
+            chunk_offset += (table_size + bitmaps_size) as u64;
+            writer.write_all(&chunk_offset.to_ne_bytes())?;
+        }
+        for bitmaps in chunks {
+            let mut offset = ((bitmaps.len() + 1) * mem::size_of::<u32>()) as u32;
+            writer.write_all(&offset.to_ne_bytes())?;
+            for b in bitmaps.iter() {
+                offset += b.size() as u32;
+                writer.write_all(&offset.to_ne_bytes())?;
+            }
+            for b in bitmaps.iter() {
+                b.write_to(&mut writer)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Read the chunk offsets of `segment`, the last offset is the end of the last chunk.
+    fn read_segment_offsets(file: &mut fs::File, segment: &SegmentInfo) -> Result<Vec<u64>, Error> {
+        let mut header: [u8; 16] = [0; 16];
+        BitmapIndex::<T, U>::map_io_result(file.read_exact(&mut header))?;
+        let num_values = u64::from_ne_bytes(header[0..8].try_into().unwrap());
+        let num_chunks = u64::from_ne_bytes(header[8..16].try_into().unwrap());
+        let file_size = BitmapIndex::<T, U>::map_io_result(file.metadata())?.len();
+        if num_values != segment.num_values || (num_chunks + 3) * mem::size_of::<u64>() as u64 > file_size {
+            return Err(Self::invalid_segment());
+        }
+        let mut offsets_buf: Vec<u8> = vec![0; (num_chunks as usize + 1) * mem::size_of::<u64>()];
+        BitmapIndex::<T, U>::map_io_result(file.read_exact(&mut offsets_buf))?;
+        let offsets: Vec<u64> = offsets_buf.chunks_exact(mem::size_of::<u64>())
+            .map(|w| u64::from_ne_bytes(w.try_into().unwrap()))
+            .collect();
+        if offsets.windows(2).any(|w| w[0] > w[1]) || offsets[num_chunks as usize] > file_size {
+            return Err(Self::invalid_segment());
+        }
+        Ok(offsets)
+    }
+
+    fn run_query_on_segment(file: &mut fs::File, chunks_offsets: &[u64], query_i_bitmaps: &[usize], chunk_size: u64, start_index: u64, end_index: u64) -> Result<Vec<u64>, Error> {
+        let mut indexes: Vec<u64> = Vec::new();
+        for (chunk_id, chunk_offsets) in chunks_offsets.windows(2).enumerate() {
+            let chunk_id = chunk_id as u64;
+            if !BitmapIndex::<T, U>::chunk_in_range(chunk_id, chunk_size, start_index, end_index) {
+                continue;
+            }
+            let mut query_bitmaps: Vec<T> = Vec::with_capacity(query_i_bitmaps.len());
+            for i_bitmap in query_i_bitmaps {
+                query_bitmaps.push(Self::read_segment_bitmap(file, chunk_offsets, *i_bitmap)?);
+            }
+            let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+            BitmapIndex::<T, U>::push_indexes(&query_bitmaps_ref, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+        }
+        Ok(indexes)
+    }
+
+    fn read_segment_bitmap(file: &mut fs::File, chunk_offsets: &[u64], i_bitmap: usize) -> Result<T, Error> {
+        let mut offsets_buf: [u8; 8] = [0; 8];
+        let table_offset = chunk_offsets[0] + (i_bitmap * mem::size_of::<u32>()) as u64;
+        BitmapIndex::<T, U>::map_io_result(file.seek(SeekFrom::Start(table_offset)))?;
+        BitmapIndex::<T, U>::map_io_result(file.read_exact(&mut offsets_buf))?;
+        let start_offset = chunk_offsets[0] + u32::from_ne_bytes(offsets_buf[0..4].try_into().unwrap()) as u64;
+        let end_offset = chunk_offsets[0] + u32::from_ne_bytes(offsets_buf[4..8].try_into().unwrap()) as u64;
+        if start_offset > end_offset || end_offset > chunk_offsets[1] {
+            return Err(Self::invalid_segment());
+        }
+        let mut bitmap = T::new();
+        if start_offset == end_offset {
+            return Ok(bitmap);
+        }
+        BitmapIndex::<T, U>::map_io_result(file.seek(SeekFrom::Start(start_offset)))?;
+        BitmapIndex::<T, U>::read_bitmap_from(file, (end_offset - start_offset) as usize, true, &mut bitmap)?;
+        Ok(bitmap)
+    }
+}
@@ -0,0 +1,90 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Query selection
+//!
+//! A selection is a pre-filter for a query: a bitmap for each chunk with the rows
+//! (relative to the chunk) that can be returned, i.e. the rows already matching
+//! earlier predicates. `run_query_with_selection` runs "logical and" between the
+//! query bitmaps and the selection bitmap of each chunk before unrolling, so rows
+//! out of the selection are never decoded.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the selection of `indexes`, that must be sorted (i.e. the result of
+    /// `run_query`). The ith bitmap of the selection represents the indexes of the ith chunk.
+    pub fn selection_from_indexes(&self, indexes: &[u64]) -> Vec<T> {
+        let mut selection: Vec<T> = Vec::new();
+        for index in indexes {
+            let chunk_id = Self::get_chunk_id(*index, self.chunk_size) as usize;
+            if selection.len() <= chunk_id {
+                selection.resize(chunk_id + 1, T::new());
+            }
+            selection[chunk_id].set((*index & self.chunk_size_mask) as u32);
+        }
+        selection
+    }
+
+    /// Return the selection of all indexes of values pushed in `BitmapIndex` equal to
+    /// `value`, without unrolling the result bitmaps.
+    pub fn query_selection(&mut self, value: U) -> Result<Vec<T>, Error> {
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let mut selection: Vec<T> = Vec::new();
+        let f = |_chunk_id: u64, query_bitmaps: &[&T]| {
+            selection.push(Self::and_bitmaps(query_bitmaps));
+        };
+        self.for_each_chunk(&query_i_bitmaps, 0, self.num_values, f)?;
+        Ok(selection)
+    }
+
+    /// Return the sorted indexes of `selection`.
+    pub fn selection_indexes(&self, selection: &[T]) -> Vec<u64> {
+        let mut indexes: Vec<u64> = Vec::new();
+        for (chunk_id, b) in selection.iter().enumerate() {
+            let offset_index = chunk_id as u64 * self.chunk_size;
+            indexes.extend(b.unroll_bitmap().iter().map(|idx| offset_index + *idx as u64));
+        }
+        indexes
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values pushed in `BitmapIndex`
+    /// equal to `value` that are also in `selection`. Chunks without a selection bitmap
+    /// are skipped. The parameters `start_index` and `end_index` are optional and if
+    /// specified define the range where query is runned.
+    pub fn run_query_with_selection(&mut self, value: U, selection: &[T], start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values)
+            .min((selection.len() as u64 * self.chunk_size).saturating_sub(1));
+        if selection.is_empty() {
+            return Ok(Vec::new());
+        }
+        let chunk_size = self.chunk_size;
+        let mut indexes: Vec<u64> = Vec::new();
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            let selection_bitmap = &selection[chunk_id as usize];
+            if selection_bitmap.is_empty() {
+                return;
+            }
+            let mut query_bitmaps: Vec<&T> = query_bitmaps.to_vec();
+            query_bitmaps.insert(0, selection_bitmap);
+            Self::push_indexes(&query_bitmaps, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+        };
+        self.for_each_chunk(&query_i_bitmaps, start_index, end_index, f)?;
+        Ok(indexes)
+    }
+}
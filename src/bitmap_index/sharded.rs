@@ -0,0 +1,199 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Sharded index
+//!
+//! `ShardedIndex` spreads the values of a single column over N `BitmapIndex` shards
+//! (in memory or in storage mode, i.e. on different disks) following a `ShardStrategy`.
+//! A query runs on all shards in parallel, one thread for each shard, and the results
+//! are merged in global indexes (the position of the value in the pushed sequence).
+
+use std::ops::{BitAnd, Shr};
+use std::panic;
+use std::thread;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// `ShardStrategy` defines the shard of each pushed value: with `RoundRobin` the value
+/// with global index `i` is pushed in the shard `i % N`, with `RowRange(range_size)` values
+/// are pushed in ranges of `range_size` consecutive values and the range `r` is pushed in
+/// the shard `r % N`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShardStrategy {
+    RoundRobin,
+    RowRange(u64)
+}
+
+/// `ShardedIndex` struct that owns the shards of a sharded index.
+pub struct ShardedIndex<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    shards: Vec<BitmapIndex<T, U>>,
+    strategy: ShardStrategy,
+    num_values: u64
+}
+
+impl<T: Bitmap, U: BitValue> ShardedIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new `ShardedIndex` on `shards`. The shards can be empty or opened from a
+    /// previous `ShardedIndex` with the same strategy, `ParametersError` is returned if
+    /// there are no shards, if `RowRange` size is zero or if the number of values of the
+    /// shards is not consistent with `strategy`.
+    pub fn new(shards: Vec<BitmapIndex<T, U>>, strategy: ShardStrategy) -> Result<Self, Error> {
+        if shards.is_empty() || strategy == ShardStrategy::RowRange(0) {
+            return Err(Error::ParametersError);
+        }
+        let num_values: u64 = shards.iter().map(|s| s.num_values()).sum();
+        let s_index = ShardedIndex {
+            shards,
+            strategy,
+            num_values
+        };
+        for (i_shard, shard) in s_index.shards.iter().enumerate() {
+            if shard.num_values() != s_index.shard_num_values(i_shard, num_values) {
+                return Err(Error::ParametersError);
+            }
+        }
+        Ok(s_index)
+    }
+
+    /// Insert (in append) a `value` into the index.
+    pub fn push_value(&mut self, value: U) -> Result<(), Error> {
+        let (i_shard, _local_index) = self.to_shard_index(self.num_values);
+        self.shards[i_shard].push_value(value)?;
+        self.num_values += 1;
+        Ok(())
+    }
+
+    /// Insert (in append) values into the index.
+    pub fn push_values(&mut self, values: &[U]) -> Result<(), Error> {
+        for v in values {
+            self.push_value(*v)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the current chunk of every shard in storage mode.
+    pub fn flush_chunk(&mut self) -> Result<(), Error> {
+        for shard in self.shards.iter_mut().filter(|s| s.is_storage_mode()) {
+            shard.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Return the number of values pushed in `ShardedIndex`.
+    pub fn num_values(&self) -> u64 {
+        self.num_values
+    }
+
+    /// Return the `ShardStrategy` of `ShardedIndex`.
+    pub fn strategy(&self) -> ShardStrategy {
+        self.strategy
+    }
+
+    /// Return the shards of `ShardedIndex`.
+    pub fn shards(&self) -> &[BitmapIndex<T, U>] {
+        &self.shards
+    }
+
+    /// Consume `ShardedIndex` and return its shards.
+    pub fn into_shards(self) -> Vec<BitmapIndex<T, U>> {
+        self.shards
+    }
+
+    /// Return the shard and the index in the shard of the value with global index `index`.
+    pub fn to_shard_index(&self, index: u64) -> (usize, u64) {
+        let num_shards = self.shards.len() as u64;
+        match self.strategy {
+            ShardStrategy::RoundRobin => ((index % num_shards) as usize, index / num_shards),
+            ShardStrategy::RowRange(range_size) => {
+                let range = index / range_size;
+                ((range % num_shards) as usize, (range / num_shards) * range_size + index % range_size)
+            }
+        }
+    }
+
+    /// Return the global index of the value with index `local_index` in the shard `i_shard`.
+    pub fn to_global_index(&self, i_shard: usize, local_index: u64) -> u64 {
+        let num_shards = self.shards.len() as u64;
+        match self.strategy {
+            ShardStrategy::RoundRobin => local_index * num_shards + i_shard as u64,
+            ShardStrategy::RowRange(range_size) => {
+                let range = (local_index / range_size) * num_shards + i_shard as u64;
+                range * range_size + local_index % range_size
+            }
+        }
+    }
+
+    /// Return the number of values of the shard `i_shard` after `num_values` pushed values.
+    fn shard_num_values(&self, i_shard: usize, num_values: u64) -> u64 {
+        let num_shards = self.shards.len() as u64;
+        let i_shard = i_shard as u64;
+        let (range_size, num_ranges, last_range_values) = match self.strategy {
+            ShardStrategy::RoundRobin => (1, num_values, 0),
+            ShardStrategy::RowRange(range_size) => (range_size, num_values / range_size, num_values % range_size)
+        };
+        let mut shard_ranges = num_ranges / num_shards;
+        if i_shard < num_ranges % num_shards {
+            shard_ranges += 1;
+        }
+        let last_values = if num_ranges % num_shards == i_shard { last_range_values } else { 0 };
+        shard_ranges * range_size + last_values
+    }
+
+    /// Return a range of local indexes that contains, in every shard, all values with
+    /// global index in `[start_index, end_index]` (and possibly other values).
+    fn shard_range(&self, start_index: u64, end_index: u64) -> (u64, u64) {
+        let num_shards = self.shards.len() as u64;
+        match self.strategy {
+            ShardStrategy::RoundRobin => (start_index / num_shards, end_index / num_shards),
+            ShardStrategy::RowRange(range_size) => {
+                let start_range = start_index / range_size / num_shards;
+                let end_range = end_index / range_size / num_shards;
+                (start_range * range_size, end_range * range_size + range_size - 1)
+            }
+        }
+    }
+}
+
+impl<T: Bitmap + Send, U: BitValue + Send> ShardedIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a sorted `Vec<u64>` that contains all global indexes of values pushed in
+    /// `ShardedIndex` equal to `value`. The parameters `start_index` and `end_index` are
+    /// optional and if specified define the range where query is runned. The query runs
+    /// on all shards in parallel.
+    pub fn run_query(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(self.num_values);
+        let (shard_start, shard_end) = self.shard_range(start_index, end_index);
+
+        let shards_results: Vec<Result<Vec<u64>, Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = self.shards.iter_mut()
+                .map(|shard| scope.spawn(move || shard.run_query(value, Some(shard_start), Some(shard_end))))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|err| panic::resume_unwind(err)))
+                .collect()
+        });
+        let mut indexes: Vec<u64> = Vec::new();
+        for (i_shard, shard_result) in shards_results.into_iter().enumerate() {
+            indexes.extend(shard_result?.iter()
+                .map(|idx| self.to_global_index(i_shard, *idx))
+                .filter(|idx| *idx >= start_index && *idx <= end_index));
+        }
+        indexes.sort_unstable();
+        Ok(indexes)
+    }
+}
@@ -0,0 +1,68 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Snapshot queries
+//!
+//! Values of a `BitmapIndex` are only appended, so the index as of a previous
+//! `num_values` (a watermark, for example the `num_values` of a checkpoint read by a
+//! replica) is the prefix of the index with indexes less than the watermark. `query_at`
+//! answers a query on that prefix, ignoring values and chunks appended later, so
+//! queries at the same watermark always return the same result. Chunks changed with
+//! `rewrite_chunk` are not versioned and are always read in their last version.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    StorageReader,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Like `run_query`, but return only the indexes of values pushed before
+    /// `BitmapIndex` had `watermark_num_values` values. `ParametersError` is returned
+    /// if `watermark_num_values` is greater than the number of values.
+    pub fn query_at(&mut self, watermark_num_values: u64, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        match Self::watermark_range(watermark_num_values, self.num_values, start_index, end_index)? {
+            Some((start_index, end_index)) => self.run_query(value, Some(start_index), Some(end_index)),
+            None => Ok(Vec::new())
+        }
+    }
+
+    /// Return the range `[start_index, end_index]` of a query at `watermark_num_values`,
+    /// or `None` if the range is empty.
+    pub(super) fn watermark_range(watermark_num_values: u64, num_values: u64, start_index: Option<u64>, end_index: Option<u64>) -> Result<Option<(u64, u64)>, Error> {
+        if watermark_num_values > num_values {
+            return Err(Error::ParametersError);
+        }
+        let start_index: u64 = start_index.unwrap_or(0);
+        let end_index: u64 = end_index.unwrap_or(u64::MAX).min(watermark_num_values.saturating_sub(1));
+        match watermark_num_values > 0 && start_index <= end_index {
+            true => Ok(Some((start_index, end_index))),
+            false => Ok(None)
+        }
+    }
+}
+
+impl<T: Bitmap, U: BitValue> StorageReader<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Like `query`, but return only the indexes of values pushed before the index had
+    /// `watermark_num_values` values. `ParametersError` is returned if
+    /// `watermark_num_values` is greater than the number of flushed values.
+    pub fn query_at(&mut self, watermark_num_values: u64, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        match BitmapIndex::<T, U>::watermark_range(watermark_num_values, self.len(), start_index, end_index)? {
+            Some((start_index, end_index)) => self.query(value, Some(start_index), Some(end_index)),
+            None => Ok(Vec::new())
+        }
+    }
+}
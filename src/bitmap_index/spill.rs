@@ -0,0 +1,165 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Spill to disk
+//!
+//! A `BitmapIndex` in memory mode keeps all its full chunks in memory. With
+//! `use_spill_to_disk` the oldest full chunks are spilled to a file in a temporary
+//! directory as soon as the full chunks in memory (bitmaps or chunk arenas) take more
+//! than a number of bytes. A spilled chunk is written with the layout of a `ChunkArena`
+//! and only the offsets of its bitmaps stay in memory, so a query reads from the file
+//! just the query bitmaps of each spilled chunk. The index is still in memory mode and
+//! the directory is removed when the index is dropped.
+
+use std::fs::{self, File};
+use std::io::{Error as IoError, Read, Seek, SeekFrom, Write};
+use std::ops::{BitAnd, Shr};
+use std::path::{Path, PathBuf};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    ChunkArena,
+    Error,
+    TransmuteToUsize
+};
+
+/// `SpillChunks` struct with the full chunks spilled to the file of a temporary directory.
+pub(super) struct SpillChunks {
+    dir_path: PathBuf,
+    file: File,
+    file_size: u64,
+    max_memory_bytes: usize,
+    chunks: Vec<SpilledChunk>
+}
+
+/// The offsets of the bitmaps of a spilled chunk, relative to `file_offset`.
+struct SpilledChunk {
+    file_offset: u64,
+    offsets: Vec<u32>
+}
+
+impl SpillChunks {
+    /// Return the number of spilled chunks.
+    pub(super) fn len(&self) -> u64 {
+        self.chunks.len() as u64
+    }
+
+    /// Return the size in bytes of each bitmap of the spilled chunk `i`.
+    pub(super) fn bitmap_sizes(&self, i: usize) -> impl Iterator<Item=u64> + '_ {
+        self.chunks[i].offsets.windows(2).map(|offsets| (offsets[1] - offsets[0]) as u64)
+    }
+
+    /// Append the content of `arena` to the file.
+    fn push(&mut self, arena: &ChunkArena) -> Result<(), IoError> {
+        self.file.seek(SeekFrom::Start(self.file_size))?;
+        self.file.write_all(arena.content())?;
+        self.chunks.push(SpilledChunk {
+            file_offset: self.file_size,
+            offsets: arena.offsets().to_vec()
+        });
+        self.file_size += arena.content().len() as u64;
+        Ok(())
+    }
+}
+
+impl Drop for SpillChunks {
+    fn drop(&mut self) {
+        let _err = fs::remove_dir_all(&self.dir_path);
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Spill the oldest full chunks to a file in the directory `spill_dir`, that must not
+    /// exist, whenever the full chunks in memory take more than `max_memory_bytes` bytes.
+    /// The directory is created now and removed when `BitmapIndex` is dropped. Error occur
+    /// if `BitmapIndex` is opened in storage mode or already spills its chunks.
+    pub fn use_spill_to_disk(&mut self, spill_dir: &Path, max_memory_bytes: usize) -> Result<(), Error> {
+        if self.chunks.is_none() || self.spill_chunks.is_some() || Self::check_if_path_exixsts(spill_dir) {
+            return Err(Error::ParametersError);
+        }
+        let name = spill_dir.file_name().ok_or(Error::ParametersError)?;
+        let mut spill_path = PathBuf::from(spill_dir);
+        spill_path.push(name);
+        spill_path.set_extension("sbidx");
+        Self::map_io_result(fs::create_dir(spill_dir))?;
+        let file = match Self::open_file(&spill_path, true) {
+            Ok(file) => file,
+            Err(err) => {
+                let _err = fs::remove_dir_all(spill_dir);
+                return Err(Error::FileError(err));
+            }
+        };
+        self.spill_chunks = Some(SpillChunks {
+            dir_path: PathBuf::from(spill_dir),
+            file,
+            file_size: 0,
+            max_memory_bytes,
+            chunks: Vec::new()
+        });
+        self.spill_over_limit()
+    }
+
+    /// Return the number of full chunks spilled to disk.
+    pub fn spilled_chunks(&self) -> u64 {
+        self.spill_chunks.as_ref().map_or(0, |spill_chunks| spill_chunks.len())
+    }
+
+    /// Return the size in bytes of the full chunks kept in memory, as bitmaps or chunk
+    /// arenas. The current chunk is not included.
+    pub fn resident_chunks_size(&self) -> usize {
+        let chunks_size: usize = self.chunks.iter().flatten()
+            .map(|bitmaps| bitmaps.iter().map(|b| b.size()).sum::<usize>())
+            .sum();
+        chunks_size + self.chunk_arena_size()
+    }
+
+    /// Spill the oldest full chunks in memory until they take at most the bytes set
+    /// with `use_spill_to_disk`.
+    pub(super) fn spill_over_limit(&mut self) -> Result<(), Error> {
+        if self.spill_chunks.is_none() {
+            return Ok(());
+        }
+        let mut resident_size = self.resident_chunks_size();
+        let spill_chunks = self.spill_chunks.as_mut().unwrap();
+        while resident_size > spill_chunks.max_memory_bytes {
+            // chunks are moved to the arenas at most once, so only one of them has chunks.
+            if let Some(chunks) = self.chunks.as_mut().filter(|chunks| !chunks.is_empty()) {
+                let arena = Self::new_chunk_arena(&chunks[0])?;
+                Self::map_io_result(spill_chunks.push(&arena))?;
+                resident_size -= chunks.remove(0).iter().map(|b| b.size()).sum::<usize>();
+            } else if let Some(arena_chunks) = self.arena_chunks.as_mut().filter(|arena_chunks| !arena_chunks.is_empty()) {
+                Self::map_io_result(spill_chunks.push(&arena_chunks[0]))?;
+                resident_size -= arena_chunks.remove(0).size();
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the query bitmaps of the spilled chunk `i`, read from the file.
+    pub(super) fn spilled_query_bitmaps(spill_chunks: &SpillChunks, i: usize, query_i_bitmaps: &[usize]) -> Result<Vec<T>, Error> {
+        let chunk = &spill_chunks.chunks[i];
+        let mut file = &spill_chunks.file;
+        let mut buf: Vec<u8> = Vec::new();
+        let mut query_bitmaps: Vec<T> = Vec::with_capacity(query_i_bitmaps.len());
+        for i_bitmap in query_i_bitmaps {
+            let start_offset = chunk.offsets[*i_bitmap];
+            let end_offset = chunk.offsets[*i_bitmap + 1];
+            buf.resize((end_offset - start_offset) as usize, 0);
+            Self::map_io_result(file.seek(SeekFrom::Start(chunk.file_offset + start_offset as u64)))?;
+            Self::map_io_result(file.read_exact(&mut buf))?;
+            let mut bitmap = T::new();
+            Self::read_bitmap(&buf, false, &mut bitmap)?;
+            query_bitmaps.push(bitmap);
+        }
+        Ok(query_bitmaps)
+    }
+}
@@ -0,0 +1,201 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # StorageReader
+//!
+//! A read-only view of a `BitmapIndex` created in storage mode. Differently from
+//! `BitmapIndex::run_query_from_storage_idx` meta data and block informations are read
+//! once when the reader is opened, so queries are ordinary methods.
+//! Like `run_query_from_storage_idx` a `StorageReader` sees only the flushed chunks.
+
+use std::ops::{BitAnd, Shr};
+use std::path::Path;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BlockInfo,
+    ChunkCompression,
+    Error,
+    MetaData,
+    StorageIdx,
+    StorageStats,
+    TransmuteToUsize
+};
+
+/// `StorageReader` struct that requires the same [`Bitmap`] and `BitValue` types
+/// used to write the `BitmapIndex`.
+pub struct StorageReader<T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    storage_idx: StorageIdx,
+    meta_data: MetaData,
+    block_info: BlockInfo,
+    chunk_size: u64,
+
+    _marker: std::marker::PhantomData<(T, U)>
+}
+
+impl<T: Bitmap, U: BitValue> StorageReader<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Open a `StorageReader` on the `BitmapIndex` saved in `dir_path`.
+    pub fn open(dir_path: &Path) -> Result<Self, Error> {
+        let mut storage_idx = BitmapIndex::<T, U>::new_storage_idx(dir_path)?;
+        let meta_data = BitmapIndex::<T, U>::read_meta_data(&mut storage_idx)?.0;
+        BitmapIndex::<T, U>::check_meta_data(&meta_data)?;
+        let block_info = BitmapIndex::<T, U>::new_block_info(&meta_data.build_options)?;
+        let chunk_size = meta_data.build_options.chunk_size.clone() as u64;
+
+        Ok(StorageReader {
+            storage_idx,
+            meta_data,
+            block_info,
+            chunk_size,
+            _marker: std::marker::PhantomData
+        })
+    }
+
+    /// Read again meta data from storage, so chunks flushed after the reader has been
+    /// opened become visible.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let meta_data = BitmapIndex::<T, U>::read_meta_data(&mut self.storage_idx)?.0;
+        BitmapIndex::<T, U>::check_meta_data(&meta_data)?;
+        self.meta_data = meta_data;
+        Ok(())
+    }
+
+    /// Return the meta data read when the reader has been opened (or refreshed).
+    pub fn meta_data(&self) -> &MetaData {
+        &self.meta_data
+    }
+
+    /// Return the number of values that can be queried, i.e. the values of the
+    /// flushed chunks.
+    pub fn len(&self) -> u64 {
+        self.num_chunks() * self.chunk_size
+    }
+
+    /// Return `true` if there aren't flushed chunks.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the I/O statistics of the queries since `StorageReader` was opened.
+    pub fn storage_stats(&self) -> StorageStats {
+        self.storage_idx.stats
+    }
+
+    /// Return the number of flushed chunks.
+    pub fn num_chunks(&self) -> u64 {
+        self.meta_data.num_values / self.chunk_size
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values equal to `value`.
+    /// The parameters `start_index` and `end_index` are optional and if specified
+    /// define the range where query is runned.
+    pub fn query(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let (start_index, end_index) = self.query_range(start_index, end_index);
+        let chunk_size = self.chunk_size;
+        let mut indexes: Vec<u64> = Vec::new();
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            BitmapIndex::<T, U>::push_indexes(query_bitmaps, chunk_id, chunk_size, start_index, end_index, &mut indexes);
+        };
+        self.for_each_chunk(value, start_index, end_index, f)?;
+        Ok(indexes)
+    }
+
+    /// Return the number of values equal to `value`, without collecting their indexes.
+    pub fn query_count(&mut self, value: U, start_index: Option<u64>, end_index: Option<u64>) -> Result<u64, Error> {
+        let (start_index, end_index) = self.query_range(start_index, end_index);
+        let chunk_size = self.chunk_size;
+        let mut count: u64 = 0;
+        let f = |chunk_id: u64, query_bitmaps: &[&T]| {
+            count += BitmapIndex::<T, U>::count_indexes(query_bitmaps, chunk_id, chunk_size, start_index, end_index);
+        };
+        self.for_each_chunk(value, start_index, end_index, f)?;
+        Ok(count)
+    }
+
+    /// Return the `ChunkCompression` of each flushed chunk, see
+    /// `BitmapIndex::compression_report`.
+    pub fn compression_report(&mut self) -> Result<Vec<ChunkCompression>, Error> {
+        let num_bitmaps = self.block_info.num_blocks * self.block_info.num_bitmaps_in_block;
+        let num_chunks = self.num_chunks();
+        BitmapIndex::<T, U>::storage_compression_report(&mut self.storage_idx, num_bitmaps, self.chunk_size, num_chunks)
+    }
+
+    pub(super) fn query_range(&self, start_index: Option<u64>, end_index: Option<u64>) -> (u64, u64) {
+        (start_index.unwrap_or(0), end_index.unwrap_or(self.meta_data.num_values))
+    }
+
+    /// Return the range of flushed chunk ids that intersect `[start_index, end_index]`.
+    #[cfg(any(feature = "async", feature = "mmap"))]
+    pub(super) fn chunk_id_range(&self, start_index: u64, end_index: u64) -> (u64, u64) {
+        let end_chunk_id = self.num_chunks().min(end_index / self.chunk_size + 1);
+        (start_index / self.chunk_size, end_chunk_id)
+    }
+
+    pub(super) fn query_i_bitmaps(&self, value: U) -> Vec<usize> {
+        BitmapIndex::<T, U>::get_query_i_bitmaps(&self.block_info, value)
+    }
+
+    /// Return the indexes in `[start_index, end_index]` of the chunk `chunk_id` that
+    /// are set in all `query_i_bitmaps`.
+    #[cfg(feature = "async")]
+    pub(super) fn chunk_indexes(&mut self, chunk_id: u64, query_i_bitmaps: &[usize], start_index: u64, end_index: u64) -> Result<Vec<u64>, Error> {
+        let chunk_offset = BitmapIndex::<T, U>::map_io_result(BitmapIndex::<T, U>::read_offsets(&mut self.storage_idx, chunk_id, 1))?[0];
+        let query_bitmaps = BitmapIndex::<T, U>::read_query_bitmaps(&mut self.storage_idx.data_file, chunk_offset, query_i_bitmaps)?;
+        let query_bitmaps_ref: Vec<&T> = query_bitmaps.iter().collect();
+        let mut indexes: Vec<u64> = Vec::new();
+        BitmapIndex::<T, U>::push_indexes(&query_bitmaps_ref, chunk_id, self.chunk_size, start_index, end_index, &mut indexes);
+        Ok(indexes)
+    }
+
+    /// Return a `StorageReader` on duplicated handles of the files of this reader, that
+    /// share their cursors: the two readers must not be used at the same time.
+    #[cfg(feature = "async")]
+    pub(super) fn try_clone(&self) -> Result<Self, Error> {
+        let try_clone = |file: &std::fs::File| BitmapIndex::<T, U>::map_io_result(file.try_clone());
+        let storage_idx = StorageIdx {
+            dir_path: self.storage_idx.dir_path.clone(),
+            meta_data_file: try_clone(&self.storage_idx.meta_data_file)?,
+            offset_file: try_clone(&self.storage_idx.offset_file)?,
+            data_file: try_clone(&self.storage_idx.data_file)?,
+            stats: StorageStats::default(),
+            offset_cache: None
+        };
+        Ok(StorageReader {
+            storage_idx,
+            meta_data: self.meta_data.clone(),
+            block_info: BitmapIndex::<T, U>::new_block_info(&self.meta_data.build_options)?,
+            chunk_size: self.chunk_size,
+            _marker: std::marker::PhantomData
+        })
+    }
+
+    /// Return the offsets in the data file of `num_chunks` chunks starting from `start_chunk_id`.
+    #[cfg(feature = "mmap")]
+    pub(super) fn chunks_offsets(&mut self, start_chunk_id: u64, num_chunks: usize) -> Result<Vec<u64>, Error> {
+        BitmapIndex::<T, U>::map_io_result(BitmapIndex::<T, U>::read_offsets(&mut self.storage_idx, start_chunk_id, num_chunks))
+    }
+
+    #[cfg(feature = "mmap")]
+    pub(super) fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    #[cfg(feature = "mmap")]
+    pub(super) fn data_file(&self) -> &std::fs::File {
+        &self.storage_idx.data_file
+    }
+
+    fn for_each_chunk(&mut self, value: U, start_index: u64, end_index: u64, f: impl FnMut(u64, &[&T])) -> Result<(), Error> {
+        let query_i_bitmaps: Vec<usize> = self.query_i_bitmaps(value);
+        BitmapIndex::<T, U>::for_each_storage_chunk(&mut self.storage_idx, &query_i_bitmaps, self.chunk_size, self.meta_data.num_values, start_index, end_index, f)
+    }
+}
@@ -0,0 +1,109 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Storage statistics
+//!
+//! A `StorageIdx` counts the bytes written in each file of the index, the meta data
+//! rewrites, the chunk flushes and the bytes read by queries since it was opened, so
+//! the write amplification of a workload (for example on flash with limited endurance)
+//! can be measured. Statistics are kept in memory and aren't persisted.
+
+use std::io::{Error as IoError, Read, Seek, SeekFrom};
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    TransmuteToUsize
+};
+
+/// Cumulative I/O statistics of a `BitmapIndex` in storage mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StorageStats {
+    /// Bytes written in the data file.
+    pub data_bytes_written: u64,
+    /// Bytes written in the offset file.
+    pub offset_bytes_written: u64,
+    /// Bytes written in the meta data file.
+    pub meta_data_bytes_written: u64,
+    /// Bytes written in the user meta data file.
+    pub user_meta_data_bytes_written: u64,
+    /// Number of writes of the meta data file.
+    pub meta_data_writes: u64,
+    /// Number of chunk flushes (a partial chunk can be flushed more times).
+    pub chunk_flushes: u64,
+    /// Number of chunk rewrites.
+    pub chunk_rewrites: u64,
+    /// Number of queries that read flushed chunks.
+    pub queries: u64,
+    /// Bytes read by all queries from the data and the offset files.
+    pub bytes_read: u64,
+    /// Bytes read by the last query from the data and the offset files.
+    pub last_query_bytes_read: u64
+}
+
+impl StorageStats {
+    /// Return the total bytes written in all files.
+    pub fn total_bytes_written(&self) -> u64 {
+        self.data_bytes_written + self.offset_bytes_written + self.meta_data_bytes_written + self.user_meta_data_bytes_written
+    }
+
+    /// Record a write of the meta data file of `bytes` bytes.
+    pub(super) fn record_meta_data_write(&mut self, bytes: u64) {
+        self.meta_data_bytes_written += bytes;
+        self.meta_data_writes += 1;
+    }
+
+    /// Record a query that read `bytes` bytes.
+    pub(super) fn record_query(&mut self, bytes: u64) {
+        self.queries += 1;
+        self.bytes_read += bytes;
+        self.last_query_bytes_read = bytes;
+    }
+}
+
+/// A `Read + Seek` wrapper that counts the bytes read.
+pub(super) struct CountingReader<'a, R: Read + Seek> {
+    inner: &'a mut R,
+    pub(super) bytes_read: u64
+}
+
+impl<'a, R: Read + Seek> CountingReader<'a, R> {
+    pub(super) fn new(inner: &'a mut R) -> Self {
+        CountingReader { inner, bytes_read: 0 }
+    }
+}
+
+impl<R: Read + Seek> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let n = self.inner.read(buf)?;
+        self.bytes_read += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for CountingReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IoError> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the I/O statistics since `BitmapIndex` was opened, `None` in memory mode.
+    pub fn storage_stats(&self) -> Option<StorageStats> {
+        self.storage_idx.as_ref().map(|storage_idx| storage_idx.stats)
+    }
+
+    /// Reset the I/O statistics to zero.
+    pub fn reset_storage_stats(&mut self) {
+        if let Some(storage_idx) = self.storage_idx.as_mut() {
+            storage_idx.stats = StorageStats::default();
+        }
+    }
+}
@@ -0,0 +1,378 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # String index
+//!
+//! `StringIndex` indexes a column of strings: a `StringDictionary` maps each distinct
+//! string to a dense `u32` id, in order of first insertion, and a `BitmapIndex` indexes
+//! the ids (like `RemappedIndex`). The dictionary is ordered by string, so the strings
+//! with a prefix are a contiguous range of it and `run_query_prefix` (i.e. a
+//! `LIKE 'abc%'` filter) probes the ids of the range reading each chunk only once.
+//!
+//! In storage mode the dictionary is saved in the directory of the `BitmapIndex`, in a
+//! file with 'sbidx' extension that contains the distinct strings in id order, each
+//! preceded by its length in bytes:
+//!  |u32 len|string|u32 len|string|...|
+//!
+//! A new string is appended to the dictionary before its id is pushed in the index, so
+//! the dictionary always covers the ids of the index.
+//!
+//! A `StringNormalizer` (trim, lowercase and, with the `nfkc` feature, Unicode NFKC) is
+//! applied to the pushed strings and to the strings and prefixes of the queries. It is
+//! saved in the user meta data key `STRING_NORMALIZER_KEY` of the `BitmapIndex`, so an
+//! index can't be opened with a normalizer different from the one used to build it.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::mem;
+use std::ops::{BitAnd, Bound};
+use std::path::{Path, PathBuf};
+
+use super::{
+    Bitmap,
+    BitmapIndex,
+    BuildOptions,
+    Error
+};
+
+/// User meta data key of the `StringNormalizer` of a `StringIndex`.
+pub const STRING_NORMALIZER_KEY: &str = "bitrush.string_normalizer";
+
+const NORMALIZER_TRIM: u8 = 1;
+const NORMALIZER_LOWERCASE: u8 = 1 << 1;
+const NORMALIZER_NFKC: u8 = 1 << 2;
+
+/// `StringNormalizer` defines how the strings of a `StringIndex` are normalized before
+/// being pushed or queried. The default normalizer leaves strings unchanged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StringNormalizer {
+    flags: u8
+}
+
+impl StringNormalizer {
+    /// Return a `StringNormalizer` that leaves strings unchanged.
+    pub fn new() -> Self {
+        StringNormalizer::default()
+    }
+
+    /// Remove leading and trailing whitespaces.
+    pub fn with_trim(mut self) -> Self {
+        self.flags |= NORMALIZER_TRIM;
+        self
+    }
+
+    /// Convert strings to lowercase.
+    pub fn with_lowercase(mut self) -> Self {
+        self.flags |= NORMALIZER_LOWERCASE;
+        self
+    }
+
+    /// Convert strings to the Unicode normalization form KC.
+    #[cfg(feature = "nfkc")]
+    pub fn with_nfkc(mut self) -> Self {
+        self.flags |= NORMALIZER_NFKC;
+        self
+    }
+
+    /// Return `value` normalized.
+    pub fn normalize<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let value = match self.flags & NORMALIZER_TRIM != 0 {
+            true => value.trim(),
+            false => value
+        };
+        self.normalize_chars(value)
+    }
+
+    /// Return `prefix` normalized. Trailing whitespaces are kept: they are part of the
+    /// strings that start with `prefix`.
+    pub fn normalize_prefix<'a>(&self, prefix: &'a str) -> Cow<'a, str> {
+        let prefix = match self.flags & NORMALIZER_TRIM != 0 {
+            true => prefix.trim_start(),
+            false => prefix
+        };
+        self.normalize_chars(prefix)
+    }
+
+    fn normalize_chars<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let mut value = Cow::Borrowed(value);
+        #[cfg(feature = "nfkc")]
+        if self.flags & NORMALIZER_NFKC != 0 {
+            use unicode_normalization::UnicodeNormalization;
+            value = Cow::Owned(value.nfkc().collect());
+        }
+        if self.flags & NORMALIZER_LOWERCASE != 0 {
+            value = Cow::Owned(value.to_lowercase());
+        }
+        value
+    }
+
+    /// Return the normalizer saved in `bytes`. `MetaDataError` is returned if it isn't a
+    /// normalizer or if it requires NFKC and the `nfkc` feature is disabled.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut supported = NORMALIZER_TRIM | NORMALIZER_LOWERCASE;
+        if cfg!(feature = "nfkc") {
+            supported |= NORMALIZER_NFKC;
+        }
+        match bytes {
+            [flags] if flags & !supported == 0 => Ok(StringNormalizer { flags: *flags }),
+            _ => Err(Error::MetaDataError)
+        }
+    }
+}
+
+/// `StringDictionary` maps each distinct string to a dense `u32` id, in order of first
+/// insertion, and keeps the strings ordered for prefix lookups.
+#[derive(Clone, Debug, Default)]
+pub struct StringDictionary {
+    ids: BTreeMap<String, u32>,
+    values: Vec<String>
+}
+
+impl StringDictionary {
+    /// Return a new empty `StringDictionary`.
+    pub fn new() -> Self {
+        StringDictionary::default()
+    }
+
+    /// Return the number of distinct strings.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Return `true` if the dictionary has no strings.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Return the id of `value`, `None` if it isn't in the dictionary.
+    pub fn id(&self, value: &str) -> Option<u32> {
+        self.ids.get(value).copied()
+    }
+
+    /// Return the string of the id `id`, `None` if `id` isn't in the dictionary.
+    pub fn value(&self, id: u32) -> Option<&str> {
+        self.values.get(id as usize).map(|value| value.as_str())
+    }
+
+    /// Return the ids of the strings that start with `prefix`, in string order. They are
+    /// a contiguous range of the ordered dictionary, found with a single lookup.
+    pub fn prefix_ids(&self, prefix: &str) -> Vec<u32> {
+        self.ids.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(value, _id)| value.starts_with(prefix))
+            .map(|(_value, id)| *id)
+            .collect()
+    }
+
+    /// Insert `value` with the next id and return it. `ParametersError` is returned if
+    /// the dictionary has already `u32::MAX + 1` strings.
+    pub(super) fn insert(&mut self, value: &str) -> Result<u32, Error> {
+        if self.values.len() > u32::MAX as usize {
+            return Err(Error::ParametersError);
+        }
+        let id = self.values.len() as u32;
+        self.values.push(value.to_string());
+        self.ids.insert(value.to_string(), id);
+        Ok(id)
+    }
+}
+
+/// `StringIndex` struct that requires a [`Bitmap`] type for the `BitmapIndex` of the
+/// ids of the strings.
+pub struct StringIndex<T: Bitmap>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    index: BitmapIndex<T, u32>,
+    dictionary: StringDictionary,
+    dictionary_file: Option<File>,
+    normalizer: StringNormalizer
+}
+
+impl<T: Bitmap> StringIndex<T>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new empty `StringIndex` in memory mode. `build_options` are the options
+    /// of the `BitmapIndex` of ids.
+    pub fn new(build_options: BuildOptions) -> Result<Self, Error> {
+        Self::new_with_normalizer(build_options, StringNormalizer::new())
+    }
+
+    /// Return a new empty `StringIndex` in memory mode that normalizes strings with
+    /// `normalizer`.
+    pub fn new_with_normalizer(build_options: BuildOptions, normalizer: StringNormalizer) -> Result<Self, Error> {
+        let mut index = BitmapIndex::new(build_options)?;
+        index.set_user_meta_data(STRING_NORMALIZER_KEY, &[normalizer.flags])?;
+        Ok(StringIndex {
+            index,
+            dictionary: StringDictionary::new(),
+            dictionary_file: None,
+            normalizer
+        })
+    }
+
+    /// Create a new `StringIndex` in storage mode in the directory `dir_path`, that must
+    /// not exist.
+    pub fn create(dir_path: &Path, build_options: BuildOptions) -> Result<Self, Error> {
+        Self::create_with_normalizer(dir_path, build_options, StringNormalizer::new())
+    }
+
+    /// Create a new `StringIndex` in storage mode in the directory `dir_path`, that must
+    /// not exist, that normalizes strings with `normalizer`.
+    pub fn create_with_normalizer(dir_path: &Path, build_options: BuildOptions, normalizer: StringNormalizer) -> Result<Self, Error> {
+        let mut index = BitmapIndex::create(dir_path, build_options)?;
+        index.set_user_meta_data(STRING_NORMALIZER_KEY, &[normalizer.flags])?;
+        let file_r = OpenOptions::new().append(true).create_new(true).open(Self::dictionary_path(dir_path));
+        Ok(StringIndex {
+            index,
+            dictionary: StringDictionary::new(),
+            dictionary_file: Some(BitmapIndex::<T, u32>::map_io_result(file_r)?),
+            normalizer
+        })
+    }
+
+    /// Open a `StringIndex` previusly created in `dir_path` with the normalizer saved in
+    /// its user meta data. A string torn from the end of the dictionary (i.e. after a
+    /// crash) is discarded: its id has never been pushed in the index.
+    pub fn open(dir_path: &Path) -> Result<Self, Error> {
+        let index = BitmapIndex::<T, u32>::open(dir_path)?;
+        let normalizer = match index.user_meta_data(STRING_NORMALIZER_KEY) {
+            Some(bytes) => StringNormalizer::from_bytes(bytes)?,
+            None => StringNormalizer::new()
+        };
+        Self::open_index(dir_path, index, normalizer)
+    }
+
+    /// Open a `StringIndex` previusly created in `dir_path`, see `open`.
+    /// `MetaDataError` is returned if the index has been built with a normalizer
+    /// different from `normalizer`.
+    pub fn open_with_normalizer(dir_path: &Path, normalizer: StringNormalizer) -> Result<Self, Error> {
+        let string_index = Self::open(dir_path)?;
+        if string_index.normalizer != normalizer {
+            return Err(Error::MetaDataError);
+        }
+        Ok(string_index)
+    }
+
+    fn open_index(dir_path: &Path, index: BitmapIndex<T, u32>, normalizer: StringNormalizer) -> Result<Self, Error> {
+        let file_r = OpenOptions::new().read(true).append(true).open(Self::dictionary_path(dir_path));
+        let mut dictionary_file = BitmapIndex::<T, u32>::map_io_result(file_r)?;
+        let mut buf: Vec<u8> = Vec::new();
+        BitmapIndex::<T, u32>::map_io_result(dictionary_file.read_to_end(&mut buf))?;
+
+        let mut dictionary = StringDictionary::new();
+        let mut offset: usize = 0;
+        while let Some(len_buf) = buf.get(offset..(offset + mem::size_of::<u32>())) {
+            let len = u32::from_ne_bytes([len_buf[0], len_buf[1], len_buf[2], len_buf[3]]) as usize;
+            let start = offset + mem::size_of::<u32>();
+            let value = match buf.get(start..(start + len)) {
+                Some(value) => std::str::from_utf8(value).map_err(|_| Error::MetaDataError)?,
+                None => break
+            };
+            dictionary.insert(value)?;
+            offset = start + len;
+        }
+        if offset != buf.len() {
+            BitmapIndex::<T, u32>::map_io_result(dictionary_file.set_len(offset as u64))?;
+        }
+        Ok(StringIndex {
+            index,
+            dictionary,
+            dictionary_file: Some(dictionary_file),
+            normalizer
+        })
+    }
+
+    /// Insert (in append) `value`, normalized, into the index, adding it to the
+    /// dictionary if it is a new string.
+    pub fn push_value(&mut self, value: &str) -> Result<(), Error> {
+        let value = self.normalizer.normalize(value);
+        let id = match self.dictionary.id(&value) {
+            Some(id) => id,
+            None => self.insert_value(&value)?
+        };
+        self.index.push_value(id)
+    }
+
+    /// Insert (in append) values into the index, see `push_value`.
+    pub fn push_values<S: AsRef<str>>(&mut self, values: &[S]) -> Result<(), Error> {
+        for value in values {
+            self.push_value(value.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Flush the current chunk of the index, see `BitmapIndex::flush_chunk`.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.index.flush_chunk()
+    }
+
+    /// Return the number of values pushed in the index.
+    pub fn num_values(&self) -> u64 {
+        self.index.num_values()
+    }
+
+    /// Return the normalizer of the strings of the index.
+    pub fn normalizer(&self) -> StringNormalizer {
+        self.normalizer
+    }
+
+    /// Return the dictionary of the normalized strings pushed in the index.
+    pub fn dictionary(&self) -> &StringDictionary {
+        &self.dictionary
+    }
+
+    /// Return the `BitmapIndex` of the ids.
+    pub fn index_mut(&mut self) -> &mut BitmapIndex<T, u32> {
+        &mut self.index
+    }
+
+    /// Return a `Vec<u64>` that contains all indexes of values equal to `value` once
+    /// normalized, an empty `Vec` if `value` has never been pushed. The parameters
+    /// `start_index` and `end_index` are optional and if specified define the range where
+    /// query is runned.
+    pub fn run_query(&mut self, value: &str, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        match self.dictionary.id(&self.normalizer.normalize(value)) {
+            Some(id) => self.index.run_query(id, start_index, end_index),
+            None => Ok(Vec::new())
+        }
+    }
+
+    /// Return a sorted `Vec<u64>` that contains all indexes of values that start with
+    /// `prefix` once normalized (see `StringNormalizer::normalize_prefix`). The ids of the strings with `prefix` are probed together, reading each
+    /// chunk only once. The parameters `start_index` and `end_index` are optional and if
+    /// specified define the range where query is runned.
+    pub fn run_query_prefix(&mut self, prefix: &str, start_index: Option<u64>, end_index: Option<u64>) -> Result<Vec<u64>, Error> {
+        let prefix = self.normalizer.normalize_prefix(prefix);
+        let ids_i_bitmaps: Vec<Vec<usize>> = self.dictionary.prefix_ids(&prefix).into_iter()
+            .map(|id| BitmapIndex::<T, u32>::get_query_i_bitmaps(&self.index.block_info, id)).collect();
+        let start_index = start_index.unwrap_or(0);
+        let end_index = end_index.unwrap_or(self.index.num_values);
+        let mut indexes: Vec<u64> = self.index.run_query_i_bitmaps_grouped(&ids_i_bitmaps, start_index, end_index)?.concat();
+        indexes.sort_unstable();
+        Ok(indexes)
+    }
+
+    fn insert_value(&mut self, value: &str) -> Result<u32, Error> {
+        if self.dictionary.len() > u32::MAX as usize || value.len() > u32::MAX as usize {
+            return Err(Error::ParametersError);
+        }
+        if let Some(dictionary_file) = self.dictionary_file.as_mut() {
+            let mut buf: Vec<u8> = Vec::with_capacity(mem::size_of::<u32>() + value.len());
+            buf.extend_from_slice(&(value.len() as u32).to_ne_bytes());
+            buf.extend_from_slice(value.as_bytes());
+            BitmapIndex::<T, u32>::map_io_result(dictionary_file.write_all(&buf))?;
+        }
+        self.dictionary.insert(value)
+    }
+
+    fn dictionary_path(dir_path: &Path) -> PathBuf {
+        let mut path = PathBuf::from(dir_path);
+        if let Some(name) = dir_path.file_name() {
+            path.push(name);
+        }
+        path.set_extension("sbidx");
+        path
+    }
+}
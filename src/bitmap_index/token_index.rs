@@ -0,0 +1,158 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Token index
+//!
+//! `TokenIndex` indexes documents of text (i.e. log lines) for "document contains
+//! token" queries. Each pushed document is split in tokens from a tokenizer, a
+//! `StringDictionary` (the same of `StringIndex`) maps each distinct token to a token id
+//! and each token id has a bitmap with a bit set for each document that contains the
+//! token. Queries of several tokens run "logical and" between the bitmaps of the tokens.
+//!
+//! Differently from `StringIndex`, where each value has one string, a document has many
+//! tokens, so the bitmaps are by token and not by block of the value and they are not
+//! split in chunks: a `TokenIndex` works only in memory, can't be saved and indexes up
+//! to `u32::MAX + 1` documents. To persist a column with one string per value use a
+//! `StringIndex`.
+
+use std::ops::BitAnd;
+
+use super::{
+    Bitmap,
+    Error,
+    StringDictionary
+};
+
+/// A tokenizer splits the text of a document in tokens.
+pub type Tokenizer = Box<dyn Fn(&str) -> Vec<String> + Send + Sync>;
+
+/// `TokenIndex` struct that requires a [`Bitmap`] type for the bitmaps of the tokens.
+pub struct TokenIndex<T: Bitmap>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    tokenizer: Tokenizer,
+    dictionary: StringDictionary,
+    bitmaps: Vec<T>,
+    num_documents: u64
+}
+
+impl<T: Bitmap> Default for TokenIndex<T>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Bitmap> TokenIndex<T>
+where for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return a new empty `TokenIndex` with the default tokenizer, that splits the
+    /// text on every not alphanumeric char and lowercases the tokens.
+    pub fn new() -> Self {
+        Self::with_tokenizer(Box::new(default_tokenizer))
+    }
+
+    /// Return a new empty `TokenIndex` that splits documents with `tokenizer`.
+    pub fn with_tokenizer(tokenizer: Tokenizer) -> Self {
+        TokenIndex {
+            tokenizer,
+            dictionary: StringDictionary::new(),
+            bitmaps: Vec::new(),
+            num_documents: 0
+        }
+    }
+
+    /// Insert (in append) a document into the index. Return `ParametersError` if the
+    /// index has already `u32::MAX + 1` documents.
+    pub fn push_document(&mut self, text: &str) -> Result<(), Error> {
+        if self.num_documents > u32::MAX as u64 {
+            return Err(Error::ParametersError);
+        }
+        let document = self.num_documents as u32;
+        for token in (self.tokenizer)(text) {
+            let token_id = match self.dictionary.id(&token) {
+                Some(token_id) => token_id,
+                None => {
+                    let token_id = self.dictionary.insert(&token)?;
+                    self.bitmaps.push(T::new());
+                    token_id
+                }
+            };
+            self.bitmaps[token_id as usize].set(document);
+        }
+        self.num_documents += 1;
+        Ok(())
+    }
+
+    /// Insert (in append) documents into the index.
+    pub fn push_documents<S: AsRef<str>>(&mut self, texts: &[S]) -> Result<(), Error> {
+        for text in texts {
+            self.push_document(text.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Return the number of documents pushed in the index.
+    pub fn num_documents(&self) -> u64 {
+        self.num_documents
+    }
+
+    /// Return the number of distinct tokens of the documents.
+    pub fn num_tokens(&self) -> usize {
+        self.dictionary.len()
+    }
+
+    /// Return the dictionary of the tokens, the id of a token is the position of its
+    /// bitmap.
+    pub fn dictionary(&self) -> &StringDictionary {
+        &self.dictionary
+    }
+
+    /// Return a `Vec<u64>` that contains the indexes of the documents that contain `token`.
+    pub fn run_query(&self, token: &str) -> Vec<u64> {
+        self.run_query_all(&[token])
+    }
+
+    /// Return a `Vec<u64>` that contains the indexes of the documents that contain all
+    /// `tokens`. Tokens are compared as returned from the tokenizer, so with the default
+    /// tokenizer they must be lowercase.
+    pub fn run_query_all(&self, tokens: &[&str]) -> Vec<u64> {
+        let mut token_bitmaps: Vec<&T> = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match self.dictionary.id(token) {
+                Some(token_id) => token_bitmaps.push(&self.bitmaps[token_id as usize]),
+                None => return Vec::new()
+            }
+        }
+        token_bitmaps.sort_unstable_by_key(|bitmap| bitmap.size());
+        let (first, others) = match token_bitmaps.split_first() {
+            Some(split) => split,
+            None => return Vec::new()
+        };
+        let mut b_result: T = (*first).clone();
+        for bitmap in others {
+            if b_result.is_empty() {
+                break;
+            }
+            b_result = &b_result & *bitmap;
+        }
+        b_result.unroll_bitmap().iter().map(|document| *document as u64).collect()
+    }
+
+    /// Return a `Vec<u64>` that contains the indexes of the documents that contain all
+    /// the tokens of `text`, split with the tokenizer of the index.
+    pub fn run_query_text(&self, text: &str) -> Vec<u64> {
+        let tokens = (self.tokenizer)(text);
+        let tokens: Vec<&str> = tokens.iter().map(|token| token.as_str()).collect();
+        self.run_query_all(&tokens)
+    }
+}
+
+/// Split `text` on every not alphanumeric char and lowercase the tokens.
+fn default_tokenizer(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
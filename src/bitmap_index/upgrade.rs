@@ -0,0 +1,207 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Upgrade
+//!
+//! Indexes written by 0.1 have a meta data file with only the number of values and the
+//! `bit_block_size` and `ChunkSize` (followed, after the first write, by the same fields
+//! of the last checkpoint) and a data file where each chunk is a table of the
+//! `num_bitmaps + 1` u32 offsets of its bitmaps, relative to the chunk start, followed
+//! by the bitmaps; there is no chunk trailer. Each write was appended, so the data file
+//! is the sequence of all the versions of the chunks, a partial chunk before the version
+//! that replaced it. Values were split in blocks without the order-preserving
+//! representation of `BitValue::to_ordered`.
+//!
+//! When such an index is opened it's upgraded in place: the values of the last version
+//! of each chunk are reconstructed from its bitmaps and pushed in a new index written in
+//! the `upgrade` folder inside the index folder, that is renamed `upgrade.done` once
+//! durable and then moved over the old files (the meta data file last), so an upgrade
+//! interrupted at any point is resumed by the next open. The value size and the bitmap
+//! format are not recorded by 0.1 and are taken from `U` and `T`: `MetaDataError` is
+//! returned if the number of bitmaps of the chunks doesn't match `U`.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Error as IoError, Read, Seek, SeekFrom};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::{Path, PathBuf};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BlockInfo,
+    BuildOptions,
+    ChunkSize,
+    Error,
+    TransmuteToUsize
+};
+
+/// `MetaData` written by 0.1.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BaselineMetaData {
+    num_values: u64,
+    bit_block_size: usize,
+    chunk_size: u32
+}
+
+const BASELINE_META_DATA_SIZE: u64 = mem::size_of::<BaselineMetaData>() as u64;
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Upgrade the index in `dir_path` if it was written by 0.1 or resume its upgrade,
+    /// otherwise do nothing.
+    pub(super) fn upgrade_baseline(dir_path: &Path) -> Result<(), Error> {
+        let done_path = dir_path.join("upgrade.done");
+        if !Self::check_if_path_exixsts(&done_path) {
+            let meta_data_path = Self::storage_file_path(dir_path, OsStr::new("mbidx"));
+            let meta_data_size = match fs::metadata(&meta_data_path) {
+                Ok(m) => m.len(),
+                Err(_) => return Ok(())
+            };
+            if meta_data_size != BASELINE_META_DATA_SIZE && meta_data_size != 2 * BASELINE_META_DATA_SIZE {
+                return Ok(());
+            }
+            Self::write_upgrade(dir_path, &done_path)?;
+        }
+        Self::map_io_result(Self::replace_with_upgrade(dir_path, &done_path))
+    }
+
+    /// Write the upgraded copy of the index in `dir_path` to `done_path`.
+    fn write_upgrade(dir_path: &Path, done_path: &Path) -> Result<(), Error> {
+        let (num_values, build_options) = Self::read_baseline_meta_data(dir_path)?;
+        let block_info = Self::new_block_info(&build_options).map_err(|_| Error::MetaDataError)?;
+        let chunk_size = build_options.chunk_size();
+        let num_full_chunks = num_values / chunk_size;
+        let last_chunk_size = num_values % chunk_size;
+
+        let upgrade_path = dir_path.join("upgrade");
+        if Self::check_if_path_exixsts(&upgrade_path) {
+            Self::map_io_result(fs::remove_dir_all(&upgrade_path))?;
+        }
+        let mut upgraded = Self::create(&upgrade_path, build_options)?;
+        let data_path = Self::storage_file_path(dir_path, OsStr::new("dbidx"));
+        let mut data_file = Self::map_io_result(fs::File::open(data_path))?;
+        let data_len = Self::map_io_result(data_file.metadata())?.len();
+
+        let mut pushed_chunks = 0;
+        let mut last_chunk: Option<Vec<U>> = None;
+        let mut chunk_start = 0;
+        while let Some((values, chunk_end)) = Self::read_baseline_chunk(&mut data_file, chunk_start, data_len, &block_info)? {
+            let count = values.len() as u64;
+            if count == chunk_size && pushed_chunks < num_full_chunks {
+                upgraded.push_column(&values)?;
+                pushed_chunks += 1;
+                last_chunk = None;
+            } else if count == last_chunk_size && pushed_chunks == num_full_chunks {
+                // the newest version of the last partial chunk wins.
+                last_chunk = Some(values);
+            }
+            chunk_start = chunk_end;
+        }
+
+        if pushed_chunks != num_full_chunks || (last_chunk_size != 0 && last_chunk.is_none()) {
+            return Err(Error::InconsistentStorage(format!(
+                "{} values in the meta data, {} found in the data file", num_values, pushed_chunks * chunk_size
+            )));
+        }
+        if let Some(values) = last_chunk {
+            upgraded.push_column(&values)?;
+            upgraded.flush_chunk()?;
+        }
+        drop(upgraded);
+        for entry in Self::map_io_result(fs::read_dir(&upgrade_path))? {
+            let path = Self::map_io_result(entry)?.path();
+            Self::map_io_result(fs::File::open(path).and_then(|file| file.sync_all()))?;
+        }
+        Self::map_io_result(fs::rename(&upgrade_path, done_path))
+    }
+
+    /// Return the number of values and the build options of a 0.1 meta data file.
+    fn read_baseline_meta_data(dir_path: &Path) -> Result<(u64, BuildOptions), Error> {
+        let meta_data_path = Self::storage_file_path(dir_path, OsStr::new("mbidx"));
+        let buf = Self::map_io_result(fs::read(meta_data_path))?;
+        let meta_data: BaselineMetaData = Self::copy_from_slice_u8(&buf);
+        let chunk_size = ChunkSize::from_u32(meta_data.chunk_size).ok_or(Error::MetaDataError)?;
+        Ok((meta_data.num_values, BuildOptions::new(meta_data.bit_block_size, chunk_size)))
+    }
+
+    /// Return the values of the 0.1 chunk at `chunk_start` with the offset of its end,
+    /// or `None` if the chunk is not entirely in the first `data_len` bytes (i.e. a torn
+    /// write).
+    fn read_baseline_chunk<R: Read + Seek>(data_file: &mut R, chunk_start: u64, data_len: u64, block_info: &BlockInfo) -> Result<Option<(Vec<U>, u64)>, Error> {
+        let num_bitmaps = block_info.num_blocks * block_info.num_bitmaps_in_block;
+        let table_size = ((num_bitmaps + 1) * mem::size_of::<u32>()) as u64;
+        if chunk_start + table_size > data_len {
+            return Ok(None);
+        }
+        let mut buf = vec![0u8; table_size as usize];
+        Self::map_io_result(data_file.seek(SeekFrom::Start(chunk_start)))?;
+        Self::map_io_result(data_file.read_exact(&mut buf))?;
+        let table: Vec<u32> = buf.chunks_exact(mem::size_of::<u32>())
+            .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect();
+        if table[0] as u64 != table_size {
+            return Err(Error::MetaDataError);
+        }
+        if table.windows(2).any(|w| w[0] > w[1]) {
+            return Err(Error::InconsistentStorage(format!("bitmap offsets of the chunk at {} are not sorted", chunk_start)));
+        }
+        let chunk_end = chunk_start + table[num_bitmaps] as u64;
+        if chunk_end > data_len {
+            return Ok(None);
+        }
+
+        let mut buf = vec![0u8; (chunk_end - chunk_start - table_size) as usize];
+        Self::map_io_result(data_file.read_exact(&mut buf))?;
+        let mut raw_values: Vec<u128> = Vec::new();
+        let mut bitmap = T::new();
+        for (i_bitmap, bitmap_range) in table.windows(2).enumerate() {
+            let bitmap_buf = &buf[(bitmap_range[0] as u64 - table_size) as usize..(bitmap_range[1] as u64 - table_size) as usize];
+            Self::read_bitmap(bitmap_buf, true, &mut bitmap)?;
+            let i_block = i_bitmap / block_info.num_bitmaps_in_block;
+            let block_bits = (i_bitmap % block_info.num_bitmaps_in_block) as u128;
+            for pos in bitmap.unroll_bitmap() {
+                let pos = pos as usize;
+                if pos >= raw_values.len() {
+                    raw_values.resize(pos + 1, 0);
+                }
+                raw_values[pos] |= block_bits << (i_block * block_info.bit_block_size);
+            }
+        }
+        // 0.1 didn't use `to_ordered`, that is its own inverse.
+        let values = raw_values.into_iter().map(|raw_value| Self::value_from_ordered(raw_value).to_ordered()).collect();
+        Ok(Some((values, chunk_end)))
+    }
+
+    /// Move the files of the upgraded index in `done_path` over the ones in `dir_path`,
+    /// the meta data file last, and remove `done_path`.
+    fn replace_with_upgrade(dir_path: &Path, done_path: &Path) -> Result<(), IoError> {
+        let mut meta_data_path = None;
+        for entry in fs::read_dir(done_path)? {
+            let path = entry?.path();
+            let extension = path.extension().unwrap_or_default().to_os_string();
+            if extension == "mbidx" {
+                meta_data_path = Some(path);
+            } else {
+                fs::rename(&path, Self::storage_file_path(dir_path, &extension))?;
+            }
+        }
+        if let Some(path) = meta_data_path {
+            fs::rename(path, Self::storage_file_path(dir_path, OsStr::new("mbidx")))?;
+        }
+        fs::remove_dir(done_path)
+    }
+
+    fn storage_file_path(dir_path: &Path, extension: &OsStr) -> PathBuf {
+        let mut path = PathBuf::from(dir_path);
+        path.push(dir_path.file_name().unwrap_or_default());
+        path.set_extension(extension);
+        path
+    }
+}
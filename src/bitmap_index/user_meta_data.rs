@@ -0,0 +1,116 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # User meta data
+//!
+//! Key-value pairs attached to a `BitmapIndex`. In storage mode user meta data are
+//! saved in a file with 'ubidx' extension, rewritten every time a pair is set.
+//!
+//! # Encoding
+//!  |u32 num_pairs|u32 key_len|key|u32 value_len|value|...|
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::PathBuf;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    StorageIdx,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the user meta data value associated to `key`.
+    pub fn user_meta_data(&self, key: &str) -> Option<&[u8]> {
+        self.user_meta_data.get(key).map(|value| value.as_slice())
+    }
+
+    /// Associate `value` to `key` in user meta data. If `BitmapIndex` is opened in
+    /// storage mode user meta data are immediately saved.
+    pub fn set_user_meta_data(&mut self, key: &str, value: &[u8]) -> Result<(), Error> {
+        self.user_meta_data.insert(key.to_string(), value.to_vec());
+        if let Some(storage_idx) = self.storage_idx.as_mut() {
+            Self::write_user_meta_data(storage_idx, &self.user_meta_data)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn user_meta_data_path(storage_idx: &StorageIdx) -> PathBuf {
+        let mut path = storage_idx.dir_path.clone();
+        if let Some(name) = storage_idx.dir_path.file_name() {
+            path.push(name);
+        }
+        path.set_extension("ubidx");
+        path
+    }
+
+    pub(super) fn read_user_meta_data(storage_idx: &StorageIdx) -> Result<BTreeMap<String, Vec<u8>>, Error> {
+        let path = Self::user_meta_data_path(storage_idx);
+        let mut user_meta_data = BTreeMap::new();
+        if !path.exists() {
+            return Ok(user_meta_data);
+        }
+        let buf = Self::map_io_result(fs::read(&path))?;
+        let mut offset: usize = 0;
+        let num_pairs = Self::read_user_meta_data_u32(&buf, &mut offset)?;
+        for _i in 0..num_pairs {
+            let key_len = Self::read_user_meta_data_u32(&buf, &mut offset)?;
+            let key = Self::read_user_meta_data_slice(&buf, &mut offset, key_len)?;
+            let key = match String::from_utf8(key.to_vec()) {
+                Ok(key) => key,
+                Err(_) => return Err(Self::invalid_user_meta_data())
+            };
+            let value_len = Self::read_user_meta_data_u32(&buf, &mut offset)?;
+            let value = Self::read_user_meta_data_slice(&buf, &mut offset, value_len)?;
+            user_meta_data.insert(key, value.to_vec());
+        }
+        Ok(user_meta_data)
+    }
+
+    fn read_user_meta_data_slice<'b>(buf: &'b [u8], offset: &mut usize, len: usize) -> Result<&'b [u8], Error> {
+        let slice = match buf.get(*offset..(*offset + len)) {
+            Some(slice) => slice,
+            None => return Err(Self::invalid_user_meta_data())
+        };
+        *offset += len;
+        Ok(slice)
+    }
+
+    fn read_user_meta_data_u32(buf: &[u8], offset: &mut usize) -> Result<usize, Error> {
+        let s = Self::read_user_meta_data_slice(buf, offset, mem::size_of::<u32>())?;
+        Ok(u32::from_ne_bytes([s[0], s[1], s[2], s[3]]) as usize)
+    }
+
+    fn invalid_user_meta_data() -> Error {
+        Error::FileError(IoError::new(ErrorKind::InvalidData, "invalid user meta data"))
+    }
+
+    fn write_user_meta_data(storage_idx: &mut StorageIdx, user_meta_data: &BTreeMap<String, Vec<u8>>) -> Result<(), Error> {
+        let mut buf: Vec<u8> = Vec::new();
+        buf.extend_from_slice(&(user_meta_data.len() as u32).to_ne_bytes());
+        for (key, value) in user_meta_data {
+            buf.extend_from_slice(&(key.len() as u32).to_ne_bytes());
+            buf.extend_from_slice(key.as_bytes());
+            buf.extend_from_slice(&(value.len() as u32).to_ne_bytes());
+            buf.extend_from_slice(value);
+        }
+        // write a temporary file and rename it, so user meta data are never torn.
+        let path = Self::user_meta_data_path(storage_idx);
+        let tmp_path = path.with_extension("ubidx.tmp");
+        Self::map_io_result(fs::write(&tmp_path, &buf))?;
+        Self::map_io_result(fs::rename(&tmp_path, &path))?;
+        storage_idx.stats.user_meta_data_bytes_written += buf.len() as u64;
+        Ok(())
+    }
+}
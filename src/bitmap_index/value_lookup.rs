@@ -0,0 +1,56 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Value lookup
+//!
+//! Each value sets exactly one bitmap of each block of its chunk, so `get_value`
+//! reconstructs the value at a position looking in each block for the bitmap that
+//! contains the position (see `Bitmap::contains`), without the original column. Like
+//! `rebuild`, the low `bin_shift` bits of a reconstructed value are 0.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    BlockInfo,
+    Error,
+    TransmuteToUsize
+};
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return the value at `position`, reconstructed from the bitmaps of its chunk.
+    /// `ParametersError` is returned if the position hasn't been pushed and
+    /// `BitmapError` if a block of the chunk has no bitmap that contains the position.
+    pub fn get_value(&mut self, position: u64) -> Result<U, Error> {
+        if position >= self.num_values {
+            return Err(Error::ParametersError);
+        }
+        let chunk_id = Self::get_chunk_id(position, self.chunk_size);
+        let pos = (position & self.chunk_size_mask) as u32;
+        let ordered_value = if chunk_id == Self::get_chunk_id(self.num_values, self.chunk_size) {
+            Self::ordered_value_at(&self.block_info, &self.bitmaps, pos)
+        } else {
+            let bitmaps = self.chunk_bitmaps(chunk_id)?;
+            Self::ordered_value_at(&self.block_info, &bitmaps, pos)
+        };
+        ordered_value.map(Self::value_from_ordered).ok_or(Error::BitmapError)
+    }
+
+    /// Return the order-preserving representation of the value at `pos` of the chunk with
+    /// `bitmaps`, or `None` if a block has no bitmap that contains `pos`.
+    pub(super) fn ordered_value_at(block_info: &BlockInfo, bitmaps: &[T], pos: u32) -> Option<u128> {
+        let mut ordered_value: u128 = 0;
+        for (i_block, block_bitmaps) in bitmaps.chunks(block_info.num_bitmaps_in_block).enumerate() {
+            let block_bits = block_bitmaps.iter().position(|bitmap| bitmap.contains(pos))? as u128;
+            ordered_value |= block_bits << (block_info.bin_shift + i_block * block_info.bit_block_size);
+        }
+        Some(ordered_value)
+    }
+}
@@ -0,0 +1,89 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Value scan
+//!
+//! `scan_values` walks the chunks of an index and returns every position with its value,
+//! reconstructed from the bitmaps of the chunk (see `rebuild`), so the indexed column can
+//! be exported when the source data is gone. The values of a chunk are reconstructed
+//! when the scan enters it: only one chunk of values is kept in memory at a time.
+
+use std::ops::{BitAnd, Shr};
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    TransmuteToUsize
+};
+
+/// An iterator over the `(position, value)` pairs of a `BitmapIndex`, see `scan_values`.
+pub struct ValueScan<'a, T: Bitmap, U: BitValue>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'b> &'b T: BitAnd<&'b T, Output=T> {
+    b_index: &'a mut BitmapIndex<T, U>,
+    next_index: u64,
+    end_index: u64,
+    chunk_start: u64,
+    chunk_values: Vec<U>
+}
+
+impl<T: Bitmap, U: BitValue> Iterator for ValueScan<'_, T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'b> &'b T: BitAnd<&'b T, Output=T> {
+    type Item = Result<(u64, U), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_index >= self.end_index {
+            return None;
+        }
+        let index = self.next_index;
+        if index >= self.chunk_start + self.chunk_values.len() as u64 {
+            let chunk_id = BitmapIndex::<T, U>::get_chunk_id(index, self.b_index.chunk_size);
+            match self.b_index.chunk_values(chunk_id) {
+                Ok(chunk_values) => {
+                    self.chunk_start = chunk_id * self.b_index.chunk_size;
+                    self.chunk_values = chunk_values;
+                },
+                Err(err) => {
+                    // the scan ends at the first error.
+                    self.next_index = self.end_index;
+                    return Some(Err(err));
+                }
+            }
+        }
+        self.next_index += 1;
+        Some(Ok((index, self.chunk_values[(index - self.chunk_start) as usize])))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.end_index - self.next_index) as usize;
+        (len, Some(len))
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Return an iterator over the `(position, value)` pairs of the index, from
+    /// `start_index` (if specified) to the last value, unflushed values included. With a
+    /// `bin_shift` the low `bin_shift` bits of the values are 0. `ParametersError` is
+    /// returned if `start_index` is greater than the number of values.
+    pub fn scan_values(&mut self, start_index: Option<u64>) -> Result<ValueScan<'_, T, U>, Error> {
+        let start_index = start_index.unwrap_or(0);
+        if start_index > self.num_values {
+            return Err(Error::ParametersError);
+        }
+        Ok(ValueScan {
+            next_index: start_index,
+            end_index: self.num_values,
+            chunk_start: 0,
+            chunk_values: Vec::new(),
+            b_index: self
+        })
+    }
+}
@@ -0,0 +1,231 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # Value statistics
+//!
+//! With `use_value_stats` a `BitmapIndex` keeps the cardinality of every bitmap of
+//! every full chunk, so `estimate_count` and `top_values` never read the bitmaps of
+//! the chunks. In storage mode the statistics are saved in a sidecar file with 'vbidx'
+//! extension, updated when a chunk is full or rewritten, and `open` loads them if the
+//! file exists. Each record keeps the offset of its chunk in the data file: a record
+//! that doesn't match the offsets file (i.e. the process crashed between the flush of
+//! the chunk and its record) is computed again from the bitmaps of the chunk on `open`.
+//!
+//! # Encoding
+//!  |u64 chunk_offset|u32 cardinality|...|u64 chunk_offset|u32 cardinality|...|
+//!
+//! One record for each full chunk, with the cardinality of each bitmap of the chunk.
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{Error as IoError, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::{BitAnd, Shr};
+use std::path::PathBuf;
+
+use super::{
+    BitValue,
+    Bitmap,
+    BitmapIndex,
+    Error,
+    StorageIdx,
+    TransmuteToUsize
+};
+
+/// `ValueStats` struct with the bitmap cardinalities of each full chunk.
+pub(super) struct ValueStats {
+    num_bitmaps: usize,
+    file: Option<File>,
+    chunks: Vec<ChunkStats>
+}
+
+struct ChunkStats {
+    chunk_offset: u64,
+    cardinalities: Vec<u32>
+}
+
+impl ValueStats {
+    fn record_size(&self) -> u64 {
+        (mem::size_of::<u64>() + self.num_bitmaps * mem::size_of::<u32>()) as u64
+    }
+
+    /// Set the record of the chunk `chunk_id`, that must be at most the number of
+    /// chunks, and write it in the sidecar file.
+    fn set_chunk(&mut self, chunk_id: u64, chunk_stats: ChunkStats) -> Result<(), IoError> {
+        let record_size = self.record_size();
+        if let Some(file) = self.file.as_mut() {
+            let mut record: Vec<u8> = Vec::with_capacity(record_size as usize);
+            record.extend_from_slice(&chunk_stats.chunk_offset.to_ne_bytes());
+            record.extend(chunk_stats.cardinalities.iter().flat_map(|cardinality| cardinality.to_ne_bytes()));
+            file.seek(SeekFrom::Start(chunk_id * record_size))?;
+            file.write_all(&record)?;
+        }
+        match self.chunks.get_mut(chunk_id as usize) {
+            Some(old_chunk_stats) => *old_chunk_stats = chunk_stats,
+            None => self.chunks.push(chunk_stats)
+        }
+        Ok(())
+    }
+
+    /// Return the records read from `buf`.
+    fn read_chunks(buf: &[u8], num_bitmaps: usize) -> Vec<ChunkStats> {
+        let record_size = mem::size_of::<u64>() + num_bitmaps * mem::size_of::<u32>();
+        buf.chunks_exact(record_size).map(|record| {
+            let (chunk_offset, cardinalities) = record.split_at(mem::size_of::<u64>());
+            ChunkStats {
+                chunk_offset: u64::from_ne_bytes(chunk_offset.try_into().unwrap()),
+                cardinalities: cardinalities.chunks_exact(mem::size_of::<u32>())
+                    .map(|cardinality| u32::from_ne_bytes(cardinality.try_into().unwrap()))
+                    .collect()
+            }
+        }).collect()
+    }
+}
+
+impl<T: Bitmap, U: BitValue> BitmapIndex<T, U>
+where <U as Shr<usize>>::Output: TransmuteToUsize,
+for <'a> &'a T: BitAnd<&'a T, Output=T> {
+
+    /// Compute the statistics of all full chunks and keep them updated for the next
+    /// chunks. In storage mode they are saved in the sidecar file and loaded from the
+    /// next `open`.
+    pub fn use_value_stats(&mut self) -> Result<(), Error> {
+        let file = match self.storage_idx.as_ref() {
+            Some(storage_idx) => Some(Self::map_io_result(Self::open_file(&Self::value_stats_path(storage_idx), true))?),
+            None => None
+        };
+        self.sync_value_stats(Vec::new(), file)
+    }
+
+    /// Return `true` if `BitmapIndex` keeps the statistics of its chunks.
+    pub fn has_value_stats(&self) -> bool {
+        self.value_stats.is_some()
+    }
+
+    /// Return an upper bound of the number of values equal to `value`, computed from the
+    /// statistics of the full chunks and the bitmaps of the current chunk. The estimate
+    /// of each chunk is the minimum cardinality of the bitmaps of `value`, so it is exact
+    /// if the index has a single block. `ParametersError` is returned if `BitmapIndex`
+    /// doesn't keep statistics (see `use_value_stats`).
+    pub fn estimate_count(&self, value: U) -> Result<u64, Error> {
+        let value_stats = self.value_stats.as_ref().ok_or(Error::ParametersError)?;
+        let query_i_bitmaps: Vec<usize> = Self::get_query_i_bitmaps(&self.block_info, value);
+        let full_chunks_count: u64 = value_stats.chunks.iter().map(|chunk_stats| {
+            query_i_bitmaps.iter().map(|i_bitmap| chunk_stats.cardinalities[*i_bitmap] as u64).min().unwrap_or(0)
+        }).sum();
+        let current_count = query_i_bitmaps.iter().map(|i_bitmap| self.bitmaps[*i_bitmap].cardinality()).min().unwrap_or(0);
+        Ok(full_chunks_count + current_count)
+    }
+
+    /// Return the (at most) `k` values with the most occurrences and their number of
+    /// occurrences, in decreasing order of occurrences. With a `bin_shift` the values are
+    /// the first values of their bins. `ParametersError` is returned if `BitmapIndex`
+    /// doesn't keep statistics or if it has more than one block, since the occurrences of
+    /// a value are not known from the cardinalities of the bitmaps of each block.
+    pub fn top_values(&self, k: usize) -> Result<Vec<(U, u64)>, Error> {
+        let value_stats = self.value_stats.as_ref().ok_or(Error::ParametersError)?;
+        if self.block_info.num_blocks != 1 {
+            return Err(Error::ParametersError);
+        }
+        let mut counts: Vec<(usize, u64)> = self.bitmaps.iter().map(|b| b.cardinality()).enumerate().collect();
+        for chunk_stats in value_stats.chunks.iter() {
+            for (count, cardinality) in counts.iter_mut().zip(chunk_stats.cardinalities.iter()) {
+                count.1 += *cardinality as u64;
+            }
+        }
+        counts.retain(|count| count.1 > 0);
+        counts.sort_by(|count0, count1| count1.1.cmp(&count0.1).then(count0.0.cmp(&count1.0)));
+        let bin_shift = self.block_info.bin_shift;
+        Ok(counts.into_iter().take(k).map(|(i_bitmap, count)| {
+            (Self::value_from_ordered((i_bitmap as u128) << bin_shift), count)
+        }).collect())
+    }
+
+    pub(super) fn value_stats_path(storage_idx: &StorageIdx) -> PathBuf {
+        let mut path = storage_idx.dir_path.clone();
+        if let Some(name) = storage_idx.dir_path.file_name() {
+            path.push(name);
+        }
+        path.set_extension("vbidx");
+        path
+    }
+
+    /// Load the statistics from the sidecar file, if it exists.
+    pub(super) fn open_value_stats(&mut self) -> Result<(), Error> {
+        let path = match self.storage_idx.as_ref() {
+            Some(storage_idx) => Self::value_stats_path(storage_idx),
+            None => return Ok(())
+        };
+        if !path.exists() {
+            return Ok(());
+        }
+        let buf = Self::map_io_result(fs::read(&path))?;
+        let chunks = ValueStats::read_chunks(&buf, self.bitmaps.len());
+        let file = Self::map_io_result(Self::open_file(&path, false))?;
+        self.sync_value_stats(chunks, Some(file))
+    }
+
+    /// Set the statistics from `chunks`: records of chunks that don't exist are removed,
+    /// missing records and records of a different chunk offset are computed from the
+    /// bitmaps of the chunk.
+    fn sync_value_stats(&mut self, mut chunks: Vec<ChunkStats>, file: Option<File>) -> Result<(), Error> {
+        let num_chunks = Self::get_chunk_id(self.num_values, self.chunk_size);
+        let chunk_offsets = match self.storage_idx.as_mut() {
+            Some(storage_idx) => Self::map_io_result(Self::read_offsets(storage_idx, 0, num_chunks as usize))?,
+            None => vec![0; num_chunks as usize]
+        };
+        chunks.truncate(num_chunks as usize);
+        let mut value_stats = ValueStats {
+            num_bitmaps: self.bitmaps.len(),
+            file,
+            chunks: Vec::with_capacity(num_chunks as usize)
+        };
+        let mut chunks = chunks.into_iter();
+        for (chunk_id, chunk_offset) in chunk_offsets.into_iter().enumerate() {
+            let chunk_stats = match chunks.next() {
+                Some(chunk_stats) if chunk_stats.chunk_offset == chunk_offset => {
+                    value_stats.chunks.push(chunk_stats);
+                    continue;
+                },
+                _ => Self::chunk_stats(chunk_offset, &self.chunk_bitmaps(chunk_id as u64)?)
+            };
+            Self::map_io_result(value_stats.set_chunk(chunk_id as u64, chunk_stats))?;
+        }
+        if let Some(file) = value_stats.file.as_ref() {
+            Self::map_io_result(file.set_len(num_chunks * value_stats.record_size()))?;
+        }
+        self.value_stats = Some(value_stats);
+        Ok(())
+    }
+
+    fn chunk_stats(chunk_offset: u64, bitmaps: &[T]) -> ChunkStats {
+        ChunkStats {
+            chunk_offset,
+            cardinalities: bitmaps.iter().map(|b| b.cardinality() as u32).collect()
+        }
+    }
+
+    /// Set the statistics of the full chunk `chunk_id` written at `chunk_offset` (0 in
+    /// memory mode) from its `bitmaps`.
+    pub(super) fn update_value_stats(value_stats: &mut Option<ValueStats>, chunk_id: u64, chunk_offset: u64, bitmaps: &[T]) -> Result<(), Error> {
+        match value_stats.as_mut() {
+            Some(value_stats) => Self::map_io_result(value_stats.set_chunk(chunk_id, Self::chunk_stats(chunk_offset, bitmaps))),
+            None => Ok(())
+        }
+    }
+
+    /// Set the offset of the full chunk `chunk_id`, moved to `chunk_offset`.
+    pub(super) fn repoint_value_stats(value_stats: &mut Option<ValueStats>, chunk_id: u64, chunk_offset: u64) -> Result<(), Error> {
+        let value_stats = match value_stats.as_mut() {
+            Some(value_stats) => value_stats,
+            None => return Ok(())
+        };
+        let cardinalities = match value_stats.chunks.get(chunk_id as usize) {
+            Some(chunk_stats) => chunk_stats.cardinalities.clone(),
+            None => return Ok(())
+        };
+        Self::map_io_result(value_stats.set_chunk(chunk_id, ChunkStats { chunk_offset, cardinalities }))
+    }
+}
@@ -0,0 +1,142 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # BitVecBitmap
+//!
+//! An uncompressed bitmap backed by a [`bitvec`] `BitVec`, enabled with the `bitvec`
+//! feature. It isn't meant to index large chunks (each bitmap takes one bit for each
+//! position up to its last set bit) but gives a simple, well-tested baseline to compare
+//! [`OZBCBitmap`] or a custom bitmap against within the same `BitmapIndex` machinery.
+//!
+//! Serialized content:
+//!
+//!  |32bit num_bits|ceil(num_bits / 64) * (|64bit word|)|
+//!
+//! where the bits of each word are in least significant bit order and `num_bits` is
+//! the position of the last set bit plus one.
+//!
+//! [`bitvec`]: https://docs.rs/bitvec
+//! [`OZBCBitmap`]: ../ozbcbitmap/mod.rs
+
+use std::mem;
+use std::ops::BitAnd;
+use bitvec::prelude::{BitVec, Lsb0};
+use crate::bitmap_index::Bitmap;
+
+const BITVEC_FORMAT_TAG: u32 = u32::from_be_bytes(*b"BVEC");
+const BITVEC_WORD_BITS: usize = 64;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BitVecBitmap {
+    bits: BitVec<u64, Lsb0>,
+}
+
+/// Impl [`BitAnd`] running "logical and" bit operation between 2 bitmaps.
+impl BitAnd for &BitVecBitmap {
+    type Output = BitVecBitmap;
+
+    fn bitand(self, b2: Self) -> BitVecBitmap {
+        let words: Vec<u64> = self.bits.as_raw_slice().iter()
+            .zip(b2.bits.as_raw_slice().iter())
+            .map(|(w0, w1)| w0 & w1)
+            .collect();
+        let mut bits = BitVec::from_vec(words);
+        let num_bits = bits.last_one().map_or(0, |i| i + 1);
+        bits.truncate(num_bits);
+        BitVecBitmap { bits }
+    }
+}
+
+/// Impl [`Bitmap`] to allow to use BitVecBitmap in [`BitmapIndex`].
+impl Bitmap for BitVecBitmap {
+
+    /// Return new empty bitmap.
+    fn new() -> BitVecBitmap {
+        BitVecBitmap { bits: BitVec::new() }
+    }
+
+    /// Return the BitVecBitmap format tag.
+    fn format_tag() -> u32 {
+        BITVEC_FORMAT_TAG
+    }
+
+    /// Remove all bits keeping the allocation of the `BitVec`.
+    fn clear(&mut self) {
+        self.bits.clear();
+    }
+
+    /// Set the ith bit (starting from zero). Like [`OZBCBitmap`] you must set the
+    /// bitmap in increasing order otherwise nothing happend.
+    fn set(&mut self, i: u32) {
+        let i = i as usize;
+        if i < self.bits.len() {
+            return;
+        }
+        self.bits.resize(i, false);
+        self.bits.push(true);
+    }
+
+    /// Return a vector with all positions of set bit.
+    fn unroll_bitmap(&self) -> Vec<u32> {
+        self.bits.iter_ones().map(|i| i as u32).collect()
+    }
+
+    /// Return `true` if no bit is set.
+    fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// Return the number of set bits.
+    fn cardinality(&self) -> u64 {
+        self.bits.count_ones() as u64
+    }
+
+    /// Return `true` if the ith bit is set.
+    fn contains(&self, i: u32) -> bool {
+        self.bits.get(i as usize).is_some_and(|bit| *bit)
+    }
+
+    /// Get bitmap content size.
+    fn size(&self) -> usize {
+        mem::size_of::<u32>() + mem::size_of_val(self.bits.as_raw_slice())
+    }
+
+    /// Write bitmap content into buffer_out and return the number of bytes written.
+    fn write_to_buffer(&self, buffer_out: &mut [u8]) -> Result<usize, ()> {
+        let size = self.size();
+        if buffer_out.len() < size {
+            return Err(());
+        }
+        buffer_out[0..4].copy_from_slice(&(self.bits.len() as u32).to_ne_bytes());
+        for (word, word_out) in self.bits.as_raw_slice().iter().zip(buffer_out[4..size].chunks_exact_mut(8)) {
+            word_out.copy_from_slice(&word.to_ne_bytes());
+        }
+        Ok(size)
+    }
+
+    /// Read bitmap content from buffer_in, buffer_in must have the exact length of bitmap content.
+    /// If `check_bitmap == true` the bits after `num_bits` must be zero and the last bit
+    /// must be set.
+    fn read_from_buffer(&mut self, buffer_in: &[u8], check_bitmap: bool) -> Result<(), ()> {
+        let num_bits = match buffer_in.get(0..4) {
+            Some(b) => u32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as usize,
+            None => return Err(())
+        };
+        let num_words = num_bits.div_ceil(BITVEC_WORD_BITS);
+        if buffer_in.len() != mem::size_of::<u32>() + mem::size_of::<u64>() * num_words {
+            return Err(());
+        }
+        let words: Vec<u64> = buffer_in[4..].chunks_exact(8)
+            .map(|b| u64::from_ne_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .collect();
+        let mut bits: BitVec<u64, Lsb0> = BitVec::from_vec(words);
+        if check_bitmap && bits.last_one().map_or(0, |i| i + 1) != num_bits {
+            return Err(());
+        }
+        bits.truncate(num_bits);
+        self.bits = bits;
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+// This code is released under the
+// General Public License (GPL), version 3
+// http://www.gnu.org/licenses/gpl-3.0.en.html
+// (c) Lorenzo Vannucci
+
+//! # HOZBCBitmap
+//!
+//! A two-level compressed bitmap for bitmap indexes of very sparse values
+//! (i.e. UUID-like values where most bitmaps have few bits set).
+//!
+//! # Encoding
+//! HOZBCBitmap splits bits in ranges of 2^16 bits. A summary level has one bit for
+//! each range, set if the range has at least one bit set, and each non-empty range
+//! is a leaf [`OZBCBitmap`] with the bits of the range. The "logical and" between two
+//! bitmaps intersects the summaries first, so ranges empty in one of the two bitmaps
+//! are skipped without decoding any leaf word.
+//!
+//! Serialized content:
+//!
+//!  |32bit num_leaves|num_leaves * (|32bit range_id|32bit leaf_size|)|leaves content|
+//!
+//! [`OZBCBitmap`]: ../ozbcbitmap/mod.rs
+
+use std::mem;
+use std::io::{Error as IoError, Write};
+use std::ops::BitAnd;
+use crate::bitmap_index::Bitmap;
+use crate::ozbcbitmap::OZBCBitmap;
+
+const HOZBC_RANGE_BITS: u32 = 16;
+const HOZBC_RANGE_MASK: u32 = (1 << HOZBC_RANGE_BITS) - 1;
+const HOZBC_FORMAT_TAG: u32 = u32::from_be_bytes(*b"HOZB");
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct HOZBCBitmap {
+    summary: Vec<u64>,
+    range_ids: Vec<u32>,
+    leaves: Vec<OZBCBitmap>,
+}
+
+/// Impl [`BitAnd`] running "logical and" bit operation between 2 bitmaps.
+impl BitAnd for &HOZBCBitmap {
+    type Output = HOZBCBitmap;
+
+    fn bitand(self, b2: Self) -> HOZBCBitmap {
+        let mut bitmap_to_return = HOZBCBitmap::new();
+        let mut rank: (usize, usize) = (0, 0);
+
+        for (w0, w1) in self.summary.iter().zip(b2.summary.iter()) {
+            let mut common: u64 = w0 & w1;
+            while common != 0 {
+                let bit = common.trailing_zeros();
+                let lower_bits: u64 = (1 << bit) - 1;
+                let i = rank.0 + (w0 & lower_bits).count_ones() as usize;
+                let j = rank.1 + (w1 & lower_bits).count_ones() as usize;
+                let leaf = &self.leaves[i] & &b2.leaves[j];
+                if !leaf.is_empty() {
+                    bitmap_to_return.push_leaf(self.range_ids[i], leaf);
+                }
+                common &= common - 1;
+            }
+            rank.0 += w0.count_ones() as usize;
+            rank.1 += w1.count_ones() as usize;
+        }
+        bitmap_to_return
+    }
+}
+
+/// Impl [`Bitmap`] to allow to use HOZBCBitmap in [`BitmapIndex`].
+impl Bitmap for HOZBCBitmap {
+
+    /// Return new empty bitmap.
+    fn new() -> HOZBCBitmap {
+        HOZBCBitmap {
+            summary: Vec::new(),
+            range_ids: Vec::new(),
+            leaves: Vec::new(),
+        }
+    }
+
+    /// Return the HOZBC format tag.
+    fn format_tag() -> u32 {
+        HOZBC_FORMAT_TAG
+    }
+
+    /// Remove all leaves keeping the allocations of summary, range ids and leaves.
+    fn clear(&mut self) {
+        self.summary.clear();
+        self.range_ids.clear();
+        self.leaves.clear();
+    }
+
+    /// Set the ith bit (starting from zero). Like [`OZBCBitmap`] you must set the
+    /// bitmap in increasing order otherwise nothing happend.
+    fn set(&mut self, i: u32) {
+        let range_id = i >> HOZBC_RANGE_BITS;
+        match self.range_ids.last() {
+            Some(last_range_id) if *last_range_id == range_id => {
+                self.leaves.last_mut().unwrap().set(i & HOZBC_RANGE_MASK);
+            },
+            Some(last_range_id) if *last_range_id > range_id => {},
+            _ => {
+                let mut leaf = OZBCBitmap::new();
+                leaf.set(i & HOZBC_RANGE_MASK);
+                self.push_leaf(range_id, leaf);
+            }
+        }
+    }
+
+    /// Return a vector with all positions of set bit.
+    fn unroll_bitmap(&self) -> Vec<u32> {
+        let mut unrolled_bitmap: Vec<u32> = Vec::new();
+        for (range_id, leaf) in self.range_ids.iter().zip(self.leaves.iter()) {
+            let range_offset = range_id << HOZBC_RANGE_BITS;
+            unrolled_bitmap.extend(leaf.unroll_bitmap().iter().map(|pos| range_offset + pos));
+        }
+        unrolled_bitmap
+    }
+
+    /// Return `true` if no bit is set.
+    fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Return the number of set bits of all leaves.
+    fn cardinality(&self) -> u64 {
+        self.leaves.iter().map(|leaf| leaf.cardinality()).sum()
+    }
+
+    /// Return `true` if the ith bit is set in the leaf of its range.
+    fn contains(&self, i: u32) -> bool {
+        match self.range_ids.binary_search(&(i >> HOZBC_RANGE_BITS)) {
+            Ok(leaf_id) => self.leaves[leaf_id].contains(i & HOZBC_RANGE_MASK),
+            Err(_) => false
+        }
+    }
+
+    /// Get bitmap content size.
+    fn size(&self) -> usize {
+        let header_size = mem::size_of::<u32>() * (1 + 2 * self.leaves.len());
+        header_size + self.leaves.iter().map(|leaf| leaf.size()).sum::<usize>()
+    }
+
+    /// Write bitmap content into buffer_out and return the number of bytes written.
+    fn write_to_buffer(&self, buffer_out: &mut [u8]) -> Result<usize, ()> {
+        if buffer_out.len() < self.size() {
+            return Err(());
+        }
+        let mut offset: usize = 0;
+        let mut write_u32 = |buffer_out: &mut [u8], value: u32| {
+            buffer_out[offset..(offset + 4)].copy_from_slice(&value.to_ne_bytes());
+            offset += 4;
+        };
+        write_u32(buffer_out, self.leaves.len() as u32);
+        for (range_id, leaf) in self.range_ids.iter().zip(self.leaves.iter()) {
+            write_u32(buffer_out, *range_id);
+            write_u32(buffer_out, leaf.size() as u32);
+        }
+        for leaf in &self.leaves {
+            offset += leaf.write_to_buffer(&mut buffer_out[offset..])?;
+        }
+        Ok(offset)
+    }
+
+    /// Write bitmap content into writer without any intermediate buffer.
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<usize, IoError> {
+        writer.write_all(&(self.leaves.len() as u32).to_ne_bytes())?;
+        for (range_id, leaf) in self.range_ids.iter().zip(self.leaves.iter()) {
+            writer.write_all(&range_id.to_ne_bytes())?;
+            writer.write_all(&(leaf.size() as u32).to_ne_bytes())?;
+        }
+        for leaf in &self.leaves {
+            leaf.write_to(writer)?;
+        }
+        Ok(self.size())
+    }
+
+    /// Read bitmap content from buffer_in, buffer_in must have the exact length of bitmap content.
+    fn read_from_buffer(&mut self, buffer_in: &[u8], check_bitmap: bool) -> Result<(), ()> {
+        let read_u32 = |offset: usize| -> Result<u32, ()> {
+            match buffer_in.get(offset..(offset + 4)) {
+                Some(b) => Ok(u32::from_ne_bytes([b[0], b[1], b[2], b[3]])),
+                None => Err(())
+            }
+        };
+        let num_leaves = read_u32(0)? as usize;
+        let mut leaf_offset: usize = mem::size_of::<u32>() * (1 + 2 * num_leaves);
+        if leaf_offset > buffer_in.len() {
+            return Err(());
+        }
+
+        let mut bitmap = HOZBCBitmap::new();
+        for i in 0..num_leaves {
+            let range_id = read_u32(4 + i * 8)?;
+            let leaf_size = read_u32(8 + i * 8)? as usize;
+            let leaf_buffer = match buffer_in.get(leaf_offset..(leaf_offset + leaf_size)) {
+                Some(leaf_buffer) => leaf_buffer,
+                None => return Err(())
+            };
+            if bitmap.range_ids.last().is_some_and(|last_range_id| *last_range_id >= range_id) {
+                return Err(());
+            }
+            let mut leaf = OZBCBitmap::new();
+            leaf.read_from_buffer(leaf_buffer, check_bitmap)?;
+            bitmap.push_leaf(range_id, leaf);
+            leaf_offset += leaf_size;
+        }
+        if leaf_offset != buffer_in.len() {
+            return Err(());
+        }
+
+        *self = bitmap;
+        Ok(())
+    }
+}
+
+impl HOZBCBitmap {
+    fn push_leaf(&mut self, range_id: u32, leaf: OZBCBitmap) {
+        let i_word = (range_id >> 6) as usize;
+        if self.summary.len() <= i_word {
+            self.summary.resize(i_word + 1, 0);
+        }
+        self.summary[i_word] |= 1 << (range_id & 63);
+        self.range_ids.push(range_id);
+        self.leaves.push(leaf);
+    }
+}
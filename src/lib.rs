@@ -7,8 +7,13 @@
 //! Bitrush-Index is a Rust library that provides a serializable bitmap index
 //! able to index millions values/sec on a single thread. On default this
 //! library build bitmap-index using [`ozbcbitmap`] but if you want you can
-//! also use another compressed/uncrompressed bitmap.
-//! Only equality-query (A = X) are supported.
+//! also use another compressed/uncrompressed bitmap. For very sparse values
+//! (i.e. UUID-like columns) [`hozbcbitmap`] adds a summary level over
+//! [`ozbcbitmap`] so empty regions are skipped during queries.
+//! Besides equality-query (A = X) an index supports queries on a prefix of
+//! the value bits or of a string (`run_query_prefix`), approximate range
+//! queries on bins (`run_query_range_approx`) and approximate queries on
+//! half-precision floats (`run_query_approx`, `half` feature).
 //!
 //! ## Example
 //!```
@@ -61,20 +66,92 @@
 //!```
 //!
 //! [`ozbcbitmap`]: ./ozbcbitmap/mod.rs
+//! [`hozbcbitmap`]: ./hozbcbitmap/mod.rs
 
 mod bitmap_index;
 pub use bitmap_index::{
     BitValue,
+    META_DATA_VERSION,
+    ORDERED_KEY_FORMAT,
     BitmapIndex,
     StorageIdx,
+    StorageReader,
+    ReaderSession,
+    SharedTail,
+    StorageStats,
+    DiskSize,
+    ChunkCompression,
     Bitmap,
+    BitmapView,
     MetaData,
     BuildOptions,
-    ChunkSize
+    ChunkSize,
+    ChunkFormat,
+    HotTier,
+    Error,
+    IndexWriter,
+    IndexWriterHandle,
+    IndexWriterReceiver,
+    OfflineBuilder,
+    FlushPolicy,
+    FlushEvent,
+    ChunkPayload,
+    ReplicationStream,
+    QueryExplain,
+    ChunkExplain,
+    QueryProgress,
+    QueryResult,
+    AppendedChunk,
+    ValueDivergence,
+    ValueScan,
+    FrozenIndex,
+    DynBitmapIndex,
+    DynValue,
+    DynValueType,
+    ColumnCombine,
+    TokenIndex,
+    Tokenizer,
+    StringIndex,
+    StringDictionary,
+    StringNormalizer,
+    STRING_NORMALIZER_KEY,
+    GeoGrid,
+    SegmentedIndex,
+    SegmentInfo,
+    MergePolicy,
+    ContainerIndex,
+    PartitionedIndex,
+    PartitionInfo,
+    ShardedIndex,
+    ShardStrategy,
+    CompositeKey,
+    BitEnum,
+    ENUM_MAPPING_KEY,
+    RemappedIndex,
+    LowCardinalityIndex,
+    DictionaryId,
+    is_low_cardinality
 };
 
+#[cfg(feature = "async")]
+pub use bitmap_index::QueryStream;
+#[cfg(feature = "arrow")]
+pub use bitmap_index::ArrowBitValue;
+#[cfg(feature = "polars")]
+pub use bitmap_index::indexes_to_polars_mask;
+#[cfg(feature = "half")]
+pub use bitmap_index::{F16Value, Bf16Value};
+
 mod ozbcbitmap;
-pub use ozbcbitmap::OZBCBitmap;
+pub use ozbcbitmap::{OZBCBitmap, OZBCBitmapRef};
+
+mod hozbcbitmap;
+pub use hozbcbitmap::HOZBCBitmap;
+
+#[cfg(feature = "bitvec")]
+mod bitvecbitmap;
+#[cfg(feature = "bitvec")]
+pub use bitvecbitmap::BitVecBitmap;
 
 /// Return default options to create a BitmapIndex.
 pub fn new_default_index_options<U: BitValue>() -> BuildOptions {
@@ -84,3 +161,42 @@ pub fn new_default_index_options<U: BitValue>() -> BuildOptions {
     }
     BuildOptions::new(16, ChunkSize::M16) 
 }
+
+/// Minimum number of values that each used bitmap of a chunk should have on average,
+/// see `new_sampled_index_options`.
+const MIN_VALUES_PER_BITMAP: u64 = 64;
+
+/// Return options to create a BitmapIndex chosen from a `sample` of the values to index.
+/// For each block size, from the largest (fewest bitmaps to intersect in a query), the
+/// cardinality of every block is estimated from the distinct block values in the sample:
+/// a block is considered saturated (all its bitmaps used) if at least half of the sampled
+/// values are distinct in the block. The first block size (and the smallest chunk size)
+/// where the used bitmaps have at least `MIN_VALUES_PER_BITMAP` values for chunk is
+/// returned. With an empty sample the default options are returned.
+pub fn new_sampled_index_options<U: BitValue>(sample: &[U]) -> BuildOptions
+where <U as std::ops::Shr<usize>>::Output: bitmap_index::TransmuteToUsize {
+    use bitmap_index::TransmuteToUsize;
+
+    if sample.is_empty() {
+        return new_default_index_options::<U>();
+    }
+    let value_size = std::mem::size_of::<U>() << 3;
+    let chunk_sizes = [ChunkSize::M1, ChunkSize::M2, ChunkSize::M4, ChunkSize::M8, ChunkSize::M16, ChunkSize::M32];
+    for bit_block_size in (2..=16).rev().filter(|b| value_size.is_multiple_of(*b)) {
+        let block_mask: usize = (1 << bit_block_size) - 1;
+        let mut max_cardinality: u64 = 0;
+        for shift_value in (0..value_size).step_by(bit_block_size) {
+            let mut used = vec![false; 1 << bit_block_size];
+            for v in sample {
+                used[(*v >> shift_value).transmute_to_usize() & block_mask] = true;
+            }
+            let distinct = used.iter().filter(|u| **u).count() as u64;
+            let cardinality = if distinct * 2 >= sample.len() as u64 { 1 << bit_block_size } else { distinct };
+            max_cardinality = max_cardinality.max(cardinality);
+        }
+        if let Some(chunk_size) = chunk_sizes.iter().find(|c| (**c).clone() as u64 / max_cardinality >= MIN_VALUES_PER_BITMAP) {
+            return BuildOptions::new(bit_block_size, chunk_size.clone());
+        }
+    }
+    new_default_index_options::<U>()
+}
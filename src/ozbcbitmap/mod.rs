@@ -21,22 +21,81 @@
 //! Note:
 //! - The max size of this compressed bitmap is twice the size of the same uncompressed bitmap.
 //! - The max number of consecutive zero bits that can be rapresented from
-//! a single word is ((2^15) - 1) * (2^10) = (2^25 - 2^10) bits.
+//!   a single word is ((2^15) - 1) * (2^10) = (2^25 - 2^10) bits.
+//!
+//! # Wide words encoding
+//! With `set_wide_words(true)` the bitmap is serialized in 32bits words, flagged from
+//! the highest bit of the serialized header. There are two types of words:
+//!
+//!  0:  |1bit word_type=0|15bit   bytes_zero|16bit   dirty_bytes|
+//!  1:  |1bit word_type=1|         31bit bytes_zero             |
+//!
+//! Where dirty_bytes are 2 consecutive uncompressed bytes, so a single word represents
+//! any run of zeros of a bitmap and ultra-sparse bitmaps don't need filler words.
+//! Bitmaps are always 16bits words in memory, the wide words encoding is only used
+//! from serialization and it can't be read as [`OZBCBitmapRef`].
 //!
 //! A older version of OZBCBitmap encoding: https://github.com/uccidibuti/OZBCBitmap .
-
-/// [`Debug`]: https://doc.rust-lang.org/std/fmt/trait.Debug.html
-/// [`BitAnd`]: https://doc.rust-lang.org/std/ops/trait.BitAnd.html
-/// [`Bitmap`]: ../bitmap_index/bitmap.rs
-/// [`BitmapIndex`]: ../bitmap_index/mod.rs
+//!
+//! [`Debug`]: https://doc.rust-lang.org/std/fmt/trait.Debug.html
+//! [`Display`]: https://doc.rust-lang.org/std/fmt/trait.Display.html
+//! [`PartialEq`]: https://doc.rust-lang.org/std/cmp/trait.PartialEq.html
+//! [`BitAnd`]: https://doc.rust-lang.org/std/ops/trait.BitAnd.html
+//! [`Bitmap`]: ../bitmap_index/bitmap.rs
+//! [`BitmapView`]: ../bitmap_index/bitmap.rs
+//! [`BitmapIndex`]: ../bitmap_index/mod.rs
 
 use std::mem;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
 use std::ops::{BitAnd};
 use std::result::Result;
-use crate::bitmap_index::Bitmap;
+use crate::bitmap_index::{Bitmap, BitmapView};
+
+const OZBC_MAX_128_BYTES_ZERO: u16 = (1 << 15) - 1;
+const OZBC_MAX_BYTES_ZERO: u32 = (OZBC_MAX_128_BYTES_ZERO as u32) << 7;
+const OZBC_FORMAT_TAG: u32 = u32::from_be_bytes(*b"OZBC");
+/// Number of first and last set positions printed from [`Display`].
+const OZBC_DISPLAY_POSITIONS: usize = 5;
+/// Size of the scratch buffer used from `read_from`.
+const OZBC_READ_BUFFER_SIZE: usize = 1 << 16;
+/// Flag of the serialized header of a bitmap in wide words encoding.
+const OZBC_WIDE_WORDS_FLAG: u32 = 1 << 31;
+/// Max number of bytes of a bitmap, the bytes of `u32` positions.
+const OZBC_MAX_NUM_BYTES: u64 = 1 << 29;
+
+/// Positions of the set bits of each byte value, used from `unroll_bitmap` to decode
+/// a dirty byte without testing its bits one at a time.
+const OZBC_BYTE_POSITIONS: [[u8; 8]; 256] = byte_positions();
+/// Number of set bits of each byte value.
+const OZBC_BYTE_NUM_SET: [u8; 256] = byte_num_set();
+
+const fn byte_positions() -> [[u8; 8]; 256] {
+    let mut positions = [[0; 8]; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut num_set = 0;
+        let mut j = 0;
+        while j < 8 {
+            if (byte >> j) & 1 == 1 {
+                positions[byte][num_set] = j as u8;
+                num_set += 1;
+            }
+            j += 1;
+        }
+        byte += 1;
+    }
+    positions
+}
 
-const OZBC_MAX_128_BYTES_ZERO: u16 = ((1 << 15) - 1);
-const OZBC_MAX_BYTES_ZERO: u32 = ((OZBC_MAX_128_BYTES_ZERO as u32) << 7);
+const fn byte_num_set() -> [u8; 256] {
+    let mut num_set = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        num_set[byte] = (byte as u8).count_ones() as u8;
+        byte += 1;
+    }
+    num_set
+}
 
 macro_rules! get_bytes_from_word {
     (0 $input:expr) => {
@@ -59,10 +118,76 @@ macro_rules! get_dirty_byte {
     };
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 pub struct OZBCBitmap {
     buffer: Vec<u16>,
     num_bytes: u32,
+    wide_words: bool,
+    wide_words_count: WideWordsCount,
+}
+
+/// Number of words of the wide words encoding of a bitmap, updated when a byte is
+/// added so `size` doesn't walk the bitmap.
+#[derive(Clone, Copy, Default)]
+struct WideWordsCount {
+    num_words: u32,
+    /// First byte of the last word.
+    last_start_byte: Option<u32>,
+}
+
+impl WideWordsCount {
+    /// Count the words of a new set `byte`, following the last byte counted (see
+    /// `OZBCBitmap::for_each_wide_word`).
+    fn push_byte(&mut self, byte: u32) {
+        match self.last_start_byte {
+            Some(start_byte) if byte == start_byte + 1 => {},
+            last_start_byte => {
+                let end_byte = last_start_byte.map(|start_byte| start_byte + 2).unwrap_or(0);
+                self.num_words += if byte - end_byte >= (1 << 15) { 2 } else { 1 };
+                self.last_start_byte = Some(byte);
+            }
+        }
+    }
+}
+
+/// `OZBCBitmapRef` is a borrowed view of a serialized [`OZBCBitmap`]: words are decoded
+/// directly from the serialized bytes, so "logical and" and unroll don't copy the
+/// bitmap content into an owned buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct OZBCBitmapRef<'a> {
+    words: &'a [u8],
+    num_bytes: u32,
+}
+
+/// Words of an OZBC bitmap, owned (`[u16]`) or serialized (`OZBCBitmapRef`).
+trait OZBCWords {
+    fn num_words(&self) -> usize;
+    fn word(&self, i: usize) -> u16;
+}
+
+impl OZBCWords for [u16] {
+    #[inline]
+    fn num_words(&self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn word(&self, i: usize) -> u16 {
+        // words are always read in 0..num_words().
+        unsafe { *self.get_unchecked(i) }
+    }
+}
+
+impl OZBCWords for OZBCBitmapRef<'_> {
+    #[inline]
+    fn num_words(&self) -> usize {
+        self.words.len() >> 1
+    }
+
+    #[inline]
+    fn word(&self, i: usize) -> u16 {
+        u16::from_ne_bytes([self.words[i << 1], self.words[(i << 1) + 1]])
+    }
 }
 
 /// Impl [`Debug`] trait printing each bit of every bitmap words.
@@ -73,90 +198,83 @@ impl std::fmt::Debug for OZBCBitmap {
         for word in &self.buffer {
             let t: u16 = get_word_type!(word);
             for j in (0..16).rev() {
-                let x: u16 = (word >> j) & (1 as u16);
+                let x: u16 = (word >> j) & 1;
                 output.push_str(&x.to_string());
                 if j == 15 || (j == 8 && t == 0) {
-                    output.push_str("|");
+                    output.push('|');
                 }
             }
-            output.push_str("\n");
+            output.push('\n');
         }
         write!(f, "{}", output)
     }
 }
 
 
+/// Impl [`Display`] printing a summary of the bitmap: the number of bits set, the number of
+/// bytes and words and the first and last set positions, i.e.
+/// `OZBCBitmap { cardinality: 3, bytes: 13, words: 3, positions: [0, 50, 100] }`.
+impl std::fmt::Display for OZBCBitmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut cardinality: u64 = 0;
+        let mut first_positions: Vec<u32> = Vec::with_capacity(OZBC_DISPLAY_POSITIONS);
+        let mut last_positions: std::collections::VecDeque<u32> = std::collections::VecDeque::with_capacity(OZBC_DISPLAY_POSITIONS);
+        let (mut i, mut scanned_bytes) = (0, 0);
+        while let Some((byte, dirty_byte)) = OZBCBitmap::next_set_byte(self.buffer.as_slice(), &mut i, &mut scanned_bytes) {
+            cardinality += dirty_byte.count_ones() as u64;
+            for bit in (0..8).filter(|bit| dirty_byte & (1 << bit) != 0) {
+                let position = (byte << 3) + bit;
+                if first_positions.len() < OZBC_DISPLAY_POSITIONS {
+                    first_positions.push(position);
+                    continue;
+                }
+                if last_positions.len() == OZBC_DISPLAY_POSITIONS {
+                    last_positions.pop_front();
+                }
+                last_positions.push_back(position);
+            }
+        }
+        let mut positions: Vec<String> = first_positions.iter().map(|position| position.to_string()).collect();
+        if cardinality > (OZBC_DISPLAY_POSITIONS * 2) as u64 {
+            positions.push(String::from("..."));
+        }
+        positions.extend(last_positions.iter().map(|position| position.to_string()));
+        write!(f, "OZBCBitmap {{ cardinality: {}, bytes: {}, words: {}, positions: [{}] }}",
+               cardinality, self.num_bytes, self.buffer.len(), positions.join(", "))
+    }
+}
+
+/// Impl [`PartialEq`] comparing the bits set of 2 bitmaps, so bitmaps with the same bits
+/// set are equal also if they are encoded in different words (i.e. a different trailing
+/// zeros run or a zeros run split in different words).
+impl PartialEq for OZBCBitmap {
+    fn eq(&self, b1: &OZBCBitmap) -> bool {
+        if self.buffer == b1.buffer {
+            return true;
+        }
+        let (mut i, mut j) = (0, 0); // self and b1 indexes
+        let (mut scanned_bytes0, mut scanned_bytes1) = (0, 0);
+        loop {
+            let dirty0 = OZBCBitmap::next_set_byte(self.buffer.as_slice(), &mut i, &mut scanned_bytes0);
+            let dirty1 = OZBCBitmap::next_set_byte(b1.buffer.as_slice(), &mut j, &mut scanned_bytes1);
+            if dirty0 != dirty1 {
+                return false;
+            }
+            if dirty0.is_none() {
+                return true;
+            }
+        }
+    }
+}
+
+impl Eq for OZBCBitmap {}
+
 /// Impl [`BitAnd`] running "logical and" bit operation between 2 bitmaps.
 impl BitAnd for &OZBCBitmap {
     type Output = OZBCBitmap;
 
     fn bitand(self, b2: Self) -> OZBCBitmap {
-        let mut bitmap_to_return = OZBCBitmap::new();
-        let v0 = self.buffer.as_slice();
-        let v1 = b2.buffer.as_slice();
-        let mut i: usize = 0; // v0 index
-        let mut j: usize = 0; // v1 index
-        let mut count_bytes: u32 = 0;
-        let mut scanned_bytes: (u32, u32) = (0, 0);
-
-        while i < v0.len() && j < v1.len() {
-            let w0: u32 = unsafe { *v0.get_unchecked(i) } as u32;
-            let w1: u32 = unsafe { *v1.get_unchecked(j) } as u32;
-
-            let word_type: u8 = ((get_word_type!(w1) << 1) | get_word_type!(w0)) as u8;
-            let bytes_in_word = match word_type {
-                // w0 and w1 are of type 0
-                0b00 => (get_bytes_from_word!(0 w0), get_bytes_from_word!(0 w1)),
-                // w0 is of type 1, w1 is of type 0
-                0b01 => (get_bytes_from_word!(1 w0), get_bytes_from_word!(0 w1)),
-                // w0 is of type 0, w1 is of type 1
-                0b10 => (get_bytes_from_word!(0 w0), get_bytes_from_word!(1 w1)),
-                // w0 and w1 are of type 1
-                0b11 => (get_bytes_from_word!(1 w0), get_bytes_from_word!(1 w1)),
-                _ => panic!("Error occured on bitmap and"),
-            };
-            i += 1;
-            j += 1;
-            scanned_bytes.0 += bytes_in_word.0;
-            scanned_bytes.1 += bytes_in_word.1;
-
-            if scanned_bytes.0 < scanned_bytes.1 {
-                scanned_bytes.1 -= bytes_in_word.1;
-                j -= 1;
-            } else if scanned_bytes.0 > scanned_bytes.1 {
-                scanned_bytes.0 -= bytes_in_word.0;
-                i -= 1;
-            } else if word_type == 0 {
-                let mut bytes_zero = scanned_bytes.0 - count_bytes - 1;
-                let dirty_byte =
-                    unsafe { get_dirty_byte!(*v0.get_unchecked(i - 1) & *v1.get_unchecked(j - 1)) };
-
-                if dirty_byte != 0 {
-                    count_bytes = scanned_bytes.0;
-                    if bytes_zero < (1 << 7) {
-                        bitmap_to_return
-                            .buffer
-                            .push((bytes_zero << 8) as u16 | dirty_byte);
-                    } else {
-                        while bytes_zero > OZBC_MAX_BYTES_ZERO {
-                            bitmap_to_return
-                                .buffer
-                                .push(OZBC_MAX_128_BYTES_ZERO | (1 << 15));
-                            bytes_zero -= OZBC_MAX_BYTES_ZERO;
-                        }
-                        bitmap_to_return
-                            .buffer
-                            .push((bytes_zero >> 7) as u16 | (1 << 15));
-                        bitmap_to_return
-                            .buffer
-                            .push(((bytes_zero as u16 & 127) << 8) | dirty_byte);
-                    }
-                } // end if dirty_bytes != 0
-            } // end if word_type == 0
-        } // end while
-
-        bitmap_to_return.num_bytes = count_bytes;
-        bitmap_to_return
+        OZBCBitmap::and_words(self.buffer.as_slice(), b2.buffer.as_slice())
     }
 }
 
@@ -168,7 +286,32 @@ impl Bitmap for OZBCBitmap {
         OZBCBitmap {
             buffer: Vec::new(),
             num_bytes: 0,
+            wide_words: false,
+            wide_words_count: WideWordsCount::default(),
+        }
+    }
+
+    /// Return the OZBC format tag.
+    fn format_tag() -> u32 {
+        OZBC_FORMAT_TAG
+    }
+
+    /// Serialize the bitmap in wide words encoding (see module documentation).
+    fn set_wide_words(&mut self, wide_words: bool) {
+        if wide_words && !self.wide_words {
+            self.wide_words_count = WideWordsCount::default();
+            let mut scanned_bytes = 0;
+            let mut i = 0;
+            while let Some((byte, _dirty_byte)) = OZBCBitmap::next_set_byte(self.buffer.as_slice(), &mut i, &mut scanned_bytes) {
+                self.wide_words_count.push_byte(byte);
+            }
         }
+        self.wide_words = wide_words;
+    }
+
+    /// Reserve a word for each of `num_bits` set bits (the worst case of sparse bits).
+    fn reserve(&mut self, num_bits: u32) {
+        self.buffer.reserve(num_bits as usize);
     }
 
     /// Set the ith bit (starting from zero). You must set the bitmap in increasing
@@ -201,6 +344,9 @@ impl Bitmap for OZBCBitmap {
         let mut bytes_zero: i32 = (i >> 3) as i32 - self.num_bytes as i32;
         if bytes_zero >= 0 {
             self.num_bytes += bytes_zero as u32 + 1;
+            if self.wide_words {
+                self.wide_words_count.push_byte(self.num_bytes - 1);
+            }
             if bytes_zero < 128 {
                 self.buffer.push(((bytes_zero as u16) << 8) | dirty_byte);
             } else {
@@ -220,35 +366,49 @@ impl Bitmap for OZBCBitmap {
 
     /// Return a vector with all positions of set bit.
     fn unroll_bitmap(&self) -> Vec<u32> {
-        let mut pos_set: u32 = 0;
-        let mut unrolled_bitmap: Vec<u32> = Vec::with_capacity(self.buffer.len());
+        OZBCBitmap::unroll_words(self.buffer.as_slice())
+    }
 
-        for word in &self.buffer {
-            if get_word_type!(word) as u32 == 0 {
-                let bytes_zero = (word >> 8) as u32;
-                pos_set += bytes_zero << 3;
-                let dirty_byte = get_dirty_byte!(word);
+    /// Remove all words keeping the buffer allocation.
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.num_bytes = 0;
+        self.wide_words_count = WideWordsCount::default();
+    }
 
-                for j in 0..8 {
-                    if (dirty_byte >> j) & 1 == 1 {
-                        unrolled_bitmap.push(pos_set + j);
-                    }
-                }
-                pos_set += 8;
-            } else {
-                let bytes_zero: u32 = ((word & OZBC_MAX_128_BYTES_ZERO) as u32) << 7;
-                pos_set += bytes_zero << 3;
-            }
-        }
-        unrolled_bitmap
+    /// Return the "logical and not" between 2 bitmaps without unrolling them.
+    fn and_not(&self, b1: &OZBCBitmap) -> OZBCBitmap {
+        OZBCBitmap::and_not_words(self.buffer.as_slice(), b1.buffer.as_slice())
+    }
+
+    /// Return `true` if no bit is set.
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Return the number of set bits, counted from the dirty bytes without unrolling
+    /// the bitmap.
+    fn cardinality(&self) -> u64 {
+        self.buffer.iter()
+            .filter(|word| get_word_type!(**word) == 0)
+            .map(|word| OZBC_BYTE_NUM_SET[get_dirty_byte!(*word) as usize] as u64)
+            .sum()
+    }
+
+    /// Return `true` if the ith bit is set, walking the words without unrolling the
+    /// bitmap.
+    fn contains(&self, i: u32) -> bool {
+        !self.intersect_with_sorted(&[i]).is_empty()
     }
 
     /// Get bitmap content size.
     fn size(&self) -> usize {
+        if self.wide_words {
+            return mem::size_of::<u32>() * (self.wide_words_count.num_words as usize + 1);
+        }
         let word_size = mem::size_of::<u16>();
         let buffer_content_size = self.buffer.len() * word_size;
-        let bitmap_size = buffer_content_size + mem::size_of::<u32>();
-        bitmap_size
+        buffer_content_size + mem::size_of::<u32>()
     }
 
     /// Write bitmap content into buffer_out and return the number of bytes written.
@@ -257,60 +417,456 @@ impl Bitmap for OZBCBitmap {
         if buffer_out.len() < bitmap_content_size {
             return Err(());
         }
-        let num_bytes_raw: [u8; 4] = unsafe { mem::transmute(self.num_bytes) };
-        // let num_bytes_raw: [u8; 4] = self.num_bytes.to_ne_bytes();
+        if self.wide_words {
+            let buffer_raw = self.wide_words_raw();
+            buffer_out[0..buffer_raw.len()].copy_from_slice(&buffer_raw);
+            return Ok(buffer_raw.len());
+        }
+        let num_bytes_raw: [u8; 4] = self.num_bytes.to_ne_bytes();
         buffer_out[0..(num_bytes_raw.len())].copy_from_slice(&num_bytes_raw);
 
-        let buffer_raw: &[u8] = unsafe {
-            std::slice::from_raw_parts(
-                self.buffer.as_ptr() as *const u8,
-                self.buffer.len() * mem::size_of::<u16>(),
-            )
-        };
+        let buffer_raw: &[u8] = self.buffer_raw();
         let start_offset = num_bytes_raw.len();
         let end_offset = start_offset + buffer_raw.len();
         buffer_out[start_offset..end_offset].copy_from_slice(buffer_raw);
         Ok(end_offset)
     }
 
+    /// Write bitmap content into writer without any intermediate buffer.
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<usize, IoError> {
+        if self.wide_words {
+            let buffer_raw = self.wide_words_raw();
+            writer.write_all(&buffer_raw)?;
+            return Ok(buffer_raw.len());
+        }
+        writer.write_all(&self.num_bytes.to_ne_bytes())?;
+        writer.write_all(self.buffer_raw())?;
+        Ok(self.size())
+    }
+
     /// Read bitmap content from buffer_in, buffer_in must have the exact length of bitmap content.
+    /// An error is returned if the length of buffer_in is not the header size plus a
+    /// whole number of words.
     fn read_from_buffer(&mut self, buffer_in: &[u8], check_bitmap: bool) -> Result<(), ()> {
-        let num_bytes_pointer: *const u32 = buffer_in[0..4].as_ptr() as *const u32;
-        let num_bytes: u32 = unsafe { *num_bytes_pointer };
-        // let num_bytes: u32 = u32::from_ne_bytes(buffer_in[0..4].try_into().unwrap());
-
-        let buffer_pointer: *const u16 = buffer_in[4..buffer_in.len()].as_ptr() as *const u16;
-        let buffer_len: usize = (buffer_in.len() - 4) / mem::size_of::<u16>();
-
-        let buffer_slice: &[u16] =
-            unsafe { std::slice::from_raw_parts(buffer_pointer, buffer_len) };
+        if !OZBCBitmap::is_valid_size(buffer_in.len()) {
+            return Err(());
+        }
+        // buffer_in can be not aligned, so words are decoded byte by byte.
+        let num_bytes: u32 = u32::from_ne_bytes([buffer_in[0], buffer_in[1], buffer_in[2], buffer_in[3]]);
+        if num_bytes & OZBC_WIDE_WORDS_FLAG != 0 {
+            return self.read_wide_words(num_bytes & !OZBC_WIDE_WORDS_FLAG, &buffer_in[mem::size_of::<u32>()..], check_bitmap);
+        }
 
-        let mut buffer = vec![0; buffer_slice.len()];
-        buffer.copy_from_slice(buffer_slice);
+        let buffer: Vec<u16> = buffer_in[mem::size_of::<u32>()..]
+            .chunks_exact(mem::size_of::<u16>())
+            .map(|word| u16::from_ne_bytes([word[0], word[1]]))
+            .collect();
 
-        if check_bitmap == true {
+        if check_bitmap {
             let buffer_num_bytes = OZBCBitmap::get_buffer_num_bytes(&buffer);
-            if buffer_num_bytes != num_bytes {
+            if buffer_num_bytes != num_bytes as u64 {
                 return Err(());
             }
         }
 
         self.num_bytes = num_bytes;
         self.buffer = buffer;
+        self.wide_words = false;
 
         Ok(())
     }
+
+    /// Read bitmap content of `size` bytes from reader, words are decoded from a scratch
+    /// buffer of `OZBC_READ_BUFFER_SIZE` bytes directly in the bitmap buffer.
+    fn read_from<R: Read>(&mut self, reader: &mut R, size: usize, check_bitmap: bool) -> Result<(), IoError> {
+        let header_size = mem::size_of::<u32>();
+        if !OZBCBitmap::is_valid_size(size) {
+            return Err(IoError::new(ErrorKind::InvalidData, "invalid OZBC bitmap size"));
+        }
+        let mut header: [u8; 4] = [0; 4];
+        reader.read_exact(&mut header)?;
+        let num_bytes: u32 = u32::from_ne_bytes(header);
+        if num_bytes & OZBC_WIDE_WORDS_FLAG != 0 {
+            let mut words: Vec<u8> = vec![0; size - header_size];
+            reader.read_exact(&mut words)?;
+            return self.read_wide_words(num_bytes & !OZBC_WIDE_WORDS_FLAG, &words, check_bitmap)
+                .map_err(|_err| IoError::new(ErrorKind::InvalidData, "invalid OZBC bitmap content"));
+        }
+
+        let mut num_words_left = (size - header_size) / mem::size_of::<u16>();
+        self.buffer.clear();
+        self.buffer.reserve(num_words_left);
+        let mut scratch: Vec<u8> = vec![0; OZBC_READ_BUFFER_SIZE.min(num_words_left * mem::size_of::<u16>())];
+        while num_words_left > 0 {
+            let num_words = num_words_left.min(scratch.len() / mem::size_of::<u16>());
+            let scratch_words = &mut scratch[0..(num_words * mem::size_of::<u16>())];
+            reader.read_exact(scratch_words)?;
+            self.buffer.extend(scratch_words.chunks_exact(mem::size_of::<u16>())
+                .map(|word| u16::from_ne_bytes([word[0], word[1]])));
+            num_words_left -= num_words;
+        }
+        self.num_bytes = num_bytes;
+        self.wide_words = false;
+
+        if check_bitmap && OZBCBitmap::get_buffer_num_bytes(&self.buffer) != num_bytes as u64 {
+            self.clear();
+            return Err(IoError::new(ErrorKind::InvalidData, "invalid OZBC bitmap content"));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> OZBCBitmapRef<'a> {
+    /// Return a view on buffer_in, buffer_in must have the exact length of bitmap content.
+    /// An error is returned for a bitmap in wide words encoding.
+    #[allow(clippy::result_unit_err)]
+    pub fn from_buffer(buffer_in: &'a [u8]) -> Result<Self, ()> {
+        let header_size = mem::size_of::<u32>();
+        if !OZBCBitmap::is_valid_size(buffer_in.len()) {
+            return Err(());
+        }
+        let num_bytes: u32 = u32::from_ne_bytes([buffer_in[0], buffer_in[1], buffer_in[2], buffer_in[3]]);
+        if num_bytes & OZBC_WIDE_WORDS_FLAG != 0 {
+            return Err(());
+        }
+        Ok(OZBCBitmapRef {
+            words: &buffer_in[header_size..],
+            num_bytes,
+        })
+    }
+
+    /// Return the number of bits represented from the bitmap.
+    pub fn num_bits(&self) -> u64 {
+        (self.num_bytes as u64) << 3
+    }
+
+    /// Return a vector with all positions of set bit.
+    pub fn unroll_bitmap(&self) -> Vec<u32> {
+        OZBCBitmap::unroll_words(self)
+    }
+
+    /// Return an owned copy of the bitmap.
+    pub fn to_bitmap(&self) -> OZBCBitmap {
+        OZBCBitmap {
+            buffer: (0..self.num_words()).map(|i| self.word(i)).collect(),
+            num_bytes: self.num_bytes,
+            wide_words: false,
+            wide_words_count: WideWordsCount::default(),
+        }
+    }
+}
+
+/// Impl [`BitmapView`] to run queries directly on serialized OZBCBitmap.
+impl BitmapView for OZBCBitmap {
+    type View<'a> = OZBCBitmapRef<'a>;
+
+    fn view(buffer_in: &[u8]) -> Result<OZBCBitmapRef<'_>, ()> {
+        OZBCBitmapRef::from_buffer(buffer_in)
+    }
+
+    fn and_views(b0: &OZBCBitmapRef<'_>, b1: &OZBCBitmapRef<'_>) -> OZBCBitmap {
+        OZBCBitmap::and_words(b0, b1)
+    }
+
+    fn and_view(&self, b1: &OZBCBitmapRef<'_>) -> OZBCBitmap {
+        OZBCBitmap::and_words(self.buffer.as_slice(), b1)
+    }
+
+    fn unroll_view(view: &OZBCBitmapRef<'_>) -> Vec<u32> {
+        view.unroll_bitmap()
+    }
 }
 
 impl OZBCBitmap {
-    fn get_buffer_num_bytes(buffer: &Vec<u16>) -> u32 {
+    /// Return the positions of `positions` that are set in the bitmap. `positions`
+    /// must be sorted in increasing order (i.e. the indexes of a previous query), the
+    /// words of the bitmap are walked once against the positions so a small candidate
+    /// set is intersected without building a bitmap for it.
+    pub fn intersect_with_sorted(&self, positions: &[u32]) -> Vec<u32> {
+        let mut intersection: Vec<u32> = Vec::new();
+        let mut i: usize = 0;
+        let mut k: usize = 0;
+        let mut scanned_bytes: u32 = 0;
+        while k < positions.len() {
+            let (byte, dirty_byte) = match OZBCBitmap::next_set_byte(self.buffer.as_slice(), &mut i, &mut scanned_bytes) {
+                Some(dirty) => dirty,
+                None => break
+            };
+            k += positions[k..].partition_point(|pos| (pos >> 3) < byte);
+            while k < positions.len() && (positions[k] >> 3) == byte {
+                if (dirty_byte >> (positions[k] & 7)) & 1 == 1 {
+                    intersection.push(positions[k]);
+                }
+                k += 1;
+            }
+        }
+        intersection
+    }
+
+    fn and_words<V0: OZBCWords + ?Sized, V1: OZBCWords + ?Sized>(v0: &V0, v1: &V1) -> OZBCBitmap {
+        let mut bitmap_to_return = OZBCBitmap::new();
+        let mut i: usize = 0; // v0 index
+        let mut j: usize = 0; // v1 index
+        let mut count_bytes: u32 = 0;
+        let mut scanned_bytes: (u32, u32) = (0, 0);
+
+        while i < v0.num_words() && j < v1.num_words() {
+            let w0: u32 = v0.word(i) as u32;
+            let w1: u32 = v1.word(j) as u32;
+
+            let word_type: u8 = ((get_word_type!(w1) << 1) | get_word_type!(w0)) as u8;
+            let bytes_in_word = match word_type {
+                // w0 and w1 are of type 0
+                0b00 => (get_bytes_from_word!(0 w0), get_bytes_from_word!(0 w1)),
+                // w0 is of type 1, w1 is of type 0
+                0b01 => (get_bytes_from_word!(1 w0), get_bytes_from_word!(0 w1)),
+                // w0 is of type 0, w1 is of type 1
+                0b10 => (get_bytes_from_word!(0 w0), get_bytes_from_word!(1 w1)),
+                // w0 and w1 are of type 1
+                0b11 => (get_bytes_from_word!(1 w0), get_bytes_from_word!(1 w1)),
+                _ => panic!("Error occured on bitmap and"),
+            };
+            i += 1;
+            j += 1;
+            scanned_bytes.0 += bytes_in_word.0;
+            scanned_bytes.1 += bytes_in_word.1;
+
+            if scanned_bytes.0 < scanned_bytes.1 {
+                scanned_bytes.1 -= bytes_in_word.1;
+                j -= 1;
+            } else if scanned_bytes.0 > scanned_bytes.1 {
+                scanned_bytes.0 -= bytes_in_word.0;
+                i -= 1;
+            } else if word_type == 0 {
+                let mut bytes_zero = scanned_bytes.0 - count_bytes - 1;
+                let dirty_byte = get_dirty_byte!(v0.word(i - 1) & v1.word(j - 1));
+
+                if dirty_byte != 0 {
+                    count_bytes = scanned_bytes.0;
+                    if bytes_zero < (1 << 7) {
+                        bitmap_to_return
+                            .buffer
+                            .push((bytes_zero << 8) as u16 | dirty_byte);
+                    } else {
+                        while bytes_zero > OZBC_MAX_BYTES_ZERO {
+                            bitmap_to_return
+                                .buffer
+                                .push(OZBC_MAX_128_BYTES_ZERO | (1 << 15));
+                            bytes_zero -= OZBC_MAX_BYTES_ZERO;
+                        }
+                        bitmap_to_return
+                            .buffer
+                            .push((bytes_zero >> 7) as u16 | (1 << 15));
+                        bitmap_to_return
+                            .buffer
+                            .push(((bytes_zero as u16 & 127) << 8) | dirty_byte);
+                    }
+                } // end if dirty_bytes != 0
+            } // end if word_type == 0
+        } // end while
+
+        bitmap_to_return.num_bytes = count_bytes;
+        bitmap_to_return
+    }
+
+    fn and_not_words<V0: OZBCWords + ?Sized, V1: OZBCWords + ?Sized>(v0: &V0, v1: &V1) -> OZBCBitmap {
+        let mut bitmap_to_return = OZBCBitmap::new();
+        let (mut i, mut j) = (0, 0); // v0 and v1 indexes
+        let (mut scanned_bytes0, mut scanned_bytes1) = (0, 0);
+        let mut dirty1 = OZBCBitmap::next_dirty_byte(v1, &mut j, &mut scanned_bytes1);
+
+        while let Some((byte0, dirty_byte0)) = OZBCBitmap::next_dirty_byte(v0, &mut i, &mut scanned_bytes0) {
+            while let Some((byte1, _)) = dirty1 {
+                if byte1 >= byte0 {
+                    break;
+                }
+                dirty1 = OZBCBitmap::next_dirty_byte(v1, &mut j, &mut scanned_bytes1);
+            }
+            let dirty_byte = match dirty1 {
+                Some((byte1, dirty_byte1)) if byte1 == byte0 => dirty_byte0 & !dirty_byte1,
+                _ => dirty_byte0
+            };
+            if dirty_byte != 0 {
+                bitmap_to_return.push_dirty_byte(byte0, dirty_byte);
+            }
+        }
+        bitmap_to_return
+    }
+
+    /// Return the position and the content of the next dirty byte from the ith word,
+    /// `scanned_bytes` is the number of bytes represented from the words before i.
+    fn next_dirty_byte<V: OZBCWords + ?Sized>(v: &V, i: &mut usize, scanned_bytes: &mut u32) -> Option<(u32, u16)> {
+        while *i < v.num_words() {
+            let word = v.word(*i);
+            *i += 1;
+            if get_word_type!(word) == 0 {
+                let byte = *scanned_bytes + (word >> 8) as u32;
+                *scanned_bytes = byte + 1;
+                return Some((byte, get_dirty_byte!(word)));
+            }
+            *scanned_bytes += get_bytes_from_word!(1 word as u32);
+        }
+        None
+    }
+
+    /// Return the position and the content of the next dirty byte with at least a bit set.
+    fn next_set_byte<V: OZBCWords + ?Sized>(v: &V, i: &mut usize, scanned_bytes: &mut u32) -> Option<(u32, u16)> {
+        loop {
+            match OZBCBitmap::next_dirty_byte(v, i, scanned_bytes) {
+                Some((_byte, 0)) => continue,
+                dirty => return dirty
+            }
+        }
+    }
+
+    /// Append `dirty_byte` as the byte in position `byte`, that must follow the last
+    /// byte of the bitmap.
+    fn push_dirty_byte(&mut self, byte: u32, dirty_byte: u16) {
+        let mut bytes_zero = byte - self.num_bytes;
+        self.num_bytes = byte + 1;
+        if self.wide_words {
+            self.wide_words_count.push_byte(byte);
+        }
+        if bytes_zero < 128 {
+            self.buffer.push(((bytes_zero as u16) << 8) | dirty_byte);
+        } else {
+            while bytes_zero > OZBC_MAX_BYTES_ZERO {
+                self.buffer.push((1 << 15) | OZBC_MAX_128_BYTES_ZERO);
+                bytes_zero -= OZBC_MAX_BYTES_ZERO;
+            }
+            self.buffer.push((1 << 15) | ((bytes_zero >> 7) as u16));
+            self.buffer.push((((bytes_zero as u16) & 127) << 8) | dirty_byte);
+        }
+    }
+
+    /// Call `f` with each word of the wide words encoding of the bitmap and return
+    /// the number of bytes represented from the words.
+    fn for_each_wide_word(&self, mut f: impl FnMut(u32)) -> u32 {
+        let (mut i, mut scanned_bytes) = (0, 0);
+        let mut end_byte: u32 = 0;
+        let mut group: Option<(u32, u32)> = None;
+        while let Some((byte, dirty_byte)) = OZBCBitmap::next_set_byte(self.buffer.as_slice(), &mut i, &mut scanned_bytes) {
+            group = match group {
+                Some((start_byte, dirty_bytes)) if byte == start_byte + 1 => Some((start_byte, dirty_bytes | (dirty_byte as u32) << 8)),
+                Some((start_byte, dirty_bytes)) => {
+                    end_byte = OZBCBitmap::push_wide_word(start_byte, dirty_bytes, end_byte, &mut f);
+                    Some((byte, dirty_byte as u32))
+                },
+                None => Some((byte, dirty_byte as u32))
+            };
+        }
+        match group {
+            Some((start_byte, dirty_bytes)) => {
+                OZBCBitmap::push_wide_word(start_byte, dirty_bytes, end_byte, &mut f);
+                start_byte + if dirty_bytes >> 8 != 0 { 2 } else { 1 }
+            },
+            None => 0
+        }
+    }
+
+    /// Call `f` with the words of 2 dirty bytes starting from `start_byte` and return
+    /// the end of the words, `end_byte` is the end of the previous word.
+    fn push_wide_word(start_byte: u32, dirty_bytes: u32, end_byte: u32, f: &mut impl FnMut(u32)) -> u32 {
+        let mut bytes_zero = start_byte - end_byte;
+        if bytes_zero >= (1 << 15) {
+            f(OZBC_WIDE_WORDS_FLAG | bytes_zero);
+            bytes_zero = 0;
+        }
+        f((bytes_zero << 16) | dirty_bytes);
+        start_byte + 2
+    }
+
+    /// Return the serialized content of the bitmap in wide words encoding.
+    fn wide_words_raw(&self) -> Vec<u8> {
+        let mut buffer_raw: Vec<u8> = vec![0; mem::size_of::<u32>()];
+        let num_bytes = self.for_each_wide_word(|word| buffer_raw.extend_from_slice(&word.to_ne_bytes()));
+        buffer_raw[0..mem::size_of::<u32>()].copy_from_slice(&(num_bytes | OZBC_WIDE_WORDS_FLAG).to_ne_bytes());
+        buffer_raw
+    }
+
+    /// Read the bitmap from the content `words` in wide words encoding of a bitmap of
+    /// `num_bytes` bytes.
+    fn read_wide_words(&mut self, num_bytes: u32, words: &[u8], check_bitmap: bool) -> Result<(), ()> {
+        if !words.len().is_multiple_of(mem::size_of::<u32>()) {
+            return Err(());
+        }
+        let mut bitmap = OZBCBitmap::new();
+        bitmap.wide_words = true;
+        let mut end_byte: u64 = 0;
+        for word in words.chunks_exact(mem::size_of::<u32>()).map(|w| u32::from_ne_bytes([w[0], w[1], w[2], w[3]])) {
+            if word & OZBC_WIDE_WORDS_FLAG != 0 {
+                end_byte += (word & !OZBC_WIDE_WORDS_FLAG) as u64;
+                continue;
+            }
+            let start_byte = end_byte + (word >> 16) as u64;
+            for (byte, dirty_byte) in [(start_byte, word & 255), (start_byte + 1, (word >> 8) & 255)] {
+                if dirty_byte == 0 {
+                    continue;
+                }
+                if byte >= OZBC_MAX_NUM_BYTES {
+                    return Err(());
+                }
+                bitmap.push_dirty_byte(byte as u32, dirty_byte as u16);
+            }
+            end_byte = start_byte + 2;
+        }
+        if check_bitmap && bitmap.num_bytes != num_bytes {
+            return Err(());
+        }
+        *self = bitmap;
+        Ok(())
+    }
+
+    fn unroll_words<V: OZBCWords + ?Sized>(v: &V) -> Vec<u32> {
+        let num_set: usize = (0..v.num_words()).map(|i| v.word(i))
+            .filter(|word| get_word_type!(*word) == 0)
+            .map(|word| OZBC_BYTE_NUM_SET[get_dirty_byte!(word) as usize] as usize)
+            .sum();
+        let mut scanned_bytes: u32 = 0;
+        let mut unrolled_bitmap: Vec<u32> = Vec::with_capacity(num_set);
+
+        for i in 0..v.num_words() {
+            let word = v.word(i);
+            if get_word_type!(word) as u32 == 0 {
+                let byte = scanned_bytes + (word >> 8) as u32;
+                let dirty_byte = get_dirty_byte!(word) as usize;
+                let pos_byte = byte << 3;
+                let num_set = OZBC_BYTE_NUM_SET[dirty_byte] as usize;
+                unrolled_bitmap.extend(OZBC_BYTE_POSITIONS[dirty_byte][0..num_set].iter().map(|j| pos_byte | *j as u32));
+                scanned_bytes = byte + 1;
+            } else {
+                scanned_bytes += get_bytes_from_word!(1 word as u32);
+            }
+        }
+        unrolled_bitmap
+    }
+
+    fn buffer_raw(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.buffer.as_ptr() as *const u8,
+                self.buffer.len() * mem::size_of::<u16>(),
+            )
+        }
+    }
+
+    /// Return the number of bytes represented from `buffer`, as `u64` so the words of
+    /// an invalid buffer can't overflow it.
+    fn get_buffer_num_bytes(buffer: &[u16]) -> u64 {
         buffer.iter().fold(0, |mut num_bytes, &word| {
             num_bytes += match get_word_type!(word) {
                 0 => get_bytes_from_word!(0(word as u32)),
                 1 => get_bytes_from_word!(1(word as u32)),
                 _ => 0,
-            };
+            } as u64;
             num_bytes
         })
     }
+
+    /// Return `true` if `size` is the size of a serialized bitmap: the header followed
+    /// by a whole number of words.
+    fn is_valid_size(size: usize) -> bool {
+        let header_size = mem::size_of::<u32>();
+        size >= header_size && (size - header_size).is_multiple_of(mem::size_of::<u16>())
+    }
 }
@@ -1,8 +1,50 @@
 use bitrush_index::{
+    AppendedChunk,
+    BitEnum,
+    Bitmap,
     BuildOptions,
     BitmapIndex,
     ChunkSize,
-    OZBCBitmap
+    ChunkCompression,
+    ChunkFormat,
+    ChunkPayload,
+    ColumnCombine,
+    CompositeKey,
+    ContainerIndex,
+    DiskSize,
+    DynBitmapIndex,
+    DynValue,
+    DynValueType,
+    Error,
+    FlushEvent,
+    FlushPolicy,
+    FrozenIndex,
+    GeoGrid,
+    HotTier,
+    IndexWriter,
+    IndexWriterHandle,
+    LowCardinalityIndex,
+    MergePolicy,
+    OfflineBuilder,
+    OZBCBitmap,
+    ORDERED_KEY_FORMAT,
+    PartitionedIndex,
+    QueryProgress,
+    QueryResult,
+    ReaderSession,
+    RemappedIndex,
+    SegmentedIndex,
+    ShardedIndex,
+    ShardStrategy,
+    SharedTail,
+    StorageReader,
+    StorageStats,
+    TokenIndex,
+    Tokenizer,
+    ValueDivergence,
+    is_low_cardinality,
+    StringIndex,
+    StringNormalizer
 };
 use rand::Rng;
 
@@ -49,13 +91,30 @@ fn memory_mode() {
 }
 
 
+#[test]
+fn memory_mode_usize_isize() {
+    let n = (1 << 20) + 1000;
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut u_index = BitmapIndex::<OZBCBitmap, usize>::new(build_options.clone()).unwrap();
+    let mut i_index = BitmapIndex::<OZBCBitmap, isize>::new(build_options).unwrap();
+    let values: Vec<usize> = create_random_number(n).iter().map(|v| (*v % 1000) as usize).collect();
+    let i_values: Vec<isize> = values.iter().map(|v| *v as isize - 500).collect();
+    assert!(u_index.push_values(&values).is_ok());
+    assert!(i_index.push_values(&i_values).is_ok());
+
+    let val_to_find = values[n - 1];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(u_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+    assert_eq!(i_index.run_query(val_to_find as isize - 500, None, None).unwrap(), linear_search_result);
+}
+
 #[test]
 fn storage_mode() {
     let n =  3 * 1000 * 1000;
     
     let build_options = BuildOptions::new(16, ChunkSize::M1);
     let path = std::path::Path::new("test_storage_mode");
-    let b_index_r = BitmapIndex::<OZBCBitmap, u32>::create(&path, build_options);
+    let b_index_r = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options);
     assert!(b_index_r.is_ok());
 
     let mut b_index: BitmapIndex<OZBCBitmap, u32> = b_index_r.unwrap();
@@ -77,7 +136,7 @@ fn storage_mode() {
     assert!(run_query_r.is_ok());
     let values_indexes: Vec<u64> = run_query_r.unwrap();
 
-    let _err = std::fs::remove_dir_all(&path);
+    let _err = std::fs::remove_dir_all(path);
 
     assert_eq!(linear_search_result.len(), values_indexes.len());
 
@@ -85,3 +144,3155 @@ fn storage_mode() {
         assert_eq!(linear_search_result[i], values_indexes[i]);
     }
 }
+
+#[derive(Clone, Debug)]
+struct TaggedBitmap(OZBCBitmap);
+
+impl std::ops::BitAnd for &TaggedBitmap {
+    type Output = TaggedBitmap;
+
+    fn bitand(self, b2: Self) -> TaggedBitmap {
+        TaggedBitmap(&self.0 & &b2.0)
+    }
+}
+
+impl Bitmap for TaggedBitmap {
+    fn new() -> Self { TaggedBitmap(OZBCBitmap::new()) }
+    fn format_tag() -> u32 { 1 }
+    fn set(&mut self, i: u32) { self.0.set(i) }
+    fn unroll_bitmap(&self) -> Vec<u32> { self.0.unroll_bitmap() }
+    fn size(&self) -> usize { self.0.size() }
+    fn write_to_buffer(&self, buffer_out: &mut [u8]) -> Result<usize, ()> { self.0.write_to_buffer(buffer_out) }
+    fn read_from_buffer(&mut self, buffer_in: &[u8], check_bitmap: bool) -> Result<(), ()> { self.0.read_from_buffer(buffer_in, check_bitmap) }
+}
+
+#[test]
+fn storage_mode_open_checks() {
+    let n = 1 << 20;
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_open_checks");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n);
+    assert!(b_index.push_values(&values).is_ok());
+    drop(b_index);
+
+    let wrong_value = BitmapIndex::<OZBCBitmap, u64>::open(path);
+    let wrong_bitmap = BitmapIndex::<TaggedBitmap, u32>::open(path);
+    let same_size_value = BitmapIndex::<OZBCBitmap, i32>::open(path);
+    let b_index_r = BitmapIndex::<OZBCBitmap, u32>::open(path);
+
+    let _err = std::fs::remove_dir_all(path);
+
+    assert!(matches!(wrong_value, Err(Error::MetaDataError)));
+    assert!(matches!(wrong_bitmap, Err(Error::MetaDataError)));
+    assert!(same_size_value.is_ok());
+    let mut b_index = b_index_r.unwrap();
+    let val_to_find = values[0];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[test]
+fn storage_reader() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_reader");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    assert!(b_index.push_values(&values).is_ok());
+
+    let reader_r = StorageReader::<OZBCBitmap, u16>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    let mut reader = reader_r.unwrap();
+
+    assert_eq!(reader.len(), 1 << 20);
+    let val_to_find = values[7];
+    let (start_index, end_index) = (1000, (1 << 20) - 5000);
+    let linear_search_result: Vec<u64> = values.iter().enumerate()
+        .filter(|(i, v)| **v == val_to_find && *i >= start_index && *i <= end_index)
+        .map(|(i, _v)| i as u64).collect();
+
+    let values_indexes = reader.query(val_to_find, Some(start_index as u64), Some(end_index as u64)).unwrap();
+    assert_eq!(linear_search_result, values_indexes);
+    let count = reader.query_count(val_to_find, Some(start_index as u64), Some(end_index as u64)).unwrap();
+    assert_eq!(linear_search_result.len() as u64, count);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn memory_mode_parallel_query() {
+    let n = (2 << 20) + 1000;
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    let thread_pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+    b_index.set_thread_pool(std::sync::Arc::new(thread_pool));
+
+    let values: Vec<u32> = create_random_number(n);
+    assert!(b_index.push_values(&values).is_ok());
+
+    let val_to_find = values[n - 1];
+    let values_indexes = b_index.run_query(val_to_find, None, None).unwrap();
+    let values_indexes_par = b_index.run_query_par(val_to_find, None, None).unwrap();
+    assert_eq!(values_indexes, values_indexes_par);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn memory_mode_parallel_capped_query() {
+    let n = (1 << 20) + 5000;
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v % 10).collect();
+    assert!(b_index.push_values(&values).is_ok());
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == values[0]).map(|(i, _v)| i as u64).collect();
+
+    b_index.set_max_query_results(Some(10));
+    assert!(matches!(b_index.run_query_par(values[0], None, None), Err(Error::TooManyResults(10))));
+    // every chunk is under the limit, the total is over.
+    let max_query_results = linear_search_result.len() as u64 - 1;
+    b_index.set_max_query_results(Some(max_query_results));
+    assert!(matches!(b_index.run_query_par(values[0], None, None), Err(Error::TooManyResults(m)) if m == max_query_results));
+    b_index.set_max_query_results(Some(linear_search_result.len() as u64));
+    assert_eq!(b_index.run_query_par(values[0], None, None).unwrap(), linear_search_result);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn storage_reader_query_stream() {
+    use futures_core::Stream;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct ThreadWaker(std::thread::Thread);
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let n = (1 << 21) + 10;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_reader_query_stream");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u8>::create(path, build_options).unwrap();
+    let values: Vec<u8> = create_random_number(n).iter().map(|v| *v as u8).collect();
+    assert!(b_index.push_values(&values).is_ok());
+
+    let reader_r = StorageReader::<OZBCBitmap, u8>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    let mut reader = reader_r.unwrap();
+
+    let val_to_find = values[0];
+    let values_indexes = reader.query(val_to_find, Some(10), None).unwrap();
+
+    let mut stream = reader.query_stream(val_to_find, Some(10), None);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut stream_indexes: Vec<u64> = Vec::new();
+    loop {
+        match std::pin::Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(index)) => stream_indexes.push(index.unwrap()),
+            Poll::Ready(None) => break,
+            Poll::Pending => std::thread::park()
+        }
+    }
+    drop(stream);
+    assert_eq!(values_indexes, stream_indexes);
+
+    // a stream dropped before its end stops its worker.
+    let mut stream = reader.query_stream(val_to_find, None, None);
+    let first_r = std::pin::Pin::new(&mut stream).poll_next(&mut cx);
+    drop(stream);
+    assert!(!matches!(first_r, Poll::Ready(Some(Err(_)))));
+    assert_eq!(reader.query(val_to_find, Some(10), None).unwrap(), values_indexes);
+}
+
+#[test]
+fn index_writer_flush_policy() {
+    let n = 100 * 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_index_writer_flush_policy");
+    let b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let flush_policy = FlushPolicy::chunk_full().with_max_memory_bytes(1024);
+    let mut writer = IndexWriter::new(b_index, flush_policy).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    assert_eq!(writer.extend(values.iter().cloned()).unwrap(), n as u64);
+    let unflushed_values = writer.unflushed_values();
+    assert!(unflushed_values < 1 << 12);
+    drop(writer);
+
+    let b_index_r = BitmapIndex::<OZBCBitmap, u16>::open(path);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for v in values.iter() {
+        sender.send(*v).unwrap();
+    }
+    drop(sender);
+    let mut writer = IndexWriter::new(b_index_r.unwrap(), FlushPolicy::chunk_full()).unwrap();
+    let num_flushed_values = writer.index().num_values();
+    assert_eq!(num_flushed_values, (n as u64) - unflushed_values);
+    assert_eq!(writer.consume(&receiver).unwrap(), n as u64);
+    drop(writer.finish().unwrap());
+
+    let b_index_r = BitmapIndex::<OZBCBitmap, u16>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    let mut b_index = b_index_r.unwrap();
+
+    let all_values: Vec<u16> = values[0..(num_flushed_values as usize)].iter().chain(values.iter()).cloned().collect();
+    let val_to_find = values[0];
+    let linear_search_result: Vec<u64> = all_values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[cfg(feature = "csv")]
+#[test]
+fn build_from_csv() {
+    let n = 10 * 1000;
+
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let mut csv_content = String::from("id,value\n");
+    for (i, v) in values.iter().enumerate() {
+        csv_content.push_str(&format!("{},{}\n", i, v));
+    }
+    let path = std::path::Path::new("test_build_from_csv.csv");
+    std::fs::write(path, csv_content).unwrap();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let b_index_r = BitmapIndex::<OZBCBitmap, u16>::build_from_csv(path, "value", build_options.clone());
+    let wrong_column = BitmapIndex::<OZBCBitmap, u16>::build_from_csv(path, "values", build_options.clone());
+    let wrong_type = BitmapIndex::<OZBCBitmap, u8>::build_from_csv(path, "id", BuildOptions::new(8, ChunkSize::M1));
+    let _err = std::fs::remove_file(path);
+
+    assert!(matches!(wrong_column, Err(Error::ParametersError)));
+    assert!(matches!(wrong_type, Err(Error::FileError(_))));
+    let mut b_index = b_index_r.unwrap();
+    assert_eq!(b_index.num_values(), n as u64);
+    let val_to_find = values[0];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn build_from_arrow_ipc() {
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let n = 10 * 1000;
+
+    let values: Vec<u32> = create_random_number(n);
+    let schema = Arc::new(Schema::new(vec![Field::new("value", DataType::Int64, false)]));
+    let path = std::path::Path::new("test_build_from_arrow_ipc.arrow");
+    let file = std::fs::File::create(path).unwrap();
+    let mut writer = arrow_ipc::writer::FileWriter::try_new(file, &schema).unwrap();
+    for batch_values in values.chunks(1000) {
+        let array = Int64Array::from_iter_values(batch_values.iter().map(|v| *v as i64));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(array)]).unwrap();
+        writer.write(&batch).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let b_index_r = BitmapIndex::<OZBCBitmap, u32>::build_from_arrow_ipc(path, "value", build_options);
+    let _err = std::fs::remove_file(path);
+
+    let mut b_index = b_index_r.unwrap();
+    assert_eq!(b_index.num_values(), n as u64);
+    let val_to_find = values[0];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[test]
+fn storage_mode_user_meta_data() {
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_user_meta_data");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u8>::create(path, build_options).unwrap();
+    assert!(b_index.set_user_meta_data("key_0", b"value_0").is_ok());
+    assert!(b_index.set_user_meta_data("key_1", b"").is_ok());
+    assert!(b_index.set_user_meta_data("key_0", b"value_1").is_ok());
+    drop(b_index);
+
+    let b_index_r = BitmapIndex::<OZBCBitmap, u8>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    let b_index = b_index_r.unwrap();
+    assert_eq!(b_index.user_meta_data("key_0"), Some(&b"value_1"[..]));
+    assert_eq!(b_index.user_meta_data("key_1"), Some(&b""[..]));
+    assert_eq!(b_index.user_meta_data("key_2"), None);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn build_from_parquet() {
+    use arrow_array::{RecordBatch, UInt32Array, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+    use std::sync::Arc;
+
+    let n = 10 * 1000;
+
+    let values: Vec<u32> = create_random_number(n);
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("value", DataType::UInt32, false)
+    ]));
+    let ids = UInt64Array::from_iter_values(0..(n as u64));
+    let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(ids), Arc::new(UInt32Array::from(values.clone()))]).unwrap();
+    let path = std::path::Path::new("test_build_from_parquet.parquet");
+    let file = std::fs::File::create(path).unwrap();
+    let properties = WriterProperties::builder().set_max_row_group_row_count(Some(4000)).build();
+    let mut writer = ArrowWriter::try_new(file, schema, Some(properties)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let b_index_r = BitmapIndex::<OZBCBitmap, u32>::build_from_parquet(path, "value", build_options);
+    let _err = std::fs::remove_file(path);
+
+    let mut b_index = b_index_r.unwrap();
+    assert_eq!(b_index.num_values(), n as u64);
+    assert_eq!(b_index.parquet_row_groups(), vec![0..4000, 4000..8000, 8000..10000]);
+    let val_to_find = values[0];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[cfg(feature = "polars")]
+#[test]
+fn memory_mode_polars_results() {
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u8>::new(build_options).unwrap();
+    let values: Vec<u8> = (0..1500000).map(|i| (i % 7) as u8).collect();
+    assert!(b_index.push_values(&values).is_ok());
+
+    let values_indexes = b_index.run_query(3, None, None).unwrap();
+    let polars_indexes = b_index.run_query_polars("idx", 3, None, None).unwrap();
+    assert_eq!(polars_indexes.name().as_str(), "idx");
+    let polars_indexes: Vec<u64> = polars_indexes.into_no_null_iter().collect();
+    assert_eq!(values_indexes, polars_indexes);
+
+    let mask = b_index.run_query_polars_mask("mask", 3).unwrap();
+    assert_eq!(mask.len(), values.len());
+    assert_eq!(mask.sum(), Some(values_indexes.len() as u32));
+    let mask_values: Vec<bool> = (0..mask.len()).map(|i| mask.get(i).unwrap()).collect();
+    let expected: Vec<bool> = values.iter().map(|v| *v == 3).collect();
+    assert_eq!(mask_values, expected);
+}
+
+#[test]
+fn storage_mode_query_explain() {
+    let n = (2 << 20) + 1000;
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_query_explain");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n);
+    let push_r = b_index.push_values(&values);
+
+    let val_to_find = values[n - 1];
+    let start_index = Some(1 << 20);
+    let query_r = b_index.run_query(val_to_find, start_index, None);
+    let explain_r = b_index.run_query_explain(val_to_find, start_index, None);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let (values_indexes, explain) = explain_r.unwrap();
+    assert_eq!(values_indexes, query_r.unwrap());
+    assert_eq!(explain.chunks.len(), 3);
+    assert!(!explain.chunks[0].scanned);
+    assert!(explain.chunks[1].scanned && explain.chunks[1].from_storage);
+    assert!(explain.chunks[2].scanned && !explain.chunks[2].from_storage);
+    assert_eq!(explain.num_scanned_chunks(), 2);
+    assert_eq!(explain.bytes_read(), explain.chunks[1].bytes_read);
+    assert_eq!(explain.chunks[1].bitmap_sizes.len(), explain.query_i_bitmaps.len());
+    let num_indexes: u64 = explain.chunks.iter().map(|chunk| chunk.num_indexes).sum();
+    assert_eq!(num_indexes, values_indexes.len() as u64);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn storage_reader_query_mmap() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_reader_query_mmap");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| *v & 0xfffff).collect();
+    let push_r = b_index.push_values(&values);
+
+    let reader_r = StorageReader::<OZBCBitmap, u32>::open(path);
+    let mut reader = reader_r.unwrap();
+    let val_to_find = values[7];
+    let values_indexes = reader.query(val_to_find, Some(5), None);
+    let values_indexes_mmap = reader.query_mmap(val_to_find, Some(5), None);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let values_indexes = values_indexes.unwrap();
+    assert!(values_indexes.contains(&7));
+    assert_eq!(values_indexes, values_indexes_mmap.unwrap());
+}
+
+#[cfg(feature = "bitvec")]
+#[test]
+fn storage_mode_bitvec_bitmap() {
+    use bitrush_index::BitVecBitmap;
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(4, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_bitvec_bitmap");
+    let mut b_index = BitmapIndex::<BitVecBitmap, u8>::create(path, build_options.clone()).unwrap();
+    let mut b_index_ozbc = BitmapIndex::<OZBCBitmap, u8>::new(build_options).unwrap();
+    let values: Vec<u8> = create_random_number(n).iter().map(|v| *v as u8).collect();
+    let push_r = b_index.push_values(&values);
+    assert!(b_index_ozbc.push_values(&values).is_ok());
+
+    let val_to_find = values[n - 1];
+    let values_indexes = b_index.run_query(val_to_find, Some(5), None);
+    let reader_r = StorageReader::<BitVecBitmap, u8>::open(path);
+    let reader_ozbc_r = StorageReader::<OZBCBitmap, u8>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert!(reader_ozbc_r.is_err());
+
+    let values_indexes = values_indexes.unwrap();
+    assert!(values_indexes.contains(&(n as u64 - 1)));
+    assert_eq!(values_indexes, b_index_ozbc.run_query(val_to_find, Some(5), None).unwrap());
+    let reader_indexes = reader_r.unwrap().query(val_to_find, None, None).unwrap();
+    assert_eq!(reader_indexes, b_index_ozbc.run_query(val_to_find, None, Some((1 << 20) - 1)).unwrap());
+}
+
+#[test]
+fn memory_mode_chunk_arena() {
+    let n = (2 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::new(build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    assert!(b_index.push_values(&values[0..(1 << 20) + 5]).is_ok());
+    assert!(b_index.use_chunk_arena().is_ok());
+    assert!(b_index.chunk_arena_size() > 0);
+    assert!(b_index.push_values(&values[(1 << 20) + 5..]).is_ok());
+
+    let val_to_find = values[n - 1];
+    let (start_index, end_index) = (1000, (1 << 21) + 500);
+    let linear_search_result: Vec<u64> = values.iter().enumerate()
+        .filter(|(i, v)| **v == val_to_find && *i >= start_index && *i <= end_index)
+        .map(|(i, _v)| i as u64).collect();
+    let values_indexes = b_index.run_query(val_to_find, Some(start_index as u64), Some(end_index as u64)).unwrap();
+    assert_eq!(linear_search_result, values_indexes);
+
+    let (explain_indexes, explain) = b_index.run_query_explain(val_to_find, None, None).unwrap();
+    assert_eq!(explain.chunks.len(), 3);
+    assert_eq!(explain_indexes, b_index.run_query(val_to_find, None, None).unwrap());
+}
+
+#[test]
+fn memory_mode_spill_to_disk() {
+    let n = (3 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let spill_path = std::path::Path::new("test_memory_mode_spill_to_disk");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::new(build_options.clone()).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values[0..(2 << 20) + 5]);
+    let chunk_size = b_index.resident_chunks_size() / 2;
+    // one full chunk stays in memory, as bitmaps and then as chunk arena.
+    let spill_r = b_index.use_spill_to_disk(spill_path, chunk_size + chunk_size / 2);
+    let spilled_after_use = b_index.spilled_chunks();
+    let arena_r = b_index.use_chunk_arena().and_then(|_| b_index.push_values(&values[(2 << 20) + 5..]));
+    let coarse_r = b_index.use_coarse_layer();
+    let spill_dir_exists = spill_path.exists();
+    let spill_again_r = b_index.use_spill_to_disk(std::path::Path::new("test_memory_mode_spill_to_disk_2"), 0);
+
+    let val_to_find = values[n - 1];
+    let (start_index, end_index) = (1000, (2 << 20) + 5000);
+    let query_r = b_index.run_query(val_to_find, Some(start_index as u64), Some(end_index as u64));
+    let fetch_r = b_index.fetch_bitmaps(0, val_to_find);
+    let spilled_chunks = b_index.spilled_chunks();
+    let resident_chunks_size = b_index.resident_chunks_size();
+    drop(b_index);
+    let spill_dir_removed = !spill_path.exists();
+
+    let path = std::path::Path::new("test_memory_mode_spill_to_disk_storage");
+    let storage_r = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options)
+        .and_then(|mut s_index| s_index.use_spill_to_disk(spill_path, 0));
+    let _err = std::fs::remove_dir_all(path);
+    let _err = std::fs::remove_dir_all(spill_path);
+
+    assert!(push_r.is_ok() && spill_r.is_ok() && arena_r.is_ok() && coarse_r.is_ok());
+    assert_eq!(spilled_after_use, 1);
+    assert!(spill_dir_exists && spill_dir_removed);
+    assert!(matches!(spill_again_r, Err(Error::ParametersError)));
+    assert!(matches!(storage_r, Err(Error::ParametersError)));
+    assert_eq!(spilled_chunks, 2);
+    assert!(resident_chunks_size > 0 && resident_chunks_size <= chunk_size + chunk_size / 2);
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate()
+        .filter(|(i, v)| **v == val_to_find && *i >= start_index && *i <= end_index)
+        .map(|(i, _v)| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    let first_chunk_indexes: Vec<u64> = values[0..(1 << 20)].iter().enumerate()
+        .filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    let fetched = fetch_r.unwrap();
+    let chunk_indexes: Vec<u64> = (&fetched[0] & &fetched[1]).unroll_bitmap().iter().map(|i| *i as u64).collect();
+    assert_eq!(chunk_indexes, first_chunk_indexes);
+}
+
+#[test]
+fn storage_mode_chunk_format() {
+    let n = 3 * (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_chunk_format");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v & 0x0fff0fff).collect();
+    // Chunks in Plain, DeltaOffsets and SkipEmpty format, the last partial chunk
+    // has few non empty bitmaps and is written in Sparse format.
+    let mut push_r = b_index.push_values(&values[0..(1 << 20)]);
+    b_index.set_chunk_format(ChunkFormat::DeltaOffsets);
+    push_r = push_r.and(b_index.push_values(&values[(1 << 20)..(1 << 21)]));
+    b_index.set_chunk_format(ChunkFormat::SkipEmpty);
+    push_r = push_r.and(b_index.push_values(&values[(1 << 21)..]));
+    let flush_r = b_index.flush_chunk();
+    drop(b_index);
+
+    let val_to_find = values[n - 1];
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path);
+    let query_r = open_r.map(|mut b_index| b_index.run_query(val_to_find, None, None));
+    let reader_query_r = StorageReader::<OZBCBitmap, u32>::open(path)
+        .map(|mut reader| reader.query(val_to_find, None, None));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && flush_r.is_ok());
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate()
+        .filter(|(_i, v)| **v == val_to_find)
+        .map(|(i, _v)| i as u64).collect();
+    assert_eq!(query_r.unwrap().unwrap(), linear_search_result);
+    let reader_indexes = reader_query_r.unwrap().unwrap();
+    assert_eq!(reader_indexes, linear_search_result.into_iter().filter(|i| *i < 3 * (1 << 20)).collect::<Vec<u64>>());
+}
+
+/// Return the first u32 of the chunk at `extent` of the data file `data_path`, that is
+/// the chunk header with its format for the formats with a header.
+fn chunk_header_word(data_path: &std::path::Path, extent: std::ops::Range<u64>) -> u32 {
+    let data = std::fs::read(data_path).unwrap();
+    let start = extent.start as usize;
+    u32::from_ne_bytes([data[start], data[start + 1], data[start + 2], data[start + 3]])
+}
+
+#[test]
+fn storage_mode_skip_empty_chunk_format() {
+    let n = 3 * (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_skip_empty_chunk_format");
+    let data_path = path.join("test_storage_mode_skip_empty_chunk_format.dbidx");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    b_index.set_chunk_format(ChunkFormat::SkipEmpty);
+    // Only 4096 values of each block are used, so most bitmaps are empty and are
+    // flagged in the presence bitmap, but not enough for the Sparse format.
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v & 0x0fff0fff).collect();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    // The chunk 1 has only empty bitmaps.
+    let rewrite_r = b_index.rewrite_chunk(1, |bitmaps| bitmaps.iter_mut().for_each(|b| b.clear()));
+    let extent_r = b_index.chunk_disk_extent(0);
+    drop(b_index);
+    let header = extent_r.ok().flatten().map(|extent| chunk_header_word(&data_path, extent));
+
+    let vals_to_find = [values[0], values[(1 << 20) + 1], values[n - 1], 0xf000_0000];
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path).and_then(|mut b_index| {
+        let mut results: Vec<Vec<u64>> = Vec::new();
+        for val in vals_to_find.iter() {
+            results.push(b_index.run_query(*val, None, None)?);
+        }
+        results.push(b_index.run_query(values[n - 1], Some(1 << 20), Some((2 << 20) + 999))?);
+        Ok(results)
+    });
+    let reader_r = StorageReader::<OZBCBitmap, u32>::open(path).and_then(|mut reader| {
+        vals_to_find.iter().map(|val| reader.query(*val, None, None)).collect::<Result<Vec<Vec<u64>>, Error>>()
+    });
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && rewrite_r.is_ok());
+    assert_eq!(header, Some((1 << 31) | 2));
+
+    let linear_search = |val: u32, start: usize, end: usize| -> Vec<u64> {
+        (start..end).filter(|i| values[*i] == val && !((1 << 20)..(2 << 20)).contains(i)).map(|i| i as u64).collect()
+    };
+    let results = open_r.unwrap();
+    let reader_results = reader_r.unwrap();
+    for (i, val) in vals_to_find.iter().enumerate() {
+        assert_eq!(results[i], linear_search(*val, 0, n));
+        assert_eq!(reader_results[i], linear_search(*val, 0, 3 << 20));
+    }
+    assert!(results[3].is_empty());
+    assert_eq!(results[4], linear_search(values[n - 1], 1 << 20, (2 << 20) + 1000));
+}
+
+#[test]
+fn storage_mode_sparse_chunk_format_threshold() {
+    // With 2 blocks of 65536 bitmaps a chunk is Sparse with less than 2048 non-empty
+    // bitmaps: 1024 values of the low block and `high_values` of the high one.
+    let chunk_values = |high_values: u32| -> Vec<u32> {
+        (0..(1u32 << 20)).map(|i| (i % 1024) | ((i % high_values) << 16)).collect()
+    };
+    let mut values: Vec<u32> = chunk_values(1023);
+    values.extend(chunk_values(1024));
+    values.extend(chunk_values(1023).iter().take(5000));
+    let n = values.len();
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_sparse_chunk_format_threshold");
+    let data_path = path.join("test_storage_mode_sparse_chunk_format_threshold.dbidx");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    let extents_r: Result<Vec<_>, Error> = (0..3).map(|chunk_id| b_index.chunk_disk_extent(chunk_id)).collect();
+    drop(b_index);
+    let headers: Vec<u32> = extents_r.unwrap().into_iter()
+        .map(|extent| chunk_header_word(&data_path, extent.unwrap())).collect();
+
+    let vals_to_find = [values[1023], values[(1 << 20) + 2047], values[n - 1], 1023 | (1023 << 16)];
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path).and_then(|mut b_index| {
+        vals_to_find.iter().map(|val| b_index.run_query(*val, None, None)).collect::<Result<Vec<Vec<u64>>, Error>>()
+    });
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    // Sparse, Plain (without a header) and Sparse again for the last partial chunk.
+    assert_eq!(headers[0], (1 << 31) | 3);
+    assert_eq!(headers[1] & (1 << 31), 0);
+    assert_eq!(headers[2], (1 << 31) | 3);
+    for (indexes, val) in open_r.unwrap().iter().zip(vals_to_find.iter()) {
+        let linear_search_result: Vec<u64> = (0..n).filter(|i| values[*i] == *val).map(|i| i as u64).collect();
+        assert!(!linear_search_result.is_empty());
+        assert_eq!(*indexes, linear_search_result);
+    }
+}
+
+#[test]
+fn storage_mode_bitmap_capacity() {
+    let n = 1500 * 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1).with_expected_density(1.0 / 256.0);
+    let path = std::path::Path::new("test_storage_mode_bitmap_capacity");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n);
+    let push_r = b_index.push_values(&values[..(n / 2)]).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path);
+    let query_r = open_r.and_then(|mut b_index| {
+        b_index.push_values(&values[(n / 2)..])?;
+        b_index.run_query(values[n - 1], None, None)
+    });
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == values[n - 1]).map(|(i, _v)| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+}
+
+#[test]
+fn run_query_grouped() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<u32> = create_random_number(n);
+    let probe_values = [values[0], values[n / 2], values[n - 1], values[n - 1], 0];
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let mut m_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options.clone()).unwrap();
+    assert!(m_index.push_values(&values[..(1 << 20)]).is_ok());
+    assert!(m_index.use_chunk_arena().is_ok());
+    assert!(m_index.push_values(&values[(1 << 20)..]).is_ok());
+
+    let path = std::path::Path::new("test_run_query_grouped");
+    let mut s_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let push_r = s_index.push_values(&values);
+    let s_grouped_r = s_index.run_query_grouped(&probe_values);
+    let s_queries_r: Result<Vec<Vec<u64>>, Error> = probe_values.iter()
+        .map(|value| s_index.run_query(*value, None, None)).collect();
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let m_grouped = m_index.run_query_grouped(&probe_values).unwrap();
+    let s_grouped = s_grouped_r.unwrap();
+    let s_queries = s_queries_r.unwrap();
+    assert_eq!(m_grouped.len(), probe_values.len());
+    for (i, value) in probe_values.iter().enumerate() {
+        let values_indexes = m_index.run_query(*value, None, None).unwrap();
+        assert!(i == probe_values.len() - 1 || !values_indexes.is_empty());
+        assert_eq!(m_grouped[i], (*value, values_indexes));
+        assert_eq!(s_grouped[i], (*value, s_queries[i].clone()));
+    }
+    assert!(m_index.run_query_grouped(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn run_query_and() {
+    let n = (1 << 20) + 1000;
+    let values_a: Vec<u32> = create_random_number(n).iter().map(|v| v & 0xff).collect();
+    let values_b: Vec<u16> = create_random_number(n).iter().map(|v| (v & 0x0f) as u16).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut a_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options.clone()).unwrap();
+    assert!(a_index.push_values(&values_a).is_ok());
+
+    let path = std::path::Path::new("test_run_query_and");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let push_r = b_index.push_values(&values_b);
+    let (value_a, value_b) = (values_a[n - 1], values_b[n - 1]);
+    let start_index = Some(1000);
+    let query_r = a_index.run_query_and(value_a, &mut b_index, value_b, start_index, None);
+    let other_query_r = b_index.run_query_and(value_b, &mut a_index, value_a, start_index, None);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let linear_search_result: Vec<u64> = (1000..n)
+        .filter(|i| values_a[*i] == value_a && values_b[*i] == value_b)
+        .map(|i| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    assert_eq!(other_query_r.unwrap(), linear_search_result);
+
+    let mut c_index = BitmapIndex::<OZBCBitmap, u32>::new(BuildOptions::new(8, ChunkSize::M2)).unwrap();
+    assert!(matches!(a_index.run_query_and(value_a, &mut c_index, 0, None, None), Err(Error::ParametersError)));
+}
+
+#[test]
+fn run_query_and_not() {
+    let n = (1 << 20) + 2000;
+    let mut values_a: Vec<u32> = create_random_number(n).iter().map(|v| v & 0xff).collect();
+    // The first row not indexed from b_index must not be returned.
+    values_a[n - 1000] = values_a[n - 1];
+    let values_b: Vec<u16> = create_random_number(n).iter().map(|v| (v & 0x0f) as u16).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut a_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options.clone()).unwrap();
+    assert!(a_index.push_values(&values_a).is_ok());
+
+    let path = std::path::Path::new("test_run_query_and_not");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let push_r = b_index.push_values(&values_b[..(n - 1000)]);
+    let (value_a, value_b) = (values_a[n - 1], values_b[n - 1]);
+    let query_r = a_index.run_query_and_not(value_a, &mut b_index, value_b, Some(1000), None);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    // Only the rows indexed from both indexes are queried.
+    let linear_search_result: Vec<u64> = (1000..(n - 1000))
+        .filter(|i| values_a[*i] == value_a && values_b[*i] != value_b)
+        .map(|i| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+}
+
+#[test]
+fn run_query_with_selection() {
+    let n = (1 << 20) + 1000;
+    let values_a: Vec<u32> = create_random_number(n).iter().map(|v| v & 0x0f).collect();
+    let values_b: Vec<u32> = create_random_number(n).iter().map(|v| v & 0xff).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut a_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options.clone()).unwrap();
+    assert!(a_index.push_values(&values_a).is_ok());
+    let (value_a, value_b) = (values_a[n - 1], values_b[n - 1]);
+    let selection = a_index.query_selection(value_a).unwrap();
+    let a_indexes = a_index.run_query(value_a, None, None).unwrap();
+    let selection_from_indexes = a_index.selection_from_indexes(&a_indexes);
+    assert_eq!(selection.len(), 2);
+    assert_eq!(selection_from_indexes, selection);
+
+    let path = std::path::Path::new("test_run_query_with_selection");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let push_r = b_index.push_values(&values_b);
+    let query_r = b_index.run_query_with_selection(value_b, &selection, Some(1000), None);
+    let first_chunk_r = b_index.run_query_with_selection(value_b, &selection[..1], None, None);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let linear_search_result: Vec<u64> = (0..n)
+        .filter(|i| values_a[*i] == value_a && values_b[*i] == value_b)
+        .map(|i| i as u64).collect();
+    let query_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i >= 1000).collect();
+    let first_chunk_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i < (1 << 20)).collect();
+    assert_eq!(query_r.unwrap(), query_result);
+    assert_eq!(first_chunk_r.unwrap(), first_chunk_result);
+}
+
+#[test]
+fn storage_mode_query_result_file() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v & 0xffff).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_query_result_file");
+    let result_path = std::path::Path::new("test_storage_mode_query_result_file.qbidx");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let push_r = b_index.push_values(&values);
+    let val_to_find = values[n - 1];
+    let query_r = b_index.run_query(val_to_find, None, None);
+    let save_r = b_index.save_query_result(val_to_find, result_path);
+    let load_r = b_index.load_query_result(val_to_find, result_path);
+    let other_value_r = b_index.load_query_result(val_to_find + 1, result_path);
+    let stale_r = b_index.push_value(0).and_then(|_| b_index.load_query_result(val_to_find, result_path));
+    let _err = std::fs::remove_dir_all(path);
+    let _err = std::fs::remove_file(result_path);
+    assert!(push_r.is_ok());
+
+    let selection = load_r.unwrap();
+    assert_eq!(selection, save_r.unwrap());
+    assert_eq!(b_index.selection_indexes(&selection), query_r.unwrap());
+    assert!(matches!(other_value_r, Err(Error::MetaDataError)));
+    assert!(matches!(stale_r, Err(Error::MetaDataError)));
+}
+
+#[test]
+fn fetch_bitmaps() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v & 0xffff).collect();
+    let val_to_find = values[n - 1];
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut m_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options.clone()).unwrap();
+    assert!(m_index.push_values(&values[..(1 << 20)]).is_ok());
+    assert!(m_index.use_chunk_arena().is_ok());
+    assert!(m_index.push_values(&values[(1 << 20)..]).is_ok());
+
+    let path = std::path::Path::new("test_fetch_bitmaps");
+    let mut s_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let push_r = s_index.push_values(&values);
+    let s_chunks_r: Result<Vec<Vec<OZBCBitmap>>, Error> = (0..2).map(|chunk_id| s_index.fetch_bitmaps(chunk_id, val_to_find)).collect();
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let s_chunks = s_chunks_r.unwrap();
+    let mut values_indexes: Vec<u64> = Vec::new();
+    for chunk_id in 0..2 {
+        let bitmaps = m_index.fetch_bitmaps(chunk_id, val_to_find).unwrap();
+        assert_eq!(bitmaps.len(), 4);
+        assert_eq!(bitmaps, s_chunks[chunk_id as usize]);
+        let b_result = bitmaps.iter().skip(1).fold(bitmaps[0].clone(), |b0, b1| &b0 & b1);
+        values_indexes.extend(b_result.unroll_bitmap().iter().map(|idx| (chunk_id << 20) + *idx as u64));
+    }
+    assert_eq!(values_indexes, m_index.run_query(val_to_find, None, None).unwrap());
+    assert!(matches!(m_index.fetch_bitmaps(2, val_to_find), Err(Error::ParametersError)));
+}
+
+#[test]
+fn chunk_introspection() {
+    let n = (2 << 20) + 1000;
+    let values: Vec<u32> = create_random_number(n);
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_chunk_introspection");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options.clone()).unwrap();
+    let push_r = b_index.push_values(&values);
+    let extents_r: Result<Vec<_>, Error> = (0..4).map(|chunk_id| b_index.chunk_disk_extent(chunk_id)).collect();
+    let flush_r = b_index.flush_chunk().and_then(|_| b_index.chunk_disk_extent(2));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    assert_eq!(b_index.chunk_count(), 3);
+    assert_eq!(b_index.chunk_boundaries(), vec![0..(1 << 20), (1 << 20)..(2 << 20), (2 << 20)..(n as u64)]);
+    let extents = extents_r.unwrap();
+    let extent_0 = extents[0].clone().unwrap();
+    let extent_1 = extents[1].clone().unwrap();
+    assert_eq!(extent_0.start, 0);
+    assert_eq!(extent_0.end, extent_1.start);
+    assert!(extent_1.end > extent_1.start);
+    assert!(extents[2].is_none() && extents[3].is_none());
+    assert_eq!(flush_r.unwrap().unwrap().start, extent_1.end);
+
+    let mut m_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    assert_eq!(m_index.chunk_count(), 0);
+    assert!(m_index.chunk_boundaries().is_empty());
+    assert!(m_index.chunk_disk_extent(0).unwrap().is_none());
+}
+
+#[test]
+fn storage_mode_dirty_state() {
+    let values: Vec<u32> = create_random_number((1 << 20) + 5);
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_dirty_state");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options.clone()).unwrap();
+    assert!(!b_index.is_dirty());
+    let push_r = b_index.push_values(&values[..1000]);
+    let unflushed_values = b_index.unflushed_values();
+    let flush_r = b_index.flush_chunk();
+    let flushed_dirty = b_index.is_dirty();
+    // A full chunk is flushed automatically.
+    let push_chunk_r = b_index.push_values(&values[1000..]);
+    let chunk_unflushed_values = b_index.unflushed_values();
+    drop(b_index);
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && flush_r.is_ok() && push_chunk_r.is_ok());
+
+    assert_eq!(unflushed_values, 1000);
+    assert!(!flushed_dirty);
+    assert_eq!(chunk_unflushed_values, 5);
+    let b_index = open_r.unwrap();
+    assert_eq!(b_index.num_values(), 1 << 20);
+    assert!(!b_index.is_dirty());
+
+    let mut m_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    assert!(m_index.push_values(&values[..1000]).is_ok());
+    assert_eq!(m_index.unflushed_values(), 1000);
+}
+
+#[test]
+fn storage_mode_snapshot_metadata() {
+    let values: Vec<u32> = create_random_number(2000);
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_snapshot_metadata");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options.clone()).unwrap();
+    let push_r = b_index.push_values(&values[..1000]).and_then(|_| b_index.flush_chunk())
+        .and_then(|_| b_index.push_values(&values[1000..]));
+    let (meta_data, last_checkpoint) = b_index.snapshot_metadata();
+    drop(b_index);
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    assert_eq!(meta_data.num_values(), 2000);
+    assert_eq!(meta_data.build_options().bit_block_size(), 16);
+    assert_eq!(meta_data.build_options().chunk_size(), 1 << 20);
+    assert_eq!(meta_data.value_size(), 4);
+    assert_eq!(meta_data.bitmap_tag(), OZBCBitmap::format_tag());
+    assert_eq!(last_checkpoint.unwrap().num_values(), 1000);
+    let (meta_data, last_checkpoint) = open_r.unwrap().snapshot_metadata();
+    assert_eq!(meta_data.num_values(), 1000);
+    assert_eq!(last_checkpoint.unwrap().num_values(), 1000);
+
+    let m_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    assert!(m_index.snapshot_metadata().1.is_none());
+}
+
+#[test]
+fn storage_mode_recover() {
+    let values: Vec<u32> = create_random_number(3000);
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_recover");
+    let data_path = path.join("test_storage_mode_recover.dbidx");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let push_r = b_index.push_values(&values[..1000]).and_then(|_| b_index.flush_chunk());
+    let checkpoint_extent = b_index.chunk_disk_extent(0);
+    let push_r = push_r.and_then(|_| b_index.push_values(&values[1000..2000])).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    // Tear the tail of the last flushed chunk.
+    let data_size = std::fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+    let torn_r = std::fs::OpenOptions::new().write(true).open(&data_path).and_then(|f| f.set_len(data_size - 10));
+
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path);
+    let recover_r = BitmapIndex::<OZBCBitmap, u32>::recover(path);
+    let recovered_data_size = std::fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+    let query_r = recover_r.and_then(|(mut b_index, num_values)| {
+        let recovered_num_values = b_index.num_values();
+        let indexes = b_index.run_query(values[999], None, None)?;
+        b_index.push_values(&values[1000..])?;
+        b_index.flush_chunk()?;
+        Ok((num_values, recovered_num_values, indexes))
+    });
+    let consistent_r = BitmapIndex::<OZBCBitmap, u32>::recover(path)
+        .and_then(|(mut b_index, num_values)| Ok((num_values, b_index.run_query(values[2999], None, None)?)));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && torn_r.is_ok());
+
+    assert!(open_r.is_err());
+    let (rolled_back, num_values, indexes) = query_r.unwrap();
+    assert_eq!(rolled_back, 1000);
+    assert_eq!(num_values, 1000);
+    assert_eq!(recovered_data_size, checkpoint_extent.unwrap().unwrap().end);
+    let linear_search_result: Vec<u64> = (0..1000).filter(|i| values[*i] == values[999]).map(|i| i as u64).collect();
+    assert_eq!(indexes, linear_search_result);
+
+    let (rolled_back, indexes) = consistent_r.unwrap();
+    assert_eq!(rolled_back, 0);
+    let linear_search_result: Vec<u64> = (0..3000).filter(|i| values[*i] == values[2999]).map(|i| i as u64).collect();
+    assert_eq!(indexes, linear_search_result);
+}
+
+#[test]
+fn storage_mode_partial_flushes() {
+    let n = 6000;
+    let values: Vec<u32> = create_random_number(n);
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_partial_flushes");
+    let data_path = path.join("test_storage_mode_partial_flushes.dbidx");
+    let data_size = || std::fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    // Flush the partial chunk every 100 values, reopening the index halfway.
+    let mut sizes: Vec<(u64, u64)> = Vec::new();
+    let mut flush_r = Ok(());
+    for (i, batch) in values.chunks(100).enumerate() {
+        flush_r = flush_r.and_then(|_| b_index.push_values(batch)).and_then(|_| b_index.flush_chunk());
+        let chunk_size = b_index.chunk_disk_extent(0).ok().flatten().map(|extent| extent.end - extent.start);
+        sizes.push((data_size(), chunk_size.unwrap_or(0)));
+        if i == 30 {
+            drop(b_index);
+            b_index = BitmapIndex::<OZBCBitmap, u32>::open(path).unwrap();
+        }
+    }
+    drop(b_index);
+    let recover_r = BitmapIndex::<OZBCBitmap, u32>::recover(path)
+        .and_then(|(mut b_index, num_values)| Ok((num_values, b_index.num_values(), b_index.run_query(values[n - 1], None, None)?)));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(flush_r.is_ok());
+
+    // The versions of the partial chunk take at most three times its size.
+    for (data_size, chunk_size) in sizes.iter() {
+        assert!(*data_size <= 3 * chunk_size);
+    }
+    assert!(sizes.windows(2).any(|w| w[1].0 < w[0].0));
+    let (rolled_back, num_values, indexes) = recover_r.unwrap();
+    assert_eq!(rolled_back, 0);
+    assert_eq!(num_values, n as u64);
+    let linear_search_result: Vec<u64> = (0..n).filter(|i| values[*i] == values[n - 1]).map(|i| i as u64).collect();
+    assert_eq!(indexes, linear_search_result);
+}
+
+#[test]
+fn dyn_bitmap_index() {
+    let n = 10000;
+    let values: Vec<u32> = create_random_number(n);
+    let value_types = [DynValueType::U8, DynValueType::I64, DynValueType::U32];
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_indexes: Vec<DynBitmapIndex<OZBCBitmap>> = value_types.iter()
+        .map(|value_type| DynBitmapIndex::new(*value_type, build_options.clone()).unwrap()).collect();
+    for val in values.iter() {
+        let row: [Box<dyn std::any::Any>; 3] = [Box::new(*val as u8), Box::new(-(*val as i64)), Box::new(*val)];
+        for (b_index, column) in b_indexes.iter_mut().zip(row.iter()) {
+            let value = DynValue::from_any(column.as_ref()).unwrap();
+            assert!(b_index.push_value_dyn(value).is_ok());
+        }
+    }
+
+    let val_to_find = values[n - 1];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_indexes[1].value_type(), DynValueType::I64);
+    assert_eq!(b_indexes[1].num_values(), n as u64);
+    assert_eq!(b_indexes[1].run_query_dyn(DynValue::from(-(val_to_find as i64)), None, None).unwrap(), linear_search_result);
+    assert_eq!(b_indexes[2].run_query_dyn(val_to_find.into(), None, None).unwrap(), linear_search_result);
+    let u8_indexes = b_indexes[0].run_query_dyn(DynValue::U8(val_to_find as u8), None, None).unwrap();
+    assert!(linear_search_result.iter().all(|i| u8_indexes.contains(i)));
+    assert!(matches!(b_indexes[0].push_value_dyn(DynValue::U32(0)), Err(Error::ParametersError)));
+    assert!(matches!(b_indexes[2].run_query_dyn(DynValue::I32(0), None, None), Err(Error::ParametersError)));
+    assert!(DynValue::from_any(&"string").is_none());
+
+    let path = std::path::Path::new("test_dyn_bitmap_index");
+    let mut s_index = DynBitmapIndex::<OZBCBitmap>::create(path, DynValueType::U16, build_options).unwrap();
+    let push_r: Result<Vec<()>, Error> = values.iter().map(|val| s_index.push_value_dyn(DynValue::U16(*val as u16))).collect();
+    let flush_r = s_index.flush_chunk();
+    drop(s_index);
+    let wrong_type_r = DynBitmapIndex::<OZBCBitmap>::open(path, DynValueType::U32);
+    let query_r = DynBitmapIndex::<OZBCBitmap>::open(path, DynValueType::U16)
+        .and_then(|mut s_index| s_index.run_query_dyn(DynValue::U16(val_to_find as u16), None, None));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && flush_r.is_ok());
+
+    assert!(matches!(wrong_type_r, Err(Error::MetaDataError)));
+    let u16_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v as u16 == val_to_find as u16).map(|(i, _v)| i as u64).collect();
+    assert_eq!(query_r.unwrap(), u16_result);
+}
+
+#[test]
+fn dyn_bitmap_index_run_query_columns() {
+    let n = (1 << 20) + 5000;
+    let values: Vec<u32> = create_random_number(n);
+    let mut c0 = DynBitmapIndex::<OZBCBitmap>::new(DynValueType::U8, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let mut c1 = DynBitmapIndex::<OZBCBitmap>::new(DynValueType::U16, BuildOptions::new(8, ChunkSize::M2)).unwrap();
+    for val in values.iter() {
+        assert!(c0.push_value_dyn(DynValue::U8(*val as u8)).is_ok());
+        assert!(c1.push_value_dyn(DynValue::U16((*val >> 8) as u16 & 0xf)).is_ok());
+    }
+
+    let (v0, v1) = (values[n - 1] as u8, (values[n - 1] >> 8) as u16 & 0xf);
+    let (start_index, end_index) = (1000, n as u64 - 1);
+    let linear_search = |f: &dyn Fn(&u32) -> bool| -> Vec<u64> {
+        values.iter().enumerate().filter(|(i, v)| f(v) && *i as u64 >= start_index).map(|(i, _v)| i as u64).collect()
+    };
+    let and_indexes = DynBitmapIndex::run_query_columns(vec![(&mut c0, DynValue::U8(v0)), (&mut c1, DynValue::U16(v1))],
+        ColumnCombine::And, Some(start_index), Some(end_index)).unwrap();
+    assert_eq!(and_indexes, linear_search(&|v| *v as u8 == v0 && (*v >> 8) as u16 & 0xf == v1));
+    assert!(and_indexes.contains(&(n as u64 - 1)));
+    let or_indexes = DynBitmapIndex::run_query_columns(vec![(&mut c0, DynValue::U8(v0)), (&mut c1, DynValue::U16(v1))],
+        ColumnCombine::Or, Some(start_index), Some(end_index)).unwrap();
+    assert_eq!(or_indexes, linear_search(&|v| *v as u8 == v0 || (*v >> 8) as u16 & 0xf == v1));
+    let wrong_type_r = DynBitmapIndex::run_query_columns(vec![(&mut c0, DynValue::U8(v0)), (&mut c1, DynValue::U8(0))],
+        ColumnCombine::And, None, None);
+    assert!(matches!(wrong_type_r, Err(Error::ParametersError)));
+}
+
+#[test]
+fn token_index() {
+    let words = ["error", "warn", "disk", "network", "timeout", "user", "login", "retry"];
+    let mut rng = rand::thread_rng();
+    let documents: Vec<String> = (0..20000).map(|_i| {
+        (0..4).map(|_j| words[rng.gen::<usize>() % words.len()]).collect::<Vec<&str>>().join(" ")
+    }).collect();
+    let mut t_index = TokenIndex::<OZBCBitmap>::new();
+    assert!(t_index.push_documents(&documents).is_ok());
+    assert!(t_index.push_document("Disk ERROR: /dev/sda1, retry=3").is_ok());
+    assert_eq!(t_index.num_documents(), documents.len() as u64 + 1);
+
+    let linear_search = |tokens: &[&str]| -> Vec<u64> {
+        documents.iter().enumerate()
+            .filter(|(_i, d)| tokens.iter().all(|t| d.split(' ').any(|w| w == *t)))
+            .map(|(i, _d)| i as u64).collect()
+    };
+    assert_eq!(t_index.run_query("timeout"), linear_search(&["timeout"]));
+    let mut disk_error = linear_search(&["disk", "error"]);
+    disk_error.push(documents.len() as u64);
+    assert_eq!(t_index.run_query_all(&["error", "disk"]), disk_error);
+    assert_eq!(t_index.run_query_text("Disk, Error!"), disk_error);
+    assert_eq!(t_index.run_query_all(&["sda1", "3"]), vec![documents.len() as u64]);
+    assert!(t_index.run_query_all(&["error", "missing"]).is_empty());
+
+    let tokenizer: Tokenizer = Box::new(|text: &str| text.split(',').map(|t| t.trim().to_string()).collect());
+    let mut t_index = TokenIndex::<OZBCBitmap>::with_tokenizer(tokenizer);
+    assert!(t_index.push_documents(&["a b, c", "c, a b", "a"]).is_ok());
+    assert_eq!(t_index.num_tokens(), 3);
+    assert_eq!(t_index.run_query("a b"), vec![0, 1]);
+}
+
+#[test]
+fn geo_grid_query() {
+    let grid = GeoGrid::new(10).unwrap();
+    assert!(GeoGrid::new(0).is_err() && GeoGrid::new(33).is_err());
+    let base32: Vec<char> = "0123456789bcdefghjkmnpqrstuvwxyz".chars().collect();
+    let cell = grid.cell(57.64911, 10.40744);
+    let geohash: String = (0..4).rev().map(|i| base32[((cell >> (i * 5)) & 31) as usize]).collect();
+    assert_eq!(geohash, "u4pr");
+
+    let mut rng = rand::thread_rng();
+    let points: Vec<(f64, f64)> = (0..200000).map(|_i| (rng.gen_range(-90.0, 90.0), rng.gen_range(-180.0, 180.0))).collect();
+    let mut b_index = BitmapIndex::<OZBCBitmap, u64>::new(BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let cells: Vec<u64> = points.iter().map(|(lat, lon)| grid.cell(*lat, *lon)).collect();
+    assert!(b_index.push_values(&cells).is_ok());
+
+    let (min_lat, min_lon, max_lat, max_lon) = (40.0, -10.0, 50.0, 5.0);
+    let candidates = b_index.run_query_geo_box(&grid, min_lat, min_lon, max_lat, max_lon).unwrap();
+    let in_box: Vec<u64> = points.iter().enumerate()
+        .filter(|(_i, (lat, lon))| *lat >= min_lat && *lat <= max_lat && *lon >= min_lon && *lon <= max_lon)
+        .map(|(i, _p)| i as u64).collect();
+    assert!(!in_box.is_empty());
+    assert!(in_box.iter().all(|i| candidates.binary_search(i).is_ok()));
+    assert!(candidates.len() < in_box.len() * 3 / 2);
+    assert!(matches!(grid.cover(50.0, 0.0, 40.0, 1.0), Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_bin_shift() {
+    let n = (1 << 20) + 1000;
+    let bin_shift = 12;
+    assert!(matches!(BitmapIndex::<OZBCBitmap, u32>::new(BuildOptions::new(4, ChunkSize::M1).with_bin_shift(32)), Err(Error::ParametersError)));
+
+    let build_options = BuildOptions::new(4, ChunkSize::M1).with_bin_shift(bin_shift);
+    let path = std::path::Path::new("test_storage_mode_bin_shift");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| *v >> 8).collect();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    let open_r = BitmapIndex::<OZBCBitmap, u32>::open(path);
+    let reader_r = StorageReader::<OZBCBitmap, u32>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    let mut b_index = open_r.unwrap();
+    assert_eq!(reader_r.unwrap().meta_data().build_options().bin_shift(), bin_shift);
+
+    let val_to_find = values[n - 1];
+    let bin_search = |low: u32, high: u32| -> Vec<u64> {
+        values.iter().enumerate().filter(|(_i, v)| (**v >> bin_shift) >= (low >> bin_shift) && (**v >> bin_shift) <= (high >> bin_shift))
+            .map(|(i, _v)| i as u64).collect()
+    };
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), bin_search(val_to_find, val_to_find));
+    let (low, high) = (val_to_find.saturating_sub(100000), val_to_find.saturating_add(100000));
+    let candidates = b_index.run_query_range_approx(low, high, None, None).unwrap();
+    assert_eq!(candidates, bin_search(low, high));
+    let in_range = values.iter().enumerate().filter(|(i, v)| **v >= low && **v <= high && candidates.binary_search(&(*i as u64)).is_err()).count();
+    assert_eq!(in_range, 0);
+    assert!(matches!(b_index.run_query_range_approx(high, low, None, None), Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_signed_ordered_keys() {
+    let n = (1 << 20) + 1000;
+    let bin_shift = 8;
+    let build_options = BuildOptions::new(4, ChunkSize::M1).with_bin_shift(bin_shift);
+    let path = std::path::Path::new("test_storage_mode_signed_ordered_keys");
+    let mut b_index = BitmapIndex::<OZBCBitmap, i32>::create(path, build_options).unwrap();
+    let values: Vec<i32> = create_random_number(n).iter().map(|v| (*v % 200000) as i32 - 100000).collect();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    let open_r = BitmapIndex::<OZBCBitmap, i32>::open(path);
+    let reader_r = StorageReader::<OZBCBitmap, i32>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    let mut b_index = open_r.unwrap();
+    assert_eq!(reader_r.unwrap().meta_data().key_format(), ORDERED_KEY_FORMAT);
+
+    let val_to_find = values.iter().copied().find(|v| *v < 0).unwrap();
+    let bin_search = |low: i32, high: i32| -> Vec<u64> {
+        values.iter().enumerate().filter(|(_i, v)| (**v >> bin_shift) >= (low >> bin_shift) && (**v >> bin_shift) <= (high >> bin_shift))
+            .map(|(i, _v)| i as u64).collect()
+    };
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), bin_search(val_to_find, val_to_find));
+    let (low, high) = (-5000, 5000);
+    let candidates = b_index.run_query_range_approx(low, high, None, None).unwrap();
+    assert_eq!(candidates, bin_search(low, high));
+    assert!(matches!(b_index.run_query_range_approx(high, low, None, None), Err(Error::ParametersError)));
+}
+
+/// Return a chunk written by 0.1: the offsets of its bitmaps relative to the chunk start
+/// followed by the bitmaps of the raw bits of `values`.
+fn baseline_chunk(values: &[i32], bit_block_size: usize) -> Vec<u8> {
+    let num_blocks = 32 / bit_block_size;
+    let mask = (1u32 << bit_block_size) - 1;
+    let mut bitmaps: Vec<OZBCBitmap> = (0..(num_blocks << bit_block_size)).map(|_| OZBCBitmap::new()).collect();
+    for (pos, value) in values.iter().enumerate() {
+        for i_block in 0..num_blocks {
+            let bits = (*value as u32 >> (i_block * bit_block_size)) & mask;
+            bitmaps[(i_block << bit_block_size) + bits as usize].set(pos as u32);
+        }
+    }
+    let mut table: Vec<u32> = vec![((bitmaps.len() + 1) * 4) as u32];
+    let mut content: Vec<u8> = Vec::new();
+    for bitmap in bitmaps.iter() {
+        bitmap.write_to(&mut content).unwrap();
+        table.push(table[0] + content.len() as u32);
+    }
+    let mut chunk: Vec<u8> = table.iter().flat_map(|offset| offset.to_ne_bytes()).collect();
+    chunk.extend(content);
+    chunk
+}
+
+#[test]
+fn storage_mode_upgrade_baseline_index() {
+    let n = (1 << 20) + 1000;
+    let chunk_size = 1 << 20;
+    let path = std::path::Path::new("test_storage_mode_upgrade_baseline_index");
+    let values: Vec<i32> = create_random_number(n).iter().map(|v| (*v % 2000) as i32 - 1000).collect();
+    // full chunk 0, a stale version of the partial chunk 1 and its last version.
+    let chunks = [&values[..chunk_size], &values[chunk_size..(n - 300)], &values[chunk_size..]];
+    let mut data: Vec<u8> = Vec::new();
+    let mut offsets: Vec<u64> = vec![0];
+    for chunk in chunks.iter() {
+        data.extend(baseline_chunk(chunk, 8));
+        offsets.truncate(if chunk.len() == chunk_size { 1 } else { 2 });
+        offsets.push(data.len() as u64);
+    }
+    let mut meta_data: Vec<u8> = Vec::new();
+    for num_values in [n as u64, (n - 300) as u64] {
+        meta_data.extend(num_values.to_ne_bytes());
+        meta_data.extend(8usize.to_ne_bytes());
+        meta_data.extend((ChunkSize::M1 as u32).to_ne_bytes());
+        meta_data.extend([0u8; 4]);
+    }
+    std::fs::create_dir(path).unwrap();
+    let file_path = path.join("test_storage_mode_upgrade_baseline_index");
+    std::fs::write(file_path.with_extension("mbidx"), &meta_data).unwrap();
+    std::fs::write(file_path.with_extension("obidx"), offsets.iter().flat_map(|o| o.to_ne_bytes()).collect::<Vec<u8>>()).unwrap();
+    std::fs::write(file_path.with_extension("dbidx"), &data).unwrap();
+
+    let wrong_value_r = BitmapIndex::<OZBCBitmap, u64>::open(path);
+    let untouched_meta_data = std::fs::read(file_path.with_extension("mbidx")).unwrap();
+    let open_r = BitmapIndex::<OZBCBitmap, i32>::open(path);
+    let reopen_r = BitmapIndex::<OZBCBitmap, i32>::open(path).map(|b_index| b_index.num_values());
+    let _err = std::fs::remove_dir_all(path);
+    assert!(matches!(wrong_value_r, Err(Error::MetaDataError)));
+    assert_eq!(untouched_meta_data, meta_data);
+    let mut b_index = open_r.unwrap();
+    assert_eq!(b_index.num_values(), n as u64);
+    assert_eq!(reopen_r.unwrap(), n as u64);
+
+    for val_to_find in [values[0], values[n - 1], -1000] {
+        let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+        assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+    }
+}
+
+#[test]
+fn composite_key_query() {
+    let n = (1 << 20) + 1000;
+    assert_eq!(<(u16, u16)>::unpack((513u16, 7u16).pack()), (513, 7));
+    assert_eq!(<(u8, u32)>::unpack((200u8, 123456u32).pack()), (200, 123456));
+    assert_eq!((1u8, 2u8, 3u8).pack(), 0x010203);
+
+    let keys: Vec<(u16, u16)> = create_random_number(n).iter().map(|v| ((*v >> 16) as u16 % 50, *v as u16 % 300)).collect();
+    let packed: Vec<u32> = keys.iter().map(|k| k.pack()).collect();
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(<(u16, u16)>::build_options(ChunkSize::M1)).unwrap();
+    assert!(b_index.push_values(&packed).is_ok());
+    let key_to_find = keys[n - 1];
+    for component in 0..2 {
+        let linear_search_result: Vec<u64> = keys.iter().enumerate()
+            .filter(|(_i, k)| if component == 0 { k.0 == key_to_find.0 } else { k.1 == key_to_find.1 })
+            .map(|(i, _k)| i as u64).collect();
+        assert_eq!(b_index.run_query_component(key_to_find, component, None, None).unwrap(), linear_search_result);
+    }
+    assert!(matches!(b_index.run_query_component(key_to_find, 2, None, None), Err(Error::ParametersError)));
+
+    let keys: Vec<(u8, u32)> = create_random_number(n).iter().map(|v| ((*v >> 24) as u8 % 10, *v % 1000)).collect();
+    let packed: Vec<u64> = keys.iter().map(|k| k.pack()).collect();
+    let mut b_index = BitmapIndex::<OZBCBitmap, u64>::new(<(u8, u32)>::build_options(ChunkSize::M1)).unwrap();
+    assert!(b_index.push_values(&packed).is_ok());
+    let key_to_find = keys[n - 1];
+    let linear_search_result: Vec<u64> = keys.iter().enumerate().filter(|(_i, k)| k.0 == key_to_find.0).map(|(i, _k)| i as u64).collect();
+    assert_eq!(b_index.run_query_component(key_to_find, 0, None, None).unwrap(), linear_search_result);
+
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(BuildOptions::new(16, ChunkSize::M1).with_bin_shift(8)).unwrap();
+    assert!(matches!(b_index.run_query_component((1u16, 1u16), 1, None, None), Err(Error::ParametersError)));
+}
+
+#[test]
+fn block_pattern_query() {
+    let n = (1 << 20) + 1000;
+
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| ((*v % 40) << 20) | (*v % 3000)).collect();
+    let path = std::path::Path::new("test_block_pattern_query");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let push_r = b_index.push_values(&values);
+    let val_to_find = values[n - 1];
+    let num_blocks = b_index.num_blocks();
+    let prefix_r = b_index.run_query_prefix(val_to_find, 16, Some(1000), None);
+    let low_block_r = b_index.run_query_blocks(val_to_find, &[0], None, None);
+    let all_blocks_r = b_index.run_query_blocks(val_to_find, &[3, 2, 1, 0], None, None);
+    let reference_r = b_index.run_query(val_to_find, None, None);
+    let misaligned_r = b_index.run_query_prefix(val_to_find, 12, None, None);
+    let no_blocks_r = b_index.run_query_blocks(val_to_find, &[], None, None);
+    let invalid_block_r = b_index.run_query_blocks(val_to_find, &[4], None, None);
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let linear_search = |f: &dyn Fn(u32) -> bool, start_index: usize| -> Vec<u64> {
+        values.iter().enumerate().filter(|(i, v)| *i >= start_index && f(**v)).map(|(i, _v)| i as u64).collect()
+    };
+    assert_eq!(num_blocks, 4);
+    assert_eq!(prefix_r.unwrap(), linear_search(&|v| v >> 16 == val_to_find >> 16, 1000));
+    assert_eq!(low_block_r.unwrap(), linear_search(&|v| v & 0xff == val_to_find & 0xff, 0));
+    assert_eq!(all_blocks_r.unwrap(), reference_r.unwrap());
+    assert!(matches!(misaligned_r, Err(Error::ParametersError)));
+    assert!(matches!(no_blocks_r, Err(Error::ParametersError)));
+    assert!(matches!(invalid_block_r, Err(Error::ParametersError)));
+}
+
+#[test]
+fn masked_query() {
+    let n = (1 << 20) + 1000;
+
+    let values: Vec<i16> = create_random_number(n).iter().map(|v| (*v % 4000) as i16 - 2000).collect();
+    let path = std::path::Path::new("test_masked_query");
+    let mut b_index = BitmapIndex::<OZBCBitmap, i16>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let push_r = b_index.push_values(&values);
+    let bit_r = b_index.run_query_masked(8, 8, None, None);
+    let nibbles_r = b_index.run_query_masked(0x0f0f, 0x0102, Some(1000), None);
+    let sign_r = b_index.run_query_masked(i16::MIN, i16::MIN, None, None);
+    let no_mask_r = b_index.run_query_masked(0, 0, Some(10), Some(20));
+    let outside_mask_r = b_index.run_query_masked(8, 9, None, None);
+    let expansion_r = b_index.run_query_masked(0x0101, 0x0101, None, None);
+    b_index.set_max_mask_expansion(1 << 14);
+    let expanded_r = b_index.run_query_masked(0x0101, 0x0101, None, None);
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let linear_search = |mask: i16, target: i16, start_index: usize| -> Vec<u64> {
+        values.iter().enumerate().filter(|(i, v)| *i >= start_index && **v & mask == target).map(|(i, _v)| i as u64).collect()
+    };
+    assert_eq!(bit_r.unwrap(), linear_search(8, 8, 0));
+    assert_eq!(nibbles_r.unwrap(), linear_search(0x0f0f, 0x0102, 1000));
+    assert_eq!(sign_r.unwrap(), linear_search(i16::MIN, i16::MIN, 0));
+    assert_eq!(no_mask_r.unwrap(), (10..=20).collect::<Vec<u64>>());
+    assert!(outside_mask_r.unwrap().is_empty());
+    assert!(matches!(expansion_r, Err(Error::ParametersError)));
+    assert_eq!(expanded_r.unwrap(), linear_search(0x0101, 0x0101, 0));
+}
+
+#[test]
+fn sample_query() {
+    let n = 2 << 20;
+    let values: Vec<u8> = create_random_number(n).iter().map(|v| (*v % 16) as u8).collect();
+    let path = std::path::Path::new("test_sample_query");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u8>::create(path, BuildOptions::new(4, ChunkSize::M1)).unwrap();
+    let push_r = b_index.push_values(&values);
+    let all_r = b_index.run_query(3, None, None);
+    let sample_r = b_index.run_query_sample(3, 1000, 7);
+    let same_seed_r = b_index.run_query_sample(3, 1000, 7);
+    let other_seed_r = b_index.run_query_sample(3, 1000, 8);
+    let whole_r = b_index.run_query_sample(3, n, 7);
+    let missing_r = b_index.run_query_sample(16, 1000, 7);
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let all = all_r.unwrap();
+    let sample = sample_r.unwrap();
+    assert_eq!(sample.len(), 1000);
+    assert!(sample.windows(2).all(|w| w[0] < w[1]));
+    assert!(sample.iter().all(|index| all.binary_search(index).is_ok()));
+    // every chunk has about half of the sample.
+    for chunk_id in 0..2u64 {
+        let in_chunk = sample.iter().filter(|index| **index >> 20 == chunk_id).count();
+        assert!(in_chunk > 400 && in_chunk < 600);
+    }
+    assert_eq!(same_seed_r.unwrap(), sample);
+    assert_ne!(other_seed_r.unwrap(), sample);
+    assert_eq!(whole_r.unwrap(), all);
+    assert!(missing_r.unwrap().is_empty());
+}
+
+bitrush_index::bit_enum! {
+    #[derive(Debug)]
+    enum Color: u8 { Red, Green, Blue }
+}
+
+bitrush_index::bit_enum! {
+    #[derive(Debug)]
+    enum ReorderedColor: u8 { Green, Red, Blue }
+}
+
+#[test]
+fn storage_mode_enum_values() {
+    let n = (1 << 20) + 1000;
+    assert_eq!(Color::NAMES, &["Red", "Green", "Blue"]);
+    assert_eq!(Color::from_value(Color::Blue.to_value()), Some(Color::Blue));
+    assert_eq!(Color::from_value(3), None);
+
+    let path = std::path::Path::new("test_storage_mode_enum_values");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u8>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let colors: Vec<Color> = create_random_number(n).iter().map(|v| Color::from_value((*v % 3) as u8).unwrap()).collect();
+    let mapping_r = b_index.set_enum_mapping::<Color>();
+    let push_r: Result<(), Error> = colors.iter().try_for_each(|c| b_index.push_variant(*c)).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    let open_r = BitmapIndex::<OZBCBitmap, u8>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(mapping_r.is_ok() && push_r.is_ok());
+    let mut b_index = open_r.unwrap();
+
+    let linear_search_result: Vec<u64> = colors.iter().enumerate().filter(|(_i, c)| **c == Color::Green).map(|(i, _c)| i as u64).collect();
+    assert_eq!(b_index.run_query_variant(Color::Green, None, None).unwrap(), linear_search_result);
+    assert!(matches!(b_index.run_query_variant(ReorderedColor::Green, None, None), Err(Error::MetaDataError)));
+    assert!(matches!(b_index.set_enum_mapping::<ReorderedColor>(), Err(Error::MetaDataError)));
+}
+
+#[test]
+fn storage_mode_remapped_index() {
+    let n = (1 << 20) + 1000;
+    let distinct: Vec<u64> = (0..1000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)).collect();
+    let values: Vec<u64> = create_random_number(n).iter().map(|v| distinct[*v as usize % distinct.len()]).collect();
+    let path = std::path::Path::new("test_storage_mode_remapped_index");
+    let mut r_index = RemappedIndex::<OZBCBitmap, u64>::create(path, BuildOptions::new(16, ChunkSize::M1)).unwrap();
+    let push_r = r_index.push_values(&values).and_then(|_| r_index.flush());
+    drop(r_index);
+    let open_r = RemappedIndex::<OZBCBitmap, u64>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    let mut r_index = open_r.unwrap();
+    assert_eq!(r_index.num_values(), n as u64);
+    assert_eq!(r_index.num_distinct_values(), distinct.len());
+    assert_eq!(r_index.value_id(values[0]), Some(0));
+    assert_eq!(r_index.id_value(0), Some(values[0]));
+
+    let val_to_find = values[n - 1];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(r_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+    assert!(r_index.run_query(1, None, None).unwrap().is_empty());
+}
+
+#[test]
+fn low_cardinality_index() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<u64> = create_random_number(n).iter().map(|v| (*v % 200) as u64 * 1_000_000_007).collect();
+    assert!(is_low_cardinality(&values));
+    assert!(!is_low_cardinality(&(0..300u64).collect::<Vec<u64>>()));
+
+    let build_options = LowCardinalityIndex::<OZBCBitmap, u64>::dictionary_options(ChunkSize::M1);
+    let mut l_index = LowCardinalityIndex::<OZBCBitmap, u64>::new(build_options).unwrap();
+    assert!(l_index.push_values(&values).is_ok());
+    assert_eq!(l_index.num_distinct_values(), 200);
+    let val_to_find = values[n - 1];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(l_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+
+    let new_values: Vec<u64> = (0..100u64).collect();
+    assert!(matches!(l_index.push_values(&new_values), Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_flush_policy() {
+    let n = 100 * 1000;
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    assert!(matches!(BitmapIndex::<OZBCBitmap, u16>::new(build_options.clone()).unwrap().set_flush_policy(FlushPolicy::chunk_full()), Err(Error::ParametersError)));
+
+    let path = std::path::Path::new("test_storage_mode_flush_policy");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let policy_r = b_index.set_flush_policy(FlushPolicy::chunk_full().with_max_memory_bytes(1024));
+    let push_r = values.iter().try_for_each(|v| b_index.push_value(*v));
+    let unflushed_values = b_index.unflushed_values();
+    let interval_policy_r = b_index.set_flush_policy(FlushPolicy::chunk_full().with_max_interval(std::time::Duration::ZERO));
+    let push_values_r = b_index.push_values(&values);
+    let unflushed_values_batch = b_index.unflushed_values();
+    // every interval flush rewrites the partial chunk over its older versions.
+    let data_size = std::fs::metadata(path.join("test_storage_mode_flush_policy.dbidx")).map(|m| m.len());
+    let chunk_size = b_index.chunk_disk_extent(0).ok().flatten().map(|extent| extent.end - extent.start);
+    b_index.remove_flush_policy();
+    let push_no_policy_r = b_index.push_values(&values);
+    let unflushed_values_no_policy = b_index.unflushed_values();
+    drop(b_index);
+    let open_r = BitmapIndex::<OZBCBitmap, u16>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(policy_r.is_ok() && push_r.is_ok() && interval_policy_r.is_ok() && push_values_r.is_ok() && push_no_policy_r.is_ok());
+    assert!(unflushed_values < 1 << 12);
+    assert!(unflushed_values_batch < 1 << 12);
+    assert_eq!(unflushed_values_no_policy, n as u64 + unflushed_values_batch);
+    assert!(data_size.unwrap() <= 3 * chunk_size.unwrap());
+
+    let mut b_index = open_r.unwrap();
+    assert_eq!(b_index.num_values(), 2 * n as u64 - unflushed_values_batch);
+    let val_to_find = values[n - 1];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, Some(0), Some(n as u64 - 1)).unwrap(), linear_search_result);
+}
+
+#[test]
+fn storage_mode_rebuild_storage_files() {
+    let n = (1 << 20) + 1000;
+    let path = std::path::Path::new("test_storage_mode_rebuild_storage_files");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| *v % 1000).collect();
+    let push_r = b_index.push_values(&values[..(n - 500)]).and_then(|_| b_index.flush_chunk())
+        .and_then(|_| b_index.push_values(&values[(n - 500)..])).and_then(|_| b_index.flush_chunk());
+    let rewrite_r = b_index.rewrite_chunk(0, |_bitmaps| {});
+    drop(b_index);
+    let file_path = |extension: &str| path.join("test_storage_mode_rebuild_storage_files").with_extension(extension);
+    let remove_r = std::fs::remove_file(file_path("obidx")).and_then(|_| std::fs::remove_file(file_path("mbidx")));
+    let torn_r = std::fs::OpenOptions::new().append(true).open(file_path("dbidx"))
+        .and_then(|mut f| std::io::Write::write_all(&mut f, &[7u8; 100]));
+    let rebuild_r = BitmapIndex::<OZBCBitmap, u32>::rebuild_storage_files(path);
+    let wrong_type_r = BitmapIndex::<OZBCBitmap, u16>::rebuild_storage_files(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && rewrite_r.is_ok() && remove_r.is_ok() && torn_r.is_ok());
+    assert!(matches!(wrong_type_r, Err(Error::MetaDataError)));
+
+    let mut b_index = rebuild_r.unwrap();
+    assert_eq!(b_index.num_values(), n as u64);
+    assert_eq!(b_index.unflushed_values(), 0);
+    let val_to_find = values[n - 1];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[test]
+fn storage_mode_rebuild_torn_partial_rewrite() {
+    let n = 6000;
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| *v % 1000).collect();
+    let path = std::path::Path::new("test_storage_mode_rebuild_torn_partial_rewrite");
+    let file_path = |extension: &str| path.join("test_storage_mode_rebuild_torn_partial_rewrite").with_extension(extension);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    // Flush the partial chunk every 100 values until a version is written over the older ones.
+    let mut rewrite: Option<(u64, Vec<u8>, Vec<u8>)> = None;
+    for (i, batch) in values.chunks(100).enumerate() {
+        let data_before = std::fs::read(file_path("dbidx")).unwrap();
+        assert!(b_index.push_values(batch).and_then(|_| b_index.flush_chunk()).is_ok());
+        let data_after = std::fs::read(file_path("dbidx")).unwrap();
+        if data_after.len() < data_before.len() {
+            rewrite = Some((i as u64 * 100, data_before, data_after));
+            break;
+        }
+    }
+    drop(b_index);
+    let (num_values, data_before, data_after) = rewrite.unwrap();
+
+    // the new version written in part or in whole over the older ones, before the truncation.
+    let mut rebuild_r = Vec::new();
+    for written in [data_after.len() / 2, data_after.len()] {
+        let mut data = data_before.clone();
+        data[..written].copy_from_slice(&data_after[..written]);
+        let write_r = std::fs::write(file_path("dbidx"), &data)
+            .and_then(|_| std::fs::remove_file(file_path("obidx")))
+            .and_then(|_| std::fs::remove_file(file_path("mbidx")));
+        assert!(write_r.is_ok());
+        rebuild_r.push(BitmapIndex::<OZBCBitmap, u32>::rebuild_storage_files(path)
+            .and_then(|mut b_index| Ok((b_index.num_values(), b_index.run_query(values[0], None, None)?))));
+    }
+    let _err = std::fs::remove_dir_all(path);
+
+    let linear_search_result: Vec<u64> = (0..num_values).filter(|i| values[*i as usize] == values[0]).collect();
+    for r in rebuild_r {
+        assert_eq!(r.unwrap(), (num_values, linear_search_result.clone()));
+    }
+}
+
+#[test]
+fn storage_mode_hot_tier() {
+    let n = (3 << 20) + 1000;
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    assert!(matches!(BitmapIndex::<OZBCBitmap, u16>::new(build_options.clone()).unwrap().use_hot_tier(HotTier::Chunks(2)), Err(Error::ParametersError)));
+
+    let path = std::path::Path::new("test_storage_mode_hot_tier");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values[..(2 << 20)]);
+    let hot_tier_r = b_index.use_hot_tier(HotTier::Chunks(2));
+    let num_hot_chunks = b_index.num_hot_chunks();
+    let push_next_r = b_index.push_values(&values[(2 << 20)..]);
+    let num_hot_chunks_next = b_index.num_hot_chunks();
+    let val_to_find = values[n - 1];
+    let hot_query_r = b_index.run_query(val_to_find, Some(1 << 20), None);
+    let hot_bytes_read = b_index.storage_stats().unwrap().last_query_bytes_read;
+    let query_r = b_index.run_query(val_to_find, None, None);
+    let rewrite_r = b_index.rewrite_chunk(2, |bitmaps| bitmaps.iter_mut().for_each(|b| b.clear()));
+    let rewritten_query_r = b_index.run_query(val_to_find, Some(2 << 20), Some((3 << 20) - 1));
+    let bytes_hot_tier_r = b_index.use_hot_tier(HotTier::Bytes(b_index.hot_tier_size() / 2));
+    let num_hot_chunks_bytes = b_index.num_hot_chunks();
+    b_index.remove_hot_tier();
+    let cold_query_r = b_index.run_query(val_to_find, None, None);
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && hot_tier_r.is_ok() && push_next_r.is_ok() && rewrite_r.is_ok() && bytes_hot_tier_r.is_ok());
+    assert_eq!(num_hot_chunks, 2);
+    assert_eq!(num_hot_chunks_next, 2);
+    assert_eq!(num_hot_chunks_bytes, 1);
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    let hot_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i >= 1 << 20).collect();
+    assert_eq!(hot_query_r.unwrap(), hot_result);
+    assert_eq!(hot_bytes_read, 0);
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    assert!(rewritten_query_r.unwrap().is_empty());
+    let cold_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i < 2 << 20 || *i >= 3 << 20).collect();
+    assert_eq!(cold_query_r.unwrap(), cold_result);
+}
+
+#[test]
+fn storage_mode_offset_cache() {
+    let n = (2 << 20) + 1000;
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    assert!(matches!(BitmapIndex::<OZBCBitmap, u16>::new(build_options.clone()).unwrap().use_offset_cache(), Err(Error::ParametersError)));
+
+    let path = std::path::Path::new("test_storage_mode_offset_cache");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values[..(1 << 20)]);
+    let cache_r = b_index.use_offset_cache();
+    let val_to_find = values[n - 1];
+    let first_query_r = b_index.run_query(val_to_find, None, None);
+    let first_bytes_read = b_index.storage_stats().unwrap().last_query_bytes_read;
+    let cache_size = b_index.offset_cache_size();
+    let second_query_r = b_index.run_query(val_to_find, None, None);
+    let second_bytes_read = b_index.storage_stats().unwrap().last_query_bytes_read;
+    b_index.set_chunk_format(ChunkFormat::Sparse);
+    let push_next_r = b_index.push_values(&values[(1 << 20)..]);
+    let rewrite_r = b_index.rewrite_chunk(0, |bitmaps| bitmaps.iter_mut().for_each(|b| b.clear()));
+    let query_r = b_index.run_query(val_to_find, None, None);
+    b_index.remove_offset_cache();
+    let cache_size_removed = b_index.offset_cache_size();
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && cache_r.is_ok() && push_next_r.is_ok() && rewrite_r.is_ok());
+    assert!(cache_size > 0);
+    assert_eq!(cache_size_removed, 0);
+    assert!(second_bytes_read < first_bytes_read);
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    let flushed_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i < 1 << 20).collect();
+    assert_eq!(first_query_r.unwrap(), flushed_result);
+    assert_eq!(second_query_r.unwrap(), flushed_result);
+    let rewritten_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i >= 1 << 20).collect();
+    assert_eq!(query_r.unwrap(), rewritten_result);
+}
+
+#[test]
+fn storage_mode_query_cache() {
+    let n = (2 << 20) + 1000;
+    let path = std::path::Path::new("test_storage_mode_query_cache");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values[..((1 << 20) + 10)]);
+    b_index.use_query_cache(2);
+    let val_to_find = values[n - 1];
+    let first_query_r = b_index.run_query(val_to_find, None, None);
+    let first_queries = b_index.storage_stats().unwrap().queries;
+    let second_query_r = b_index.run_query(val_to_find, None, None);
+    let range_query_r = b_index.run_query(val_to_find, Some(1000), Some((1 << 20) + 5));
+    let second_queries = b_index.storage_stats().unwrap().queries;
+    let push_next_r = b_index.push_values(&values[((1 << 20) + 10)..]);
+    let query_r = b_index.run_query(val_to_find, None, None);
+    let rewrite_r = b_index.rewrite_chunk(0, |bitmaps| bitmaps.iter_mut().for_each(|b| b.clear()));
+    let rewritten_query_r = b_index.run_query(val_to_find, None, None);
+    let other_queries_r: Result<Vec<Vec<u64>>, Error> = (0..4).map(|v| b_index.run_query(v, None, None)).collect();
+    let query_cache_len = b_index.query_cache_len();
+    b_index.remove_query_cache();
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && push_next_r.is_ok() && rewrite_r.is_ok() && other_queries_r.is_ok());
+    assert_eq!(second_queries, first_queries);
+    assert_eq!(query_cache_len, 2);
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    let pushed_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i < (1 << 20) + 10).collect();
+    assert_eq!(first_query_r.unwrap(), pushed_result);
+    assert_eq!(second_query_r.unwrap(), pushed_result);
+    let range_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i >= 1000 && *i <= (1 << 20) + 5).collect();
+    assert_eq!(range_query_r.unwrap(), range_result);
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    let rewritten_result: Vec<u64> = linear_search_result.iter().copied().filter(|i| *i >= 1 << 20).collect();
+    assert_eq!(rewritten_query_r.unwrap(), rewritten_result);
+}
+
+#[test]
+fn bulk_build() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<i16> = create_random_number(n).iter().map(|v| *v as i16).collect();
+    let mut pushed_index = BitmapIndex::<OZBCBitmap, i16>::new(BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    pushed_index.push_values(&values).unwrap();
+    let mut built_index = BitmapIndex::<OZBCBitmap, i16>::build(None, BuildOptions::new(8, ChunkSize::M1), &values).unwrap();
+    let push_r = built_index.push_column(values.iter().take(10).copied());
+    assert_eq!(push_r.unwrap(), 10);
+    assert_eq!(built_index.num_values(), n as u64 + 10);
+    for val_to_find in [values[0], values[n - 1], i16::MIN, -1, 0] {
+        let mut expected = pushed_index.run_query(val_to_find, None, None).unwrap();
+        expected.extend(values.iter().take(10).enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| (n + i) as u64));
+        assert_eq!(built_index.run_query(val_to_find, None, None).unwrap(), expected);
+    }
+
+    let path = std::path::Path::new("test_bulk_build");
+    let column_path = std::path::Path::new("test_bulk_build.column");
+    let column: Vec<u8> = values.iter().flat_map(|v| v.to_ne_bytes()).collect();
+    let write_r = std::fs::write(column_path, &column);
+    let build_r = BitmapIndex::<OZBCBitmap, i16>::build_from_column_file(Some(path), BuildOptions::new(8, ChunkSize::M1), column_path);
+    let flush_r = build_r.and_then(|mut b_index| b_index.flush_chunk());
+    let open_r = BitmapIndex::<OZBCBitmap, i16>::open(path);
+    let torn_r = std::fs::write(column_path, &column[..3]);
+    let mut torn_index = BitmapIndex::<OZBCBitmap, i16>::new(BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let torn_push_r = torn_index.push_column_file(column_path);
+    let _err = std::fs::remove_dir_all(path);
+    let _err = std::fs::remove_file(column_path);
+    assert!(write_r.is_ok() && flush_r.is_ok() && torn_r.is_ok());
+    assert!(matches!(torn_push_r, Err(Error::FileError(_))));
+    assert_eq!(torn_index.num_values(), 0);
+
+    let mut b_index = open_r.unwrap();
+    assert_eq!(b_index.num_values(), n as u64);
+    let val_to_find = values[n - 1];
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), pushed_index.run_query(val_to_find, None, None).unwrap());
+}
+
+#[test]
+fn offline_builder() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let mut pushed_index = BitmapIndex::<OZBCBitmap, u16>::new(BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    pushed_index.push_values(&values).unwrap();
+
+    let path = std::path::Path::new("test_offline_builder");
+    let tmp_dir = std::path::Path::new("test_offline_builder_tmp");
+    let create_dir_r = std::fs::create_dir(tmp_dir);
+    let builder_r = OfflineBuilder::<OZBCBitmap, u16>::new(path, BuildOptions::new(8, ChunkSize::M1), tmp_dir, 1 << 20);
+    let mut builder = builder_r.unwrap();
+    let push_r = values.chunks(100 * 1000).try_for_each(|values| builder.push_values(values));
+    let num_spilled_runs = builder.num_spilled_runs();
+    let finish_r = builder.finish();
+    let tmp_files = std::fs::read_dir(tmp_dir).map(|entries| entries.count());
+    let _err = std::fs::remove_dir_all(tmp_dir);
+    let open_r = BitmapIndex::<OZBCBitmap, u16>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(create_dir_r.is_ok() && push_r.is_ok());
+    assert!(num_spilled_runs > 1);
+    assert_eq!(tmp_files.unwrap(), 0);
+
+    let mut built_index = finish_r.unwrap();
+    let mut opened_index = open_r.unwrap();
+    assert_eq!(opened_index.num_values(), n as u64);
+    for val_to_find in [values[0], values[n - 1], 0, u16::MAX] {
+        let expected = pushed_index.run_query(val_to_find, None, None).unwrap();
+        assert_eq!(built_index.run_query(val_to_find, None, None).unwrap(), expected);
+        assert_eq!(opened_index.run_query(val_to_find, None, None).unwrap(), expected);
+    }
+}
+
+#[test]
+fn rebuild_index() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<i16> = create_random_number(n).iter().map(|v| *v as i16).collect();
+    let src_path = std::path::Path::new("test_rebuild_index_src");
+    let dst_path = std::path::Path::new("test_rebuild_index_dst");
+    let binned_path = std::path::Path::new("test_rebuild_index_binned");
+    let mut src_index = BitmapIndex::<OZBCBitmap, i16>::create(src_path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    src_index.push_values(&values).unwrap();
+    let meta_r = src_index.set_user_meta_data("unit", b"ms");
+    let flush_r = src_index.flush_chunk();
+    let rebuild_r = BitmapIndex::<OZBCBitmap, i16>::rebuild(src_path, dst_path, BuildOptions::new(4, ChunkSize::M2));
+    let open_r = BitmapIndex::<OZBCBitmap, i16>::open(dst_path);
+    let binned_r = BitmapIndex::<OZBCBitmap, i16>::rebuild(src_path, binned_path, BuildOptions::new(4, ChunkSize::M1).with_bin_shift(4));
+    let _err = std::fs::remove_dir_all(src_path);
+    let _err = std::fs::remove_dir_all(dst_path);
+    let _err = std::fs::remove_dir_all(binned_path);
+    assert!(meta_r.is_ok() && flush_r.is_ok());
+    let mut rebuilt_index = rebuild_r.unwrap();
+    let mut opened_index = open_r.unwrap();
+    let mut binned_index = binned_r.unwrap();
+    assert_eq!(rebuilt_index.num_values(), n as u64);
+    assert_eq!(opened_index.num_values(), n as u64);
+    assert_eq!(opened_index.user_meta_data("unit"), Some(&b"ms"[..]));
+    for val_to_find in [values[0], values[n - 1], i16::MIN, -1, 0] {
+        let expected = src_index.run_query(val_to_find, None, None).unwrap();
+        assert_eq!(rebuilt_index.run_query(val_to_find, None, None).unwrap(), expected);
+        assert_eq!(opened_index.run_query(val_to_find, None, None).unwrap(), expected);
+        let expected_bin: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| (**v as u16 ^ 0x8000) >> 4 == (val_to_find as u16 ^ 0x8000) >> 4).map(|(i, _v)| i as u64).collect();
+        assert_eq!(binned_index.run_query(val_to_find, None, None).unwrap(), expected_bin);
+    }
+}
+
+#[test]
+fn get_value() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<i16> = create_random_number(n).iter().map(|v| *v as i16).collect();
+    let path = std::path::Path::new("test_get_value");
+    let binned_path = std::path::Path::new("test_get_value_binned");
+    let mut b_index = BitmapIndex::<OZBCBitmap, i16>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let mut binned_index = BitmapIndex::<OZBCBitmap, i16>::create(binned_path, BuildOptions::new(4, ChunkSize::M1).with_bin_shift(4)).unwrap();
+    let push_r = b_index.push_values(&values);
+    let binned_push_r = binned_index.push_values(&values);
+    let positions: Vec<u64> = vec![0, 1, (1 << 20) - 1, 1 << 20, (1 << 20) + 1, n as u64 - 1];
+    let values_r: Vec<Result<i16, Error>> = positions.iter().map(|pos| b_index.get_value(*pos)).collect();
+    let binned_values_r: Vec<Result<i16, Error>> = positions.iter().map(|pos| binned_index.get_value(*pos)).collect();
+    let outside_r = b_index.get_value(n as u64);
+    drop(b_index);
+    drop(binned_index);
+    let _err = std::fs::remove_dir_all(path);
+    let _err = std::fs::remove_dir_all(binned_path);
+    assert!(push_r.is_ok() && binned_push_r.is_ok());
+
+    for ((pos, value_r), binned_value_r) in positions.iter().zip(values_r).zip(binned_values_r) {
+        assert_eq!(value_r.unwrap(), values[*pos as usize]);
+        assert_eq!(binned_value_r.unwrap(), values[*pos as usize] & !0xf);
+    }
+    assert!(matches!(outside_r, Err(Error::ParametersError)));
+}
+
+#[test]
+fn scan_values() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<i16> = create_random_number(n).iter().map(|v| *v as i16).collect();
+    let path = std::path::Path::new("test_scan_values");
+    let mut b_index = BitmapIndex::<OZBCBitmap, i16>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let push_r = b_index.push_values(&values);
+    let scan_r = b_index.scan_values(None).and_then(|scan| scan.collect::<Result<Vec<(u64, i16)>, Error>>());
+    let tail_r = b_index.scan_values(Some((1 << 20) - 2)).and_then(|scan| scan.take(4).collect::<Result<Vec<(u64, i16)>, Error>>());
+    let end_r = b_index.scan_values(Some(n as u64)).map(|scan| scan.count());
+    let invalid_r = b_index.scan_values(Some(n as u64 + 1)).map(|scan| scan.count());
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let expected: Vec<(u64, i16)> = values.iter().enumerate().map(|(i, v)| (i as u64, *v)).collect();
+    assert_eq!(scan_r.unwrap(), expected);
+    assert_eq!(tail_r.unwrap(), expected[(1 << 20) - 2..(1 << 20) + 2]);
+    assert_eq!(end_r.unwrap(), 0);
+    assert!(matches!(invalid_r, Err(Error::ParametersError)));
+}
+
+#[test]
+fn validate_against() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<i16> = create_random_number(n).iter().map(|v| *v as i16).collect();
+    let path = std::path::Path::new("test_validate_against");
+    let mut b_index = BitmapIndex::<OZBCBitmap, i16>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let push_r = b_index.push_values(&values);
+    let mut changed_values = values.clone();
+    changed_values[(1 << 20) + 7] = !values[(1 << 20) + 7];
+    let valid_r = b_index.validate_against(values.iter().copied());
+    let changed_r = b_index.validate_against(changed_values);
+    let shorter_r = b_index.validate_against(values[..n - 10].iter().copied());
+    let longer_r = b_index.validate_against(values.iter().copied().chain([5]));
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    assert_eq!(valid_r.unwrap(), None);
+    assert_eq!(changed_r.unwrap(), Some(ValueDivergence {
+        position: (1 << 20) + 7,
+        expected: Some(!values[(1 << 20) + 7]),
+        found: Some(values[(1 << 20) + 7])
+    }));
+    assert_eq!(shorter_r.unwrap(), Some(ValueDivergence { position: n as u64 - 10, expected: None, found: Some(values[n - 10]) }));
+    assert_eq!(longer_r.unwrap(), Some(ValueDivergence { position: n as u64, expected: Some(5), found: None }));
+}
+
+/// Serialize `positions` (sorted) in the portable Roaring format, with only run
+/// containers if `runs` is `true`.
+fn roaring_file(positions: &[u64], runs: bool) -> Vec<u8> {
+    let mut containers: Vec<(u16, Vec<u16>)> = Vec::new();
+    for pos in positions {
+        let key = (*pos >> 16) as u16;
+        if containers.last().map(|(k, _lows)| *k) != Some(key) {
+            containers.push((key, Vec::new()));
+        }
+        containers.last_mut().unwrap().1.push(*pos as u16);
+    }
+    let contents: Vec<Vec<u8>> = containers.iter().map(|(_key, lows)| {
+        let mut content: Vec<u8> = Vec::new();
+        if runs {
+            let mut run_list: Vec<(u16, u16)> = Vec::new();
+            for low in lows {
+                match run_list.last_mut() {
+                    Some((start, len)) if *start as u32 + *len as u32 + 1 == *low as u32 => *len += 1,
+                    _ => run_list.push((*low, 0))
+                }
+            }
+            content.extend((run_list.len() as u16).to_le_bytes());
+            run_list.iter().for_each(|(start, len)| content.extend(start.to_le_bytes().iter().chain(len.to_le_bytes().iter())));
+        } else if lows.len() > 4096 {
+            let mut words = vec![0u64; 1024];
+            lows.iter().for_each(|low| words[*low as usize / 64] |= 1 << (low % 64));
+            words.iter().for_each(|word| content.extend(word.to_le_bytes()));
+        } else {
+            lows.iter().for_each(|low| content.extend(low.to_le_bytes()));
+        }
+        content
+    }).collect();
+    let mut file: Vec<u8> = Vec::new();
+    if runs {
+        file.extend((12347 | (containers.len() as u32 - 1) << 16).to_le_bytes());
+        file.extend(vec![0xff; containers.len().div_ceil(8)]);
+    } else {
+        file.extend(12346u32.to_le_bytes());
+        file.extend((containers.len() as u32).to_le_bytes());
+    }
+    for (key, lows) in containers.iter() {
+        file.extend(key.to_le_bytes());
+        file.extend((lows.len() as u16 - 1).to_le_bytes());
+    }
+    if !runs || containers.len() >= 4 {
+        let mut offset = file.len() + 4 * containers.len();
+        for content in contents.iter() {
+            file.extend((offset as u32).to_le_bytes());
+            offset += content.len();
+        }
+    }
+    contents.iter().for_each(|content| file.extend(content));
+    file
+}
+
+#[test]
+fn roaring_import() {
+    let n = 300_000;
+    let mut values: Vec<u8> = create_random_number(n).iter().map(|v| (*v % 4) as u8).collect();
+    values[1000] = 7;
+    values[n - 1] = 7;
+    let positions_of = |value: u8| -> Vec<u64> {
+        values.iter().enumerate().filter(|(_i, v)| **v == value).map(|(i, _v)| i as u64).collect()
+    };
+    let roaring_dir = std::path::Path::new("test_roaring_import");
+    let create_r = std::fs::create_dir(roaring_dir);
+    let write_r: Result<Vec<()>, _> = [0, 1, 2, 3, 7].iter()
+        .map(|v| std::fs::write(roaring_dir.join(format!("{}.roaring", v)), roaring_file(&positions_of(*v), *v == 2)))
+        .collect();
+    let dir_r = BitmapIndex::<OZBCBitmap, u8>::import_roaring_dir(None, BuildOptions::new(8, ChunkSize::M1), roaring_dir, |name| name.parse().ok());
+    let overlap_path = roaring_dir.join("overlap");
+    let overlap_w = std::fs::write(&overlap_path, roaring_file(&[0, 1000], false));
+    let files = vec![(7, roaring_dir.join("7.roaring")), (5, overlap_path)];
+    let overlap_r = BitmapIndex::<OZBCBitmap, u8>::import_roaring(None, BuildOptions::new(8, ChunkSize::M1), files);
+    let _err = std::fs::remove_dir_all(roaring_dir);
+    assert!(create_r.is_ok() && write_r.is_ok() && overlap_w.is_ok());
+    assert!(matches!(overlap_r, Err(Error::FileError(_))));
+
+    let mut b_index = dir_r.unwrap();
+    assert_eq!(b_index.num_values(), n as u64);
+    for val_to_find in [0, 1, 2, 3, 7, 9] {
+        assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), positions_of(val_to_find));
+    }
+}
+
+#[test]
+fn archive_export_import() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let path = std::path::Path::new("test_archive_src");
+    let archive_path = std::path::Path::new("test_archive.bidx");
+    let imported_path = std::path::Path::new("test_archive_dst");
+    let corrupted_path = std::path::Path::new("test_archive_corrupted");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    b_index.push_values(&values).unwrap();
+    let meta_r = b_index.set_user_meta_data("source", b"dataset");
+    let flush_r = b_index.flush_chunk();
+    let export_r = b_index.export_archive(archive_path);
+    let import_r = BitmapIndex::<OZBCBitmap, u16>::import_archive(archive_path, imported_path);
+    let exists_r = BitmapIndex::<OZBCBitmap, u16>::import_archive(archive_path, imported_path);
+    let mut archive = std::fs::read(archive_path).unwrap_or_default();
+    let last = archive.len() - 1;
+    archive[last] ^= 0xff;
+    let corrupt_r = std::fs::write(archive_path, &archive);
+    let corrupted_r = BitmapIndex::<OZBCBitmap, u16>::import_archive(archive_path, corrupted_path);
+    let corrupted_exists = corrupted_path.exists();
+    let _err = std::fs::remove_dir_all(path);
+    let _err = std::fs::remove_dir_all(imported_path);
+    let _err = std::fs::remove_dir_all(corrupted_path);
+    let _err = std::fs::remove_file(archive_path);
+    assert!(meta_r.is_ok() && flush_r.is_ok() && export_r.is_ok() && corrupt_r.is_ok());
+    assert!(matches!(exists_r, Err(Error::ParametersError)));
+    assert!(matches!(corrupted_r, Err(Error::FileError(_))));
+    assert!(!corrupted_exists);
+
+    let mut imported_index = import_r.unwrap();
+    assert_eq!(imported_index.num_values(), n as u64);
+    assert_eq!(imported_index.user_meta_data("source"), Some(&b"dataset"[..]));
+    for val_to_find in [values[0], values[n - 1], 0] {
+        assert_eq!(imported_index.run_query(val_to_find, None, None).unwrap(), b_index.run_query(val_to_find, None, None).unwrap());
+    }
+    let memory_index = BitmapIndex::<OZBCBitmap, u16>::new(BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    assert!(matches!(memory_index.export_archive(archive_path), Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_string_index() {
+    let n = (1 << 20) + 1000;
+    let services = ["api", "api-gateway", "apiary", "auth", "billing", "b"];
+    let values: Vec<String> = create_random_number(n).iter()
+        .map(|v| format!("{}/{}", services[*v as usize % services.len()], (v >> 8) % 50)).collect();
+    let path = std::path::Path::new("test_storage_mode_string_index");
+    let mut s_index = StringIndex::<OZBCBitmap>::create(path, BuildOptions::new(16, ChunkSize::M1)).unwrap();
+    let push_r = s_index.push_values(&values).and_then(|_| s_index.flush());
+    drop(s_index);
+    let open_r = StringIndex::<OZBCBitmap>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    let mut s_index = open_r.unwrap();
+    assert_eq!(s_index.num_values(), n as u64);
+    assert_eq!(s_index.dictionary().len(), services.len() * 50);
+    assert_eq!(s_index.dictionary().value(0), Some(values[0].as_str()));
+    assert_eq!(s_index.dictionary().id(&values[0]), Some(0));
+
+    let linear_search = |prefix: &str, start: usize, end: usize| -> Vec<u64> {
+        (start..end).filter(|i| values[*i].starts_with(prefix)).map(|i| i as u64).collect()
+    };
+    assert_eq!(s_index.run_query(&values[n - 1], None, None).unwrap(),
+               (0..n).filter(|i| values[*i] == values[n - 1]).map(|i| i as u64).collect::<Vec<u64>>());
+    for prefix in ["api", "api-", "api/", "b", "billing/1", ""].iter() {
+        assert_eq!(s_index.run_query_prefix(prefix, None, None).unwrap(), linear_search(prefix, 0, n));
+    }
+    assert_eq!(s_index.run_query_prefix("api", Some(1000), Some((1 << 20) + 499)).unwrap(), linear_search("api", 1000, (1 << 20) + 500));
+    assert_eq!(s_index.dictionary().prefix_ids("auth/").len(), 50);
+    assert!(s_index.run_query_prefix("zz", None, None).unwrap().is_empty());
+}
+
+#[test]
+fn storage_mode_string_index_normalizer() {
+    let values = ["  Api/1", "API/2 ", "api/1", "Billing", "billing x"];
+    let normalizer = StringNormalizer::new().with_trim().with_lowercase();
+    let path = std::path::Path::new("test_storage_mode_string_index_normalizer");
+    let mut s_index = StringIndex::<OZBCBitmap>::create_with_normalizer(path, BuildOptions::new(8, ChunkSize::M1), normalizer).unwrap();
+    let push_r = s_index.push_values(&values).and_then(|_| s_index.flush());
+    drop(s_index);
+    let open_r = StringIndex::<OZBCBitmap>::open(path);
+    let open_same_r = StringIndex::<OZBCBitmap>::open_with_normalizer(path, normalizer).map(|s_index| s_index.normalizer());
+    let open_other_r = StringIndex::<OZBCBitmap>::open_with_normalizer(path, StringNormalizer::new().with_lowercase());
+    let open_default_r = StringIndex::<OZBCBitmap>::open_with_normalizer(path, StringNormalizer::new());
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert_eq!(open_same_r.unwrap(), normalizer);
+    assert!(matches!(open_other_r, Err(Error::MetaDataError)));
+    assert!(matches!(open_default_r, Err(Error::MetaDataError)));
+
+    let mut s_index = open_r.unwrap();
+    assert_eq!(s_index.normalizer(), normalizer);
+    assert_eq!(s_index.dictionary().len(), 4);
+    assert_eq!(s_index.dictionary().value(0), Some("api/1"));
+    assert_eq!(s_index.run_query(" API/1", None, None).unwrap(), vec![0, 2]);
+    assert_eq!(s_index.run_query_prefix(" Api", None, None).unwrap(), vec![0, 1, 2]);
+    assert_eq!(s_index.run_query_prefix("BILLING ", None, None).unwrap(), vec![4]);
+    assert!(s_index.push_value(" BILLING").is_ok());
+    assert_eq!(s_index.run_query("billing", None, None).unwrap(), vec![3, 5]);
+}
+
+#[cfg(feature = "nfkc")]
+#[test]
+fn string_index_nfkc_normalizer() {
+    let normalizer = StringNormalizer::new().with_nfkc().with_lowercase();
+    let mut s_index = StringIndex::<OZBCBitmap>::new_with_normalizer(BuildOptions::new(8, ChunkSize::M1), normalizer).unwrap();
+    assert!(s_index.push_values(&["\u{FF21}\u{FF30}\u{FF29}", "cafe\u{301}", "api"]).is_ok());
+    assert_eq!(s_index.dictionary().len(), 2);
+    assert_eq!(s_index.run_query("API", None, None).unwrap(), vec![0, 2]);
+    assert_eq!(s_index.run_query("caf\u{E9}", None, None).unwrap(), vec![1]);
+    assert_eq!(s_index.run_query_prefix("\u{FF41}", None, None).unwrap(), vec![0, 2]);
+}
+
+#[test]
+fn segmented_index() {
+    let n = (1 << 20) + 5000;
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v % 100).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_segmented_index");
+    let snapshot_path = std::path::Path::new("test_segmented_index_snapshot");
+    let mut s_index = SegmentedIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    // a segment with two chunks, a small segment and unflushed values.
+    let push_r = s_index.push_values(&values[..(1 << 20) + 1000]).and_then(|_| s_index.flush())
+        .and_then(|_| s_index.push_values(&values[((1 << 20) + 1000)..((1 << 20) + 3000)])).and_then(|_| s_index.flush())
+        .and_then(|_| s_index.push_values(&values[((1 << 20) + 3000)..]));
+    let query_r = s_index.run_query(values[0], None, None);
+    let range_query_r = s_index.run_query(values[0], Some(1 << 20), Some((1 << 20) + 4000));
+    let num_values = s_index.num_values();
+    let segments = s_index.segments().to_vec();
+
+    let snapshot_r = s_index.snapshot(snapshot_path).and_then(|mut snapshot| snapshot.run_query(values[0], None, None));
+    let remove_r = s_index.remove_segments_before((1 << 20) + 1000);
+    let flush_r = s_index.flush();
+    let open_r = SegmentedIndex::<OZBCBitmap, u32>::open(path).and_then(|mut s_index| {
+        let segments = s_index.segments().to_vec();
+        Ok((s_index.num_values(), segments, s_index.run_query(values[0], None, None)?))
+    });
+    let _err = std::fs::remove_dir_all(path);
+    let _err = std::fs::remove_dir_all(snapshot_path);
+    assert!(push_r.is_ok() && flush_r.is_ok());
+
+    let linear_search_result: Vec<u64> = (0..n).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+    assert_eq!(num_values, n as u64);
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    let range_result: Vec<u64> = linear_search_result.iter().cloned()
+        .filter(|i| *i >= 1 << 20 && *i <= (1 << 20) + 4000).collect();
+    assert_eq!(range_query_r.unwrap(), range_result);
+    assert_eq!(segments.len(), 2);
+    assert_eq!((segments[1].start_index(), segments[1].num_values()), ((1 << 20) + 1000, 2000));
+
+    let flushed_result: Vec<u64> = linear_search_result.iter().cloned().filter(|i| *i < (1 << 20) + 3000).collect();
+    assert_eq!(snapshot_r.unwrap(), flushed_result);
+    assert_eq!(remove_r.unwrap(), 1);
+    let (open_num_values, open_segments, indexes) = open_r.unwrap();
+    assert_eq!(open_num_values, n as u64);
+    assert_eq!(open_segments.len(), 2);
+    assert_eq!(open_segments[0].id(), 1);
+    let retained_result: Vec<u64> = linear_search_result.iter().cloned().filter(|i| *i >= (1 << 20) + 1000).collect();
+    assert_eq!(indexes, retained_result);
+}
+
+#[test]
+fn container_index() {
+    let n = (1 << 20) + 5000;
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v % 100).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_container_index.cbidx");
+    let mut c_index = ContainerIndex::<OZBCBitmap, u32>::create(path, build_options.clone()).unwrap();
+    let exists_r = ContainerIndex::<OZBCBitmap, u32>::create(path, build_options);
+    // a partial chunk rewritten by the next flush and unflushed values.
+    let push_r = c_index.push_values(&values[..1000]).and_then(|_| c_index.flush())
+        .and_then(|_| c_index.push_values(&values[1000..((1 << 20) + 3000)])).and_then(|_| c_index.flush())
+        .and_then(|_| c_index.push_values(&values[((1 << 20) + 3000)..]));
+    let unflushed_values = c_index.unflushed_values();
+    let query_r = c_index.run_query(values[0], None, None);
+    let range_query_r = c_index.run_query(values[0], Some(1000), Some((1 << 20) + 4000));
+    let open_r = ContainerIndex::<OZBCBitmap, u32>::open(path).and_then(|mut c_index| {
+        Ok((c_index.num_values(), c_index.run_query(values[0], None, None)?))
+    });
+    let flush_r = c_index.flush();
+    let size_before_compact = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let compact_r = c_index.compact();
+    let size_after_compact = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let reopen_r = ContainerIndex::<OZBCBitmap, u32>::open(path).and_then(|mut c_index| {
+        c_index.push_value(values[0])?;
+        c_index.flush()?;
+        c_index.run_query(values[0], Some(1 << 20), None)
+    });
+    let _err = std::fs::remove_file(path);
+    assert!(push_r.is_ok() && flush_r.is_ok());
+    assert!(matches!(exists_r, Err(Error::ParametersError)));
+    assert_eq!(unflushed_values, 2000);
+
+    let linear_search_result: Vec<u64> = (0..n).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    let range_result: Vec<u64> = linear_search_result.iter().cloned()
+        .filter(|i| *i >= 1000 && *i <= (1 << 20) + 4000).collect();
+    assert_eq!(range_query_r.unwrap(), range_result);
+
+    let flushed_result: Vec<u64> = linear_search_result.iter().cloned().filter(|i| *i < (1 << 20) + 3000).collect();
+    let (open_num_values, open_result) = open_r.unwrap();
+    assert_eq!(open_num_values, (1 << 20) + 3000);
+    assert_eq!(open_result, flushed_result);
+    assert_eq!(compact_r.unwrap(), size_before_compact - size_after_compact);
+    assert!(size_after_compact < size_before_compact);
+
+    let mut reopen_result: Vec<u64> = linear_search_result.iter().cloned().filter(|i| *i >= 1 << 20).collect();
+    reopen_result.push(n as u64);
+    assert_eq!(reopen_r.unwrap(), reopen_result);
+}
+
+#[test]
+fn segmented_index_merge() {
+    let n = 1200000;
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v % 100).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_segmented_index_merge");
+    let mut s_index = SegmentedIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let mut num_segments: Vec<usize> = Vec::new();
+    let mut push_r = Ok(());
+    for segment_values in values[..4000].chunks(1000) {
+        push_r = push_r.and_then(|_| s_index.push_values(segment_values)).and_then(|_| s_index.flush());
+        num_segments.push(s_index.segments().len());
+    }
+    assert!(MergePolicy::size_tiered(1, 1 << 20).is_err());
+    s_index.set_merge_policy(MergePolicy::size_tiered(2, 1 << 21).ok());
+    let merge_r = s_index.merge_segments();
+    // merge two segments across a chunk boundary.
+    let push_r = push_r.and_then(|_| s_index.push_values(&values[4000..])).and_then(|_| s_index.flush());
+    let segments = s_index.segments().to_vec();
+    let query_r = s_index.run_query(values[0], None, None);
+    let open_r = SegmentedIndex::<OZBCBitmap, u32>::open(path)
+        .and_then(|mut s_index| s_index.run_query(values[n - 1], Some(1 << 20), None));
+    let num_files = std::fs::read_dir(path).map(|d| d.count()).unwrap_or(0);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    assert_eq!(num_segments, vec![1, 2, 3, 4]);
+    assert_eq!(merge_r.unwrap(), 4);
+    assert_eq!(segments.len(), 1);
+    assert_eq!((segments[0].start_index(), segments[0].num_values()), (0, n as u64));
+    // the manifest and a single segment file.
+    assert_eq!(num_files, 2);
+    let linear_search_result: Vec<u64> = (0..n).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    let linear_search_result: Vec<u64> = ((1 << 20)..n).filter(|i| values[*i] == values[n - 1]).map(|i| i as u64).collect();
+    assert_eq!(open_r.unwrap(), linear_search_result);
+}
+
+#[test]
+fn partitioned_index() {
+    let values: Vec<u32> = create_random_number(9000).iter().map(|v| v % 100).collect();
+    let keys: Vec<u64> = vec![20, 21, 23];
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_partitioned_index");
+    let mut p_index = PartitionedIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let mut push_r = Ok(());
+    for (key, partition_values) in keys.iter().zip(values.chunks(3000)) {
+        push_r = push_r.and_then(|_| p_index.push_values(*key, partition_values));
+    }
+    let old_key_r = p_index.push_value(21, 0);
+    let query_r = p_index.run_query(values[0], ..);
+    let range_query_r = p_index.run_query(values[0], 21..=22);
+    let partition = p_index.partition_of(4000).cloned();
+    let push_r = push_r.and_then(|_| p_index.flush());
+    let open_r = PartitionedIndex::<OZBCBitmap, u32>::open(path).and_then(|mut p_index| {
+        p_index.push_values(24, &values[..1000])?;
+        let partitions = p_index.partitions().to_vec();
+        Ok((partitions, p_index.run_query(values[0], 23..)?))
+    });
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert!(old_key_r.is_err());
+
+    let linear_search_result: Vec<u64> = (0..9000).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    let range_result: Vec<u64> = linear_search_result.iter().cloned().filter(|i| *i >= 3000 && *i < 6000).collect();
+    assert_eq!(range_query_r.unwrap(), range_result);
+    let partition = partition.unwrap();
+    assert_eq!((partition.key(), partition.start_index(), partition.num_values()), (21, 3000, 3000));
+
+    let (partitions, indexes) = open_r.unwrap();
+    assert_eq!(partitions.iter().map(|p| p.key()).collect::<Vec<u64>>(), vec![20, 21, 23, 24]);
+    assert_eq!((partitions[3].start_index(), partitions[3].num_values()), (9000, 1000));
+    let mut linear_search_result: Vec<u64> = (6000..9000).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+    linear_search_result.extend((0..1000).filter(|i| values[*i] == values[0]).map(|i| 9000 + i as u64));
+    assert_eq!(indexes, linear_search_result);
+}
+
+#[test]
+fn sharded_index() {
+    let n = 10007;
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v % 100).collect();
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+
+    for strategy in [ShardStrategy::RoundRobin, ShardStrategy::RowRange(1000)] {
+        let shards: Vec<BitmapIndex<OZBCBitmap, u32>> = (0..3)
+            .map(|_| BitmapIndex::new(build_options.clone()).unwrap()).collect();
+        let mut s_index = ShardedIndex::new(shards, strategy).unwrap();
+        s_index.push_values(&values).unwrap();
+        assert_eq!(s_index.num_values(), n as u64);
+        assert_eq!(s_index.to_global_index(1, 1500), match strategy {
+            ShardStrategy::RoundRobin => 4501,
+            ShardStrategy::RowRange(_) => 4500
+        });
+
+        let linear_search_result: Vec<u64> = (0..n).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+        assert_eq!(s_index.run_query(values[0], None, None).unwrap(), linear_search_result);
+        let range_result: Vec<u64> = linear_search_result.iter().cloned().filter(|i| *i >= 2500 && *i <= 7777).collect();
+        assert_eq!(s_index.run_query(values[0], Some(2500), Some(7777)).unwrap(), range_result);
+
+        // the shards of a `ShardedIndex` can be reused only with a consistent strategy.
+        let shards = s_index.into_shards();
+        let mut shards_r = ShardedIndex::new(shards, ShardStrategy::RowRange(10));
+        assert!(shards_r.is_err());
+        let shards: Vec<BitmapIndex<OZBCBitmap, u32>> = (0..3)
+            .map(|_| BitmapIndex::new(build_options.clone()).unwrap()).collect();
+        shards_r = ShardedIndex::new(shards, ShardStrategy::RowRange(0));
+        assert!(shards_r.is_err());
+    }
+}
+
+#[test]
+fn run_query_with_deadline() {
+    let values: Vec<u32> = create_random_number(3000).iter().map(|v| v % 100).collect();
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    b_index.push_values(&values).unwrap();
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+    let linear_search_result: Vec<u64> = (0..3000).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+    assert_eq!(b_index.run_query_with_deadline(values[0], None, None, deadline).unwrap(), linear_search_result);
+
+    let expired_deadline = std::time::Instant::now();
+    match b_index.run_query_with_deadline(values[0], None, None, expired_deadline) {
+        Err(Error::Timeout(QueryProgress { scanned_chunks, num_chunks, num_indexes, .. })) => {
+            assert_eq!((scanned_chunks, num_chunks, num_indexes), (0, 1, 0));
+        },
+        _ => panic!("query must time out")
+    }
+}
+
+#[test]
+fn segmented_index_merge_io_rate() {
+    let values: Vec<u32> = create_random_number(2000);
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_segmented_index_merge_io_rate");
+    let mut s_index = SegmentedIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let push_r = s_index.push_values(&values[..1000]).and_then(|_| s_index.flush())
+        .and_then(|_| s_index.push_values(&values[1000..])).and_then(|_| s_index.flush());
+    let segments_size: u64 = s_index.segments().iter()
+        .map(|segment| std::fs::metadata(s_index.segment_path(segment)).map(|m| m.len()).unwrap_or(0)).sum();
+    // merge reads at least `segments_size` bytes, so it takes at least half a second.
+    s_index.set_merge_io_rate(Some(segments_size * 2));
+    s_index.set_merge_policy(MergePolicy::size_tiered(2, 1 << 20).ok());
+    let timer = std::time::Instant::now();
+    let merge_r = s_index.merge_segments();
+    let merge_time = timer.elapsed();
+    let query_r = s_index.run_query(values[1999], None, None);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    assert_eq!(merge_r.unwrap(), 2);
+    assert!(merge_time >= std::time::Duration::from_millis(500));
+    let linear_search_result: Vec<u64> = (0..2000).filter(|i| values[*i] == values[1999]).map(|i| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+}
+
+#[test]
+fn capped_query() {
+    let values: Vec<u32> = create_random_number(5000).iter().map(|v| v % 10).collect();
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    b_index.push_values(&values).unwrap();
+    let linear_search_result: Vec<u64> = (0..5000).filter(|i| values[*i] == values[0]).map(|i| i as u64).collect();
+    let range_result: Vec<u64> = linear_search_result.iter().cloned().filter(|i| *i >= 100 && *i <= 3000).collect();
+
+    let capped_r = b_index.run_query_capped(values[0], Some(100), Some(3000), range_result.len() as u64);
+    assert_eq!(capped_r.unwrap(), QueryResult::Indexes(range_result.clone()));
+    match b_index.run_query_capped(values[0], Some(100), Some(3000), 10).unwrap() {
+        QueryResult::Selection(selection) => assert_eq!(b_index.selection_indexes(&selection), range_result),
+        QueryResult::Indexes(_) => panic!("result must be a selection")
+    }
+
+    b_index.set_max_query_results(Some(10));
+    assert!(matches!(b_index.run_query(values[0], None, None), Err(Error::TooManyResults(10))));
+    b_index.set_max_query_results(Some(linear_search_result.len() as u64));
+    assert_eq!(b_index.run_query(values[0], None, None).unwrap(), linear_search_result);
+}
+
+#[test]
+fn run_query_into() {
+    let values: Vec<u32> = create_random_number(5000).iter().map(|v| v % 10).collect();
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    b_index.push_values(&values).unwrap();
+
+    let mut indexes: Vec<u64> = Vec::with_capacity(5000);
+    let mut expected_indexes: Vec<u64> = Vec::new();
+    for value in values[0..3].iter() {
+        assert!(b_index.run_query_into(*value, Some(1000), None, &mut indexes).is_ok());
+        expected_indexes.extend(b_index.run_query(*value, Some(1000), None).unwrap());
+    }
+    assert_eq!(indexes, expected_indexes);
+
+    indexes.clear();
+    b_index.set_max_query_results(Some(1));
+    assert!(b_index.run_query_into(values[0], None, None, &mut indexes).is_err());
+    assert!(indexes.is_empty());
+}
+
+#[test]
+fn run_query_visit() {
+    let values: Vec<u32> = create_random_number(5000).iter().map(|v| v % 10).collect();
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    b_index.push_values(&values).unwrap();
+
+    let mut indexes: Vec<u64> = Vec::new();
+    let visit_r = b_index.run_query_visit(values[0], Some(100), Some(3000), |index| {
+        indexes.push(index);
+        std::ops::ControlFlow::Continue(())
+    });
+    assert_eq!(visit_r.unwrap(), std::ops::ControlFlow::Continue(()));
+    assert_eq!(indexes, b_index.run_query(values[0], Some(100), Some(3000)).unwrap());
+
+    let mut first_index: Option<u64> = None;
+    let visit_r = b_index.run_query_visit(values[0], Some(1), None, |index| {
+        first_index = Some(index);
+        std::ops::ControlFlow::Break(())
+    });
+    assert_eq!(visit_r.unwrap(), std::ops::ControlFlow::Break(()));
+    assert_eq!(first_index, (1..5000).find(|i| values[*i as usize] == values[0]));
+}
+
+#[test]
+fn push_values_batches() {
+    let values: Vec<u32> = create_random_number((1 << 20) + 10000).iter().map(|v| v % 100).collect();
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let mut b_index_batch = BitmapIndex::<OZBCBitmap, u32>::new(build_options.clone()).unwrap();
+    let mut b_index_single = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    b_index_batch.push_values(&values[0..3]).unwrap();
+    b_index_batch.push_values(&values[3..]).unwrap();
+    for v in values.iter() {
+        b_index_single.push_value(*v).unwrap();
+    }
+    assert_eq!(b_index_batch.num_values(), b_index_single.num_values());
+    for value in [values[0], values[1 << 20], 7] {
+        assert_eq!(b_index_batch.run_query(value, None, None).unwrap(), b_index_single.run_query(value, None, None).unwrap());
+    }
+}
+
+#[test]
+fn sampled_index_options() {
+    let sample: Vec<u32> = create_random_number(10000);
+    let build_options = bitrush_index::new_sampled_index_options(&sample);
+    assert_eq!(build_options.bit_block_size(), 16);
+    assert_eq!(build_options.chunk_size(), ChunkSize::M4 as u64);
+
+    let sample: Vec<u32> = sample.iter().map(|v| v % 100).collect();
+    let build_options = bitrush_index::new_sampled_index_options(&sample);
+    assert_eq!(build_options.bit_block_size(), 16);
+    assert_eq!(build_options.chunk_size(), ChunkSize::M1 as u64);
+
+    let sample: Vec<u8> = Vec::new();
+    let build_options = bitrush_index::new_sampled_index_options(&sample);
+    assert_eq!(build_options.bit_block_size(), 8);
+    assert_eq!(build_options.chunk_size(), ChunkSize::M32 as u64);
+}
+
+#[test]
+fn build_options_presets() {
+    let values: Vec<u32> = create_random_number(5000).iter().map(|v| v % 1000).collect();
+    for build_options in [BuildOptions::optimize_for_space::<u32>(), BuildOptions::optimize_for_speed::<u32>()] {
+        let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+        b_index.push_values(&values).unwrap();
+        let expected: Vec<u64> = (0..5000).filter(|i| values[*i as usize] == values[0]).collect();
+        assert_eq!(b_index.run_query(values[0], None, None).unwrap(), expected);
+    }
+    assert_eq!(BuildOptions::optimize_for_speed::<u32>().bit_block_size(), 16);
+    assert_eq!(BuildOptions::optimize_for_speed::<u8>().bit_block_size(), 8);
+    assert!(BitmapIndex::<OZBCBitmap, u8>::new(BuildOptions::optimize_for_space::<u8>()).is_ok());
+    assert!(BitmapIndex::<OZBCBitmap, u64>::new(BuildOptions::optimize_for_speed::<u64>()).is_ok());
+}
+
+#[test]
+fn meta_data_version_and_timestamps() {
+    let start = std::time::SystemTime::now() - std::time::Duration::from_secs(1);
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_meta_data_version_and_timestamps");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(1000).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values);
+    let flush_r = b_index.flush_chunk();
+    drop(b_index);
+
+    let reader_r = StorageReader::<OZBCBitmap, u16>::open(path);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && flush_r.is_ok());
+    let reader = reader_r.unwrap();
+    let meta_data = reader.meta_data();
+    let version = meta_data.library_version();
+    assert_eq!(format!("{}.{}.{}", version.0, version.1, version.2), env!("CARGO_PKG_VERSION"));
+    assert!(meta_data.created_at() >= start);
+    assert!(meta_data.modified_at() >= meta_data.created_at());
+    assert!(meta_data.modified_at() <= std::time::SystemTime::now());
+}
+
+#[test]
+fn storage_mode_wide_offsets_chunk_format() {
+    let n = (1 << 20) + 2000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_wide_offsets_chunk_format");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    b_index.set_chunk_format(ChunkFormat::WideOffsets);
+    let values: Vec<u32> = create_random_number(n);
+    // the partial chunk written in WideOffsets format is read again on open.
+    let push_r = b_index.push_values(&values[0..(n - 1000)]).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    let reopen_r = BitmapIndex::<OZBCBitmap, u32>::open(path).and_then(|mut b_index| {
+        b_index.push_values(&values[(n - 1000)..])?;
+        b_index.flush_chunk()?;
+        Ok(b_index.chunk_format())
+    });
+
+    let val_to_find = values[n - 1];
+    let query_r = BitmapIndex::<OZBCBitmap, u32>::open(path).and_then(|mut b_index| b_index.run_query(val_to_find, None, None));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert_eq!(reopen_r.unwrap(), ChunkFormat::Plain);
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate()
+        .filter(|(_i, v)| **v == val_to_find)
+        .map(|(i, _v)| i as u64).collect();
+    assert_eq!(query_r.unwrap(), linear_search_result);
+}
+
+#[test]
+fn index_writer_handle() {
+    let n = 100 * 1000;
+    let batch_size = 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_index_writer_handle");
+    let b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let mut writer = IndexWriter::new(b_index, FlushPolicy::chunk_full()).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let (handle, receiver) = IndexWriterHandle::channel(8);
+
+    // every producer sends the batches `i_producer, i_producer + 4, ...` in reverse order.
+    let producers: Vec<_> = (0..4).map(|i_producer| {
+        let handle = handle.clone();
+        let values = values.clone();
+        std::thread::spawn(move || {
+            for i_batch in (0..(n / batch_size)).rev().filter(|i| i % 4 == i_producer) {
+                let position = i_batch * batch_size;
+                handle.push_values_at(position as u64, values[position..(position + batch_size)].to_vec()).unwrap();
+            }
+        })
+    }).collect();
+    drop(handle);
+    let consume_r = writer.consume_ordered(&receiver);
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    let mut b_index = writer.finish().unwrap();
+
+    let (handle, receiver) = IndexWriterHandle::channel(8);
+    handle.push_value_at(n as u64 + 1, 0).unwrap();
+    drop(handle);
+    let mut writer = IndexWriter::new(BitmapIndex::<OZBCBitmap, u16>::open(path).unwrap(), FlushPolicy::chunk_full()).unwrap();
+    let missing_position_r = writer.consume_ordered(&receiver);
+    drop(writer);
+    let _err = std::fs::remove_dir_all(path);
+
+    assert_eq!(consume_r.unwrap(), n as u64);
+    assert!(matches!(missing_position_r, Err(Error::ParametersError)));
+    let val_to_find = values[0];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[test]
+fn frozen_index() {
+    fn assert_send_sync<S: Send + Sync>() {}
+    assert_send_sync::<FrozenIndex<OZBCBitmap, u32>>();
+
+    let n = (1 << 20) + 1000;
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let values: Vec<u32> = create_random_number(n).iter().map(|v| v % 1000).collect();
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options.clone()).unwrap();
+    b_index.push_values(&values).unwrap();
+    let memory_frozen = std::sync::Arc::new(b_index.freeze().unwrap());
+
+    let path = std::path::Path::new("test_frozen_index");
+    let storage_frozen_r = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).and_then(|mut b_index| {
+        b_index.push_values(&values)?;
+        b_index.freeze()
+    });
+    let storage_frozen = std::sync::Arc::new(storage_frozen_r.unwrap());
+
+    let handles: Vec<_> = (0..4).map(|i| {
+        let memory_frozen = memory_frozen.clone();
+        let storage_frozen = storage_frozen.clone();
+        let value = values[i];
+        std::thread::spawn(move || {
+            let memory_indexes = memory_frozen.run_query(value, Some(10), None).unwrap();
+            let storage_indexes = storage_frozen.run_query(value, Some(10), None).unwrap();
+            let storage_count = storage_frozen.run_query_count(value, Some(10), None).unwrap();
+            (value, memory_indexes, storage_indexes, storage_count)
+        })
+    }).collect();
+    let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    drop(storage_frozen);
+    let _err = std::fs::remove_dir_all(path);
+
+    assert_eq!(memory_frozen.num_values(), n as u64);
+    for (value, memory_indexes, storage_indexes, storage_count) in results {
+        let linear_search_result: Vec<u64> = values.iter().enumerate()
+            .filter(|(i, v)| **v == value && *i >= 10)
+            .map(|(i, _v)| i as u64).collect();
+        assert_eq!(memory_indexes, linear_search_result);
+        assert_eq!(storage_indexes, linear_search_result);
+        assert_eq!(storage_count, linear_search_result.len() as u64);
+    }
+}
+
+#[test]
+fn storage_mode_rewrite_chunk() {
+    let n = (2 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_rewrite_chunk");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let val_to_find = values[(1 << 20) + 5];
+    let mut push_r = b_index.push_values(&values[0..(n - 500)]).and_then(|_| b_index.flush_chunk());
+    // remove the value at position 5 of the chunk 1 (and of chunk 0 in the second rewrite).
+    let remove_position = |bitmaps: &mut [OZBCBitmap]| {
+        for b in bitmaps.iter_mut() {
+            let mut new_b = OZBCBitmap::new();
+            for position in b.unroll_bitmap().into_iter().filter(|position| *position != 5) {
+                new_b.set(position);
+            }
+            *b = new_b;
+        }
+    };
+    let rewrite_r = b_index.rewrite_chunk(1, remove_position);
+    let extent_r = b_index.chunk_disk_extent(1);
+    let partial_rewrite_r = b_index.rewrite_chunk(2, remove_position);
+    push_r = push_r.and(b_index.push_values(&values[(n - 500)..])).and_then(|_| b_index.flush_chunk());
+    let second_rewrite_r = b_index.rewrite_chunk(0, remove_position);
+    drop(b_index);
+
+    let recover_r = BitmapIndex::<OZBCBitmap, u16>::recover(path);
+    let query_r = recover_r.and_then(|(mut b_index, rolled_back)| {
+        Ok((rolled_back, b_index.run_query(val_to_find, None, None)?, b_index.run_query(values[5], None, None)?))
+    });
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert_eq!(extent_r.unwrap(), Some(rewrite_r.unwrap()));
+    assert!(matches!(partial_rewrite_r, Err(Error::ParametersError)));
+    assert!(second_rewrite_r.is_ok());
+
+    let (rolled_back, indexes, indexes_0) = query_r.unwrap();
+    assert_eq!(rolled_back, 0);
+    let linear_search = |value: u16| -> Vec<u64> {
+        values.iter().enumerate()
+            .filter(|(i, v)| **v == value && *i != 5 && *i != (1 << 20) + 5)
+            .map(|(i, _v)| i as u64).collect()
+    };
+    assert_eq!(indexes, linear_search(val_to_find));
+    assert_eq!(indexes_0, linear_search(values[5]));
+}
+
+#[test]
+fn query_at_watermark() {
+    let n = (1 << 21) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_query_at_watermark");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let val_to_find = values[7];
+    let watermark: u64 = (1 << 20) + 300;
+    let push_r = b_index.push_values(&values[0..watermark as usize]).and_then(|_| b_index.flush_chunk());
+    let push_r = push_r.and(b_index.push_values(&values[watermark as usize..]));
+    let query_r = b_index.query_at(watermark, val_to_find, Some(1000), None);
+    let empty_r = b_index.query_at(0, val_to_find, None, None);
+    let invalid_r = b_index.query_at(n as u64 + 1, val_to_find, None, None);
+
+    let reader_r = StorageReader::<OZBCBitmap, u16>::open(path);
+    let reader_query_r = reader_r.and_then(|mut reader| reader.query_at(1 << 20, val_to_find, None, Some(1 << 21)));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    let linear_search = |start_index: usize, watermark: u64| -> Vec<u64> {
+        values.iter().enumerate()
+            .filter(|(i, v)| **v == val_to_find && *i >= start_index && (*i as u64) < watermark)
+            .map(|(i, _v)| i as u64).collect()
+    };
+    assert_eq!(query_r.unwrap(), linear_search(1000, watermark));
+    assert!(empty_r.unwrap().is_empty());
+    assert!(matches!(invalid_r, Err(Error::ParametersError)));
+    assert_eq!(reader_query_r.unwrap(), linear_search(0, 1 << 20));
+}
+
+#[test]
+fn reader_session() {
+    let n = (1 << 21) + 1000;
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let val_to_find = values[7];
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_reader_session");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let tail: SharedTail<OZBCBitmap> = SharedTail::new();
+    let mut session = ReaderSession::new(StorageReader::<OZBCBitmap, u16>::open(path).unwrap(), tail.clone());
+    let empty_r = session.query(val_to_find, None, None);
+    let push_r = b_index.push_values(&values[..(1 << 20) + 500]).and(b_index.publish_tail(&tail));
+    let published_r = std::thread::spawn(move || {
+        let query_r = session.query(val_to_find, None, None);
+        let count_r = session.query_count(val_to_find, Some(1000), Some((1 << 20) + 100));
+        (session, query_r, count_r)
+    }).join().unwrap();
+    let (mut session, published_r, count_r) = published_r;
+    let push_r = push_r.and(b_index.push_values(&values[(1 << 20) + 500..]));
+    let stale_tail_r = session.query(val_to_find, None, None);
+    let publish_r = b_index.publish_tail(&tail);
+    let last_r = session.query(val_to_find, Some(1000), None);
+    let memory_index = BitmapIndex::<OZBCBitmap, u16>::new(BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    let memory_publish_r = memory_index.publish_tail(&tail);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && publish_r.is_ok());
+
+    let linear_search = |start_index: u64, end_index: u64| -> Vec<u64> {
+        values.iter().enumerate()
+            .filter(|(i, v)| **v == val_to_find && *i as u64 >= start_index && (*i as u64) < end_index)
+            .map(|(i, _v)| i as u64).collect()
+    };
+    assert!(empty_r.unwrap().is_empty());
+    assert_eq!(published_r.unwrap(), linear_search(0, (1 << 20) + 500));
+    assert_eq!(count_r.unwrap(), linear_search(1000, (1 << 20) + 101).len() as u64);
+    // the tail of the second chunk is older than the durable chunks.
+    assert_eq!(stale_tail_r.unwrap(), linear_search(0, 1 << 21));
+    assert_eq!(tail.num_values(), n as u64);
+    assert_eq!(last_r.unwrap(), linear_search(1000, n as u64));
+    assert!(matches!(memory_publish_r, Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_appended_since() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<i16> = create_random_number(n).iter().map(|v| *v as i16).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_appended_since");
+    let mut b_index = BitmapIndex::<OZBCBitmap, i16>::create(path, build_options).unwrap();
+    let push_r = b_index.push_values(&values);
+    let watermark = (1 << 20) - 100;
+    let appended_r = b_index.appended_since(watermark);
+    let values_r = appended_r.as_ref().map_err(|_| Error::ParametersError).and_then(|chunks| {
+        chunks.iter().map(|chunk| b_index.appended_chunk_values(chunk)).collect::<Result<Vec<Vec<i16>>, Error>>()
+    });
+    let up_to_date_r = b_index.appended_since(n as u64);
+    let future_r = b_index.appended_since(n as u64 + 1);
+    let invalid_r = b_index.appended_chunk_values(&AppendedChunk { chunk_id: 0, value_range: 0..(1 << 20) + 1 });
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let appended = appended_r.unwrap();
+    let value_ranges: Vec<std::ops::Range<u64>> = appended.iter().map(|chunk| chunk.value_range.clone()).collect();
+    assert_eq!(appended.iter().map(|chunk| chunk.chunk_id).collect::<Vec<u64>>(), vec![0, 1]);
+    assert_eq!(value_ranges, vec![watermark..(1 << 20), (1 << 20)..(n as u64)]);
+    assert_eq!(values_r.unwrap().concat(), values[(watermark as usize)..].to_vec());
+    assert!(up_to_date_r.unwrap().is_empty());
+    assert!(matches!(future_r, Err(Error::ParametersError)));
+    assert!(matches!(invalid_r, Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_backup() {
+    let n = (1 << 20) + 1000;
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let val_to_find = values[0];
+    let linear_search = |num_values: usize| -> Vec<u64> {
+        values[..num_values].iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect()
+    };
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_backup");
+    let backup_path = std::path::Path::new("test_storage_mode_backup_copy");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options.clone()).unwrap();
+    let push_r = b_index.push_values(&values);
+    let backup_r = b_index.backup_to(backup_path);
+    let existing_r = b_index.backup_to(backup_path);
+    let backup_query_r = BitmapIndex::<OZBCBitmap, u16>::open(backup_path).and_then(|mut backup_idx| {
+        backup_idx.run_query(val_to_find, None, None).map(|indexes| (backup_idx.num_values(), indexes))
+    });
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    let _err = std::fs::remove_dir_all(backup_path);
+    let memory_r = BitmapIndex::<OZBCBitmap, u16>::new(build_options.clone()).and_then(|b_index| b_index.backup_to(backup_path));
+
+    // backups taken while another thread pushes and flushes the index.
+    let writer_values = values.clone();
+    let mut writer_idx = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let writer = std::thread::spawn(move || {
+        for batch in writer_values.chunks(1 << 17) {
+            writer_idx.push_values(batch)?;
+            writer_idx.flush_chunk()?;
+        }
+        Ok::<(), Error>(())
+    });
+    let mut online_r = Vec::new();
+    while online_r.len() < 16 && !writer.is_finished() {
+        let online_path = std::path::PathBuf::from(format!("test_storage_mode_backup_online_{}", online_r.len()));
+        let r = BitmapIndex::<OZBCBitmap, u16>::backup(path, &online_path).and_then(|meta_data| {
+            let mut backup_idx = BitmapIndex::<OZBCBitmap, u16>::open(&online_path)?;
+            let indexes = backup_idx.run_query(val_to_find, None, None)?;
+            Ok((meta_data.num_values(), backup_idx.num_values(), indexes))
+        });
+        let _err = std::fs::remove_dir_all(&online_path);
+        online_r.push(r);
+    }
+    let writer_r = writer.join().unwrap();
+    let _err = std::fs::remove_dir_all(path);
+
+    assert!(push_r.is_ok());
+    assert!(writer_r.is_ok());
+    assert_eq!(backup_r.unwrap().num_values(), 1 << 20);
+    assert_eq!(backup_query_r.unwrap(), (1 << 20, linear_search(1 << 20)));
+    assert!(matches!(existing_r, Err(Error::ParametersError)));
+    assert!(matches!(memory_r, Err(Error::ParametersError)));
+    for r in online_r {
+        let (meta_data_num_values, num_values, indexes) = r.unwrap();
+        assert_eq!(meta_data_num_values, num_values);
+        assert_eq!(indexes, linear_search(num_values as usize));
+    }
+}
+
+#[test]
+fn storage_mode_flush_events() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_flush_events");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let events = b_index.flush_events();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    let extent_r = (0..2).map(|chunk_id| b_index.chunk_disk_extent(chunk_id)).collect::<Result<Vec<_>, Error>>();
+    b_index.remove_flush_listener();
+    let no_listener_push_r = b_index.push_value(values[0]).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert!(no_listener_push_r.is_ok());
+
+    let events: Vec<FlushEvent> = events.try_iter().collect();
+    let extents = extent_r.unwrap();
+    assert_eq!(events.len(), 2);
+    for (chunk_id, (event, extent)) in events.iter().zip(extents.iter()).enumerate() {
+        let extent = extent.clone().unwrap();
+        let start_index = chunk_id as u64 * (1 << 20);
+        assert_eq!(event.chunk_id, chunk_id as u64);
+        assert_eq!(event.value_range, start_index..(n as u64).min(start_index + (1 << 20)));
+        assert_eq!(event.bytes_written, extent.end - extent.start);
+    }
+}
+
+#[test]
+fn storage_mode_replication() {
+    let n = (1 << 21) + 1000;
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let leader_path = std::path::Path::new("test_storage_mode_replication_leader");
+    let follower_path = std::path::Path::new("test_storage_mode_replication_follower");
+    let mut leader = BitmapIndex::<OZBCBitmap, u16>::create(leader_path, build_options.clone()).unwrap();
+    let mut follower = BitmapIndex::<OZBCBitmap, u16>::create(follower_path, build_options).unwrap();
+    let apply = |leader: &mut BitmapIndex<OZBCBitmap, u16>, follower: &mut BitmapIndex<OZBCBitmap, u16>| {
+        let payloads = leader.replication_stream(follower.num_values())?.collect::<Result<Vec<ChunkPayload>, Error>>()?;
+        for payload in payloads.iter() {
+            follower.apply_chunk_payload(payload)?;
+        }
+        Ok::<usize, Error>(payloads.len())
+    };
+    // a full chunk and a partial chunk, then the partial chunk is completed.
+    let push_r = leader.push_values(&values[..(1 << 20) + 500]).and_then(|_| leader.flush_chunk());
+    let first_apply_r = apply(&mut leader, &mut follower);
+    let push_r = push_r.and_then(|_| leader.push_values(&values[((1 << 20) + 500)..])).and_then(|_| leader.flush_chunk());
+    let second_apply_r = apply(&mut leader, &mut follower);
+    let up_to_date_r = apply(&mut leader, &mut follower);
+    let rewrite_r = leader.rewrite_chunk(0, |bitmaps| bitmaps.iter_mut().for_each(|b| b.clear()))
+        .and_then(|_| leader.chunk_payload(0))
+        .and_then(|payload| follower.apply_chunk_payload(&payload));
+    let stale_r = leader.chunk_payload(2).and_then(|payload| follower.apply_chunk_payload(&payload));
+    let mut corrupted = leader.chunk_payload(2).unwrap();
+    corrupted.bytes.pop();
+    let corrupted_r = follower.apply_chunk_payload(&corrupted);
+    let leader_result = leader.run_query(values[n - 1], None, None);
+    let follower_result = follower.run_query(values[n - 1], None, None);
+    drop(follower);
+    let reopen_r = BitmapIndex::<OZBCBitmap, u16>::open(follower_path)
+        .and_then(|mut follower| Ok((follower.num_values(), follower.run_query(values[n - 1], None, None)?)));
+    let _err = std::fs::remove_dir_all(leader_path);
+    let _err = std::fs::remove_dir_all(follower_path);
+    assert!(push_r.is_ok() && rewrite_r.is_ok());
+    assert_eq!(first_apply_r.unwrap(), 2);
+    assert_eq!(second_apply_r.unwrap(), 2);
+    assert_eq!(up_to_date_r.unwrap(), 0);
+    assert!(matches!(stale_r, Err(Error::ParametersError)));
+    assert!(matches!(corrupted_r, Err(Error::BitmapError)));
+
+    let linear_search_result: Vec<u64> = (0..n).filter(|i| *i >= 1 << 20 && values[*i] == values[n - 1]).map(|i| i as u64).collect();
+    assert_eq!(leader_result.unwrap(), linear_search_result);
+    assert_eq!(follower_result.unwrap(), linear_search_result);
+    assert_eq!(reopen_r.unwrap(), (n as u64, linear_search_result));
+
+    let mut memory_index = BitmapIndex::<OZBCBitmap, u16>::new(BuildOptions::new(8, ChunkSize::M1)).unwrap();
+    assert!(matches!(memory_index.replication_stream(0), Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_storage_stats() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_storage_stats");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options.clone()).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    let user_meta_data_r = b_index.set_user_meta_data("key", b"value");
+    let write_stats = b_index.storage_stats();
+    let query_r = b_index.run_query(values[7], None, None);
+    let query_stats = b_index.storage_stats();
+    b_index.reset_storage_stats();
+    let reset_stats = b_index.storage_stats();
+    drop(b_index);
+    let data_size_r = std::fs::metadata(path.join("test_storage_mode_storage_stats.dbidx")).map(|m| m.len());
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert!(user_meta_data_r.is_ok());
+    assert!(query_r.is_ok());
+
+    let write_stats = write_stats.unwrap();
+    assert_eq!(write_stats.chunk_flushes, 2);
+    assert_eq!(write_stats.meta_data_writes, 3);
+    assert_eq!(write_stats.data_bytes_written, data_size_r.unwrap());
+    assert_eq!(write_stats.offset_bytes_written, 2 * 16);
+    assert_eq!(write_stats.user_meta_data_bytes_written, 4 + 4 + 3 + 4 + 5);
+    assert_eq!(write_stats.queries, 0);
+    assert!(write_stats.total_bytes_written() > write_stats.data_bytes_written);
+
+    let query_stats = query_stats.unwrap();
+    assert_eq!(query_stats.queries, 1);
+    assert!(query_stats.bytes_read > 0);
+    assert_eq!(query_stats.bytes_read, query_stats.last_query_bytes_read);
+    assert_eq!(reset_stats, Some(StorageStats::default()));
+    assert_eq!(BitmapIndex::<OZBCBitmap, u16>::new(build_options).unwrap().storage_stats(), None);
+}
+
+#[test]
+fn storage_mode_coarse_layer() {
+    let n = (2 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_coarse_layer");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options.clone()).unwrap();
+    let values: Vec<u32> = create_random_number(n).iter().enumerate()
+        .map(|(i, v)| (i as u32 >> 20) * 10000 + *v % 1000).collect();
+    let push_r = b_index.push_values(&values[0..(1 << 20)]);
+    drop(b_index);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::open(path).unwrap();
+    let val_to_find = values[n - 1];
+    let reference_r = b_index.run_query(val_to_find, None, None);
+    let reference_bytes_read = b_index.storage_stats().unwrap().last_query_bytes_read;
+    let coarse_r = b_index.use_coarse_layer();
+    let push_r = push_r.and_then(|_| b_index.push_values(&values[(1 << 20)..]));
+    let coarse_size = b_index.coarse_layer_size();
+    let query_r = b_index.run_query(val_to_find, None, None);
+    let coarse_bytes_read = b_index.storage_stats().unwrap().last_query_bytes_read;
+    let grouped_r = b_index.run_query_grouped(&[val_to_find, values[0]]);
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok() && coarse_r.is_ok());
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert!(reference_r.unwrap().is_empty());
+    assert_eq!(query_r.unwrap(), linear_search_result);
+    assert_eq!(coarse_size, 2 * 1024 / 8);
+    assert!(coarse_bytes_read < reference_bytes_read);
+    let grouped = grouped_r.unwrap();
+    assert_eq!(grouped[0].1, linear_search_result);
+    assert!(grouped[1].1.contains(&0));
+
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::new(build_options).unwrap();
+    assert!(b_index.use_chunk_arena().is_ok() && b_index.use_coarse_layer().is_ok());
+    assert!(b_index.push_values(&values).is_ok());
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+}
+
+#[test]
+fn storage_mode_value_stats() {
+    let n = (2 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_value_stats");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u8>::create(path, build_options.clone()).unwrap();
+    let mut values: Vec<u8> = create_random_number(n).iter().map(|v| (*v % 7) as u8 * (*v % 3) as u8).collect();
+    let push_r = b_index.push_values(&values[0..(1 << 20) + 10]);
+    let no_stats_r = b_index.estimate_count(0);
+    let stats_r = b_index.use_value_stats();
+    let push_r = push_r.and_then(|_| b_index.push_values(&values[(1 << 20) + 10..(2 << 20)]));
+    // the values of the chunk 0 become 0.
+    let rewrite_r = b_index.rewrite_chunk(0, |bitmaps| {
+        let all_positions: Vec<u32> = (0..(1 << 20)).collect();
+        bitmaps.iter_mut().for_each(|b| b.clear());
+        all_positions.iter().for_each(|pos| bitmaps[0].set(*pos));
+    });
+    values[0..(1 << 20)].iter_mut().for_each(|v| *v = 0);
+    let push_r = push_r.and_then(|_| b_index.push_values(&values[(2 << 20)..])).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    let mut b_index = BitmapIndex::<OZBCBitmap, u8>::open(path).unwrap();
+    let has_stats = b_index.has_value_stats();
+    let estimate_r: Result<Vec<u64>, Error> = (0..13).map(|v| b_index.estimate_count(v)).collect();
+    let top_r = b_index.top_values(3);
+    let push_value_r = b_index.push_value(1);
+    let after_push_r = b_index.estimate_count(1);
+    drop(b_index);
+    let _err = std::fs::remove_dir_all(path);
+
+    let mut m_index = BitmapIndex::<OZBCBitmap, u16>::new(build_options).unwrap();
+    let m_values: Vec<u16> = values.iter().map(|v| *v as u16 * 300).collect();
+    let m_push_r = m_index.push_values(&m_values).and_then(|_| m_index.use_value_stats());
+    let m_estimate_r = m_index.estimate_count(600);
+    let m_top_r = m_index.top_values(3);
+
+    assert!(push_r.is_ok() && stats_r.is_ok() && rewrite_r.is_ok() && push_value_r.is_ok() && m_push_r.is_ok());
+    assert!(matches!(no_stats_r, Err(Error::ParametersError)));
+    assert!(has_stats);
+    let counts: Vec<u64> = (0..13).map(|v| values.iter().filter(|value| **value == v).count() as u64).collect();
+    assert_eq!(estimate_r.unwrap(), counts);
+    let mut expected_top: Vec<(u8, u64)> = counts.iter().enumerate().map(|(v, count)| (v as u8, *count)).collect();
+    expected_top.sort_by(|c0, c1| c1.1.cmp(&c0.1).then(c0.0.cmp(&c1.0)));
+    assert_eq!(top_r.unwrap(), expected_top[0..3].to_vec());
+    assert_eq!(after_push_r.unwrap(), counts[1] + 1);
+    assert!(m_estimate_r.unwrap() >= counts[2]);
+    assert!(matches!(m_top_r, Err(Error::ParametersError)));
+}
+
+#[test]
+fn storage_mode_on_disk_size() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_on_disk_size");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options.clone()).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    let no_user_meta_data_size_r = b_index.on_disk_size();
+    let user_meta_data_r = b_index.set_user_meta_data("key", b"value");
+    let disk_size_r = b_index.on_disk_size();
+    drop(b_index);
+    let file_size = |extension: &str| {
+        std::fs::metadata(path.join("test_storage_mode_on_disk_size").with_extension(extension)).map(|m| m.len()).unwrap_or(0)
+    };
+    let files_size = (file_size("mbidx"), file_size("obidx"), file_size("dbidx"), file_size("ubidx"));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert!(user_meta_data_r.is_ok());
+
+    assert_eq!(no_user_meta_data_size_r.unwrap().unwrap().user_meta_data_file, 0);
+    let disk_size: DiskSize = disk_size_r.unwrap().unwrap();
+    assert_eq!((disk_size.meta_data_file, disk_size.offset_file, disk_size.data_file, disk_size.user_meta_data_file), files_size);
+    assert_eq!(disk_size.total(), files_size.0 + files_size.1 + files_size.2 + files_size.3);
+    assert_eq!(disk_size.chunks.len(), 2);
+    assert_eq!(disk_size.chunks.iter().sum::<u64>(), disk_size.data_file);
+    assert_eq!(BitmapIndex::<OZBCBitmap, u16>::new(build_options).unwrap().on_disk_size().unwrap(), None);
+}
+
+#[test]
+fn compression_report() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_compression_report");
+    let mut storage_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options.clone()).unwrap();
+    let mut memory_index = BitmapIndex::<OZBCBitmap, u16>::new(build_options).unwrap();
+    // values < 16, so only 16 + 1 of the 512 bitmaps of each chunk are non-empty.
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| (*v % 16) as u16).collect();
+    assert!(memory_index.push_values(&values).is_ok());
+    let push_r = storage_index.push_values(&values);
+    let storage_report_r = storage_index.compression_report();
+    drop(storage_index);
+    let reader_report_r = StorageReader::<OZBCBitmap, u16>::open(path).and_then(|mut reader| reader.compression_report());
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let memory_report: Vec<ChunkCompression> = memory_index.compression_report().unwrap();
+    assert_eq!(memory_report.len(), 2);
+    assert_eq!(memory_report, storage_report_r.unwrap());
+    assert_eq!(&memory_report[0..1], &reader_report_r.unwrap()[..]);
+    for (chunk_id, chunk) in memory_report.iter().enumerate() {
+        assert_eq!(chunk.chunk_id, chunk_id as u64);
+        assert_eq!(chunk.non_empty_bitmaps, 17);
+        assert_eq!(chunk.uncompressed_size, 512 * chunk.num_values.div_ceil(8));
+        assert!(chunk.compression_ratio() > 1.0);
+    }
+    assert_eq!(memory_report[1].num_values, 1000);
+}
+
+#[test]
+fn storage_mode_open_consistency_check() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(8, ChunkSize::M1);
+    let path = std::path::Path::new("test_storage_mode_open_consistency_check");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u16>::create(path, build_options).unwrap();
+    let values: Vec<u16> = create_random_number(n).iter().map(|v| *v as u16).collect();
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+    let data_path = path.join("test_storage_mode_open_consistency_check.dbidx");
+    let offset_path = path.join("test_storage_mode_open_consistency_check.obidx");
+    let consistent_r = BitmapIndex::<OZBCBitmap, u16>::open(path).map(|_b_index| ());
+
+    // a chunk appended to the data file without its meta data.
+    let data_size = std::fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+    let append_r = std::fs::OpenOptions::new().append(true).open(&data_path).and_then(|mut f| std::io::Write::write_all(&mut f, &[0; 64]));
+    let data_tail_r = BitmapIndex::<OZBCBitmap, u16>::open(path).map(|_b_index| ());
+    let recover_r = BitmapIndex::<OZBCBitmap, u16>::recover(path).map(|(_b_index, rolled_back)| rolled_back);
+    let recovered_data_size = std::fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+    // the offsets of the last chunk are lost.
+    let truncate_r = std::fs::OpenOptions::new().write(true).open(&offset_path).and_then(|f| f.set_len(8));
+    let offsets_r = BitmapIndex::<OZBCBitmap, u16>::open(path).map(|_b_index| ());
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+    assert!(append_r.is_ok());
+    assert!(truncate_r.is_ok());
+
+    assert!(consistent_r.is_ok());
+    assert!(matches!(data_tail_r, Err(Error::InconsistentStorage(ref message)) if message.contains("recover")));
+    assert_eq!(recover_r.unwrap(), 0);
+    assert_eq!(recovered_data_size, data_size);
+    assert!(matches!(offsets_r, Err(Error::InconsistentStorage(_))));
+}
+
+#[test]
+fn storage_mode_wide_words() {
+    let n = (1 << 20) + 1000;
+
+    let build_options = BuildOptions::new(16, ChunkSize::M1).with_wide_words(true);
+    let path = std::path::Path::new("test_storage_mode_wide_words");
+    let mut b_index = BitmapIndex::<OZBCBitmap, u32>::create(path, build_options).unwrap();
+    let values: Vec<u32> = create_random_number(n);
+    let push_r = b_index.push_values(&values).and_then(|_| b_index.flush_chunk());
+    drop(b_index);
+
+    let val_to_find = values[7];
+    let query_r = BitmapIndex::<OZBCBitmap, u32>::open(path).and_then(|mut b_index| {
+        let wide_words = b_index.snapshot_metadata().0.build_options().wide_words();
+        Ok((wide_words, b_index.run_query(val_to_find, None, None)?))
+    });
+    #[cfg(feature = "mmap")]
+    let mmap_query_r = StorageReader::<OZBCBitmap, u32>::open(path).and_then(|mut reader| reader.query_mmap(val_to_find, None, None));
+    let _err = std::fs::remove_dir_all(path);
+    assert!(push_r.is_ok());
+
+    let linear_search_result: Vec<u64> = values.iter().enumerate()
+        .filter(|(_i, v)| **v == val_to_find)
+        .map(|(i, _v)| i as u64).collect();
+    let (wide_words, indexes) = query_r.unwrap();
+    assert!(wide_words);
+    assert_eq!(indexes, linear_search_result);
+    #[cfg(feature = "mmap")]
+    assert_eq!(mmap_query_r.unwrap(), linear_search_result[..linear_search_result.partition_point(|i| *i < 1 << 20)]);
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "bitvec")]
+
+use bitrush_index::{Bitmap, BitVecBitmap, OZBCBitmap};
+use rand::Rng;
+
+fn create_sorted_values(n: usize, max_value: u32) -> Vec<u32> {
+    let mut rng = rand::thread_rng();
+    let mut values: Vec<u32> = (0..n).map(|_i| rng.gen::<u32>() % max_value).collect();
+    values.sort_unstable();
+    values.dedup();
+    values
+}
+
+#[test]
+fn set_base() {
+    let mut b0 = BitVecBitmap::new();
+    let values = [0, 1, 100, 100000, 99999, 2, 100001, 1000000];
+    let values_ok = [0, 1, 100, 100000, 100001, 1000000];
+
+    for val in values.iter() {
+        b0.set(*val);
+    }
+    assert_eq!(b0.unroll_bitmap(), values_ok);
+
+    b0.clear();
+    assert!(b0.is_empty());
+    assert_eq!(b0, BitVecBitmap::new());
+    b0.set(70000);
+    assert_eq!(b0.unroll_bitmap(), vec![70000]);
+}
+
+#[test]
+fn bitand_and_write_read() {
+    let values_0 = create_sorted_values(100000, 1 << 22);
+    let values_1 = create_sorted_values(100000, 1 << 21);
+
+    let mut v0 = BitVecBitmap::new();
+    let mut v1 = BitVecBitmap::new();
+    let mut b0 = OZBCBitmap::new();
+    let mut b1 = OZBCBitmap::new();
+    for val in values_0.iter() {
+        v0.set(*val);
+        b0.set(*val);
+    }
+    for val in values_1.iter() {
+        v1.set(*val);
+        b1.set(*val);
+    }
+    let v_and = (&v0) & (&v1);
+    assert_eq!(v0.unroll_bitmap(), values_0);
+    assert_eq!(v_and.unroll_bitmap(), ((&b0) & (&b1)).unroll_bitmap());
+
+    let mut buf: Vec<u8> = vec![0; v_and.size()];
+    assert_eq!(v_and.write_to_buffer(&mut buf), Ok(buf.len()));
+    let mut v2 = BitVecBitmap::new();
+    assert!(v2.read_from_buffer(&buf, true).is_ok());
+    assert_eq!(v2, v_and);
+
+    assert!(v2.read_from_buffer(&buf[0..(buf.len() - 1)], true).is_err());
+    buf[0] = buf[0].wrapping_sub(1);
+    assert!(v2.read_from_buffer(&buf, true).is_err());
+    assert_eq!(v2, v_and);
+}
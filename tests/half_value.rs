@@ -0,0 +1,55 @@
+#![cfg(feature = "half")]
+
+use bitrush_index::{Bf16Value, BitmapIndex, ChunkSize, F16Value, OZBCBitmap};
+use half::{bf16, f16};
+use rand::Rng;
+
+#[test]
+fn half_value_order() {
+    let floats = [-65504.0, -2.5, -1.0, -0.0, 0.0, 0.5, 1.0, 3.25, 65504.0];
+    let values: Vec<F16Value> = floats.iter().map(|v| F16Value::new(f16::from_f32(*v))).collect();
+    assert!(values.windows(2).all(|w| w[0] < w[1]));
+    for (v, f) in values.iter().zip(floats.iter()) {
+        assert_eq!(v.value().to_f32().to_bits(), f.to_bits());
+    }
+    let values: Vec<Bf16Value> = floats.iter().map(|v| Bf16Value::from(bf16::from_f32(*v))).collect();
+    assert!(values.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(values[1].to_string(), "-2.5");
+}
+
+#[test]
+fn half_value_index() {
+    let n = (1 << 20) + 1000;
+    let mut rng = rand::thread_rng();
+    let values: Vec<F16Value> = (0..n).map(|_i| F16Value::new(f16::from_f32(rng.gen_range(-100.0f32, 100.0)))).collect();
+    let mut b_index = BitmapIndex::<OZBCBitmap, F16Value>::new(F16Value::build_options(ChunkSize::M1)).unwrap();
+    assert!(b_index.push_values(&values).is_ok());
+
+    let val_to_find = values[n - 1];
+    let linear_search_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v == val_to_find).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query(val_to_find, None, None).unwrap(), linear_search_result);
+
+    let (low, high) = (F16Value::new(f16::from_f32(-1.0)), F16Value::new(f16::from_f32(1.0)));
+    let range_result: Vec<u64> = values.iter().enumerate().filter(|(_i, v)| **v >= low && **v <= high).map(|(i, _v)| i as u64).collect();
+    assert_eq!(b_index.run_query_range_approx(low, high, None, None).unwrap(), range_result);
+}
+
+#[test]
+fn half_value_approx_query() {
+    let n = (1 << 20) + 1000;
+    let mut rng = rand::thread_rng();
+    let floats: Vec<f16> = (0..n).map(|_i| f16::from_f32(rng.gen_range(-4.0f32, 4.0))).collect();
+    let values: Vec<F16Value> = floats.iter().map(|v| F16Value::new(*v)).collect();
+    let mut b_index = BitmapIndex::<OZBCBitmap, F16Value>::new(F16Value::build_options(ChunkSize::M1)).unwrap();
+    assert!(b_index.push_values(&values).is_ok());
+
+    let linear_search = |value: f32, epsilon: f32| -> Vec<u64> {
+        floats.iter().enumerate().filter(|(_i, v)| (v.to_f32() - value).abs() <= epsilon).map(|(i, _v)| i as u64).collect()
+    };
+    for (value, epsilon) in [(1.0f32, 0.01f32), (-2.5, 0.1), (0.0, 0.001), (3.0, 0.0), (0.1, 0.25)] {
+        let approx_r = b_index.run_query_approx(f16::from_f32(value), f16::from_f32(epsilon), None, None);
+        assert_eq!(approx_r.unwrap(), linear_search(f16::from_f32(value).to_f32(), f16::from_f32(epsilon).to_f32()));
+    }
+    assert!(b_index.run_query_approx(f16::from_f32(1.0), f16::from_f32(-0.5), None, None).is_err());
+    assert!(b_index.run_query_approx(f16::NAN, f16::from_f32(0.5), None, None).is_err());
+}
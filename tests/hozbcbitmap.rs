@@ -0,0 +1,109 @@
+use bitrush_index::{Bitmap, BitmapIndex, BuildOptions, ChunkSize, HOZBCBitmap, OZBCBitmap};
+use rand::Rng;
+
+fn create_sparse_values(n: usize) -> Vec<u32> {
+    let mut rng = rand::thread_rng();
+    let mut values: Vec<u32> = (0..n).map(|_i| rng.gen::<u32>()).collect();
+    values.sort_unstable();
+    values.dedup();
+    values
+}
+
+#[test]
+fn set_base() {
+    let mut b0 = HOZBCBitmap::new();
+    let mut b1 = b0.clone();
+    let values = [0, 1, 100, 100000, 99999, 2, 100001, 1000000, 65536, 70000];
+    let values_ok = [0, 1, 100, 100000, 100001, 1000000];
+
+    for val in values.iter() {
+        b0.set(*val);
+    }
+
+    for val in values_ok.iter() {
+        b1.set(*val);
+    }
+
+    assert_eq!(b0, b1);
+    assert_eq!(b0.unroll_bitmap(), values_ok);
+
+    b0.clear();
+    assert!(b0.is_empty());
+    assert_eq!(b0, HOZBCBitmap::new());
+    b0.set(70000);
+    assert_eq!(b0.unroll_bitmap(), vec![70000]);
+}
+
+#[test]
+fn bitand_and_unroll() {
+    let values_0 = create_sparse_values(10000);
+    let mut values_1 = create_sparse_values(10000);
+    values_1.extend(values_0.iter().step_by(3));
+    values_1.sort_unstable();
+    values_1.dedup();
+
+    let mut h0 = HOZBCBitmap::new();
+    let mut h1 = HOZBCBitmap::new();
+    let mut b0 = OZBCBitmap::new();
+    let mut b1 = OZBCBitmap::new();
+    for val in values_0.iter() {
+        h0.set(*val);
+        b0.set(*val);
+    }
+    for val in values_1.iter() {
+        h1.set(*val);
+        b1.set(*val);
+    }
+
+    let h_and = (&h0) & (&h1);
+    let b_and = (&b0) & (&b1);
+    assert_eq!(h0.unroll_bitmap(), values_0);
+    assert_eq!(h_and.unroll_bitmap(), b_and.unroll_bitmap());
+    for val in values_1.iter() {
+        assert_eq!(h0.contains(*val), values_0.binary_search(val).is_ok());
+        assert_eq!(h0.contains(*val + 1), values_0.binary_search(&(*val + 1)).is_ok());
+    }
+}
+
+#[test]
+fn write_read_advanced() {
+    let mut b0 = HOZBCBitmap::new();
+    for val in create_sparse_values(100000).iter() {
+        b0.set(*val);
+    }
+
+    let b1 = b0.clone();
+    let mut buf: Vec<u8> = vec![0; b0.size()];
+    let r_write = b0.write_to_buffer(&mut buf);
+    assert!(r_write.is_ok());
+    assert_eq!(r_write.unwrap(), buf.len());
+    let mut stream: Vec<u8> = Vec::new();
+    assert_eq!(b0.write_to(&mut stream).unwrap(), buf.len());
+    assert_eq!(stream, buf);
+
+    b0 = HOZBCBitmap::new();
+    let r_read = b0.read_from_buffer(&buf, true);
+    assert!(r_read.is_ok());
+    assert_eq!(b0, b1);
+
+    assert!(b0.read_from_buffer(&buf[0..(buf.len() - 1)], true).is_err());
+    assert_eq!(b0, b1);
+}
+
+#[test]
+fn memory_mode_index() {
+    let build_options = BuildOptions::new(16, ChunkSize::M1);
+    let mut h_index = BitmapIndex::<HOZBCBitmap, u64>::new(build_options.clone()).unwrap();
+    let mut b_index = BitmapIndex::<OZBCBitmap, u64>::new(build_options).unwrap();
+
+    let mut rng = rand::thread_rng();
+    let mut values: Vec<u64> = (0..(1500 * 1000)).map(|_i| rng.gen::<u64>()).collect();
+    values[1200 * 1000] = values[10];
+    assert!(h_index.push_values(&values).is_ok());
+    assert!(b_index.push_values(&values).is_ok());
+
+    let h_indexes = h_index.run_query(values[10], None, None).unwrap();
+    let b_indexes = b_index.run_query(values[10], None, None).unwrap();
+    assert_eq!(h_indexes, b_indexes);
+    assert!(h_indexes.contains(&(1200 * 1000)));
+}
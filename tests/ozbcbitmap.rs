@@ -1,4 +1,4 @@
-use bitrush_index::{Bitmap, OZBCBitmap};
+use bitrush_index::{Bitmap, BitmapView, OZBCBitmap, OZBCBitmapRef};
 use rand::Rng;
 
 #[test]
@@ -19,6 +19,36 @@ fn set_base() {
     assert_eq!(b0, b1);
 }
 
+#[test]
+fn with_capacity() {
+    let mut b0 = OZBCBitmap::new();
+    let mut b1 = OZBCBitmap::with_capacity(1000);
+    let mut b2 = OZBCBitmap::with_capacity(0);
+    for val in (0..2000).map(|i| i * 100) {
+        b0.set(val);
+        b1.set(val);
+        b2.set(val);
+    }
+    assert_eq!(b0, b1);
+    assert_eq!(b0, b2);
+    assert_eq!(b1.size(), b0.size());
+}
+
+#[test]
+fn clear() {
+    let mut b0 = OZBCBitmap::new();
+    for val in [0, 1, 100, 100000].iter() {
+        b0.set(*val);
+    }
+    b0.clear();
+    assert!(b0.is_empty());
+    assert_eq!(b0, OZBCBitmap::new());
+    assert_eq!(b0.size(), OZBCBitmap::new().size());
+
+    b0.set(50);
+    assert_eq!(b0.unroll_bitmap(), vec![50]);
+}
+
 #[test]
 fn set_advanced() {
     let mut b0 = OZBCBitmap::new();
@@ -66,6 +96,129 @@ fn bitand_and_unroll() {
     assert_eq!(unrolled_values, values_and);
 }
 
+#[test]
+fn and_not() {
+    let mut b0 = OZBCBitmap::new();
+    let mut b1 = b0.clone();
+    let values_0 = [0, 1, 100, 100000, 100009, 1000000, 1000100, 1060000, 1 << 30, (1 << 30) + 3];
+    let values_1 = [1, 7, 9, 99999, 100000, 100001, 100101, 1060000, (1 << 30) + 3, (1 << 31) + 5];
+    let values_and_not = [0, 100, 100009, 1000000, 1000100, 1 << 30];
+
+    for val in values_0.iter() {
+        b0.set(*val);
+    }
+    for val in values_1.iter() {
+        b1.set(*val);
+    }
+    let b_and_not = b0.and_not(&b1);
+    assert_eq!(b_and_not.unroll_bitmap(), values_and_not);
+
+    let mut b_expected = OZBCBitmap::new();
+    for val in values_and_not.iter() {
+        b_expected.set(*val);
+    }
+    assert_eq!(b_and_not, b_expected);
+    assert!(b0.and_not(&b0).is_empty());
+    assert_eq!(b0.and_not(&OZBCBitmap::new()), b0);
+
+    let mut rng = rand::thread_rng();
+    let (mut b2, mut b3) = (OZBCBitmap::new(), OZBCBitmap::new());
+    for i in 0..100000 {
+        if rng.gen::<u8>() < 64 {
+            b2.set(i);
+        }
+        if rng.gen::<u8>() < 64 {
+            b3.set(i);
+        }
+    }
+    let b3_values = b3.unroll_bitmap();
+    let expected: Vec<u32> = b2.unroll_bitmap().into_iter().filter(|val| b3_values.binary_search(val).is_err()).collect();
+    assert_eq!(b2.and_not(&b3).unroll_bitmap(), expected);
+}
+
+#[test]
+fn unroll_all_byte_values() {
+    let mut b0 = OZBCBitmap::new();
+    let mut values: Vec<u32> = Vec::new();
+    for byte in 0..256u32 {
+        for j in (0..8).filter(|j| (byte >> j) & 1 == 1) {
+            values.push(byte * 1000 * 8 + j);
+        }
+    }
+    values.push(u32::MAX - 3);
+    values.push(u32::MAX);
+    for val in values.iter() {
+        b0.set(*val);
+    }
+    assert_eq!(b0.unroll_bitmap(), values);
+}
+
+#[test]
+fn intersect_with_sorted() {
+    let mut rng = rand::thread_rng();
+    let mut b0 = OZBCBitmap::new();
+    let mut values: Vec<u32> = (0..10000).map(|_i| rng.gen::<u32>() % (1 << 26)).collect();
+    values.sort_unstable();
+    values.dedup();
+    for val in values.iter() {
+        b0.set(*val);
+    }
+
+    let mut candidates: Vec<u32> = values.iter().step_by(7).copied().collect();
+    candidates.extend((0..1000).map(|_i| rng.gen::<u32>()));
+    candidates.sort_unstable();
+    let expected: Vec<u32> = candidates.iter().filter(|pos| values.binary_search(pos).is_ok()).copied().collect();
+    assert_eq!(b0.intersect_with_sorted(&candidates), expected);
+    assert!(b0.intersect_with_sorted(&[]).is_empty());
+    assert!(OZBCBitmap::new().intersect_with_sorted(&candidates).is_empty());
+    for pos in candidates.iter() {
+        assert_eq!(b0.contains(*pos), values.binary_search(pos).is_ok());
+    }
+}
+
+#[test]
+fn canonical_equality() {
+    let mut b0 = OZBCBitmap::new();
+    b0.set(1600);
+    b0.set(1601);
+
+    // The 200 zero bytes before bit 1600 split in a word with an empty dirty byte
+    // and a word with the remaining zero bytes, followed by a trailing run of 128 zero bytes.
+    let words: [u16; 3] = [127 << 8, (72 << 8) | 0b11, (1 << 15) | 1];
+    let num_bytes: u32 = 201 + 128;
+    let mut buffer: Vec<u8> = num_bytes.to_ne_bytes().to_vec();
+    buffer.extend(words.iter().flat_map(|w| w.to_ne_bytes()));
+    let mut b1 = OZBCBitmap::new();
+    assert!(b1.read_from_buffer(&buffer, true).is_ok());
+
+    assert_eq!(b1.unroll_bitmap(), b0.unroll_bitmap());
+    assert_eq!(b0, b1);
+    let mut b2 = OZBCBitmap::new();
+    b2.set(1600);
+    assert_ne!(b0, b2);
+    assert_ne!(b1, b2);
+    b2.set(1601);
+    b2.set(1700);
+    assert_ne!(b1, b2);
+}
+
+#[test]
+fn display_summary() {
+    let mut b0 = OZBCBitmap::new();
+    assert_eq!(b0.to_string(), "OZBCBitmap { cardinality: 0, bytes: 0, words: 0, positions: [] }");
+    for val in [0, 50, 100].iter() {
+        b0.set(*val);
+    }
+    assert_eq!(b0.to_string(), "OZBCBitmap { cardinality: 3, bytes: 13, words: 3, positions: [0, 50, 100] }");
+
+    let mut b1 = OZBCBitmap::new();
+    for val in 0..1000000 {
+        b1.set(val * 3);
+    }
+    let summary = format!("OZBCBitmap {{ cardinality: 1000000, bytes: 375000, words: {}, positions: [0, 3, 6, 9, 12, ..., 2999985, 2999988, 2999991, 2999994, 2999997] }}", b1.size() / 2 - 2);
+    assert_eq!(b1.to_string(), summary);
+}
+
 #[test]
 fn write_read_advanced() {
     let mut b0 = OZBCBitmap::new();
@@ -94,3 +247,173 @@ fn write_read_advanced() {
     assert!(r_read.is_ok());
     assert_eq!(b0, b1);
 }
+
+#[test]
+fn bitmap_view() {
+    let mut b0 = OZBCBitmap::new();
+    let mut b1 = b0.clone();
+    let mut rng = rand::thread_rng();
+    let mut last_val: (u32, u32) = (0, 0);
+    for _i in 0..100000 {
+        last_val.0 += rng.gen::<u32>() % 2000;
+        last_val.1 += rng.gen::<u32>() % 200;
+        b0.set(last_val.0);
+        b1.set(last_val.1);
+    }
+
+    let mut buf0: Vec<u8> = vec![0; b0.size()];
+    let mut buf1: Vec<u8> = vec![0; b1.size() + 1];
+    assert!(b0.write_to_buffer(&mut buf0).is_ok());
+    assert!(b1.write_to_buffer(&mut buf1).is_ok());
+    assert!(OZBCBitmap::view(&buf1).is_err());
+    buf1.pop();
+
+    let v0 = OZBCBitmap::view(&buf0).unwrap();
+    let v1: OZBCBitmapRef = OZBCBitmapRef::from_buffer(&buf1).unwrap();
+    assert_eq!(v0.to_bitmap(), b0);
+    assert_eq!(OZBCBitmap::unroll_view(&v0), b0.unroll_bitmap());
+
+    let b_and = (&b0) & (&b1);
+    assert_eq!(OZBCBitmap::and_views(&v0, &v1), b_and);
+    assert_eq!(b0.and_view(&v1), b_and);
+}
+
+#[test]
+fn write_to_stream() {
+    let mut b0 = OZBCBitmap::new();
+    let mut rng = rand::thread_rng();
+    let mut last_val: u32 = 0;
+    for _i in 0..10000 {
+        last_val += rng.gen::<u32>() % 5000;
+        b0.set(last_val);
+    }
+
+    let mut buf: Vec<u8> = vec![0; b0.size()];
+    assert!(b0.write_to_buffer(&mut buf).is_ok());
+    let mut stream: Vec<u8> = Vec::new();
+    let r_write = b0.write_to(&mut stream);
+    assert_eq!(r_write.unwrap(), b0.size());
+    assert_eq!(stream, buf);
+}
+
+#[test]
+fn read_from_stream() {
+    let mut b0 = OZBCBitmap::new();
+    let mut rng = rand::thread_rng();
+    let mut last_val: u32 = 0;
+    // a bitmap larger than the read scratch buffer.
+    for _i in 0..100000 {
+        last_val += 1 + rng.gen::<u32>() % 100;
+        b0.set(last_val);
+    }
+    let mut stream: Vec<u8> = Vec::new();
+    assert!(b0.write_to(&mut stream).is_ok());
+    assert!(stream.len() > 1 << 16);
+
+    let mut b1 = OZBCBitmap::new();
+    b1.set(7);
+    let r_read = b1.read_from(&mut std::io::Cursor::new(&stream), stream.len(), true);
+    assert!(r_read.is_ok());
+    assert_eq!(b1, b0);
+
+    let mut b2 = OZBCBitmap::new();
+    let r_read = b2.read_from(&mut std::io::Cursor::new(&stream), stream.len() - 1, true);
+    assert_eq!(r_read.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    stream[0] ^= 1;
+    let r_read = b2.read_from(&mut std::io::Cursor::new(&stream), stream.len(), true);
+    assert_eq!(r_read.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    let r_read = b2.read_from(&mut std::io::Cursor::new(&stream[0..10]), stream.len(), false);
+    assert_eq!(r_read.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn read_from_invalid_buffer() {
+    let mut b0 = OZBCBitmap::new();
+    for val in [3, 100, 5000, 100000].iter() {
+        b0.set(*val);
+    }
+    // the bitmap content starts at an odd offset, so it is not aligned to its words.
+    let mut buf: Vec<u8> = vec![0; b0.size() + 1];
+    assert!(b0.write_to_buffer(&mut buf[1..]).is_ok());
+
+    let mut b1 = OZBCBitmap::new();
+    assert!(b1.read_from_buffer(&buf[1..], true).is_ok());
+    assert_eq!(b0, b1);
+    for len in 0..4 {
+        assert!(b1.read_from_buffer(&buf[1..(1 + len)], false).is_err());
+    }
+    // a truncated word.
+    assert!(b1.read_from_buffer(&buf[1..(buf.len() - 1)], false).is_err());
+    // a header that doesn't match the words.
+    buf[1] ^= 1;
+    assert!(b1.read_from_buffer(&buf[1..], true).is_err());
+    assert!(b1.read_from_buffer(&[255; 4 + 2 * (1 << 16)], true).is_err());
+}
+
+#[test]
+fn wide_words() {
+    let mut b0 = OZBCBitmap::new();
+    let mut rng = rand::thread_rng();
+    let mut last_val: u32 = 0;
+    for _i in 0..10000 {
+        last_val += rng.gen::<u32>() % 200;
+        b0.set(last_val);
+    }
+    // runs of zeros longer than a 16bits filler word.
+    b0.set(1 << 30);
+    b0.set((1 << 30) + 9);
+    b0.set(3 << 30);
+
+    let mut b1 = b0.clone();
+    b1.set_wide_words(true);
+    let mut sparse = OZBCBitmap::new();
+    for i in 0..4 {
+        sparse.set(i << 30);
+    }
+    let sparse_size = sparse.size();
+    sparse.set_wide_words(true);
+    assert_eq!(sparse.size(), 4 + 4 * 7);
+    assert!(sparse.size() * 5 < sparse_size);
+    let mut buf: Vec<u8> = vec![0; b1.size()];
+    assert_eq!(b1.write_to_buffer(&mut buf), Ok(buf.len()));
+    let mut stream: Vec<u8> = Vec::new();
+    assert_eq!(b1.write_to(&mut stream).unwrap(), buf.len());
+    assert_eq!(stream, buf);
+    assert!(OZBCBitmapRef::from_buffer(&buf).is_err());
+
+    let mut b2 = OZBCBitmap::new();
+    assert!(b2.read_from_buffer(&buf, true).is_ok());
+    assert_eq!(b2, b0);
+    assert_eq!(b2.unroll_bitmap(), b0.unroll_bitmap());
+    // a bitmap read from a wide words content is written in wide words.
+    assert_eq!(b2.size(), buf.len());
+    let mut b3 = OZBCBitmap::new();
+    assert!(b3.read_from(&mut std::io::Cursor::new(&stream), stream.len(), true).is_ok());
+    assert_eq!(b3, b0);
+
+    assert!(b2.read_from_buffer(&buf[0..(buf.len() - 2)], false).is_err());
+    buf[0] ^= 1;
+    assert!(b2.read_from_buffer(&buf, true).is_err());
+
+    // the size of a bitmap set in wide words is counted while its bits are set.
+    let mut b4 = OZBCBitmap::new();
+    b4.set_wide_words(true);
+    for val in b0.unroll_bitmap() {
+        b4.set(val);
+        if val % 7 == 0 || val >= 1 << 30 {
+            let mut b4_buf: Vec<u8> = vec![0; b4.size()];
+            assert_eq!(b4.write_to_buffer(&mut b4_buf), Ok(b4_buf.len()));
+        }
+    }
+    assert_eq!(b4.size(), b1.size());
+    b4.clear();
+    b4.set(1 << 20);
+    assert_eq!(b4.size(), 4 + 4 * 2);
+
+    let mut empty = OZBCBitmap::new();
+    empty.set_wide_words(true);
+    let mut buf: Vec<u8> = vec![0; empty.size()];
+    assert!(empty.write_to_buffer(&mut buf).is_ok());
+    assert!(b2.read_from_buffer(&buf, true).is_ok());
+    assert!(b2.is_empty());
+}